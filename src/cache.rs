@@ -0,0 +1,121 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::Commit;
+
+// ---------- Message cache ----------
+// The last generated commit, kept in the git directory with a hash of what
+// it was generated from. Re-running after quitting the editor (or a failing
+// hook) reuses it instead of asking the model again, for `cache_minutes`.
+pub const CACHE_FILE: &str = "git-cmt-rs-cache.json";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CacheEntry {
+    // `key()` of the prompt and the settings that shape the answer.
+    pub key: String,
+    // Seconds since the Unix epoch.
+    pub created: u64,
+    pub commit: Commit,
+}
+
+impl CacheEntry {
+    pub fn new(key: String, commit: Commit) -> Self {
+        CacheEntry {
+            key,
+            created: now(),
+            commit,
+        }
+    }
+
+    // The commit when it was generated for `key` no more than `minutes`
+    // before `now`; a clock that went backwards doesn't count as fresh.
+    pub fn lookup(&self, key: &str, now: u64, minutes: u64) -> Option<&Commit> {
+        let age = now.checked_sub(self.created)?;
+        (self.key == key && age <= minutes * 60).then_some(&self.commit)
+    }
+
+    // Whole minutes since the entry was written, for telling the user.
+    pub fn age_minutes(&self, now: u64) -> u64 {
+        now.saturating_sub(self.created) / 60
+    }
+}
+
+// SHA-256, in hex, of `settings` (one line) followed by `prompt`.
+pub fn key(settings: &str, prompt: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update(settings)
+        .chain_update("\n")
+        .chain_update(prompt)
+        .finalize();
+    digest.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+// A missing or unreadable cache is an empty one.
+pub fn load(path: &Path) -> Option<CacheEntry> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+pub fn store(path: &Path, entry: &CacheEntry) -> Result<()> {
+    let json = serde_json::to_string(entry).context("failed to serialize the cache entry")?;
+    fs::write(path, json).with_context(|| format!("failed to write {}", path.display()))
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_reused_for_the_same_key_while_fresh() {
+        let entry = CacheEntry {
+            key: "abc".into(),
+            created: 1_000,
+            commit: Commit {
+                r#type: "feat".into(),
+                message: "add paging".into(),
+                ..Default::default()
+            },
+        };
+        let hit = entry.lookup("abc", 1_000 + 5 * 60, 5).unwrap();
+        assert_eq!(hit.message, "add paging");
+        assert!(entry.lookup("abc", 1_000 + 5 * 60 + 1, 5).is_none());
+        assert!(entry.lookup("abd", 1_001, 5).is_none());
+        assert!(entry.lookup("abc", 999, 5).is_none());
+        assert!(entry.lookup("abc", 1_000, 0).is_some());
+        assert_eq!(entry.age_minutes(1_000 + 150), 2);
+    }
+
+    #[test]
+    fn entries_survive_a_round_trip_and_junk_is_ignored() {
+        let dir = std::env::temp_dir().join(format!("git-cmt-rs-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(CACHE_FILE);
+        assert!(load(&path).is_none());
+        store(&path, &CacheEntry::new("k".into(), Commit::default())).unwrap();
+        let entry = load(&path).unwrap();
+        assert_eq!(entry.key, "k");
+        assert!(entry.lookup("k", now(), 5).is_some());
+        fs::write(&path, "{not json").unwrap();
+        assert!(load(&path).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keys_are_sha256_of_the_settings_then_the_prompt() {
+        // `printf 'openai\ndiff' | sha256sum`
+        assert_eq!(
+            key("openai", "diff"),
+            "d3b4e10cf4fbe61f074f35eea7ffc32d201cc70f96d99fafec0c54f590f020ad"
+        );
+        assert_ne!(key("openai", "diff"), key("openai diff", ""));
+    }
+}
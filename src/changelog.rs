@@ -0,0 +1,147 @@
+// ---------- CHANGELOG.md ----------
+// `--changelog`: before committing, turn the commit subjects since the last
+// tag into a Keep a Changelog section and prepend it to CHANGELOG.md. Git
+// access lives in main; this module only does the text.
+
+pub const CHANGELOG_FILE: &str = "CHANGELOG.md";
+
+const HEADER: &str = "# Changelog
+
+All notable changes to this project will be documented in this file.
+
+The format is based on [Keep a Changelog](https://keepachangelog.com/en/1.1.0/).
+";
+
+// Keep a Changelog headings, in the order they are printed, with the
+// Conventional Commit types filed under each. Anything else is "Changed".
+const GROUPS: &[(&str, &[&str])] = &[
+    ("Added", &["feat"]),
+    ("Changed", &[]),
+    ("Removed", &["revert"]),
+    ("Fixed", &["fix"]),
+    ("Security", &["security"]),
+];
+
+// `feat(api)!: add login` -> (Some("feat"), Some("api"), "add login").
+fn parse_subject(subject: &str) -> (Option<&str>, Option<&str>, &str) {
+    let Some((prefix, message)) = subject.split_once(": ") else {
+        return (None, None, subject.trim());
+    };
+    let prefix = prefix.trim_end_matches('!');
+    let (kind, scope) = match prefix.split_once('(') {
+        Some((kind, scope)) => (kind, scope.strip_suffix(')')),
+        None => (prefix, None),
+    };
+    if kind.is_empty() || !kind.chars().all(|c| c.is_ascii_alphabetic()) {
+        return (None, None, subject.trim());
+    }
+    (Some(kind), scope.filter(|s| !s.is_empty()), message.trim())
+}
+
+fn group_for(kind: Option<&str>) -> &'static str {
+    let kind = kind.map(str::to_lowercase);
+    GROUPS
+        .iter()
+        .find(|(_, kinds)| kinds.iter().any(|k| Some(*k) == kind.as_deref()))
+        .map_or("Changed", |(heading, _)| heading)
+}
+
+// A `## [version] - date` section, or None when there is nothing to list.
+pub fn section(version: &str, date: &str, subjects: &[String]) -> Option<String> {
+    if subjects.is_empty() {
+        return None;
+    }
+    let mut out = format!("## [{version}] - {date}\n");
+    for (heading, _) in GROUPS {
+        let entries: Vec<String> = subjects
+            .iter()
+            .map(|subject| parse_subject(subject))
+            .filter(|(kind, _, _)| group_for(*kind) == *heading)
+            .map(|(_, scope, message)| match scope {
+                Some(scope) => format!("- **{scope}:** {message}\n"),
+                None => format!("- {message}\n"),
+            })
+            .collect();
+        if entries.is_empty() {
+            continue;
+        }
+        out.push_str(&format!("\n### {heading}\n\n"));
+        entries.iter().for_each(|entry| out.push_str(entry));
+    }
+    Some(out)
+}
+
+// Insert `section` above the newest release, keeping any preamble and an
+// `## [Unreleased]` section on top. A missing file gets the standard header.
+pub fn prepend(existing: Option<&str>, section: &str) -> String {
+    let existing = existing.unwrap_or(HEADER);
+    let lines: Vec<&str> = existing.split_inclusive('\n').collect();
+    let is_release = |line: &&str| line.starts_with("## ") && !line.contains("[Unreleased]");
+    let at = lines.iter().position(is_release).unwrap_or(lines.len());
+
+    let mut out: String = lines[..at].concat();
+    if !out.is_empty() && !out.ends_with("\n\n") {
+        out.push_str(if out.ends_with('\n') { "\n" } else { "\n\n" });
+    }
+    out.push_str(section);
+    if at < lines.len() {
+        out.push('\n');
+        out.push_str(&lines[at..].concat());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subjects(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn subjects_are_grouped_by_conventional_type() {
+        let out = section(
+            "1.2.0",
+            "2026-10-16",
+            &subjects(&[
+                "feat(api): add OAuth login",
+                "fix: handle empty diffs",
+                "refactor(llm)!: split backends",
+                "Merge tweaks",
+                "feat: add --changelog",
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            "## [1.2.0] - 2026-10-16\n\
+             \n### Added\n\n\
+             - **api:** add OAuth login\n\
+             - add --changelog\n\
+             \n### Changed\n\n\
+             - **llm:** split backends\n\
+             - Merge tweaks\n\
+             \n### Fixed\n\n\
+             - handle empty diffs\n"
+        );
+        assert_eq!(section("1.2.0", "2026-10-16", &[]), None);
+    }
+
+    #[test]
+    fn new_sections_go_above_the_latest_release() {
+        let section = "## [1.1.0] - 2026-10-16\n\n### Fixed\n\n- a bug\n";
+        let created = prepend(None, section);
+        assert!(created.starts_with("# Changelog\n\nAll notable changes"));
+        assert!(created.ends_with("1.1.0/).\n\n## [1.1.0] - 2026-10-16\n\n### Fixed\n\n- a bug\n"));
+
+        let existing =
+            "# Changelog\n\n## [Unreleased]\n\n- wip\n\n## [1.0.0] - 2026-01-01\n\n- first\n";
+        assert_eq!(
+            prepend(Some(existing), section),
+            "# Changelog\n\n## [Unreleased]\n\n- wip\n\n\
+             ## [1.1.0] - 2026-10-16\n\n### Fixed\n\n- a bug\n\n\
+             ## [1.0.0] - 2026-01-01\n\n- first\n"
+        );
+    }
+}
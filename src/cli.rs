@@ -0,0 +1,522 @@
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+// ---------- Command-line interface ----------
+// Connection settings (model, base URL, key) stay in environment variables;
+// flags cover per-invocation behavior.
+#[derive(Debug, Parser)]
+#[command(
+    name = "git-cmt-rs",
+    version,
+    about = "AI-powered Conventional Commit message generator"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+
+    /// Run as if started in PATH, like `git -C`: git commands, the config file, .gitcmtignore and relative paths all resolve there
+    #[arg(short = 'C', long = "repo", value_name = "PATH", global = true)]
+    pub repo: Option<PathBuf>,
+
+    /// Read the diff from stdin instead of staging and running `git diff --cached`; prints the message unless --commit is given
+    #[arg(long, visible_alias = "stdin", group = "diff_source")]
+    pub diff_stdin: bool,
+
+    /// With --diff-stdin or --diff-file, commit the message with `git commit` in the current directory instead of only printing it
+    #[arg(long, requires = "diff_source", conflicts_with_all = ["dry_run", "message_only"])]
+    pub commit: bool,
+
+    /// Read the diff from a patch file instead of staging and running `git diff --cached`; prints the message unless --commit is given
+    #[arg(long, value_name = "PATH", group = "diff_source")]
+    pub diff_file: Option<PathBuf>,
+
+    /// Stage new untracked files too, with `git add .` (the default)
+    #[arg(long, overrides_with = "no_include_untracked", global = true)]
+    pub include_untracked: bool,
+
+    /// Stage only changes to tracked files, with `git add -u`; new files stay out of the commit
+    #[arg(long, overrides_with = "include_untracked", global = true)]
+    pub no_include_untracked: bool,
+
+    /// Colour the output even when it isn't going to a terminal
+    #[arg(long, overrides_with = "no_color", global = true)]
+    pub color: bool,
+
+    /// Never colour the output; a non-empty NO_COLOR environment variable does the same
+    #[arg(long, overrides_with = "color", global = true)]
+    pub no_color: bool,
+
+    /// Stage from the repository root even when run in a subdirectory (the default)
+    #[arg(long, overrides_with = "cwd", global = true)]
+    pub root: bool,
+
+    /// Stage only the current directory's subtree when run in a subdirectory
+    #[arg(long, overrides_with = "root", global = true)]
+    pub cwd: bool,
+
+    /// Commit the index as it is instead of staging changes first; untracked files are listed to you and the model but not committed
+    #[arg(long, conflicts_with_all = ["diff_stdin", "diff_file"])]
+    pub no_stage: bool,
+
+    /// Stage without first listing the changes and asking "Stage these N files?"
+    #[arg(long)]
+    pub force_stage: bool,
+
+    /// Commit even while a rebase, `git am`, cherry-pick or revert is stopped part-way
+    #[arg(long)]
+    pub allow_in_progress: bool,
+
+    /// Answer yes to the questions before the commit (the staging preview, --confirm-message); the push is still asked about
+    #[arg(short, long)]
+    pub yes: bool,
+
+    /// Pick the files to stage from a checklist of changes, with their line counts, instead of staging everything
+    #[arg(long, conflicts_with_all = ["no_stage", "diff_stdin", "diff_file", "dry_run", "message_only", "hook", "wip"])]
+    pub select: bool,
+
+    /// Send only file names and change counts (`--name-status` and `--stat`), never file contents; combine with --hint to say what changed
+    #[arg(long, visible_alias = "stat-only", global = true)]
+    pub names_only: bool,
+
+    /// Send only the changed file names (`--name-only`), no statuses, counts or contents: the cheapest and least informed prompt
+    #[arg(long, conflicts_with = "names_only", global = true)]
+    pub brief: bool,
+
+    /// Switch to --stat-only when the whole diff is larger than N, in the diff budget's unit (also: GIT_CMT_STAT_THRESHOLD; default: 3x the budget)
+    #[arg(long, value_name = "N", global = true)]
+    pub stat_threshold: Option<usize>,
+
+    /// Never switch to --stat-only on its own; send the trimmed diff however large it is
+    #[arg(long, global = true)]
+    pub no_auto_stat: bool,
+
+    /// Extra context for the model about what changed or why, sent with the diff
+    #[arg(long, value_name = "TEXT", global = true)]
+    pub hint: Option<String>,
+
+    /// Reference this ticket in the message instead of the one ticket_pattern finds in the branch name
+    #[arg(long, value_name = "ID", global = true)]
+    pub ticket: Option<String>,
+
+    /// Add a `Co-authored-by: Name <email>` trailer to the commit; repeat for each co-author
+    #[arg(long, value_name = "NAME <EMAIL>", global = true)]
+    pub co_author: Vec<String>,
+
+    /// Send the diff as-is, without replacing likely secrets (API keys, tokens, private keys)
+    #[arg(long, global = true)]
+    pub no_redact: bool,
+
+    /// Amend the last commit instead of creating a new one; a later push uses --force-with-lease
+    #[arg(long)]
+    pub amend: bool,
+
+    /// Prepend a Keep a Changelog section for the commits since the last tag to CHANGELOG.md and include it in the commit
+    #[arg(long)]
+    pub changelog: bool,
+
+    /// Version for the --changelog section heading (default: the last tag)
+    #[arg(long, value_name = "VERSION", requires = "changelog")]
+    pub changelog_version: Option<String>,
+
+    /// Ask for 2-4 bullet points explaining the change, written as the commit body
+    #[arg(long, global = true)]
+    pub body: bool,
+
+    /// Mark the commit as a breaking change (`feat!:`) whatever the model decided
+    #[arg(long)]
+    pub breaking: bool,
+
+    /// Run as the prepare-commit-msg hook: write the message for the staged changes above MSGFILE's contents
+    #[arg(long, num_args = 1..=3, value_names = ["MSGFILE", "SOURCE", "SHA"], conflicts_with_all = ["diff_stdin", "diff_file", "amend", "changelog", "dry_run", "message_only", "interactive", "wip", "tag_version", "tag", "push_tags"])]
+    pub hook: Vec<String>,
+
+    /// Commit the changes as they are with a fixed WIP message: no model call, no hooks, no push
+    #[arg(long, conflicts_with_all = ["diff_stdin", "diff_file", "amend", "changelog", "breaking", "dry_run", "message_only", "interactive"])]
+    pub wip: bool,
+
+    /// Message for --wip commits (default: "wip: work in progress")
+    #[arg(long, value_name = "MESSAGE", requires = "wip")]
+    pub wip_message: Option<String>,
+
+    /// Generate the message and print it without staging, committing or pushing
+    #[arg(long, conflicts_with = "changelog")]
+    pub dry_run: bool,
+
+    /// Print only the generated message on stdout, for scripts and hooks; implies --dry-run
+    #[arg(long, visible_alias = "print")]
+    pub message_only: bool,
+
+    /// After committing, write a Markdown PR description for the branch's commits since --pr-base to .git/PR_DESCRIPTION.md and stdout
+    #[arg(long, conflicts_with_all = ["diff_stdin", "diff_file", "dry_run", "message_only", "hook", "wip"])]
+    pub pr_description: bool,
+
+    /// Branch or commit the PR is based on (also: pr_base in the config file; default origin/main)
+    #[arg(long, value_name = "REF", requires = "pr_description")]
+    pub pr_base: Option<String>,
+
+    /// Also copy the generated message to the system clipboard, with or without --dry-run
+    #[arg(long, conflicts_with = "hook")]
+    pub copy: bool,
+
+    /// Commit straight away instead of opening the editor to review the message
+    #[arg(long)]
+    pub no_edit: bool,
+
+    /// Show the final message and ask "Commit with this message? (y/n/e)" before committing; `e` opens the editor. Assumes yes when stdin is not a terminal
+    #[arg(long, conflicts_with_all = ["dry_run", "message_only", "hook", "wip"])]
+    pub confirm_message: bool,
+
+    /// When a hook fails the commit after modifying staged files, re-stage them and retry once without asking
+    #[arg(long)]
+    pub hook_retry: bool,
+
+    /// Review the type, scope and message one prompt at a time (Enter keeps each); implies --no-edit
+    #[arg(long)]
+    pub interactive: bool,
+
+    /// Skip git hooks: passes --no-verify to `git commit` and `git push` (blocked by GIT_CMT_ALLOW_NO_VERIFY=false)
+    #[arg(long)]
+    pub no_verify: bool,
+
+    /// Push with plain `git push --force` instead of --force-with-lease (dangerous on shared branches)
+    #[arg(long)]
+    pub force: bool,
+
+    /// Don't retry with `git push --set-upstream origin <branch>` when the branch has no upstream; the push fails instead
+    #[arg(long)]
+    pub no_auto_set_upstream: bool,
+
+    /// Ask the model again even if the same changes were sent in the last cache_minutes (default 5)
+    #[arg(long, global = true)]
+    pub no_cache: bool,
+
+    /// Don't `git fetch` before the push question to check whether the branch is behind its upstream
+    #[arg(long)]
+    pub no_fetch: bool,
+
+    /// On a detached HEAD, push the new commit to this branch on origin or --remote (`HEAD:<branch>`) instead of skipping the push
+    #[arg(long, value_name = "NAME")]
+    pub branch: Option<String>,
+
+    /// Push to this remote instead of the branch's own (branch.<name>.remote) or origin
+    #[arg(long, value_name = "NAME")]
+    pub remote: Option<String>,
+
+    /// Tag the new commit with an annotated tag, e.g. v1.2.0
+    #[arg(long, value_name = "TAG", conflicts_with_all = ["dry_run", "message_only", "wip"])]
+    pub tag_version: Option<String>,
+
+    /// Tag the new commit with an annotated tag whose message is generated from the same changes; the push then uses --follow-tags
+    #[arg(long, value_name = "TAG", conflicts_with_all = ["tag_version", "push_tags", "dry_run", "message_only", "wip"])]
+    pub tag: Option<String>,
+
+    /// Generate the --tag message from the commit subjects since the previous tag instead of the diff
+    #[arg(long, requires = "tag")]
+    pub tag_from_history: bool,
+
+    /// Also push the --tag-version tag, or the tags already on the new commit, after the branch
+    #[arg(long, conflicts_with_all = ["dry_run", "message_only", "wip"])]
+    pub push_tags: bool,
+
+    /// Stream the response and show live progress (also: GIT_CMT_STREAM=1)
+    #[arg(long, global = true)]
+    pub stream: bool,
+
+    /// Gzip request bodies over 1 KB (`Content-Encoding: gzip`); only for endpoints that accept it (also: GIT_CMT_COMPRESS_REQUESTS=1)
+    #[arg(long, global = true)]
+    pub compress_requests: bool,
+
+    /// Write the commit message in this language, as a BCP 47 code like fr or pt-BR (also: GIT_CMT_LANG)
+    #[arg(long, value_name = "CODE", global = true)]
+    pub lang: Option<String>,
+
+    /// LLM provider to route requests through (also: GIT_CMT_PROVIDER)
+    #[arg(long, value_enum, global = true)]
+    pub provider: Option<Provider>,
+
+    /// Total seconds to spend retrying rate-limited or failed requests (also: GIT_CMT_RETRY_BUDGET; default 60)
+    #[arg(long, value_name = "SECS", global = true)]
+    pub retry_budget: Option<u64>,
+
+    /// Commit message format: conventional (default), github, angular or emoji
+    #[arg(long, value_enum, value_name = "FORMAT", global = true)]
+    pub format: Option<FormatPreset>,
+
+    /// OpenAI API flavor for openai/openrouter: chat completions (default) or the Responses API
+    #[arg(long, value_enum, global = true)]
+    pub api: Option<Api>,
+
+    /// OpenAI organization ID sent as the OpenAI-Organization header (also: OPENAI_ORG_ID)
+    #[arg(long, value_name = "ID", global = true)]
+    pub org: Option<String>,
+
+    /// OpenAI project ID sent as the OpenAI-Project header (also: OPENAI_PROJECT_ID)
+    #[arg(long, value_name = "ID", global = true)]
+    pub project: Option<String>,
+
+    /// Cap on the number of tokens the model may generate; 0 sends no cap (also: GIT_CMT_MAX_TOKENS; default 256)
+    #[arg(long, value_name = "N", global = true)]
+    pub max_tokens: Option<u32>,
+
+    /// Request field that carries --max-tokens; newer OpenAI models require max_completion_tokens
+    #[arg(long, value_enum, value_name = "FIELD", global = true)]
+    pub max_tokens_field: Option<MaxTokensField>,
+
+    /// Budget the diff in characters instead of tokens; longer diffs are truncated (also: GIT_CMT_MAX_DIFF_CHARS)
+    #[arg(long, value_name = "N", global = true)]
+    pub max_diff_chars: Option<usize>,
+
+    /// Nucleus sampling: sample from the smallest set of tokens whose probability adds up to P (0.0-1.0); sent instead of temperature 0 (also: GIT_CMT_TOP_P)
+    #[arg(long, value_name = "P", global = true)]
+    pub top_p: Option<f32>,
+
+    /// Penalize tokens by how often they already appear, from -2.0 to 2.0; positive values discourage repeated words (also: GIT_CMT_FREQUENCY_PENALTY; default 0, not sent)
+    #[arg(long, value_name = "F", global = true, allow_negative_numbers = true)]
+    pub frequency_penalty: Option<f32>,
+
+    /// Penalize tokens that already appear at all, from -2.0 to 2.0 (also: GIT_CMT_PRESENCE_PENALTY; default 0, not sent)
+    #[arg(long, value_name = "F", global = true, allow_negative_numbers = true)]
+    pub presence_penalty: Option<f32>,
+
+    /// Similarity (0-100%) above which a deleted and an added file count as a rename; 0 turns detection off (also: GIT_CMT_RENAME_THRESHOLD; default 50)
+    #[arg(long, value_name = "PERCENT", global = true)]
+    pub rename_threshold: Option<u8>,
+
+    /// Similarity (0-100%) above which a new file counts as a copy of a modified one; 0 turns detection off (also: GIT_CMT_COPY_THRESHOLD; default 50)
+    #[arg(long, value_name = "PERCENT", global = true)]
+    pub copy_threshold: Option<u8>,
+
+    /// Lines of unchanged context around each change in the diff; 0 fits the most changes into the budget (also: GIT_CMT_CONTEXT_LINES; default 3)
+    #[arg(long, value_name = "N", global = true)]
+    pub context_lines: Option<u32>,
+
+    /// Send the subjects of the last N commits so the message matches the repository's style (also: GIT_CMT_CONTEXT_COMMITS; default 0, off)
+    #[arg(long, value_name = "N", global = true)]
+    pub context_commits: Option<usize>,
+
+    /// Diff algorithm passed to `git diff`; histogram often reads better for refactors (default: git's own, usually myers)
+    #[arg(long, value_name = "ALGORITHM", global = true)]
+    pub diff_algorithm: Option<DiffAlgorithm>,
+
+    /// How git is driven for staging, the staged diff, the commit and branch lookups: libgit2 (default) or exec, a `git` subprocess each time
+    #[arg(long, value_enum, value_name = "BACKEND", global = true)]
+    pub git_backend: Option<GitBackend>,
+
+    /// Prompt size in tokens; the diff is truncated to fit and a larger prompt is refused (also: GIT_CMT_MAX_PROMPT_TOKENS; default: the model's context window)
+    #[arg(long, value_name = "N", global = true)]
+    pub max_prompt_tokens: Option<usize>,
+
+    /// Summarize each file separately, then write the message from those summaries, when the diff is too large (also: GIT_CMT_SUMMARIZE_LARGE_DIFFS=1)
+    #[arg(long, global = true)]
+    pub summarize_large_diffs: bool,
+
+    /// Token count above which --summarize-large-diffs kicks in (also: GIT_CMT_SUMMARIZE_ABOVE; default 4000)
+    #[arg(long, value_name = "TOKENS", global = true)]
+    pub summarize_above: Option<usize>,
+
+    /// Ask before sending a request estimated to cost more than this many US cents (also: GIT_CMT_COST_WARN_ABOVE)
+    #[arg(long, value_name = "CENTS", global = true)]
+    pub cost_warn_above: Option<f64>,
+
+    /// Proxy URL for LLM requests (http, https, socks5, socks5h), or `none`; overrides HTTPS_PROXY/ALL_PROXY
+    #[arg(long, value_name = "URL", global = true)]
+    pub proxy: Option<String>,
+
+    /// PEM file with extra root certificate(s) to trust for LLM requests (e.g. an internal CA)
+    #[arg(long, value_name = "PATH", global = true)]
+    pub ca_cert: Option<PathBuf>,
+
+    /// DANGEROUS: skip TLS certificate verification for LLM requests
+    #[arg(long, global = true)]
+    pub insecure: bool,
+
+    /// Log redacted LLM requests and responses (diff elided, API key removed) to stderr or --log-file
+    #[arg(long, global = true)]
+    pub debug_http: bool,
+
+    /// Append --debug-http output to this file instead of stderr
+    #[arg(long, value_name = "PATH", requires = "debug_http", global = true)]
+    pub log_file: Option<PathBuf>,
+
+    /// Print extra diagnostics (e.g. the proxy in use, the prompt sent) to stderr
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Provider {
+    /// OpenAI or any OpenAI-compatible endpoint set via OPENAI_BASE_URL
+    #[value(name = "openai")]
+    OpenAi,
+    /// OpenRouter's multi-model API (uses OPENROUTER_API_KEY)
+    #[value(name = "openrouter")]
+    OpenRouter,
+    /// Anthropic's Messages API (uses ANTHROPIC_API_KEY)
+    Anthropic,
+    /// Ollama's native API (uses OLLAMA_HOST)
+    Ollama,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Api {
+    /// `/chat/completions`, understood by every OpenAI-compatible server
+    #[default]
+    Chat,
+    /// `/responses`, OpenAI's newer API with `text.format` structured outputs
+    Responses,
+}
+
+// `--format` presets; `crate::format` has what each one does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FormatPreset {
+    /// `type(scope): message`
+    #[default]
+    Conventional,
+    /// `[type] message`
+    Github,
+    /// Conventional with Angular's types (build, ci, perf, ...) enforced
+    Angular,
+    /// Conventional behind the type's gitmoji, e.g. `✨ feat: ...`
+    Emoji,
+}
+
+// Git's `--diff-algorithm` values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffAlgorithm {
+    /// The basic greedy algorithm, git's default
+    Myers,
+    /// Myers, spending extra time to find the smallest diff
+    Minimal,
+    /// Matches unique lines first; good at keeping moved blocks together
+    Patience,
+    /// Patience extended to low-occurrence lines; usually best for code
+    Histogram,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GitBackend {
+    /// In process through libgit2, falling back to `git` where it can't match it
+    #[default]
+    Libgit2,
+    /// A `git` subprocess per operation
+    Exec,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Privacy {
+    /// The diff itself, trimmed to the budget
+    #[default]
+    Full,
+    /// File names and change counts only; no file contents leave the machine
+    NamesOnly,
+    /// File names alone, for the tightest token budgets
+    Brief,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaxTokensField {
+    /// `max_tokens`, understood by most OpenAI-compatible servers
+    #[default]
+    #[value(name = "max_tokens")]
+    MaxTokens,
+    /// `max_completion_tokens`, required by OpenAI's reasoning models
+    #[value(name = "max_completion_tokens")]
+    MaxCompletionTokens,
+}
+
+// Where the ticket from `ticket_pattern` or `--ticket` goes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TicketStyle {
+    /// At the end of the subject: `fix(auth): handle expired tokens (PROJ-1234)`
+    #[default]
+    Subject,
+    /// As a `Refs: PROJ-1234` footer
+    Footer,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CliCommand {
+    /// Inspect git-cmt-rs configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// List the chat models available from the configured provider
+    Models,
+    /// Show recent commits created by git-cmt-rs: the suggested message and what was committed
+    Log {
+        /// Number of most recent entries to show
+        #[arg(long, value_name = "N", default_value_t = 10)]
+        last: usize,
+    },
+    /// Poll the working tree and auto-commit new changes (staged, no editor, no push), prefixed with [auto]
+    Watch {
+        /// Seconds between checks of `git status`
+        #[arg(long, value_name = "SECONDS", default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+        /// Stop after this many auto-commits
+        #[arg(long, value_name = "N", default_value_t = 10)]
+        max_auto_commits: usize,
+    },
+    /// Manage the prepare-commit-msg hook that puts a generated message in `git commit`'s editor
+    Hook {
+        #[command(subcommand)]
+        action: HookAction,
+    },
+    /// Install the prepare-commit-msg hook; the same as `hook install`
+    Init {
+        /// Remove the hook instead, as `hook uninstall` does
+        #[arg(long)]
+        uninstall: bool,
+    },
+    /// Revert a commit with `git revert --no-commit` and commit it as `revert(scope): why`
+    Revert {
+        /// The commit to revert
+        #[arg(value_name = "SHA")]
+        commit: String,
+    },
+    /// Print one message for all the commits since BASE; with --apply, replace them with one commit (`git reset --soft`)
+    Squash {
+        /// Squash the commits since the branch left BASE (default: the remote's default branch from refs/remotes/<remote>/HEAD, else pr_base)
+        #[arg(value_name = "BASE")]
+        base: Option<String>,
+        /// Replace the commits with a single commit carrying the message, opening the editor first
+        #[arg(long)]
+        apply: bool,
+        /// With --apply, squash even if the commits include merges or are already on a remote
+        #[arg(long, requires = "apply")]
+        force: bool,
+    },
+    /// Undo the last commit, keeping its changes staged (`git reset --soft HEAD~1`)
+    Undo {
+        /// Discard the commit AND its changes (`git reset --hard HEAD~1`); asks for confirmation
+        #[arg(long)]
+        hard: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Subcommand)]
+pub enum HookAction {
+    /// Write the hook into the hooks directory (`.git/hooks`, or core.hooksPath)
+    Install,
+    /// Remove the hook, if git-cmt-rs installed it
+    Uninstall,
+    /// Say whether the hook is installed, and whether it is git-cmt-rs's
+    Status,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigAction {
+    /// Print the resolved configuration as TOML, annotated with each value's source
+    Show,
+    /// Check the config file, settings, API key and a test request to the provider
+    Validate,
+}
@@ -0,0 +1,1926 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::exclude::Excludes;
+
+// ---------- Diff budgeting ----------
+// Fits a diff into a `Budget` without letting one large file crowd out the
+// rest or cutting a hunk in half. Every file keeps its header and hunk
+// headers; the remaining budget is shared between file bodies, smallest
+// first, so each file gets at most an equal share of what is left. Files
+// whose bodies were cut, and how much was dropped, are listed in a final
+// summary line.
+
+// How much of the diff may be sent and how it is measured: tokens for the
+// configured model by default (`llm::diff_budget()`), or characters with
+// `--max-diff-chars`.
+pub struct Budget {
+    pub limit: usize,
+    pub unit: &'static str,
+    count: Box<dyn Fn(&str) -> usize>,
+}
+
+impl Budget {
+    pub fn new(limit: usize, unit: &'static str, count: impl Fn(&str) -> usize + 'static) -> Self {
+        Budget {
+            limit,
+            unit,
+            count: Box::new(count),
+        }
+    }
+
+    pub fn chars(limit: usize) -> Self {
+        Budget::new(limit, "chars", chars)
+    }
+
+    pub fn size(&self, text: &str) -> usize {
+        (self.count)(text)
+    }
+}
+
+struct FileDiff<'a> {
+    // None for text before the first `diff --git` (e.g. a patch's email header).
+    path: Option<&'a str>,
+    header: Vec<&'a str>,
+    hunks: Vec<Hunk<'a>>,
+}
+
+struct Hunk<'a> {
+    header: Option<&'a str>,
+    body: Vec<&'a str>,
+}
+
+impl FileDiff<'_> {
+    fn skeleton_size(&self, size: &dyn Fn(&str) -> usize) -> usize {
+        let hunk_headers = self.hunks.iter().filter_map(|h| h.header);
+        self.header
+            .iter()
+            .copied()
+            .chain(hunk_headers)
+            .map(size)
+            .sum()
+    }
+
+    fn body_size(&self, size: &dyn Fn(&str) -> usize) -> usize {
+        self.hunks
+            .iter()
+            .flat_map(|h| &h.body)
+            .copied()
+            .map(size)
+            .sum()
+    }
+
+    // Header and hunk headers always; then whole hunk bodies while they fit
+    // in `budget`. A hunk that doesn't fit keeps only its added definition
+    // lines (`fn`, `struct`, `class`, ...) so the model still sees what was
+    // introduced. Returns how much of the body was kept.
+    fn render(&self, budget: usize, size: &dyn Fn(&str) -> usize, out: &mut String) -> usize {
+        self.header.iter().for_each(|line| out.push_str(line));
+        let mut used = 0usize;
+        for hunk in &self.hunks {
+            if let Some(header) = hunk.header {
+                out.push_str(header);
+            }
+            let hunk_size: usize = hunk.body.iter().copied().map(size).sum();
+            if used.saturating_add(hunk_size) <= budget {
+                used += hunk_size;
+                hunk.body.iter().for_each(|line| out.push_str(line));
+                continue;
+            }
+            for line in hunk.body.iter().filter(|line| is_definition(line)) {
+                used += size(line);
+                out.push_str(line);
+            }
+        }
+        used
+    }
+}
+
+// An added line that declares something, e.g. `+    pub async fn run(`.
+fn is_definition(line: &str) -> bool {
+    const MODIFIERS: [&str; 7] = [
+        "pub ",
+        "pub(crate) ",
+        "pub(super) ",
+        "async ",
+        "export ",
+        "default ",
+        "unsafe ",
+    ];
+    const KEYWORDS: [&str; 10] = [
+        "fn ",
+        "struct ",
+        "enum ",
+        "trait ",
+        "impl ",
+        "impl<",
+        "class ",
+        "def ",
+        "function ",
+        "interface ",
+    ];
+    let Some(code) = line.strip_prefix('+') else {
+        return false;
+    };
+    let mut code = code.trim_start();
+    while let Some(rest) = MODIFIERS.iter().find_map(|m| code.strip_prefix(m)) {
+        code = rest;
+    }
+    KEYWORDS.iter().any(|k| code.starts_with(k))
+}
+
+fn chars(line: &str) -> usize {
+    line.chars().count()
+}
+
+fn split_files(diff: &str) -> Vec<FileDiff<'_>> {
+    let mut files: Vec<FileDiff> = Vec::new();
+    for line in diff.split_inclusive('\n') {
+        if line.starts_with("diff --git ") {
+            files.push(FileDiff {
+                path: line.trim_end().rsplit_once(" b/").map(|(_, path)| path),
+                header: vec![line],
+                hunks: Vec::new(),
+            });
+            continue;
+        }
+        let Some(file) = files.last_mut() else {
+            files.push(FileDiff {
+                path: None,
+                header: Vec::new(),
+                hunks: vec![Hunk {
+                    header: None,
+                    body: vec![line],
+                }],
+            });
+            continue;
+        };
+        if line.starts_with("@@") {
+            file.hunks.push(Hunk {
+                header: Some(line),
+                body: Vec::new(),
+            });
+        } else if let Some(hunk) = file.hunks.last_mut() {
+            hunk.body.push(line);
+        } else if file.path.is_some() {
+            file.header.push(line);
+        }
+    }
+    files
+}
+
+// ---------- Decoding ----------
+// Git diffs are bytes, and files in other encodings (Latin-1, Shift-JIS, ...)
+// aren't valid UTF-8. Invalid sequences become U+FFFD in the prompt; the
+// count of bytes replaced lets main warn. Nothing else is affected: the
+// commit itself is made from the index, not from this text.
+pub fn decode_lossy(bytes: Vec<u8>) -> (String, usize) {
+    match String::from_utf8(bytes) {
+        Ok(text) => (text, 0),
+        Err(e) => {
+            let bytes = e.into_bytes();
+            let lossy = bytes.utf8_chunks().map(|chunk| chunk.invalid().len()).sum();
+            (String::from_utf8_lossy(&bytes).into_owned(), lossy)
+        }
+    }
+}
+
+// ---------- Stat summary ----------
+// Budget for the `--stat` overview, separate from `--max-diff-chars` so it
+// survives even when the diff itself is cut hard.
+const MAX_STAT_CHARS: usize = 1024;
+// Untracked files listed by name before the rest are counted.
+const MAX_UNTRACKED_LISTED: usize = 50;
+
+// The diff as sent to the model, preceded by a `git diff --stat` overview
+// when one is available (not for `--diff-stdin` / `--diff-file`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Changes {
+    pub stat: Option<String>,
+    // Totals from `parse_diff_stats()`, leading the prompt as one line.
+    pub stats: Option<DiffStats>,
+    pub diff: String,
+    // `Files changed:` from `--name-status`, added once the diff was cut.
+    pub files_changed: Option<String>,
+    // `--no-stage`: files left out of the commit, listed after the diff.
+    pub untracked: Option<String>,
+    // Staged gitlinks, listed with their own log after the diff.
+    pub submodules: Vec<SubmoduleChange>,
+    // `--context-commits`: recent subjects, so the message matches the
+    // repository's style.
+    pub recent_commits: Option<String>,
+    // `revert <SHA>`: which commit the diff undoes.
+    pub revert: Option<String>,
+    // A merge being concluded: both sides' logs and the conflicted files.
+    pub merge: Option<String>,
+    // `squash`: the messages of the commits being replaced.
+    pub squash: Option<String>,
+    // `--hint`: the author's note, last so it reads as the final word.
+    pub hint: Option<String>,
+    // The untruncated diff, file by file, for `--summarize-large-diffs`.
+    pub files: Vec<FileChange>,
+    // Every changed path, new names for renames, for `resolve_scope()`.
+    pub paths: Vec<String>,
+}
+
+impl Changes {
+    pub fn new(stat: Option<String>, diff: String) -> Self {
+        let stat = stat
+            .filter(|stat| !stat.trim().is_empty())
+            .map(|stat| fit_stat(&stat, MAX_STAT_CHARS));
+        Changes {
+            stat,
+            stats: None,
+            diff,
+            files_changed: None,
+            untracked: None,
+            submodules: Vec::new(),
+            recent_commits: None,
+            revert: None,
+            merge: None,
+            squash: None,
+            hint: None,
+            files: Vec::new(),
+            paths: Vec::new(),
+        }
+    }
+
+    // `--names-only`: the stat and the full file list stand in for the diff,
+    // so no file contents are sent.
+    pub fn names_only(stat: Option<String>, entries: &[(String, String)]) -> Self {
+        let stats = stat.as_deref().map(parse_diff_stats);
+        let paths = entries
+            .iter()
+            .map(|(_, path)| path.rsplit(" → ").next().unwrap_or(path).to_string())
+            .collect();
+        Changes {
+            files_changed: files_changed(entries),
+            paths,
+            ..Changes::new(stat, String::new()).with_stats(stats)
+        }
+    }
+
+    // `--brief`: the file names alone stand in for the diff, with no stat,
+    // statuses or counts.
+    pub fn brief(paths: &[String]) -> Self {
+        Changes {
+            paths: paths.to_vec(),
+            ..Changes::new(
+                None,
+                format!("Summarise changes to these files: [{}]\n", paths.join(", ")),
+            )
+        }
+    }
+
+    // The untrimmed diff of every file that would be sent, in `budget`'s
+    // unit; lockfiles, binaries and moves only ever get their one-line note.
+    pub fn full_size(&self, budget: &Budget) -> usize {
+        self.files
+            .iter()
+            .filter(|file| file.note.is_none())
+            .map(|file| budget.size(&file.diff))
+            .sum()
+    }
+
+    pub fn with_hint(self, hint: Option<&str>) -> Self {
+        Changes {
+            hint: hint.map(|hint| format!("Hint from the author:\n{hint}\n")),
+            ..self
+        }
+    }
+
+    // The diff alone reads like any other change, so say what it undoes and
+    // that the message should give the reason.
+    pub fn with_revert(self, sha: &str, subject: &str) -> Self {
+        Changes {
+            revert: Some(format!(
+                "This change reverts commit {sha} ({subject:?}); the diff undoes it.\n\
+                 Use the type revert, the scope of the original commit, and a message \
+                 saying why it is being reverted rather than what it did.\n"
+            )),
+            ..self
+        }
+    }
+
+    pub fn with_merge(self, merge: String) -> Self {
+        Changes {
+            merge: Some(merge),
+            ..self
+        }
+    }
+
+    pub fn with_squash(self, squash: String) -> Self {
+        Changes {
+            squash: Some(squash),
+            ..self
+        }
+    }
+
+    pub fn with_recent_commits(self, subjects: &[String]) -> Self {
+        let recent_commits = (!subjects.is_empty()).then(|| {
+            let list: String = subjects.iter().map(|s| format!("- {s}\n")).collect();
+            format!("Recent commits in this repository (match their style):\n{list}")
+        });
+        Changes {
+            recent_commits,
+            ..self
+        }
+    }
+
+    // Nothing to say about an empty change set, so it is dropped.
+    pub fn with_stats(self, stats: Option<DiffStats>) -> Self {
+        Changes {
+            stats: stats.filter(|stats| stats.files_changed > 0),
+            ..self
+        }
+    }
+
+    pub fn with_files(self, files: Vec<FileChange>) -> Self {
+        let paths = files.iter().map(|file| file.path.clone()).collect();
+        Changes {
+            files,
+            paths,
+            ..self
+        }
+    }
+
+    pub fn with_submodules(self, submodules: Vec<SubmoduleChange>) -> Self {
+        Changes { submodules, ..self }
+    }
+
+    pub fn with_untracked(self, paths: &[String]) -> Self {
+        Changes {
+            untracked: untracked_list(paths),
+            ..self
+        }
+    }
+
+    // Only for a truncated diff: otherwise every file is already visible.
+    pub fn with_files_changed(self, entries: &[(String, String)]) -> Self {
+        if !was_truncated(&self.diff) {
+            return self;
+        }
+        Changes {
+            files_changed: files_changed(entries),
+            ..self
+        }
+    }
+
+    // The budgeted diff, with the sections that are exempt from the budget
+    // around it.
+    pub fn text(&self) -> String {
+        let mut text = match &self.stat {
+            Some(stat) if self.diff.is_empty() => format!("Summary:\n{stat}"),
+            Some(stat) => format!("Summary:\n{stat}\n{}", self.diff),
+            None => self.diff.clone(),
+        };
+        if let Some(stats) = &self.stats {
+            text.insert_str(0, &format!("Overview: {stats}\n\n"));
+        }
+        let submodules = submodule_list(&self.submodules);
+        let sections = [
+            &self.files_changed,
+            &submodules,
+            &self.untracked,
+            &self.recent_commits,
+            &self.revert,
+            &self.merge,
+            &self.squash,
+            &self.hint,
+        ];
+        for section in sections.into_iter().flatten() {
+            if !text.ends_with('\n') {
+                text.push('\n');
+            }
+            text.push('\n');
+            text.push_str(section);
+        }
+        text
+    }
+}
+
+// ---------- Diff stats ----------
+// Totals for the whole change, read from either a full diff or `git diff
+// --stat` output, so `--names-only` and piped diffs get the same overview.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiffStats {
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+    pub binary_files: usize,
+    pub renamed_files: usize,
+}
+
+// "3 files changed, 10 insertions(+), 2 deletions(-), 1 binary, 1 renamed"
+impl fmt::Display for DiffStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let files = if self.files_changed == 1 {
+            "file"
+        } else {
+            "files"
+        };
+        write!(
+            f,
+            "{} {files} changed, {}(+), {}(-)",
+            self.files_changed,
+            plural(self.insertions, "insertion"),
+            plural(self.deletions, "deletion")
+        )?;
+        if self.binary_files > 0 {
+            write!(f, ", {} binary", self.binary_files)?;
+        }
+        if self.renamed_files > 0 {
+            write!(f, ", {} renamed", self.renamed_files)?;
+        }
+        Ok(())
+    }
+}
+
+// A diff has `diff --git` lines; anything else is taken for `--stat` output.
+pub fn parse_diff_stats(diff: &str) -> DiffStats {
+    if diff.lines().any(|line| line.starts_with("diff --git ")) {
+        stats_from_diff(diff)
+    } else {
+        stats_from_stat(diff)
+    }
+}
+
+fn stats_from_diff(diff: &str) -> DiffStats {
+    let mut stats = DiffStats::default();
+    for file in split_files(diff).iter().filter(|file| file.path.is_some()) {
+        stats.files_changed += 1;
+        if file.is_binary() {
+            stats.binary_files += 1;
+        }
+        if file
+            .header
+            .iter()
+            .any(|line| line.starts_with("rename from "))
+        {
+            stats.renamed_files += 1;
+        }
+        let body = || file.hunks.iter().flat_map(|h| &h.body);
+        stats.insertions += body().filter(|line| line.starts_with('+')).count();
+        stats.deletions += body().filter(|line| line.starts_with('-')).count();
+    }
+    stats
+}
+
+// Per-file lines (" src/{old => new}.rs | 4 ++--", " logo.png | Bin 0 -> 512
+// bytes") give binaries and renames; the `+`/`-` bars are scaled, so the
+// totals line is what counts. Without one the per-file counts are summed.
+fn stats_from_stat(stat: &str) -> DiffStats {
+    let mut stats = DiffStats::default();
+    let mut totals = None;
+    for line in stat.lines() {
+        let Some((path, change)) = line.split_once(" | ") else {
+            if line.contains(" changed") {
+                totals = Some(stat_totals(line));
+            }
+            continue;
+        };
+        stats.files_changed += 1;
+        let change = change.trim();
+        if change.starts_with("Bin") {
+            stats.binary_files += 1;
+        } else if let Some(count) = change
+            .split_whitespace()
+            .next()
+            .and_then(|n| n.parse::<usize>().ok())
+        {
+            let bar = change.split_whitespace().nth(1).unwrap_or("");
+            let plus = bar.chars().filter(|&c| c == '+').count();
+            let minus = bar.chars().filter(|&c| c == '-').count();
+            if let Some(added) = (count * plus).checked_div(plus + minus) {
+                stats.insertions += added;
+                stats.deletions += count - added;
+            }
+        }
+        if path.contains(" => ") {
+            stats.renamed_files += 1;
+        }
+    }
+    if let Some((files, insertions, deletions)) = totals {
+        stats.files_changed = files;
+        stats.insertions = insertions;
+        stats.deletions = deletions;
+    }
+    stats
+}
+
+// " 3 files changed, 10 insertions(+), 2 deletions(-)"; either count may be
+// missing.
+fn stat_totals(line: &str) -> (usize, usize, usize) {
+    let mut totals = (0, 0, 0);
+    for part in line.split(',') {
+        let mut words = part.split_whitespace();
+        let (Some(n), Some(word)) = (words.next(), words.next()) else {
+            continue;
+        };
+        let Ok(n) = n.parse() else { continue };
+        if word.starts_with("file") {
+            totals.0 = n;
+        } else if word.starts_with("insertion") {
+            totals.1 = n;
+        } else if word.starts_with("deletion") {
+            totals.2 = n;
+        }
+    }
+    totals
+}
+
+// ---------- Scope map ----------
+// `[scope_map]` path prefixes (`"src/auth/" = "auth"`) name the scope outright,
+// whatever the model picked. The first changed path with a match decides;
+// when several prefixes match it, the longest wins.
+pub fn resolve_scope(paths: &[&str], scope_map: &HashMap<String, String>) -> Option<String> {
+    paths.iter().find_map(|path| {
+        scope_map
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, scope)| scope.trim().to_string())
+    })
+}
+
+// ---------- File list ----------
+// Once the diff is cut the model may see only a few of the files, so a
+// truncated prompt also lists every changed file by status (from
+// `Git::name_status()`), outside the `--max-diff-chars` budget.
+const MAX_FILES_LISTED: usize = 100;
+const STATUS_NAMES: &[(&str, &str)] = &[
+    ("A", "added"),
+    ("M", "modified"),
+    ("D", "deleted"),
+    ("R", "renamed"),
+    ("C", "copied"),
+    ("T", "type changed"),
+];
+
+fn was_truncated(prompt: &str) -> bool {
+    prompt.contains(TRUNCATION_MARKER)
+}
+
+fn files_changed(entries: &[(String, String)]) -> Option<String> {
+    if entries.is_empty() {
+        return None;
+    }
+    let counts: Vec<String> = STATUS_NAMES
+        .iter()
+        .map(|(code, name)| {
+            let n = entries.iter().filter(|(status, _)| status == code).count();
+            (n, name)
+        })
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, name)| format!("{n} {name}"))
+        .collect();
+    let mut out = format!(
+        "Files changed ({}{}):\n",
+        plural(entries.len(), "file"),
+        if counts.is_empty() {
+            String::new()
+        } else {
+            format!(": {}", counts.join(", "))
+        }
+    );
+    for (status, path) in entries.iter().take(MAX_FILES_LISTED) {
+        out.push_str(&format!("{status} {path}\n"));
+    }
+    if entries.len() > MAX_FILES_LISTED {
+        out.push_str(&format!(
+            "... and {} more\n",
+            entries.len() - MAX_FILES_LISTED
+        ));
+    }
+    Some(out)
+}
+
+// Untracked files named in the prompt, so the model doesn't credit the
+// commit with them; shown to the user as well. None when there are none.
+pub fn untracked_list(paths: &[String]) -> Option<String> {
+    if paths.is_empty() {
+        return None;
+    }
+    let mut out = String::from("Untracked files (not included in this commit):\n");
+    for path in paths.iter().take(MAX_UNTRACKED_LISTED) {
+        out.push_str(&format!("- {path}\n"));
+    }
+    if paths.len() > MAX_UNTRACKED_LISTED {
+        out.push_str(&format!("+{} more\n", paths.len() - MAX_UNTRACKED_LISTED));
+    }
+    Some(out)
+}
+
+// Keep whole per-file lines while they fit, always ending with git's
+// "N files changed" totals line.
+fn fit_stat(stat: &str, max_chars: usize) -> String {
+    let lines: Vec<&str> = stat.lines().collect();
+    let Some((totals, files)) = lines.split_last() else {
+        return String::new();
+    };
+    let mut out = String::new();
+    let mut used = chars(totals);
+    for (n, line) in files.iter().enumerate() {
+        if used + chars(line) + 1 > max_chars {
+            out.push_str(&format!(" ... ({} more files)\n", files.len() - n));
+            break;
+        }
+        used += chars(line) + 1;
+        out.push_str(line);
+        out.push('\n');
+    }
+    out.push_str(totals);
+    out.push('\n');
+    out
+}
+
+// ---------- Noise files ----------
+// Lockfiles and build artifacts are committed like anything else, but their
+// diffs only waste the prompt budget. Each matching file is replaced by a
+// one-line summary after the (budgeted) rest of the diff.
+
+// `*` and `?` wildcards. Patterns with a `/` match the whole path, others
+// just the file name, like `.gitignore`. Deliberately not the gitignore
+// matching of `Excludes`: here `*` also spans `/` (`vendor/*.map` covers
+// nested maps too, as documented for `prompt_ignore`), and a flat list of
+// noise patterns has no negation or parent directories to resolve.
+fn is_ignored(path: &str, patterns: &[String]) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    patterns.iter().any(|pattern| {
+        let target = if pattern.contains('/') { path } else { name };
+        glob_match(pattern.as_bytes(), target.as_bytes())
+    })
+}
+
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+        Some((&c, rest)) => match text.split_first() {
+            Some((&t, text)) if c == b'?' || c == t => glob_match(rest, text),
+            _ => false,
+        },
+    }
+}
+
+pub fn plural(n: usize, word: &str) -> String {
+    if n == 1 {
+        format!("{n} {word}")
+    } else {
+        format!("{n} {word}s")
+    }
+}
+
+impl FileDiff<'_> {
+    fn text(&self) -> String {
+        let mut out = String::new();
+        self.render(usize::MAX, &chars, &mut out);
+        out
+    }
+
+    // "Cargo.lock: 412 insertions, 398 deletions"
+    fn summary(&self) -> String {
+        let path = self.path.unwrap_or("(unknown)");
+        if self.is_binary() {
+            return format!("{path}: binary file changed");
+        }
+        let body = || self.hunks.iter().flat_map(|h| &h.body);
+        let insertions = body().filter(|line| line.starts_with('+')).count();
+        let deletions = body().filter(|line| line.starts_with('-')).count();
+        format!(
+            "{path}: {}, {}",
+            plural(insertions, "insertion"),
+            plural(deletions, "deletion")
+        )
+    }
+}
+
+// ---------- Generated files ----------
+// Bundles, minified assets and codegen output are summarized like lockfiles
+// (see above), but are found by heuristics as well as by path: a marker
+// comment near the top of the file, or lines far longer than anyone writes
+// by hand. Each check can be tuned or turned off from the config file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Generated {
+    // Globs as in `prompt_ignore`.
+    pub paths: Vec<String>,
+    // Looked for in the first `MARKER_LINES` lines, where the diff shows them.
+    pub markers: Vec<String>,
+    // Average added-line length above which a file counts as minified; 0
+    // turns the check off.
+    pub max_line_length: usize,
+}
+
+const MARKER_LINES: usize = 5;
+
+impl FileDiff<'_> {
+    // Why this file looks generated, for `-v`; None if it doesn't.
+    fn generated_reason(&self, generated: &Generated) -> Option<String> {
+        let path = self.path?;
+        if let Some(pattern) = generated
+            .paths
+            .iter()
+            .find(|pattern| is_ignored(path, std::slice::from_ref(pattern)))
+        {
+            return Some(format!("path matches `{pattern}`"));
+        }
+        for (n, line) in self.top_lines() {
+            if let Some(marker) = generated.markers.iter().find(|m| line.contains(m.as_str())) {
+                return Some(format!("`{marker}` on line {n}"));
+            }
+        }
+        let added: Vec<usize> = self
+            .hunks
+            .iter()
+            .flat_map(|h| &h.body)
+            .filter_map(|line| line.strip_prefix('+'))
+            .map(|line| chars(line.trim_end_matches('\n')))
+            .collect();
+        let average = added.iter().sum::<usize>() / added.len().max(1);
+        if generated.max_line_length > 0 && average > generated.max_line_length {
+            return Some(format!(
+                "average added line is {average} characters (over {})",
+                generated.max_line_length
+            ));
+        }
+        None
+    }
+
+    // Lines 1..=MARKER_LINES of the new file that appear in a hunk, with
+    // their line numbers, from each hunk's `@@ -a,b +c,d @@` header.
+    fn top_lines(&self) -> Vec<(usize, &str)> {
+        let mut lines = Vec::new();
+        for hunk in &self.hunks {
+            let Some(mut n) = hunk
+                .header
+                .and_then(|h| h.split_whitespace().nth(2))
+                .and_then(|new| new.strip_prefix('+'))
+                .and_then(|new| new.split(',').next())
+                .and_then(|start| start.parse::<usize>().ok())
+            else {
+                continue;
+            };
+            for line in &hunk.body {
+                if n > MARKER_LINES {
+                    break;
+                }
+                if line.starts_with('-') || line.starts_with('\\') {
+                    continue;
+                }
+                lines.push((n, &line[1.min(line.len())..]));
+                n += 1;
+            }
+        }
+        lines
+    }
+}
+
+// ---------- Binary files ----------
+// A staged binary file, described instead of diffed. Sizes come from
+// `Git::binary_changes()`; diffs from stdin or a file only know the path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinaryChange {
+    pub path: String,
+    pub old_size: Option<u64>,
+    pub new_size: Option<u64>,
+}
+
+impl fmt::Display for BinaryChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = &self.path;
+        match (self.old_size, self.new_size) {
+            (None, Some(new)) => write!(f, "binary added: {path} ({})", format_size(new)),
+            (Some(old), None) => write!(f, "binary deleted: {path} ({})", format_size(old)),
+            (Some(old), Some(new)) => write!(
+                f,
+                "binary changed: {path} ({} → {})",
+                format_size(old),
+                format_size(new)
+            ),
+            (None, None) => write!(f, "binary changed: {path}"),
+        }
+    }
+}
+
+// ---------- Submodules ----------
+// A staged gitlink (mode 160000). Its diff is just `Subproject commit <sha>`
+// lines, so the prompt also gets the submodule's own `git log --oneline`
+// for the range when it is checked out (`Git::submodule_changes()`).
+const MAX_SUBMODULE_LOG: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubmoduleChange {
+    pub path: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+    // Newest first; None when the submodule isn't checked out or doesn't
+    // know the range.
+    pub log: Option<Vec<String>>,
+}
+
+impl SubmoduleChange {
+    // Moved from one commit to another, rather than added or removed.
+    pub fn is_bump(&self) -> bool {
+        self.old.is_some() && self.new.is_some()
+    }
+}
+
+impl fmt::Display for SubmoduleChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = &self.path;
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => {
+                write!(f, "{path}: {} → {}", short_sha(old), short_sha(new))
+            }
+            (None, Some(new)) => write!(f, "{path}: added at {}", short_sha(new)),
+            (Some(old), None) => write!(f, "{path}: removed (was {})", short_sha(old)),
+            (None, None) => write!(f, "{path}: changed"),
+        }
+    }
+}
+
+pub fn short_sha(sha: &str) -> &str {
+    sha.get(..7).unwrap_or(sha)
+}
+
+fn submodule_list(submodules: &[SubmoduleChange]) -> Option<String> {
+    if submodules.is_empty() {
+        return None;
+    }
+    let mut out = String::from("Submodules changed:\n");
+    for submodule in submodules {
+        out.push_str(&format!("{submodule}\n"));
+        let log = submodule.log.as_deref().unwrap_or_default();
+        for line in log.iter().take(MAX_SUBMODULE_LOG) {
+            out.push_str(&format!("  {line}\n"));
+        }
+        if log.len() > MAX_SUBMODULE_LOG {
+            out.push_str(&format!(
+                "  ... and {} more commits\n",
+                log.len() - MAX_SUBMODULE_LOG
+            ));
+        }
+    }
+    Some(out)
+}
+
+// 512 -> "512 B", 12_288 -> "12 KB", 3_145_728 -> "3.0 MB".
+fn format_size(bytes: u64) -> String {
+    const KB: f64 = 1024.0;
+    let n = bytes as f64;
+    if n < KB {
+        format!("{bytes} B")
+    } else if n < KB * KB {
+        format!("{:.0} KB", n / KB)
+    } else {
+        format!("{:.1} MB", n / (KB * KB))
+    }
+}
+
+impl FileDiff<'_> {
+    // `git diff` prints "Binary files ... differ", or the whole blob after
+    // "GIT binary patch" with `--binary`.
+    fn is_binary(&self) -> bool {
+        self.header
+            .iter()
+            .any(|line| line.starts_with("Binary files ") || line.trim_end() == "GIT binary patch")
+    }
+
+    fn binary_note(&self, binaries: &[BinaryChange]) -> Option<String> {
+        let path = self.path?;
+        match binaries.iter().find(|change| change.path == path) {
+            Some(change) => Some(change.to_string()),
+            None if self.is_binary() => Some(
+                BinaryChange {
+                    path: path.to_string(),
+                    old_size: None,
+                    new_size: None,
+                }
+                .to_string(),
+            ),
+            None => None,
+        }
+    }
+}
+
+// ---------- Renames ----------
+// With rename/copy detection (`Git::detection_args()`), a moved file arrives
+// as a `rename from`/`rename to` header plus only what was edited. At 100%
+// similarity there is nothing else, so it is listed as one line instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rename {
+    pub from: String,
+    pub to: String,
+    pub copy: bool,
+}
+
+impl fmt::Display for Rename {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verb = if self.copy { "copied" } else { "renamed" };
+        write!(f, "{verb}: {} → {}", self.from, self.to)
+    }
+}
+
+impl FileDiff<'_> {
+    // Only a rename or copy: `similarity index 100%` and no hunks.
+    fn pure_rename(&self) -> Option<Rename> {
+        if !self.hunks.is_empty()
+            || !self
+                .header
+                .iter()
+                .any(|line| line.trim_end() == "similarity index 100%")
+        {
+            return None;
+        }
+        let field = |prefix: &str| {
+            self.header
+                .iter()
+                .find_map(|line| line.strip_prefix(prefix))
+                .map(|path| path.trim_end().to_string())
+        };
+        if let (Some(from), Some(to)) = (field("rename from "), field("rename to ")) {
+            return Some(Rename {
+                from,
+                to,
+                copy: false,
+            });
+        }
+        let (from, to) = (field("copy from ")?, field("copy to ")?);
+        Some(Rename {
+            from,
+            to,
+            copy: true,
+        })
+    }
+}
+
+// ---------- Per-file changes ----------
+// The diff split by file, whole, for summarizing each file on its own when
+// the total is too large to send. Noise and binary files already have a
+// one-line `note` and are never sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChange {
+    pub path: String,
+    // What the file's summary is cached under: the blob IDs from its
+    // `index` line, or the diff itself when there is none.
+    pub key: String,
+    pub diff: String,
+    // "path: N insertions, M deletions"; stands in if summarizing fails.
+    pub stat: String,
+    pub note: Option<String>,
+    // Set for pure renames and copies, which `note` then describes.
+    pub rename: Option<Rename>,
+    // Why the file was taken for generated, shown with `-v`.
+    pub generated: Option<String>,
+}
+
+pub fn file_changes(
+    diff: &str,
+    ignore: &[String],
+    generated: &Generated,
+    exclude: &Excludes,
+    binaries: &[BinaryChange],
+) -> Vec<FileChange> {
+    split_files(diff)
+        .iter()
+        .filter_map(|file| {
+            let path = file.path?;
+            let text = file.text();
+            let rename = file.pure_rename();
+            let mut reason = None;
+            let note = if exclude.is_excluded(path) || is_ignored(path, ignore) {
+                Some(file.summary())
+            } else if let Some(rename) = &rename {
+                Some(rename.to_string())
+            } else if let Some(note) = file.binary_note(binaries) {
+                Some(note)
+            } else {
+                reason = file.generated_reason(generated);
+                reason.as_ref().map(|_| file.summary())
+            };
+            let key = file
+                .header
+                .iter()
+                .find_map(|line| line.strip_prefix("index "))
+                .and_then(|ids| ids.split_whitespace().next())
+                .map_or_else(|| text.clone(), String::from);
+            Some(FileChange {
+                path: path.to_string(),
+                key,
+                stat: file.summary(),
+                diff: text,
+                note,
+                rename,
+                generated: reason,
+            })
+        })
+        .collect()
+}
+
+// The diff as sent to the model: pure renames listed, excluded files
+// (`.gitcmtignore`), files matching `ignore` and generated files summarized
+// at the end, binary files reduced to a one-line note, the rest fitted into
+// `budget`.
+pub fn prompt_diff(
+    diff: String,
+    budget: &Budget,
+    ignore: &[String],
+    generated: &Generated,
+    exclude: &Excludes,
+    binaries: &[BinaryChange],
+) -> String {
+    let files = split_files(&diff);
+    let mut kept = String::new();
+    let mut renames = Vec::new();
+    let mut excluded = Vec::new();
+    let mut noise = Vec::new();
+    let mut rewritten = false;
+    for file in &files {
+        if file.path.is_some_and(|path| exclude.is_excluded(path)) {
+            excluded.push(file.summary());
+            rewritten = true;
+        } else if file.path.is_some_and(|path| is_ignored(path, ignore)) {
+            noise.push(file.summary());
+            rewritten = true;
+        } else if let Some(rename) = file.pure_rename() {
+            renames.push(rename.to_string());
+            rewritten = true;
+        } else if let Some(note) = file.binary_note(binaries) {
+            // Keep the `diff --git` line so the note stays attached to a file.
+            kept.push_str(file.header[0]);
+            kept.push_str(&note);
+            kept.push('\n');
+            rewritten = true;
+        } else if file.generated_reason(generated).is_some() {
+            noise.push(file.summary());
+            rewritten = true;
+        } else {
+            kept.push_str(&file.text());
+        }
+    }
+    if !rewritten {
+        return truncate_diff_smart(&diff, budget);
+    }
+
+    let mut out = truncate_diff_smart(&kept, budget);
+    let sections = [
+        ("Moved files (contents unchanged):\n", renames),
+        ("Lock/generated files changed (diffs omitted):\n", noise),
+        ("Excluded files changed (diffs not sent):\n", excluded),
+    ];
+    for (title, lines) in sections {
+        if lines.is_empty() {
+            continue;
+        }
+        if !out.is_empty() && !out.ends_with('\n') {
+            out.push('\n');
+        }
+        out.push_str(title);
+        for line in lines {
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+// Starts the last line of every truncated diff.
+const TRUNCATION_MARKER: &str = "... (truncated";
+
+pub fn truncate_diff_smart(diff: &str, budget: &Budget) -> String {
+    let size = |text: &str| budget.size(text);
+    if size(diff) <= budget.limit {
+        return diff.to_string();
+    }
+    // No hunks to keep whole: fall back to a plain cut.
+    if !diff.lines().any(|line| line.starts_with("@@")) {
+        return truncate_diff(diff, budget);
+    }
+
+    let files = split_files(diff);
+    let bodies: Vec<usize> = files.iter().map(|f| f.body_size(&size)).collect();
+    let skeleton: usize = files.iter().map(|f| f.skeleton_size(&size)).sum();
+    let mut remaining = budget.limit.saturating_sub(skeleton);
+    let mut order: Vec<usize> = (0..files.len()).collect();
+    order.sort_by_key(|&i| bodies[i]);
+    let mut budgets = vec![0; files.len()];
+    for (n, &i) in order.iter().enumerate() {
+        let share = remaining / (files.len() - n);
+        budgets[i] = bodies[i].min(share);
+        remaining -= budgets[i];
+    }
+
+    let mut out = String::new();
+    let mut cut = Vec::new();
+    let mut dropped = 0;
+    for ((file, budget), body) in files.iter().zip(budgets).zip(bodies) {
+        let kept = file.render(budget, &size, &mut out);
+        if kept < body {
+            cut.push(file.path.unwrap_or("(preamble)"));
+            dropped += body - kept;
+        }
+    }
+    if !out.ends_with('\n') {
+        out.push('\n');
+    }
+    let named = files.iter().filter(|f| f.path.is_some()).count();
+    if named == 0 {
+        out.push_str(&format!(
+            "{TRUNCATION_MARKER}: {dropped} {} dropped)",
+            budget.unit
+        ));
+        return out;
+    }
+    out.push_str(&format!(
+        "{TRUNCATION_MARKER}: bodies cut for {} of {named} files, {dropped} {} dropped: {})",
+        cut.len(),
+        budget.unit,
+        cut.join(", ")
+    ));
+    out
+}
+
+// Whole lines while they fit, then as much of the next line as fits.
+fn truncate_diff(diff: &str, budget: &Budget) -> String {
+    let total = budget.size(diff);
+    if total <= budget.limit {
+        return diff.to_string();
+    }
+    let mut out = String::new();
+    let mut used = 0;
+    for line in diff.split_inclusive('\n') {
+        let size = budget.size(line);
+        if used + size <= budget.limit {
+            out.push_str(line);
+            used += size;
+            continue;
+        }
+        // The longest prefix of the line that still fits.
+        let ends: Vec<usize> = line.char_indices().map(|(i, _)| i).skip(1).collect();
+        let fits = ends.partition_point(|&end| used + budget.size(&line[..end]) <= budget.limit);
+        if fits > 0 {
+            let prefix = &line[..ends[fits - 1]];
+            out.push_str(prefix);
+            used += budget.size(prefix);
+        }
+        break;
+    }
+    format!(
+        "{out}\n{TRUNCATION_MARKER}: {} {} dropped)",
+        total.saturating_sub(used),
+        budget.unit
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, hunks: &[(&str, usize)]) -> String {
+        let mut out = format!(
+            "diff --git a/{path} b/{path}\nindex 1111111..2222222 100644\n--- a/{path}\n+++ b/{path}\n"
+        );
+        for (n, (header, lines)) in hunks.iter().enumerate() {
+            out += &format!("@@ -{n},1 +{n},{lines} @@ {header}\n");
+            for i in 0..*lines {
+                out += &format!("+{path} line {i}\n");
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn short_diff_is_untouched() {
+        let diff = file("src/a.rs", &[("fn a()", 3)]);
+        assert_eq!(truncate_diff_smart(&diff, &Budget::chars(3072)), diff);
+    }
+
+    #[test]
+    fn every_file_appears_when_one_file_dominates() {
+        let diff = [
+            file("Cargo.lock", &[("[[package]]", 40), ("[[package]]", 300)]),
+            file("src/main.rs", &[("fn main()", 5)]),
+            file("README.md", &[("## Usage", 3)]),
+        ]
+        .concat();
+        let out = truncate_diff_smart(&diff, &Budget::chars(1500));
+
+        for path in ["Cargo.lock", "src/main.rs", "README.md"] {
+            assert!(out.contains(&format!("diff --git a/{path} b/{path}\n")));
+        }
+        // Both hunk headers of the big file survive even though its body is cut.
+        assert_eq!(out.matches(" @@ [[package]]\n").count(), 2);
+        // Small files fit entirely; the big file keeps only whole hunks.
+        assert!(out.contains("+src/main.rs line 4\n"));
+        assert!(out.contains("+README.md line 2\n"));
+        assert!(out.contains("+Cargo.lock line 39\n@@ -1,1 +1,300 @@ [[package]]\n"));
+        assert!(!out.contains("+Cargo.lock line 40\n"));
+        assert!(out.contains("... (truncated: bodies cut for 1 of 3 files, "));
+        assert!(out.ends_with(" chars dropped: Cargo.lock)"), "out: {out}");
+        let body = out.rsplit_once("\n...").unwrap().0;
+        assert!(chars(body) <= 1500, "len: {}", chars(body));
+    }
+
+    #[test]
+    fn budget_is_shared_evenly_between_large_files() {
+        let hunks = [("", 10); 20];
+        let diff = [file("a.rs", &hunks), file("b.rs", &hunks)].concat();
+        let out = truncate_diff_smart(&diff, &Budget::chars(3000));
+        let a = out.matches("+a.rs line").count();
+        let b = out.matches("+b.rs line").count();
+        // Whole hunks only, so counts move in steps of 10.
+        assert!(
+            a >= 30 && a.is_multiple_of(10) && a.abs_diff(b) <= 10,
+            "a: {a}, b: {b}"
+        );
+        assert!(out.contains("bodies cut for 2 of 2 files, "));
+        assert!(out.ends_with(" chars dropped: a.rs, b.rs)"), "out: {out}");
+    }
+
+    #[test]
+    fn budgets_count_in_their_own_unit() {
+        // One "token" per line, so each file's 10-line body costs 10.
+        let lines = Budget::new(25, "tokens", |text| text.lines().count());
+        let diff = [file("a.rs", &[("", 10)]), file("b.rs", &[("", 10)])].concat();
+        let out = truncate_diff_smart(&diff, &lines);
+        assert!(
+            out.ends_with(
+                "... (truncated: bodies cut for 2 of 2 files, 20 tokens dropped: a.rs, b.rs)"
+            ),
+            "out: {out}"
+        );
+        let roomy = Budget::new(100, "tokens", |text| text.lines().count());
+        assert_eq!(truncate_diff_smart(&diff, &roomy), diff);
+    }
+
+    #[test]
+    fn headers_are_kept_even_past_the_budget() {
+        let diff: String = (0..20)
+            .map(|i| file(&format!("f{i}.rs"), &[("", 10)]))
+            .collect();
+        let out = truncate_diff_smart(&diff, &Budget::chars(100));
+        for i in 0..20 {
+            assert!(out.contains(&format!("+++ b/f{i}.rs\n")));
+        }
+        assert!(out.contains("bodies cut for 20 of 20 files"));
+    }
+
+    #[test]
+    fn cut_hunks_keep_their_added_definitions() {
+        let diff = "diff --git a/src/lib.rs b/src/lib.rs\n\
+                    --- a/src/lib.rs\n\
+                    +++ b/src/lib.rs\n\
+                    @@ -1,3 +1,40 @@ mod net;\n\
+                    +pub struct Server {\n\
+                    +    port: u16,\n\
+                    +}\n\
+                    +impl Server {\n\
+                    +    pub async fn listen(&self) {\n"
+            .to_string()
+            + &"+        todo!();\n".repeat(40)
+            + "+    }\n+}\n-fn old() {}\n";
+        let out = truncate_diff_smart(&diff, &Budget::chars(300));
+        assert!(out.contains(
+            "@@ -1,3 +1,40 @@ mod net;\n+pub struct Server {\n+impl Server {\n+    pub async fn listen(&self) {\n"
+        ), "out: {out}");
+        assert!(!out.contains("todo!"));
+        assert!(!out.contains("fn old"));
+        assert!(is_definition("+def handler(event):"));
+        assert!(is_definition("+export default class App {"));
+        assert!(!is_definition("-fn removed()"));
+        assert!(!is_definition("+let f = fn_ptr;"));
+    }
+
+    #[test]
+    fn plain_unified_diff_is_cut_at_hunk_boundaries() {
+        let diff = "--- a.txt\n+++ a.txt\n@@ -1 +1 @@\n-old\n+new\n@@ -9 +9 @@\n".to_string()
+            + &"+x\n".repeat(100);
+        let out = truncate_diff_smart(&diff, &Budget::chars(60));
+        assert!(out.starts_with("--- a.txt\n+++ a.txt\n@@ -1 +1 @@\n-old\n+new\n@@ -9 +9 @@\n"));
+        assert!(
+            out.ends_with("\n... (truncated: 300 chars dropped)"),
+            "out: {out}"
+        );
+        assert!(!out.contains("+x"));
+    }
+
+    #[test]
+    fn non_git_diff_falls_back_to_a_plain_cut() {
+        let diff = "x".repeat(3072 + 10);
+        let out = truncate_diff_smart(&diff, &Budget::chars(3072));
+        assert!(
+            out.ends_with("\n... (truncated: 10 chars dropped)"),
+            "out: {out}"
+        );
+        assert_eq!(out.chars().filter(|&c| c == 'x').count(), 3072);
+    }
+
+    fn ignore(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn glob_patterns_match_names_or_paths() {
+        let patterns = ignore(&["Cargo.lock", "*.min.js", "vendor/*.map"]);
+        assert!(is_ignored("Cargo.lock", &patterns));
+        assert!(is_ignored("crates/core/Cargo.lock", &patterns));
+        assert!(is_ignored("web/app.min.js", &patterns));
+        assert!(is_ignored("vendor/x.map", &patterns));
+        assert!(!is_ignored("src/x.map", &patterns));
+        assert!(!is_ignored("src/main.rs", &patterns));
+        assert!(!is_ignored("Cargo.lock.bak", &patterns));
+    }
+
+    #[test]
+    fn ignored_files_are_summarized_and_free_the_budget() {
+        let diff = [
+            file("Cargo.lock", &[("[[package]]", 400)]),
+            file("src/main.rs", &[("fn main()", 20)]),
+        ]
+        .concat()
+            + "-removed line\n";
+        let out = prompt_diff(
+            diff,
+            &Budget::chars(1000),
+            &ignore(&["Cargo.lock"]),
+            &Generated::default(),
+            &Excludes::default(),
+            &[],
+        );
+
+        assert!(!out.contains("+Cargo.lock line"));
+        assert!(!out.contains("truncated"), "out: {out}");
+        assert!(out.contains("+src/main.rs line 19\n-removed line\n"));
+        assert!(out.ends_with(
+            "Lock/generated files changed (diffs omitted):\n\
+             Cargo.lock: 400 insertions, 0 deletions\n"
+        ));
+    }
+
+    #[test]
+    fn excluded_files_never_reach_the_prompt() {
+        let diff = [
+            file("fixtures/users.csv", &[("", 5)]),
+            file("Cargo.lock", &[("[[package]]", 2)]),
+            file("src/main.rs", &[("fn main()", 2)]),
+        ]
+        .concat();
+        let exclude = Excludes::parse(["fixtures/", "Cargo.lock"]);
+        let out = prompt_diff(
+            diff.clone(),
+            &Budget::chars(3072),
+            &ignore(&["Cargo.lock"]),
+            &Generated::default(),
+            &exclude,
+            &[],
+        );
+
+        assert!(!out.contains("users.csv line"), "out: {out}");
+        assert!(out.contains("+src/main.rs line 1\n"));
+        assert!(out.ends_with(
+            "Excluded files changed (diffs not sent):\n\
+             fixtures/users.csv: 5 insertions, 0 deletions\n\
+             Cargo.lock: 2 insertions, 0 deletions\n"
+        ));
+
+        let files = file_changes(&diff, &[], &Generated::default(), &exclude, &[]);
+        assert!(files[0].note.is_some());
+        assert!(files[2].note.is_none());
+    }
+
+    #[test]
+    fn only_ignored_files_still_leave_a_summary() {
+        let diff = [
+            file("package-lock.json", &[("", 1)]),
+            file("go.sum", &[("", 3)]),
+        ]
+        .concat();
+        let out = prompt_diff(
+            diff,
+            &Budget::chars(3072),
+            &ignore(&["package-lock.json", "go.sum"]),
+            &Generated::default(),
+            &Excludes::default(),
+            &[],
+        );
+        assert_eq!(
+            out,
+            "Lock/generated files changed (diffs omitted):\n\
+             package-lock.json: 1 insertion, 0 deletions\n\
+             go.sum: 3 insertions, 0 deletions\n"
+        );
+    }
+
+    fn generated() -> Generated {
+        Generated {
+            paths: ignore(&["dist/*", "*.pb.rs"]),
+            markers: ignore(&["@generated", "DO NOT EDIT"]),
+            max_line_length: 200,
+        }
+    }
+
+    fn new_file(path: &str, lines: &[&str]) -> String {
+        let mut out = format!(
+            "diff --git a/{path} b/{path}\nnew file mode 100644\n--- /dev/null\n+++ b/{path}\n@@ -0,0 +1,{} @@\n",
+            lines.len()
+        );
+        for line in lines {
+            out += &format!("+{line}\n");
+        }
+        out
+    }
+
+    #[test]
+    fn generated_files_are_found_by_path_marker_or_line_length() {
+        let reason = |diff: &str| split_files(diff)[0].generated_reason(&generated());
+        assert_eq!(
+            reason(&new_file("dist/app.js", &["x"])).as_deref(),
+            Some("path matches `dist/*`")
+        );
+        assert_eq!(
+            reason(&new_file("proto/api.pb.rs", &["x"])).as_deref(),
+            Some("path matches `*.pb.rs`")
+        );
+        assert_eq!(
+            reason(&new_file(
+                "src/schema.rs",
+                &["// @generated by diesel", "use x;"]
+            ))
+            .as_deref(),
+            Some("`@generated` on line 1")
+        );
+        let minified = "a".repeat(5000);
+        assert_eq!(
+            reason(&new_file("web/app.js", &[&minified, "b"])).as_deref(),
+            Some("average added line is 2500 characters (over 200)")
+        );
+        assert_eq!(reason(&new_file("src/main.rs", &["fn main() {}"])), None);
+        assert_eq!(
+            split_files(&new_file("a.js", &[&minified]))[0].generated_reason(&Generated::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn markers_only_count_near_the_top_of_the_file() {
+        let reason = |diff: &str| split_files(diff)[0].generated_reason(&generated());
+        let late = new_file(
+            "src/a.rs",
+            &["1", "2", "3", "4", "5", "// DO NOT EDIT this block"],
+        );
+        assert_eq!(reason(&late), None);
+        // A hunk further down a file with the marker on its context lines.
+        let deep = "diff --git a/src/b.rs b/src/b.rs\n--- a/src/b.rs\n+++ b/src/b.rs\n\
+                    @@ -40,2 +40,3 @@\n // @generated\n+x\n y\n";
+        assert_eq!(reason(deep), None);
+        let top = "diff --git a/src/c.go b/src/c.go\n--- a/src/c.go\n+++ b/src/c.go\n\
+                   @@ -1,3 +1,3 @@\n-// Code generated by v1. DO NOT EDIT.\n+// Code generated by v2. DO NOT EDIT.\n package c\n";
+        assert_eq!(reason(top).as_deref(), Some("`DO NOT EDIT` on line 1"));
+    }
+
+    #[test]
+    fn generated_files_are_summarized_with_their_reason() {
+        let diff = [
+            new_file("dist/app.js", &["bundle"]),
+            new_file("src/main.rs", &["fn main() {}"]),
+        ]
+        .concat();
+        let out = prompt_diff(
+            diff.clone(),
+            &Budget::chars(3072),
+            &[],
+            &generated(),
+            &Excludes::default(),
+            &[],
+        );
+        assert!(out.starts_with("diff --git a/src/main.rs"), "out: {out}");
+        assert!(out.ends_with(
+            "Lock/generated files changed (diffs omitted):\ndist/app.js: 1 insertion, 0 deletions\n"
+        ));
+        let files = file_changes(&diff, &[], &generated(), &Excludes::default(), &[]);
+        assert_eq!(files[0].generated.as_deref(), Some("path matches `dist/*`"));
+        assert_eq!(
+            files[0].note.as_deref(),
+            Some("dist/app.js: 1 insertion, 0 deletions")
+        );
+        assert_eq!(
+            (files[1].generated.as_deref(), files[1].note.as_deref()),
+            (None, None)
+        );
+    }
+
+    #[test]
+    fn binary_sections_become_notes() {
+        let diff = "diff --git a/logo.png b/logo.png\n\
+                    index 1111111..2222222 100644\n\
+                    Binary files a/logo.png and b/logo.png differ\n\
+                    diff --git a/font.woff b/font.woff\n\
+                    new file mode 100644\n\
+                    index 0000000..3333333\n\
+                    GIT binary patch\n\
+                    literal 2048\n\
+                    zcmV-u2$%PXP)<h;3K|Lk000e1NJLTq000;O000;W1^@s6;\n\
+                    \n\
+                    diff --git a/src/x.rs b/src/x.rs\n\
+                    @@ -1 +1 @@\n\
+                    -a\n\
+                    +b\n";
+        let binaries = [BinaryChange {
+            path: "logo.png".into(),
+            old_size: Some(12 * 1024),
+            new_size: Some(15 * 1024),
+        }];
+        let out = prompt_diff(
+            diff.to_string(),
+            &Budget::chars(3072),
+            &[],
+            &Generated::default(),
+            &Excludes::default(),
+            &binaries,
+        );
+        assert_eq!(
+            out,
+            "diff --git a/logo.png b/logo.png\n\
+             binary changed: logo.png (12 KB → 15 KB)\n\
+             diff --git a/font.woff b/font.woff\n\
+             binary changed: font.woff\n\
+             diff --git a/src/x.rs b/src/x.rs\n\
+             @@ -1 +1 @@\n\
+             -a\n\
+             +b\n"
+        );
+    }
+
+    #[test]
+    fn binary_notes_describe_added_and_deleted_files() {
+        let change = |old_size, new_size| BinaryChange {
+            path: "assets/logo.png".into(),
+            old_size,
+            new_size,
+        };
+        assert_eq!(
+            change(None, Some(512)).to_string(),
+            "binary added: assets/logo.png (512 B)"
+        );
+        assert_eq!(
+            change(Some(3 * 1024 * 1024), None).to_string(),
+            "binary deleted: assets/logo.png (3.0 MB)"
+        );
+    }
+
+    const STATS_DIFF: &str = "\
+diff --git a/src/lib.rs b/src/lib.rs
+index 1111111..2222222 100644
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,4 @@
+ use std::fmt;
+-use std::io;
++use std::io::{self, Read};
++use std::path::Path;
+ 
+diff --git a/assets/logo.png b/assets/logo.png
+new file mode 100644
+index 0000000..3333333
+Binary files /dev/null and b/assets/logo.png differ
+diff --git a/src/old.rs b/src/new.rs
+similarity index 90%
+rename from src/old.rs
+rename to src/new.rs
+index 4444444..5555555 100644
+--- a/src/old.rs
++++ b/src/new.rs
+@@ -10,2 +10,2 @@
+-fn old() {}
++fn new() {}
+";
+
+    const STATS_STAT: &str = "\
+ assets/logo.png                 | Bin 0 -> 512 bytes
+ src/lib.rs                      |  3 ++-
+ src/{old.rs => new.rs}          |  2 +-
+ 3 files changed, 3 insertions(+), 2 deletions(-)
+";
+
+    #[test]
+    fn diff_stats_agree_between_a_diff_and_its_stat() {
+        let expected = DiffStats {
+            files_changed: 3,
+            insertions: 3,
+            deletions: 2,
+            binary_files: 1,
+            renamed_files: 1,
+        };
+        assert_eq!(parse_diff_stats(STATS_DIFF), expected);
+        assert_eq!(parse_diff_stats(STATS_STAT), expected);
+        assert_eq!(
+            expected.to_string(),
+            "3 files changed, 3 insertions(+), 2 deletions(-), 1 binary, 1 renamed"
+        );
+        assert_eq!(parse_diff_stats(""), DiffStats::default());
+    }
+
+    #[test]
+    fn diff_stats_from_a_stat_without_totals_sum_the_files() {
+        let stat = " a.rs | 4 +++-\n b.rs | 2 --\n";
+        assert_eq!(
+            parse_diff_stats(stat),
+            DiffStats {
+                files_changed: 2,
+                insertions: 3,
+                deletions: 3,
+                ..Default::default()
+            }
+        );
+        let only_deletions = " 1 file changed, 5 deletions(-)\n";
+        assert_eq!(
+            parse_diff_stats(only_deletions).to_string(),
+            "1 file changed, 0 insertions(+), 5 deletions(-)"
+        );
+    }
+
+    #[test]
+    fn scope_map_prefers_the_first_path_and_the_longest_prefix() {
+        let map: HashMap<String, String> = [
+            ("src/", "core"),
+            ("src/auth/", "auth"),
+            ("tests/", "test"),
+            ("docs/", "docs"),
+        ]
+        .into_iter()
+        .map(|(prefix, scope)| (prefix.to_string(), scope.to_string()))
+        .collect();
+        assert_eq!(
+            resolve_scope(&["README.md", "src/auth/login.rs", "docs/a.md"], &map).as_deref(),
+            Some("auth")
+        );
+        assert_eq!(
+            resolve_scope(&["tests/login.rs", "src/lib.rs"], &map).as_deref(),
+            Some("test")
+        );
+        assert_eq!(resolve_scope(&["Cargo.toml"], &map), None);
+        assert_eq!(resolve_scope(&["src/lib.rs"], &HashMap::new()), None);
+
+        let entries = vec![("R".to_string(), "old/a.rs → src/auth/a.rs".to_string())];
+        assert_eq!(Changes::names_only(None, &entries).paths, ["src/auth/a.rs"]);
+    }
+
+    #[test]
+    fn a_revert_is_named_before_the_hint() {
+        let text = Changes::new(None, "diff\n".into())
+            .with_revert("abc1234", "feat: add rv")
+            .with_hint(Some("flaky in CI"))
+            .text();
+        assert!(text.starts_with(
+            "diff\n\nThis change reverts commit abc1234 (\"feat: add rv\"); the diff undoes it.\n"
+        ));
+        assert!(text.ends_with("\n\nHint from the author:\nflaky in CI\n"));
+    }
+
+    #[test]
+    fn the_overview_leads_the_prompt() {
+        let changes = Changes::new(Some(STATS_STAT.into()), "diff".into())
+            .with_stats(Some(parse_diff_stats(STATS_DIFF)));
+        assert!(changes.text().starts_with(
+            "Overview: 3 files changed, 3 insertions(+), 2 deletions(-), 1 binary, 1 renamed\n\nSummary:\n"
+        ));
+        let empty = Changes::new(None, "diff".into()).with_stats(Some(DiffStats::default()));
+        assert_eq!(empty.text(), "diff");
+    }
+
+    #[test]
+    fn stat_summary_keeps_totals_within_its_own_budget() {
+        let stat: String = (0..100)
+            .map(|i| format!(" src/module_{i}.rs | 4 ++--\n"))
+            .collect::<String>()
+            + " 100 files changed, 200 insertions(+), 200 deletions(-)\n";
+        let fitted = fit_stat(&stat, 300);
+        assert!(fitted.starts_with(" src/module_0.rs | 4 ++--\n"));
+        assert!(fitted.contains(" more files)\n"));
+        assert!(fitted.ends_with(" 100 files changed, 200 insertions(+), 200 deletions(-)\n"));
+        assert!(chars(&fitted) <= 300 + 30, "len: {}", chars(&fitted));
+
+        let changes = Changes::new(Some(" a.rs | 1 +\n 1 file changed\n".into()), "diff".into());
+        assert_eq!(
+            changes.text(),
+            "Summary:\n a.rs | 1 +\n 1 file changed\n\ndiff"
+        );
+        assert_eq!(
+            Changes::new(Some("\n".into()), "diff".into()).text(),
+            "diff"
+        );
+    }
+
+    #[test]
+    fn truncated_prompts_list_every_changed_file() {
+        let diff: String = (0..40)
+            .map(|i| file(&format!("src/module_{i}.rs"), &[("fn run()", 30)]))
+            .collect();
+        let mut entries: Vec<(String, String)> = (0..40)
+            .map(|i| ("M".to_string(), format!("src/module_{i}.rs")))
+            .collect();
+        entries[0].0 = "A".into();
+        entries.extend((0..70).map(|i| ("D".to_string(), format!("old/{i}.rs"))));
+
+        let prompt = truncate_diff_smart(&diff, &Budget::chars(3072));
+        let text = Changes::new(None, prompt.clone())
+            .with_files_changed(&entries)
+            .text();
+        let (body, list) = text.split_once("\n\nFiles changed").unwrap();
+        assert_eq!(body, prompt);
+        assert!(
+            list.starts_with(
+                " (110 files: 1 added, 39 modified, 70 deleted):\nA src/module_0.rs\n"
+            )
+        );
+        assert!(list.contains("M src/module_39.rs\n"));
+        assert_eq!(
+            list.lines().filter(|line| line.starts_with("D ")).count(),
+            60
+        );
+        assert!(list.ends_with("D old/59.rs\n... and 10 more\n"));
+
+        // Nothing was cut: the diff already shows every file.
+        let small = file("src/a.rs", &[("fn a()", 2)]);
+        let changes = Changes::new(None, small.clone()).with_files_changed(&entries[..1]);
+        assert_eq!(changes.text(), small);
+    }
+
+    #[test]
+    fn names_only_sends_the_stat_and_file_list_without_contents() {
+        let entries = vec![
+            ("M".to_string(), "src/auth.rs".to_string()),
+            ("A".to_string(), "src/token.rs".to_string()),
+        ];
+        let changes = Changes::names_only(Some(" 2 files changed\n".into()), &entries)
+            .with_hint(Some("rotate tokens on login"));
+        assert_eq!(
+            changes.text(),
+            "Overview: 2 files changed, 0 insertions(+), 0 deletions(-)\n\n\
+             Summary:\n 2 files changed\n\n\
+             Files changed (2 files: 1 added, 1 modified):\nM src/auth.rs\nA src/token.rs\n\n\
+             Hint from the author:\nrotate tokens on login\n"
+        );
+        assert!(changes.files.is_empty());
+        assert_eq!(changes.with_hint(None).hint, None);
+    }
+
+    #[test]
+    fn brief_sends_only_the_file_names() {
+        let paths = vec!["src/auth.rs".to_string(), "README.md".to_string()];
+        let changes = Changes::brief(&paths).with_hint(Some("docs"));
+        assert_eq!(
+            changes.text(),
+            "Summarise changes to these files: [src/auth.rs, README.md]\n\n\
+             Hint from the author:\ndocs\n"
+        );
+        assert_eq!(changes.paths, paths);
+        assert_eq!(changes.stats, None);
+    }
+
+    #[test]
+    fn recent_commits_come_before_the_hint() {
+        let changes = Changes::new(None, "+x\n".into())
+            .with_recent_commits(&["feat(api): add paging".into(), "fix: typo".into()])
+            .with_hint(Some("paging for search"));
+        assert_eq!(
+            changes.text(),
+            "+x\n\n\
+             Recent commits in this repository (match their style):\n\
+             - feat(api): add paging\n- fix: typo\n\n\
+             Hint from the author:\npaging for search\n"
+        );
+        assert_eq!(changes.with_recent_commits(&[]).recent_commits, None);
+    }
+
+    #[test]
+    fn submodules_are_listed_with_their_log_capped() {
+        let sha = |c: char| c.to_string().repeat(40);
+        let bump = SubmoduleChange {
+            path: "vendor/lib".into(),
+            old: Some(sha('a')),
+            new: Some(sha('b')),
+            log: Some((0..23).map(|i| format!("{i:07} change {i}")).collect()),
+        };
+        let added = SubmoduleChange {
+            path: "docs".into(),
+            old: None,
+            new: Some(sha('c')),
+            log: None,
+        };
+        let text = Changes::new(None, "diff\n".into())
+            .with_submodules(vec![bump, added])
+            .text();
+        assert!(text.starts_with(
+            "diff\n\nSubmodules changed:\nvendor/lib: aaaaaaa → bbbbbbb\n  0000000 change 0\n"
+        ));
+        assert!(
+            text.contains(
+                "  0000019 change 19\n  ... and 3 more commits\ndocs: added at ccccccc\n"
+            )
+        );
+        assert!(!text.contains("change 20"));
+    }
+
+    #[test]
+    fn untracked_files_follow_the_diff_capped_at_fifty() {
+        let changes = Changes::new(None, "diff\n".into()).with_untracked(&["notes.md".into()]);
+        assert_eq!(
+            changes.text(),
+            "diff\n\nUntracked files (not included in this commit):\n- notes.md\n"
+        );
+        assert_eq!(
+            Changes::new(None, "diff".into()).with_untracked(&[]).text(),
+            "diff"
+        );
+
+        let paths: Vec<String> = (0..53).map(|i| format!("new_{i}.rs")).collect();
+        let list = untracked_list(&paths).unwrap();
+        assert_eq!(
+            list.lines().filter(|line| line.starts_with("- ")).count(),
+            50
+        );
+        assert!(list.contains("- new_49.rs\n"));
+        assert!(!list.contains("new_50.rs"));
+        assert!(list.ends_with("+3 more\n"));
+    }
+
+    #[test]
+    fn pure_renames_take_one_line_and_edited_ones_keep_their_diff() {
+        let moved = "diff --git a/src/old.rs b/src/new.rs\n\
+                     similarity index 100%\n\
+                     rename from src/old.rs\n\
+                     rename to src/new.rs\n";
+        let copied = "diff --git a/a.txt b/b.txt\n\
+                      similarity index 100%\n\
+                      copy from a.txt\n\
+                      copy to b.txt\n";
+        let edited = "diff --git a/x.rs b/y.rs\n\
+                      similarity index 90%\n\
+                      rename from x.rs\n\
+                      rename to y.rs\n\
+                      index 1111111..2222222 100644\n\
+                      --- a/x.rs\n\
+                      +++ b/y.rs\n\
+                      @@ -1 +1 @@\n\
+                      -old\n\
+                      +new\n";
+        let diff = format!("{moved}{edited}{copied}");
+        let out = prompt_diff(
+            diff.clone(),
+            &Budget::chars(10_000),
+            &[],
+            &Generated::default(),
+            &Excludes::default(),
+            &[],
+        );
+        assert_eq!(
+            out,
+            format!(
+                "{edited}Moved files (contents unchanged):\n\
+                 renamed: src/old.rs → src/new.rs\n\
+                 copied: a.txt → b.txt\n"
+            )
+        );
+
+        let files = file_changes(&diff, &[], &Generated::default(), &Excludes::default(), &[]);
+        let renames: Vec<_> = files
+            .iter()
+            .map(|f| f.rename.as_ref().map(|r| r.copy))
+            .collect();
+        assert_eq!(renames, [Some(false), None, Some(true)]);
+        assert_eq!(
+            files[0].note.as_deref(),
+            Some("renamed: src/old.rs → src/new.rs")
+        );
+        assert_eq!(files[1].note, None);
+    }
+
+    #[test]
+    fn file_changes_are_keyed_by_blob_ids() {
+        let diff = file("src/a.rs", &[("fn a()", 2)])
+            + &file("Cargo.lock", &[("[[package]]", 3)])
+            + "diff --git a/logo.png b/logo.png\nBinary files a/logo.png and b/logo.png differ\n";
+        let files = file_changes(
+            &diff,
+            &ignore(&["Cargo.lock"]),
+            &Generated::default(),
+            &Excludes::default(),
+            &[],
+        );
+        let paths: Vec<&str> = files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["src/a.rs", "Cargo.lock", "logo.png"]);
+
+        assert_eq!(files[0].key, "1111111..2222222");
+        assert_eq!(files[0].diff, file("src/a.rs", &[("fn a()", 2)]));
+        assert_eq!(files[0].stat, "src/a.rs: 2 insertions, 0 deletions");
+        assert_eq!(files[0].note, None);
+        assert_eq!(
+            files[1].note.as_deref(),
+            Some("Cargo.lock: 3 insertions, 0 deletions")
+        );
+        // No `index` line: keyed by the text itself.
+        assert_eq!(files[2].key, files[2].diff);
+        assert_eq!(files[2].note.as_deref(), Some("binary changed: logo.png"));
+    }
+}
@@ -0,0 +1,78 @@
+use thiserror::Error;
+
+// ---------- Errors ----------
+// The failures callers may want to tell apart: git itself, the LLM endpoint,
+// configuration, an empty index and unparseable model output. Code that only
+// reports errors keeps using `anyhow`; these convert into it with `?`.
+#[derive(Debug, Error)]
+pub enum GitCmtError {
+    #[error("{0}")]
+    Git(String),
+    // A non-retryable HTTP failure from the LLM endpoint, tagged with the
+    // API flavor that was called.
+    #[error("{api}: LLM request failed with status {status}: {body}")]
+    Api {
+        api: &'static str,
+        status: u16,
+        body: String,
+    },
+    // Any other LLM failure: connection, retry budget, malformed response.
+    #[error("{0}")]
+    Request(String),
+    #[error("{0}")]
+    Config(String),
+    #[error("no staged changes found")]
+    NothingToCommit,
+    #[error("{0}")]
+    ParseError(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl GitCmtError {
+    // A backend failure, prefixed with the API that was called. HTTP
+    // failures keep their status so callers can match on it.
+    pub fn llm(api: &'static str, err: anyhow::Error) -> Self {
+        match err.downcast::<crate::llm::ApiError>() {
+            Ok(crate::llm::ApiError { status, body }) => GitCmtError::Api {
+                api,
+                status: status.as_u16(),
+                body,
+            },
+            Err(err) => GitCmtError::Request(format!("{api}: {err:#}")),
+        }
+    }
+
+    pub fn config(err: anyhow::Error) -> Self {
+        GitCmtError::Config(format!("{err:#}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn llm_failures_keep_the_http_status() {
+        let err = anyhow::Error::new(crate::llm::ApiError {
+            status: reqwest::StatusCode::UNAUTHORIZED,
+            body: "bad key".into(),
+        });
+        let err = GitCmtError::llm("Chat Completions API", err);
+        assert!(matches!(err, GitCmtError::Api { status: 401, .. }));
+        assert_eq!(
+            err.to_string(),
+            "Chat Completions API: LLM request failed with status 401: bad key"
+        );
+
+        let err = GitCmtError::llm(
+            "Chat Completions API",
+            anyhow::anyhow!("connection refused").context("LLM request failed"),
+        );
+        assert!(matches!(err, GitCmtError::Request(_)));
+        assert_eq!(
+            err.to_string(),
+            "Chat Completions API: LLM request failed: connection refused"
+        );
+    }
+}
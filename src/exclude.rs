@@ -0,0 +1,151 @@
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::fs;
+use std::path::Path;
+
+pub const EXCLUDE_FILE: &str = ".gitcmtignore";
+
+// ---------- Prompt exclusions ----------
+// Paths that must never reach the LLM (generated code, fixtures with PII,
+// vendored deps), from `.gitcmtignore` at the repository root plus the
+// `prompt_exclude` config key. Both use gitignore syntax, matched by the
+// `ignore` crate. Matching files are still staged and committed; only their
+// diffs are kept out of the prompt.
+#[derive(Debug)]
+pub struct Excludes {
+    rules: Gitignore,
+}
+
+impl Default for Excludes {
+    fn default() -> Self {
+        Excludes {
+            rules: Gitignore::empty(),
+        }
+    }
+}
+
+impl Excludes {
+    // `.gitcmtignore` in `root` (if present), then the config patterns, so
+    // config lines can override the file.
+    pub fn load(root: &Path, patterns: &[String]) -> Result<Self> {
+        let path = root.join(EXCLUDE_FILE);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        Ok(Excludes::parse(
+            text.lines().chain(patterns.iter().map(String::as_str)),
+        ))
+    }
+
+    // A line that isn't a valid pattern (a reversed range like `[z-a]`) matches
+    // nothing, as in git.
+    pub fn parse<'a>(lines: impl IntoIterator<Item = &'a str>) -> Self {
+        // Paths are always relative to the root, so the root itself never
+        // has to be stripped from them.
+        let mut builder = GitignoreBuilder::new("");
+        for line in lines {
+            let _ = builder.add_line(None, line);
+        }
+        Excludes {
+            rules: builder.build().unwrap_or_else(|_| Gitignore::empty()),
+        }
+    }
+
+    // Like git, a file can't be re-included once a parent directory is
+    // excluded.
+    pub fn is_excluded(&self, path: &str) -> bool {
+        let path = path.trim_start_matches('/');
+        let parents = path
+            .match_indices('/')
+            .map(|(i, _)| &path[..i])
+            .filter(|dir| !dir.is_empty());
+        for dir in parents {
+            if self.rules.matched(dir, true).is_ignore() {
+                return true;
+            }
+        }
+        self.rules.matched(path, false).is_ignore()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn excludes(text: &str) -> Excludes {
+        Excludes::parse(text.lines())
+    }
+
+    #[test]
+    fn names_match_at_any_depth_and_slashes_anchor() {
+        let ex = excludes("*.generated.rs\n/vendor\ndocs/*.pdf\n");
+        assert!(ex.is_excluded("src/api.generated.rs"));
+        assert!(ex.is_excluded("api.generated.rs"));
+        assert!(ex.is_excluded("vendor/lib/a.c"));
+        assert!(!ex.is_excluded("src/vendor/a.c"));
+        assert!(ex.is_excluded("docs/spec.pdf"));
+        assert!(!ex.is_excluded("docs/v1/spec.pdf"));
+        assert!(!ex.is_excluded("src/main.rs"));
+    }
+
+    #[test]
+    fn directory_patterns_match_everything_below() {
+        let ex = excludes("fixtures/\n**/testdata/**\nsecrets/**/*.json\n");
+        assert!(ex.is_excluded("fixtures/users.csv"));
+        assert!(ex.is_excluded("tests/fixtures/deep/users.csv"));
+        // `fixtures/` only matches a directory, not a file of that name.
+        assert!(!ex.is_excluded("src/fixtures"));
+        assert!(ex.is_excluded("pkg/testdata/a/b.txt"));
+        assert!(ex.is_excluded("secrets/prod.json"));
+        assert!(ex.is_excluded("secrets/eu/prod.json"));
+        assert!(!ex.is_excluded("secrets/README.md"));
+    }
+
+    #[test]
+    fn negation_re_includes_unless_a_parent_is_excluded() {
+        let ex = excludes("fixtures/*.csv\n!fixtures/sample.csv\n");
+        assert!(ex.is_excluded("fixtures/users.csv"));
+        assert!(!ex.is_excluded("fixtures/sample.csv"));
+
+        // Last match wins.
+        let ex = excludes("!keep.log\n*.log\n");
+        assert!(ex.is_excluded("keep.log"));
+
+        let ex = excludes("build/\n!build/keep.txt\n");
+        assert!(ex.is_excluded("build/keep.txt"));
+    }
+
+    #[test]
+    fn comments_escapes_and_classes() {
+        let ex = excludes("# comment\n\n\\#notes.txt\n\\!bang\nlog[0-9].txt\nfile[!a].md\n");
+        assert!(!ex.is_excluded("# comment"));
+        assert!(ex.is_excluded("#notes.txt"));
+        assert!(ex.is_excluded("!bang"));
+        assert!(ex.is_excluded("log7.txt"));
+        assert!(!ex.is_excluded("logx.txt"));
+        assert!(ex.is_excluded("fileb.md"));
+        assert!(!ex.is_excluded("filea.md"));
+        assert!(!excludes("\n# only comments\n").is_excluded("a.txt"));
+        // An invalid line (a reversed range) is skipped; the rest still apply.
+        let ex = excludes("log[z-a].txt\n*.tmp\n");
+        assert!(!ex.is_excluded("log[z-a].txt"));
+        assert!(ex.is_excluded("a.tmp"));
+    }
+
+    #[test]
+    fn config_patterns_apply_after_the_file() {
+        let root = std::env::temp_dir().join(format!("git-cmt-rs-exclude-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join(EXCLUDE_FILE), "*.sql\n").unwrap();
+        let ex = Excludes::load(&root, &["!seed.sql".to_string(), "pii/".to_string()]).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(ex.is_excluded("db/dump.sql"));
+        assert!(!ex.is_excluded("db/seed.sql"));
+        assert!(ex.is_excluded("pii/people.csv"));
+        // The file is gone: nothing is excluded.
+        assert!(!Excludes::load(&root, &[]).unwrap().is_excluded("dump.sql"));
+    }
+}
@@ -0,0 +1,234 @@
+use crate::Commit;
+use crate::cli::FormatPreset;
+
+// ---------- Commit message formats ----------
+// A `--format` preset decides which types the model may pick, what else the
+// system prompt asks of it, and how the returned `Commit` becomes a subject
+// line. Adding a preset means adding a struct that implements this trait and
+// a `FormatPreset` variant.
+pub trait CommitFormatter: Sync {
+    // Sent as the prompt's type list and the JSON schema's `enum`.
+    fn types(&self) -> &'static [&'static str] {
+        CONVENTIONAL_TYPES
+    }
+
+    // Appended to the system prompt, for presets that need more than types.
+    fn instructions(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn subject(&self, commit: &Commit) -> String;
+}
+
+const CONVENTIONAL_TYPES: &[&str] = &[
+    "feat", "fix", "docs", "style", "refactor", "test", "chore", "revert",
+];
+
+// https://github.com/angular/angular/blob/main/CONTRIBUTING.md#type
+const ANGULAR_TYPES: &[&str] = &[
+    "build", "ci", "docs", "feat", "fix", "perf", "refactor", "test", "revert",
+];
+
+impl FormatPreset {
+    pub fn formatter(self) -> &'static dyn CommitFormatter {
+        match self {
+            FormatPreset::Conventional => &Conventional,
+            FormatPreset::Github => &GitHub,
+            FormatPreset::Angular => &Angular,
+            FormatPreset::Emoji => &Emoji,
+        }
+    }
+}
+
+// `type(scope): message`
+struct Conventional;
+
+impl CommitFormatter for Conventional {
+    fn subject(&self, commit: &Commit) -> String {
+        conventional_line(commit.r#type.trim(), commit)
+    }
+}
+
+// A breaking change gets `!` before the colon: `feat(api)!:` or `feat!:`.
+fn conventional_line(kind: &str, commit: &Commit) -> String {
+    let mut out = kind.to_string();
+    if !commit.scope.trim().is_empty() {
+        out.push('(');
+        out.push_str(commit.scope.trim());
+        out.push(')');
+    }
+    if commit.breaking {
+        out.push('!');
+    }
+    out.push_str(": ");
+    out.push_str(commit.message.trim());
+    out
+}
+
+// `[type] message`, `[type!] message` when breaking; there is no place for
+// a scope.
+struct GitHub;
+
+impl CommitFormatter for GitHub {
+    fn instructions(&self) -> Option<&'static str> {
+        Some("Scopes are not used: set scope to an empty string.")
+    }
+
+    fn subject(&self, commit: &Commit) -> String {
+        let bang = if commit.breaking { "!" } else { "" };
+        format!("[{}{bang}] {}", commit.r#type.trim(), commit.message.trim())
+    }
+}
+
+// Conventional layout with Angular's type list, enforced: a type outside it
+// is mapped onto the nearest one rather than passed through.
+struct Angular;
+
+impl CommitFormatter for Angular {
+    fn types(&self) -> &'static [&'static str] {
+        ANGULAR_TYPES
+    }
+
+    fn instructions(&self) -> Option<&'static str> {
+        Some(
+            "Follow Angular's commit guidelines: use only the types listed, and write the message \
+             in the imperative, present tense, without a capital first letter or a trailing period.",
+        )
+    }
+
+    fn subject(&self, commit: &Commit) -> String {
+        let kind = commit.r#type.trim().to_lowercase();
+        let kind = match kind.as_str() {
+            known if ANGULAR_TYPES.contains(&known) => known,
+            "style" => "refactor",
+            _ => "build",
+        };
+        let message = commit.message.trim().trim_end_matches('.');
+        let mut chars = message.chars();
+        let message: String = match chars.next() {
+            Some(first) => first.to_lowercase().chain(chars).collect(),
+            None => String::new(),
+        };
+        conventional_line(
+            kind,
+            &Commit {
+                message,
+                scope: commit.scope.clone(),
+                breaking: commit.breaking,
+                ..Default::default()
+            },
+        )
+    }
+}
+
+// The conventional line behind the type's gitmoji (https://gitmoji.dev).
+struct Emoji;
+
+impl CommitFormatter for Emoji {
+    fn subject(&self, commit: &Commit) -> String {
+        let line = conventional_line(commit.r#type.trim(), commit);
+        match gitmoji(&commit.r#type.trim().to_lowercase()) {
+            Some(emoji) => format!("{emoji} {line}"),
+            None => line,
+        }
+    }
+}
+
+fn gitmoji(kind: &str) -> Option<&'static str> {
+    Some(match kind {
+        "feat" => "✨",
+        "fix" => "🐛",
+        "docs" => "📝",
+        "style" => "🎨",
+        "refactor" => "♻️",
+        "test" => "✅",
+        "chore" => "🔧",
+        "perf" => "⚡️",
+        "build" => "📦",
+        "ci" => "👷",
+        "revert" => "⏪️",
+        "merge" => "🔀",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(kind: &str, scope: &str, message: &str) -> Commit {
+        Commit {
+            r#type: kind.into(),
+            scope: scope.into(),
+            message: message.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn each_preset_builds_its_own_subject() {
+        let c = commit("feat", "auth", "add login");
+        let subject = |preset: FormatPreset| preset.formatter().subject(&c);
+        assert_eq!(subject(FormatPreset::Conventional), "feat(auth): add login");
+        assert_eq!(subject(FormatPreset::Github), "[feat] add login");
+        assert_eq!(subject(FormatPreset::Angular), "feat(auth): add login");
+        assert_eq!(subject(FormatPreset::Emoji), "✨ feat(auth): add login");
+
+        let unknown = commit("wip", "", "halfway");
+        assert_eq!(
+            FormatPreset::Emoji.formatter().subject(&unknown),
+            "wip: halfway"
+        );
+    }
+
+    #[test]
+    fn breaking_changes_get_a_bang_with_or_without_a_scope() {
+        let mut scoped = commit("feat", "api", "drop v1 endpoints");
+        scoped.breaking = true;
+        let mut bare = commit("feat", "", "drop v1 endpoints");
+        bare.breaking = true;
+        let subject = |preset: FormatPreset, c: &Commit| preset.formatter().subject(c);
+        assert_eq!(
+            subject(FormatPreset::Conventional, &scoped),
+            "feat(api)!: drop v1 endpoints"
+        );
+        assert_eq!(
+            subject(FormatPreset::Conventional, &bare),
+            "feat!: drop v1 endpoints"
+        );
+        assert_eq!(
+            subject(FormatPreset::Github, &bare),
+            "[feat!] drop v1 endpoints"
+        );
+        assert_eq!(
+            subject(FormatPreset::Angular, &bare),
+            "feat!: drop v1 endpoints"
+        );
+        assert_eq!(
+            subject(FormatPreset::Emoji, &scoped),
+            "✨ feat(api)!: drop v1 endpoints"
+        );
+    }
+
+    #[test]
+    fn angular_enforces_its_types_and_subject_style() {
+        let angular = FormatPreset::Angular.formatter();
+        assert_eq!(
+            angular.subject(&commit("chore", "deps", "Bump serde.")),
+            "build(deps): bump serde"
+        );
+        assert_eq!(
+            angular.subject(&commit("Style", "", "Reformat")),
+            "refactor: reformat"
+        );
+        assert!(angular.types().contains(&"perf"));
+        assert!(!angular.types().contains(&"chore"));
+        assert!(angular.instructions().is_some());
+        assert!(
+            FormatPreset::Conventional
+                .formatter()
+                .instructions()
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,1602 @@
+use clap::ValueEnum;
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+
+use crate::cli::{DiffAlgorithm, GitBackend};
+use crate::diff::{BinaryChange, SubmoduleChange};
+use crate::error::GitCmtError;
+use crate::libgit2::Libgit2;
+use crate::status::{self, Entry};
+
+type Result<T> = std::result::Result<T, GitCmtError>;
+
+// ---------- Git repository ----------
+// Read-only queries used to build the prompt. Runs git in `dir` (the current
+// directory in normal use) so tests can point it at a scratch repository.
+// Staging, the staged diff, the commit and HEAD/branch/remote lookups go
+// through `backend` instead.
+pub struct Git {
+    dir: PathBuf,
+    // `-M` / `-C` similarity thresholds (percent) for the `diff --cached`
+    // queries; 0 turns that detection off.
+    rename_threshold: u8,
+    copy_threshold: u8,
+    // `--diff-algorithm`; None leaves it to git (and `diff.algorithm`).
+    algorithm: Option<DiffAlgorithm>,
+    // `-U<n>` for the content diff; unchanged lines cost budget too.
+    context_lines: u32,
+    backend: Rc<dyn Backend>,
+}
+
+// Git's own default for both.
+pub const DEFAULT_SIMILARITY: u8 = 50;
+
+// Git's own default.
+pub const DEFAULT_CONTEXT_LINES: u32 = 3;
+
+// The tree entry mode of a submodule commit.
+const GITLINK_MODE: &str = "160000";
+
+impl Git {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Git {
+            dir: dir.into(),
+            rename_threshold: DEFAULT_SIMILARITY,
+            copy_threshold: DEFAULT_SIMILARITY,
+            algorithm: None,
+            context_lines: DEFAULT_CONTEXT_LINES,
+            backend: Rc::new(Exec),
+        }
+    }
+
+    pub fn cwd() -> Self {
+        Git::new(".")
+    }
+
+    pub fn with_similarity(self, rename_threshold: u8, copy_threshold: u8) -> Self {
+        Git {
+            rename_threshold,
+            copy_threshold,
+            ..self
+        }
+    }
+
+    pub fn with_diff_algorithm(self, algorithm: Option<DiffAlgorithm>) -> Self {
+        Git { algorithm, ..self }
+    }
+
+    pub fn with_context_lines(self, context_lines: u32) -> Self {
+        Git {
+            context_lines,
+            ..self
+        }
+    }
+
+    // `--git-backend`. Outside a repository libgit2 has nothing to open, so
+    // `Exec` stays and reports that the way git does; the error says why
+    // libgit2 was asked for but `Exec` took over.
+    pub fn with_backend(self, backend: GitBackend) -> (Self, Option<GitCmtError>) {
+        let (backend, declined): (Rc<dyn Backend>, _) = match backend {
+            GitBackend::Exec => (Rc::new(Exec), None),
+            GitBackend::Libgit2 => match Libgit2::open(&self.dir) {
+                Ok(libgit2) => (Rc::new(libgit2), None),
+                Err(e) => (Rc::new(Exec), Some(e)),
+            },
+        };
+        (Git { backend, ..self }, declined)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn rename_threshold(&self) -> u8 {
+        self.rename_threshold
+    }
+
+    pub fn copy_threshold(&self) -> u8 {
+        self.copy_threshold
+    }
+
+    pub fn diff_algorithm(&self) -> Option<DiffAlgorithm> {
+        self.algorithm
+    }
+
+    pub fn context_lines(&self) -> u32 {
+        self.context_lines
+    }
+
+    // A moved file becomes a `rename from`/`rename to` header (plus any
+    // edits) instead of a full deletion and addition. `-C` needs `-M`, so
+    // turning renames off turns copies off too.
+    fn detection_args(&self) -> Vec<String> {
+        match (self.rename_threshold, self.copy_threshold) {
+            (0, _) => vec!["--no-renames".into()],
+            (renames, 0) => vec![format!("-M{renames}%")],
+            (renames, copies) => vec![format!("-M{renames}%"), format!("-C{copies}%")],
+        }
+    }
+
+    // `git diff --cached <args> <detection> [--diff-algorithm=X] [base]`.
+    fn diff_cached_args(&self, args: &[&str], base: Option<&str>) -> Vec<String> {
+        let mut out: Vec<String> = ["diff", "--cached"].into_iter().map(String::from).collect();
+        out.extend(args.iter().map(|arg| arg.to_string()));
+        out.extend(self.detection_args());
+        out.extend(self.algorithm.and_then(|algorithm| {
+            let value = algorithm.to_possible_value()?;
+            Some(format!("--diff-algorithm={}", value.get_name()))
+        }));
+        out.extend(base.map(String::from));
+        out
+    }
+
+    // Output is parsed (and the `--stat` totals line is looked for), so it
+    // must not be translated by the user's locale.
+    fn command(&self) -> Command {
+        let mut cmd = Command::new("git");
+        cmd.current_dir(&self.dir).env("LC_ALL", "C");
+        cmd
+    }
+
+    fn output<S: AsRef<str>>(&self, args: &[S]) -> Result<String> {
+        String::from_utf8(self.output_bytes(args)?)
+            .map_err(|_| GitCmtError::Git("git output was not valid UTF-8".into()))
+    }
+
+    fn output_bytes<S: AsRef<str>>(&self, args: &[S]) -> Result<Vec<u8>> {
+        let args: Vec<&str> = args.iter().map(AsRef::as_ref).collect();
+        let output = self.command().args(&args).output().map_err(|e| {
+            GitCmtError::Git(format!("failed to run `git {}`: {e}", args.join(" ")))
+        })?;
+        if !output.status.success() {
+            return Err(GitCmtError::Git(format!(
+                "git {} failed with status: {}",
+                args.first().copied().unwrap_or_default(),
+                output.status
+            )));
+        }
+        Ok(output.stdout)
+    }
+
+    // `git --version`, e.g. "git version 2.43.0". Spawn failures stay `Io`
+    // errors, so a missing binary (`NotFound`) can be told apart.
+    pub fn version(&self) -> Result<String> {
+        let output = self.command().arg("--version").output()?;
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    // Whether `dir` is inside a repository (or its `.git` directory).
+    pub fn is_repository(&self) -> bool {
+        self.output(&["rev-parse", "--git-dir"]).is_ok()
+    }
+
+    // Absolute path of the working tree's root.
+    pub fn toplevel(&self) -> Result<String> {
+        Ok(self
+            .output(&["rev-parse", "--show-toplevel"])?
+            .trim()
+            .to_string())
+    }
+
+    // HEAD points at a commit rather than a branch (bisects, CI checkouts).
+    pub fn is_detached(&self) -> bool {
+        self.backend.is_detached(self)
+    }
+
+    // The branch HEAD is on, e.g. `main`; an error on a detached HEAD.
+    pub fn current_branch(&self) -> Result<String> {
+        self.backend.current_branch(self)
+    }
+
+    // Short SHA and subject of HEAD, e.g. for reporting what `undo` removed.
+    pub fn head_commit(&self) -> Result<(String, String)> {
+        self.backend.head_commit(self)
+    }
+
+    // The current branch's upstream, e.g. `origin/main`; `None` for a new
+    // branch that has never been pushed (or a detached HEAD).
+    pub fn upstream(&self) -> Option<String> {
+        self.command()
+            .args(["rev-parse", "--abbrev-ref", "@{u}"])
+            .stderr(Stdio::null())
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    // `git fetch <remote>`, quietly; errors are git's own on stderr.
+    pub fn fetch(&self, remote: &str) -> Result<()> {
+        self.output(&["fetch", "--quiet", remote]).map(drop)
+    }
+
+    // Commits on HEAD that `other` lacks, and the other way round.
+    pub fn ahead_behind(&self, other: &str) -> Result<(usize, usize)> {
+        let range = format!("HEAD...{other}");
+        let output = self.output(&["rev-list", "--left-right", "--count", &range])?;
+        let mut counts = output.split_whitespace().map(str::parse::<usize>);
+        match (counts.next(), counts.next()) {
+            (Some(Ok(ahead)), Some(Ok(behind))) => Ok((ahead, behind)),
+            _ => Err(GitCmtError::Git(format!(
+                "unexpected `git rev-list --count` output: {:?}",
+                output.trim()
+            ))),
+        }
+    }
+
+    // Short SHA, subject and `--shortstat` line (empty for a merge or an
+    // empty commit) of HEAD, for reporting the commit just made.
+    pub fn head_shortstat(&self) -> Result<(String, String, String)> {
+        let output = self.output(&["show", "--shortstat", "--format=%h%n%s", "HEAD"])?;
+        let mut lines = output.lines();
+        let sha = lines.next().unwrap_or_default().to_string();
+        let subject = lines.next().unwrap_or_default().to_string();
+        let stat = lines.map(str::trim).find(|line| !line.is_empty());
+        Ok((sha, subject, stat.unwrap_or_default().to_string()))
+    }
+
+    // `branch.<name>.remote`: where `branch` pushes and pulls by default.
+    pub fn branch_remote(&self, branch: &str) -> Option<String> {
+        self.backend.branch_remote(self, branch)
+    }
+
+    // `refs/remotes/<remote>/HEAD` as e.g. `origin/main`: the remote's
+    // default branch as of the clone (or `git remote set-head`).
+    pub fn remote_head(&self, remote: &str) -> Option<String> {
+        self.backend.remote_head(self, remote)
+    }
+
+    // `commit.template`, `~` expanded and relative paths taken from `dir`
+    // as git does; `None` when unset.
+    pub fn commit_template(&self) -> Option<PathBuf> {
+        self.output(&["config", "--path", "--get", "commit.template"])
+            .ok()
+            .map(|path| path.trim().to_string())
+            .filter(|path| !path.is_empty())
+            .map(|path| self.dir.join(path))
+    }
+
+    // What starts a line git strips from an edited commit message:
+    // `core.commentChar`, `#` by default. `None` when nothing is stripped
+    // (`commit.cleanup` other than `strip` or `default`) or git picks the
+    // character per message (`auto`).
+    pub fn comment_prefix(&self) -> Option<String> {
+        let get = |key: &str| {
+            self.output(&["config", "--get", key])
+                .ok()
+                .map(|value| value.trim_end_matches('\n').to_string())
+        };
+        if get("commit.cleanup").is_some_and(|mode| mode != "strip" && mode != "default") {
+            return None;
+        }
+        match get("core.commentChar") {
+            None => Some("#".into()),
+            Some(prefix) if prefix.is_empty() || prefix == "auto" => None,
+            Some(prefix) => Some(prefix),
+        }
+    }
+
+    // Configured remote names, in `git remote` order.
+    pub fn remotes(&self) -> Result<Vec<String>> {
+        self.backend.remotes(self)
+    }
+
+    // The hooks directory: `.git/hooks`, or wherever `core.hooksPath` (or a
+    // linked worktree) puts it. git answers relative to `dir`.
+    pub fn hooks_dir(&self) -> Result<PathBuf> {
+        let path = self.output(&["rev-parse", "--git-path", "hooks"])?;
+        Ok(self.dir.join(path.trim()))
+    }
+
+    // A file in the git directory, e.g. `.git/MERGE_MSG`, wherever a linked
+    // worktree keeps it.
+    pub fn git_path(&self, name: &str) -> Result<PathBuf> {
+        let path = self.output(&["rev-parse", "--git-path", name])?;
+        Ok(self.dir.join(path.trim()))
+    }
+
+    // Where `dir` sits below the root, e.g. `crates/foo/`; empty at the root.
+    pub fn prefix(&self) -> Result<String> {
+        Ok(self
+            .output(&["rev-parse", "--show-prefix"])?
+            .trim()
+            .to_string())
+    }
+
+    // Which of `paths` (relative to the repository root) differ between the
+    // working tree and the index, e.g. after a hook reformatted them.
+    pub fn unstaged_changes(&self, paths: &[String]) -> Result<Vec<String>> {
+        if paths.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut args: Vec<String> = ["diff", "--name-only", "-z", "--"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        args.extend(paths.iter().map(|path| top_pathspec(path)));
+        let output = self.output(&args)?;
+        Ok(output
+            .split('\0')
+            .filter(|path| !path.is_empty())
+            .map(String::from)
+            .collect())
+    }
+
+    // Object ID git would give `text` as a blob, without writing it.
+    pub fn hash_object(&self, text: &str) -> Result<String> {
+        let mut child = self
+            .command()
+            .args(["hash-object", "--stdin"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| GitCmtError::Git(format!("failed to run `git hash-object`: {e}")))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(GitCmtError::Git(format!(
+                "git hash-object failed with status: {}",
+                output.status
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    // Untracked files that aren't ignored, one path each (files in new
+    // directories are listed individually).
+    pub fn untracked_files(&self) -> Result<Vec<String>> {
+        Ok(self
+            .status_entries()?
+            .into_iter()
+            .filter_map(|entry| match entry {
+                Entry::Untracked { path } => Some(path),
+                _ => None,
+            })
+            .collect())
+    }
+
+    // Every change git sees, staged or not, untracked files included.
+    pub fn status_entries(&self) -> Result<Vec<Entry>> {
+        let porcelain =
+            self.output(&["status", "--porcelain=v2", "-z", "--untracked-files=all"])?;
+        Ok(status::parse(&porcelain))
+    }
+
+    // `git diff HEAD --numstat`: lines added and deleted per tracked file,
+    // staged or not, `None` for binaries. Renames show as a deletion and an
+    // addition, like the entries `git status` lists.
+    pub fn worktree_numstat(&self) -> Result<Vec<(String, LineCounts)>> {
+        let output = self.output(&["diff", "HEAD", "--numstat", "--no-renames", "-z"])?;
+        Ok(parse_numstat(&output))
+    }
+
+    // `git status --porcelain=v2`: empty for a clean tree. Staged entries
+    // carry their index blob IDs, so the text changes whenever staged
+    // content does.
+    pub fn status(&self, include_untracked: bool) -> Result<String> {
+        let untracked = if include_untracked {
+            "--untracked-files=all"
+        } else {
+            "--untracked-files=no"
+        };
+        self.output(&["status", "--porcelain=v2", "-z", untracked])
+    }
+
+    // The most recent tag reachable from HEAD, if any.
+    pub fn last_tag(&self) -> Option<String> {
+        let tag = self
+            .output(&["describe", "--tags", "--abbrev=0"])
+            .ok()?
+            .trim()
+            .to_string();
+        (!tag.is_empty()).then_some(tag)
+    }
+
+    // `git check-ref-format`: whether `name` can be a tag at all.
+    pub fn is_valid_tag_name(&self, name: &str) -> bool {
+        self.output(&["check-ref-format", &format!("refs/tags/{name}")])
+            .is_ok()
+    }
+
+    pub fn tag_exists(&self, name: &str) -> bool {
+        self.output(&[
+            "rev-parse",
+            "--quiet",
+            "--verify",
+            &format!("refs/tags/{name}"),
+        ])
+        .is_ok()
+    }
+
+    // Tags pointing at HEAD, for `--push-tags` without `--tag-version`.
+    pub fn tags_at_head(&self) -> Result<Vec<String>> {
+        Ok(self
+            .output(&["tag", "--points-at", "HEAD"])?
+            .lines()
+            .map(String::from)
+            .collect())
+    }
+
+    // `git log <base>..HEAD --no-merges -p`: every commit on the branch with
+    // its patch, newest first. Raw bytes, like `diff_cached()`.
+    pub fn log_patch(&self, base: &str) -> Result<Vec<u8>> {
+        let range = format!("{base}..HEAD");
+        self.output_bytes(&["log", &range, "--no-merges", "-p"])
+    }
+
+    // `git log --oneline` for `range`, newest first, at most `max` commits.
+    pub fn log_oneline(&self, range: &str, max: usize) -> Result<Vec<String>> {
+        let count = format!("-{max}");
+        Ok(self
+            .output(&["log", "--oneline", "--no-decorate", &count, range, "--"])?
+            .lines()
+            .map(String::from)
+            .collect())
+    }
+
+    // Where HEAD's branch left `other`, as a full SHA.
+    pub fn merge_base(&self, other: &str) -> Result<String> {
+        Ok(self
+            .output(&["merge-base", "HEAD", other])?
+            .trim()
+            .to_string())
+    }
+
+    // Full messages of the commits in `range`, oldest first.
+    pub fn messages(&self, range: &str) -> Result<Vec<String>> {
+        Ok(self
+            .output(&["log", "--reverse", "--format=%B%x00", range, "--"])?
+            .split('\0')
+            .map(str::trim)
+            .filter(|message| !message.is_empty())
+            .map(String::from)
+            .collect())
+    }
+
+    // `git rev-list --count <range> <extra>`, e.g. `--merges` for merge
+    // commits only or `--not --remotes` for those on no remote branch.
+    pub fn count_commits(&self, range: &str, extra: &[&str]) -> Result<usize> {
+        let mut args = vec!["rev-list", "--count", range];
+        args.extend(extra);
+        let output = self.output(&args)?;
+        output.trim().parse().map_err(|_| {
+            GitCmtError::Git(format!(
+                "unexpected `git rev-list --count` output: {:?}",
+                output.trim()
+            ))
+        })
+    }
+
+    // Whether the index differs from HEAD.
+    pub fn has_staged_changes(&self) -> Result<bool> {
+        Ok(!self
+            .output(&["diff", "--cached", "--name-only"])?
+            .trim()
+            .is_empty())
+    }
+
+    // The commit being merged in while a merge waits to be committed;
+    // `None` otherwise.
+    pub fn merge_head(&self) -> Option<String> {
+        self.output(&["rev-parse", "--quiet", "--verify", "MERGE_HEAD"])
+            .ok()
+            .map(|sha| sha.trim().to_string())
+    }
+
+    // A rebase, `git am`, cherry-pick or revert that stopped part-way,
+    // from the markers git leaves in the git directory.
+    pub fn operation_in_progress(&self) -> Result<Option<Operation>> {
+        let mut args = vec!["rev-parse"];
+        for (marker, _) in OPERATION_MARKERS {
+            args.extend(["--git-path", marker]);
+        }
+        let paths = self.output(&args)?;
+        Ok(paths
+            .lines()
+            .zip(OPERATION_MARKERS)
+            .find(|(path, _)| self.dir.join(path).exists())
+            .map(|(_, (_, operation))| *operation))
+    }
+
+    // Subjects of the non-merge commits after `since` (all of history when
+    // None), newest first.
+    pub fn subjects_since(&self, since: Option<&str>) -> Result<Vec<String>> {
+        let range = since.map(|tag| format!("{tag}..HEAD"));
+        let mut args = vec!["log", "--no-merges", "--format=%s"];
+        args.push(range.as_deref().unwrap_or("HEAD"));
+        Ok(self.output(&args)?.lines().map(String::from).collect())
+    }
+
+    // Subjects of the last `n` non-merge commits, newest first; empty before
+    // the first commit.
+    pub fn recent_subjects(&self, n: usize) -> Result<Vec<String>> {
+        if self
+            .output(&["rev-parse", "--verify", "--quiet", "HEAD"])
+            .is_err()
+        {
+            return Ok(Vec::new());
+        }
+        let count = format!("-n{n}");
+        let output = self.output(&["log", "--no-merges", "--format=%s", &count])?;
+        Ok(output.lines().map(String::from).collect())
+    }
+
+    // `git diff --cached -b -U<n>`, against HEAD or, when amending, against
+    // `base`. Raw bytes: files in other encodings (e.g. Latin-1) are still
+    // valid diffs, so decoding is left to `diff::decode_lossy()`.
+    pub fn diff_cached(&self, base: Option<&str>) -> Result<Vec<u8>> {
+        self.backend.diff_cached(self, base)
+    }
+
+    // `git add :/` and friends, see `staging_args()`.
+    pub fn stage(&self, include_untracked: bool, cwd_only: bool) -> Result<()> {
+        self.backend.stage(self, include_untracked, cwd_only)
+    }
+
+    // `git commit` with `options`, editor and hooks included.
+    pub fn commit(&self, options: &CommitOptions) -> Result<()> {
+        self.backend.commit(self, options)
+    }
+
+    fn diff_args(&self, base: Option<&str>) -> Vec<String> {
+        let context = format!("-U{}", self.context_lines);
+        self.diff_cached_args(&["-b", &context], base)
+    }
+
+    // `git diff --cached --stat -b`: one line per file plus a totals line.
+    pub fn diff_stat(&self, base: Option<&str>) -> Result<String> {
+        let args = self.diff_cached_args(&["--stat", "-b"], base);
+        self.output(&args)
+    }
+
+    // `git diff --cached --name-only`: just the changed paths.
+    pub fn name_only(&self, base: Option<&str>) -> Result<Vec<String>> {
+        let args = self.diff_cached_args(&["--name-only", "-z"], base);
+        let output = self.output(&args)?;
+        Ok(output
+            .split('\0')
+            .filter(|path| !path.is_empty())
+            .map(String::from)
+            .collect())
+    }
+
+    // `git diff --cached --name-status`: a status letter (A, M, D, R, ...)
+    // and path per file; renames and copies read "old → new".
+    pub fn name_status(&self, base: Option<&str>) -> Result<Vec<(String, String)>> {
+        let args = self.diff_cached_args(&["--name-status", "-z"], base);
+        let output = self.output(&args)?;
+        let mut entries = Vec::new();
+        let mut fields = output.split('\0').filter(|field| !field.is_empty());
+        while let Some(status) = fields.next() {
+            let code = status.get(..1).unwrap_or(status).to_string();
+            let Some(path) = fields.next() else {
+                break;
+            };
+            let path = match code.as_str() {
+                "R" | "C" => format!("{path} → {}", fields.next().unwrap_or_default()),
+                _ => path.to_string(),
+            };
+            entries.push((code, path));
+        }
+        Ok(entries)
+    }
+
+    // Staged binary files, from the `-` entries of `--numstat`, with their
+    // blob sizes before (in `base`, default HEAD) and after (in the index).
+    pub fn binary_changes(&self, base: Option<&str>) -> Result<Vec<BinaryChange>> {
+        let args = self.diff_cached_args(&["--numstat", "-z"], base);
+        let numstat = self.output(&args)?;
+
+        let mut changes = Vec::new();
+        let mut fields = numstat.split('\0');
+        while let Some(entry) = fields.next() {
+            let mut parts = entry.splitn(3, '\t');
+            let (Some(added), Some(_deleted), Some(path)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                continue;
+            };
+            // Renames leave the path empty and list old and new paths as
+            // the next two fields.
+            let (old_path, path) = if path.is_empty() {
+                let old = fields.next().unwrap_or_default();
+                (
+                    old.to_string(),
+                    fields.next().unwrap_or_default().to_string(),
+                )
+            } else {
+                (path.to_string(), path.to_string())
+            };
+            if added != "-" {
+                continue;
+            }
+            let base = base.unwrap_or("HEAD");
+            changes.push(BinaryChange {
+                old_size: self.blob_size(&format!("{base}:{old_path}")),
+                new_size: self.blob_size(&format!(":{path}")),
+                path,
+            });
+        }
+        Ok(changes)
+    }
+
+    // Staged submodules: entries of `--raw` with a gitlink on either side,
+    // with the submodule's log for the range when it is checked out.
+    pub fn submodule_changes(&self, base: Option<&str>) -> Result<Vec<SubmoduleChange>> {
+        let args = self.diff_cached_args(&["--raw", "--no-abbrev", "-z"], base);
+        let raw = self.output(&args)?;
+
+        let mut changes = Vec::new();
+        let mut fields = raw.split('\0');
+        while let Some(entry) = fields.next() {
+            let Some(entry) = entry.strip_prefix(':') else {
+                continue;
+            };
+            let [old_mode, new_mode, old_sha, new_sha, status] =
+                entry.split(' ').collect::<Vec<_>>()[..]
+            else {
+                continue;
+            };
+            let mut path = fields.next().unwrap_or_default();
+            // Renames and copies list the old path, then the new one.
+            if status.starts_with(['R', 'C']) {
+                path = fields.next().unwrap_or_default();
+            }
+            if old_mode != GITLINK_MODE && new_mode != GITLINK_MODE {
+                continue;
+            }
+            let side = |mode: &str, sha: &str| (mode == GITLINK_MODE).then(|| sha.to_string());
+            let (old, new) = (side(old_mode, old_sha), side(new_mode, new_sha));
+            let log = match (&old, &new) {
+                (Some(old), Some(new)) => self.submodule_log(path, old, new),
+                _ => None,
+            };
+            changes.push(SubmoduleChange {
+                path: path.to_string(),
+                old,
+                new,
+                log,
+            });
+        }
+        Ok(changes)
+    }
+
+    // `git log --oneline old..new` inside the submodule's checkout. Paths
+    // are relative to the top level; an uninitialised submodule is an empty
+    // directory, where git would find the superproject instead.
+    fn submodule_log(&self, path: &str, old: &str, new: &str) -> Option<Vec<String>> {
+        let dir = PathBuf::from(self.toplevel().ok()?).join(path);
+        if !dir.join(".git").exists() {
+            return None;
+        }
+        let log = Git::new(dir)
+            .output(&["log", "--oneline", &format!("{old}..{new}")])
+            .ok()?;
+        Some(log.lines().map(String::from).collect())
+    }
+
+    // None when the object doesn't exist, e.g. the old side of a new file.
+    fn blob_size(&self, object: &str) -> Option<u64> {
+        self.output(&["cat-file", "-s", object])
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+}
+
+// ---------- Operations in progress ----------
+// A commit made in the middle of one would land inside its sequence rather
+// than after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Rebase,
+    Am,
+    CherryPick,
+    Revert,
+}
+
+// Checked in order: `git am` and the apply backend of `git rebase` share
+// `rebase-apply/`, which only holds `applying` for `am`.
+const OPERATION_MARKERS: &[(&str, Operation)] = &[
+    ("rebase-merge", Operation::Rebase),
+    ("rebase-apply/applying", Operation::Am),
+    ("rebase-apply", Operation::Rebase),
+    ("CHERRY_PICK_HEAD", Operation::CherryPick),
+    ("REVERT_HEAD", Operation::Revert),
+];
+
+impl Operation {
+    // The git command that continues or aborts it.
+    pub fn command(self) -> &'static str {
+        match self {
+            Operation::Rebase => "rebase",
+            Operation::Am => "am",
+            Operation::CherryPick => "cherry-pick",
+            Operation::Revert => "revert",
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::Am => write!(f, "a `git am`"),
+            other => write!(f, "a {}", other.command()),
+        }
+    }
+}
+
+// Lines added and deleted in one file; `None` when git can't count them.
+pub type LineCounts = Option<(usize, usize)>;
+
+// `added\tdeleted\tpath` records; binary files count `-` for both.
+fn parse_numstat(output: &str) -> Vec<(String, LineCounts)> {
+    output
+        .split('\0')
+        .filter_map(|record| {
+            let mut fields = record.splitn(3, '\t');
+            let (added, deleted, path) = (fields.next()?, fields.next()?, fields.next()?);
+            let counts = added.parse().ok().zip(deleted.parse().ok());
+            Some((path.to_string(), counts))
+        })
+        .collect()
+}
+
+// Diff paths are relative to the repository root, pathspecs to the current
+// directory; `:(top,literal)` makes one usable as the other, wildcards and
+// all.
+pub fn top_pathspec(path: &str) -> String {
+    format!(":(top,literal){path}")
+}
+
+// ---------- Backends ----------
+// The operations a library can do in process: staging, the staged diff,
+// the commit, and HEAD, branch and remote lookups. `Exec` runs git for
+// them like every other query here; `Libgit2` does them in process and
+// hands whatever it can't do exactly as git would back to `Exec`. Each
+// method gets the `Git` it was called through, for its settings.
+pub trait Backend {
+    fn stage(&self, git: &Git, include_untracked: bool, cwd_only: bool) -> Result<()>;
+    fn diff_cached(&self, git: &Git, base: Option<&str>) -> Result<Vec<u8>>;
+    fn commit(&self, git: &Git, options: &CommitOptions) -> Result<()>;
+    fn current_branch(&self, git: &Git) -> Result<String>;
+    fn is_detached(&self, git: &Git) -> bool;
+    fn head_commit(&self, git: &Git) -> Result<(String, String)>;
+    fn remotes(&self, git: &Git) -> Result<Vec<String>>;
+    fn branch_remote(&self, git: &Git, branch: &str) -> Option<String>;
+    fn remote_head(&self, git: &Git, remote: &str) -> Option<String>;
+}
+
+// `git commit [--amend] [--no-verify] -F <file> [-e]`; `-e` opens the editor
+// on the file's message for review. Co-authors go through `--trailer`, so
+// git places them in the trailer block after the body.
+pub struct CommitOptions<'a> {
+    pub message_file: &'a Path,
+    pub co_authors: &'a [String],
+    pub amend: bool,
+    pub edit: bool,
+    pub no_verify: bool,
+}
+
+impl CommitOptions<'_> {
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec!["commit".to_string()];
+        if self.amend {
+            args.push("--amend".into());
+        }
+        if self.no_verify {
+            args.push("--no-verify".into());
+        }
+        args.extend(["-F".into(), self.message_file.display().to_string()]);
+        if self.edit {
+            args.push("-e".into());
+        }
+        for co_author in self.co_authors {
+            args.extend(["--trailer".into(), format!("Co-authored-by: {co_author}")]);
+        }
+        args
+    }
+}
+
+// `git add` stages everything including new files; `git add -u` only
+// updates files git already tracks. `:/` is the repository root wherever
+// git runs from; `.` (with `--cwd`) is the subtree under the current
+// directory.
+pub fn staging_args(include_untracked: bool, cwd_only: bool) -> Vec<&'static str> {
+    let mut args = vec!["add"];
+    if !include_untracked {
+        args.push("-u");
+    }
+    args.push(if cwd_only { "." } else { ":/" });
+    args
+}
+
+// A `git` subprocess per operation. `git add` and `git commit` run in the
+// user's locale with the terminal attached: their output, the editor and
+// the hooks are the user's to see.
+pub struct Exec;
+
+impl Backend for Exec {
+    fn stage(&self, git: &Git, include_untracked: bool, cwd_only: bool) -> Result<()> {
+        let args = staging_args(include_untracked, cwd_only);
+        let status = Command::new("git")
+            .current_dir(&git.dir)
+            .args(&args)
+            .status()
+            .map_err(|e| {
+                GitCmtError::Git(format!("failed to run `git {}`: {e}", args.join(" ")))
+            })?;
+        if !status.success() {
+            return Err(GitCmtError::Git(format!(
+                "git add failed with status: {status}"
+            )));
+        }
+        Ok(())
+    }
+
+    fn diff_cached(&self, git: &Git, base: Option<&str>) -> Result<Vec<u8>> {
+        git.output_bytes(&git.diff_args(base))
+    }
+
+    fn commit(&self, git: &Git, options: &CommitOptions) -> Result<()> {
+        let status = Command::new("git")
+            .current_dir(&git.dir)
+            .args(options.args())
+            .status()
+            .map_err(|e| GitCmtError::Git(format!("failed to run `git commit`: {e}")))?;
+        if !status.success() {
+            return Err(GitCmtError::Git(format!(
+                "git commit failed with status: {status}"
+            )));
+        }
+        Ok(())
+    }
+
+    fn current_branch(&self, git: &Git) -> Result<String> {
+        let output = git
+            .command()
+            .args(["symbolic-ref", "--short", "HEAD"])
+            .output()
+            .map_err(|e| GitCmtError::Git(format!("failed to run `git symbolic-ref`: {e}")))?;
+        if !output.status.success() {
+            return Err(GitCmtError::Git(
+                "could not determine current branch (detached HEAD?)".into(),
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    // `symbolic-ref -q` exits 1 for exactly that; other failures are not it.
+    fn is_detached(&self, git: &Git) -> bool {
+        git.command()
+            .args(["symbolic-ref", "-q", "HEAD"])
+            .stdout(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.code() == Some(1))
+    }
+
+    fn head_commit(&self, git: &Git) -> Result<(String, String)> {
+        let output = git
+            .command()
+            .args(["log", "-1", "--format=%h%n%s"])
+            .output()
+            .map_err(|e| GitCmtError::Git(format!("failed to run `git log`: {e}")))?;
+        if !output.status.success() {
+            return Err(GitCmtError::Git("no commits to undo".into()));
+        }
+        let text = String::from_utf8_lossy(&output.stdout);
+        let mut lines = text.lines();
+        let sha = lines.next().unwrap_or_default().to_string();
+        let subject = lines.next().unwrap_or_default().to_string();
+        Ok((sha, subject))
+    }
+
+    fn remotes(&self, git: &Git) -> Result<Vec<String>> {
+        Ok(git
+            .output(&["remote"])?
+            .lines()
+            .map(str::to_string)
+            .collect())
+    }
+
+    fn branch_remote(&self, git: &Git, branch: &str) -> Option<String> {
+        git.output(&["config", "--get", &format!("branch.{branch}.remote")])
+            .ok()
+            .map(|remote| remote.trim().to_string())
+            .filter(|remote| !remote.is_empty())
+    }
+
+    fn remote_head(&self, git: &Git, remote: &str) -> Option<String> {
+        let head = format!("refs/remotes/{remote}/HEAD");
+        git.output(&["symbolic-ref", "--quiet", "--short", &head])
+            .ok()
+            .map(|branch| branch.trim().to_string())
+            .filter(|branch| !branch.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::ScratchRepo;
+    use std::fs;
+
+    fn blob(len: usize) -> Vec<u8> {
+        // NUL bytes make git treat the file as binary.
+        (0..len).map(|i| (i % 7) as u8).collect()
+    }
+
+    #[test]
+    fn unstaged_changes_are_looked_up_from_the_root() {
+        let repo = ScratchRepo::new("unstaged");
+        fs::create_dir_all(repo.dir.join("src")).unwrap();
+        repo.write("src/a*.rs", b"a\n");
+        repo.write("src/ab.rs", b"b\n");
+        repo.git(&["add", "."]);
+        assert!(repo.git.unstaged_changes(&[]).unwrap().is_empty());
+
+        repo.write("src/ab.rs", b"b\nreformatted\n");
+        let sub = Git::new(repo.dir.join("src"));
+        let paths = ["src/a*.rs".to_string(), "src/ab.rs".to_string()];
+        assert_eq!(sub.unstaged_changes(&paths).unwrap(), ["src/ab.rs"]);
+        // `literal`: the `*` matches only itself.
+        assert!(sub.unstaged_changes(&paths[..1]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn worktree_numstat_counts_staged_and_unstaged_lines() {
+        let repo = ScratchRepo::new("numstat");
+        repo.write("notes.txt", b"a\nb\n");
+        repo.write("logo.png", &blob(100));
+        repo.git(&["add", "."]);
+        repo.git(&["commit", "-q", "-m", "init"]);
+
+        repo.write("notes.txt", b"a\nc\nd\n");
+        repo.write("logo.png", &blob(200));
+        repo.git(&["add", "logo.png"]);
+        let mut stats = repo.git.worktree_numstat().unwrap();
+        stats.sort();
+        assert_eq!(
+            stats,
+            [
+                ("logo.png".to_string(), None),
+                ("notes.txt".to_string(), Some((2, 1)))
+            ]
+        );
+    }
+
+    #[test]
+    fn a_merge_in_progress_has_a_merge_head() {
+        let repo = ScratchRepo::new("merge");
+        repo.write("a.txt", b"a\n");
+        repo.git(&["add", "."]);
+        repo.git(&["commit", "-q", "-m", "init"]);
+        repo.git(&["checkout", "-q", "-b", "feature"]);
+        repo.write("b.txt", b"b\n");
+        repo.git(&["add", "."]);
+        repo.git(&["commit", "-q", "-m", "add b"]);
+        repo.git(&["checkout", "-q", "-"]);
+        assert_eq!(repo.git.merge_head(), None);
+
+        repo.git(&["merge", "-q", "--no-ff", "--no-commit", "feature"]);
+        assert!(repo.git.merge_head().is_some());
+        let log = repo.git.log_oneline("HEAD..MERGE_HEAD", 20).unwrap();
+        assert_eq!(log.len(), 1);
+        assert!(log[0].ends_with(" add b"));
+        assert!(
+            repo.git
+                .log_oneline("MERGE_HEAD..HEAD", 20)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn operations_in_progress_are_found_by_their_markers() {
+        let repo = ScratchRepo::new("in-progress");
+        assert_eq!(repo.git.operation_in_progress().unwrap(), None);
+        let git_dir = repo.dir.join(".git");
+        for (marker, expected) in [
+            ("CHERRY_PICK_HEAD", Operation::CherryPick),
+            ("REVERT_HEAD", Operation::Revert),
+        ] {
+            fs::write(
+                git_dir.join(marker),
+                b"0000000000000000000000000000000000000000\n",
+            )
+            .unwrap();
+            assert_eq!(repo.git.operation_in_progress().unwrap(), Some(expected));
+            fs::remove_file(git_dir.join(marker)).unwrap();
+        }
+
+        fs::create_dir(git_dir.join("rebase-apply")).unwrap();
+        assert_eq!(
+            repo.git.operation_in_progress().unwrap(),
+            Some(Operation::Rebase)
+        );
+        fs::write(git_dir.join("rebase-apply/applying"), b"").unwrap();
+        assert_eq!(
+            repo.git.operation_in_progress().unwrap(),
+            Some(Operation::Am)
+        );
+        fs::remove_dir_all(git_dir.join("rebase-apply")).unwrap();
+
+        // git answers relative to the directory it runs in.
+        fs::create_dir(git_dir.join("rebase-merge")).unwrap();
+        fs::create_dir(repo.dir.join("src")).unwrap();
+        let sub = Git::new(repo.dir.join("src"));
+        assert_eq!(
+            sub.operation_in_progress().unwrap(),
+            Some(Operation::Rebase)
+        );
+        assert_eq!(Operation::Rebase.to_string(), "a rebase");
+    }
+
+    #[test]
+    fn ahead_and_behind_count_both_sides() {
+        let repo = ScratchRepo::new("ahead-behind");
+        repo.write("a.txt", b"a\n");
+        repo.git(&["add", "."]);
+        repo.git(&["commit", "-q", "-m", "init"]);
+        repo.git(&["branch", "other"]);
+        assert_eq!(repo.git.ahead_behind("other").unwrap(), (0, 0));
+
+        repo.git(&["commit", "-q", "--allow-empty", "-m", "mine"]);
+        repo.git(&["checkout", "-q", "other"]);
+        repo.git(&["commit", "-q", "--allow-empty", "-m", "theirs 1"]);
+        repo.git(&["commit", "-q", "--allow-empty", "-m", "theirs 2"]);
+        repo.git(&["checkout", "-q", "-"]);
+        assert_eq!(repo.git.ahead_behind("other").unwrap(), (1, 2));
+        assert!(repo.git.ahead_behind("missing").is_err());
+    }
+
+    #[test]
+    fn binary_changes_report_sizes_before_and_after() {
+        let repo = ScratchRepo::new("binary");
+        repo.write("logo.png", &blob(12 * 1024));
+        repo.write("notes.txt", b"hello\n");
+        repo.git(&["add", "."]);
+        repo.git(&["commit", "-q", "-m", "init"]);
+
+        repo.write("logo.png", &blob(15 * 1024));
+        repo.write("icon.bin", &blob(300));
+        repo.write("notes.txt", b"hello\nworld\n");
+        repo.git(&["add", "."]);
+
+        let mut changes = repo.git.binary_changes(None).unwrap();
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(
+            changes,
+            [
+                BinaryChange {
+                    path: "icon.bin".into(),
+                    old_size: None,
+                    new_size: Some(300),
+                },
+                BinaryChange {
+                    path: "logo.png".into(),
+                    old_size: Some(12 * 1024),
+                    new_size: Some(15 * 1024),
+                },
+            ]
+        );
+
+        assert_eq!(
+            repo.git.hash_object("").unwrap(),
+            "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"
+        );
+
+        let stat = repo.git.diff_stat(None).unwrap();
+        assert!(stat.contains("logo.png"), "stat: {stat}");
+        assert!(stat.trim_end().ends_with("3 files changed, 1 insertion(+)"));
+
+        let (diff, _) = crate::diff::decode_lossy(repo.git.diff_cached(None).unwrap());
+        let prompt = crate::diff::prompt_diff(
+            diff,
+            &crate::diff::Budget::chars(3072),
+            &[],
+            &Default::default(),
+            &Default::default(),
+            &changes,
+        );
+        assert!(prompt.contains("binary changed: logo.png (12 KB → 15 KB)\n"));
+        assert!(prompt.contains("binary added: icon.bin (300 B)\n"));
+        assert!(prompt.contains("+world\n"));
+        assert!(!prompt.contains("Binary files"));
+    }
+
+    #[test]
+    fn latin1_files_still_produce_a_diff() {
+        let repo = ScratchRepo::new("latin1");
+        repo.write("menu.txt", b"cafe\n");
+        repo.git(&["add", "."]);
+        repo.git(&["commit", "-q", "-m", "init"]);
+        // "café" and "crème" in Latin-1: 0xE9 and 0xE8 are invalid UTF-8.
+        repo.write("menu.txt", b"caf\xe9\ncr\xe8me\n");
+        repo.git(&["add", "."]);
+
+        let (diff, lossy) = crate::diff::decode_lossy(repo.git.diff_cached(None).unwrap());
+        assert_eq!(lossy, 2);
+        assert!(
+            diff.contains("+caf\u{FFFD}\n+cr\u{FFFD}me\n"),
+            "diff: {diff}"
+        );
+        assert!(diff.contains("-cafe\n"));
+    }
+
+    #[test]
+    fn repositories_are_recognised() {
+        let repo = ScratchRepo::new("preflight");
+        assert!(repo.git.is_repository());
+        assert!(repo.git.version().unwrap().starts_with("git version "));
+
+        let plain = std::env::temp_dir().join(format!("git-cmt-rs-plain-{}", std::process::id()));
+        fs::create_dir_all(&plain).unwrap();
+        assert!(!Git::new(&plain).is_repository());
+        let _ = fs::remove_dir_all(&plain);
+    }
+
+    #[test]
+    fn detached_head_is_detected() {
+        let repo = ScratchRepo::new("detached");
+        repo.write("a.txt", b"a\n");
+        repo.git(&["add", "."]);
+        repo.git(&["commit", "-q", "-m", "init"]);
+        assert!(!repo.git.is_detached());
+        repo.git(&["checkout", "-q", "--detach"]);
+        assert!(repo.git.is_detached());
+    }
+
+    #[test]
+    fn upstream_is_none_until_tracking_is_set() {
+        let repo = ScratchRepo::new("upstream");
+        assert!(repo.git.remotes().unwrap().is_empty());
+        repo.git(&["commit", "-q", "--allow-empty", "-m", "init"]);
+        repo.git(&["checkout", "-q", "-b", "feature-x"]);
+        let remote = repo.dir.join(".git");
+        repo.git(&["remote", "add", "origin", remote.to_str().unwrap()]);
+        repo.git(&["fetch", "-q", "origin"]);
+        assert_eq!(repo.git.remotes().unwrap(), ["origin"]);
+        assert_eq!(repo.git.upstream(), None);
+
+        assert_eq!(repo.git.branch_remote("feature-x"), None);
+
+        repo.git(&["branch", "-q", "--set-upstream-to", "origin/feature-x"]);
+        assert_eq!(repo.git.upstream().as_deref(), Some("origin/feature-x"));
+        assert_eq!(
+            repo.git.branch_remote("feature-x").as_deref(),
+            Some("origin")
+        );
+    }
+
+    #[test]
+    fn recent_subjects_are_newest_first_and_capped() {
+        let repo = ScratchRepo::new("recent");
+        assert!(repo.git.recent_subjects(3).unwrap().is_empty());
+        for subject in ["feat: one", "fix: two", "docs: three"] {
+            repo.git(&["commit", "-q", "--allow-empty", "-m", subject]);
+        }
+        assert_eq!(
+            repo.git.recent_subjects(2).unwrap(),
+            ["docs: three", "fix: two"]
+        );
+    }
+
+    #[test]
+    fn failing_queries_are_git_errors() {
+        let repo = ScratchRepo::new("no-head");
+        let err = repo.git.subjects_since(None).unwrap_err();
+        assert!(matches!(err, GitCmtError::Git(_)), "err: {err}");
+        assert_eq!(
+            err.to_string(),
+            "git log failed with status: exit status: 128"
+        );
+    }
+
+    #[test]
+    fn paths_are_root_relative_from_a_subdirectory() {
+        let repo = ScratchRepo::new("subdir");
+        fs::create_dir_all(repo.dir.join("crates/foo")).unwrap();
+        repo.write("crates/foo/lib.rs", b"\n");
+        repo.write("top.rs", b"\n");
+        repo.git(&["add", "crates/foo/lib.rs"]);
+        let sub = Git::new(repo.dir.join("crates/foo"));
+
+        assert_eq!(sub.prefix().unwrap(), "crates/foo/");
+        assert_eq!(
+            sub.hooks_dir().unwrap().canonicalize().unwrap(),
+            repo.dir.join(".git/hooks").canonicalize().unwrap()
+        );
+        assert_eq!(repo.git.prefix().unwrap(), "");
+        assert_eq!(
+            sub.name_status(None).unwrap(),
+            [("A".to_string(), "crates/foo/lib.rs".to_string())]
+        );
+        assert_eq!(sub.untracked_files().unwrap(), ["top.rs"]);
+    }
+
+    #[test]
+    fn untracked_files_skip_ignored_and_staged_paths() {
+        let repo = ScratchRepo::new("untracked");
+        repo.write(".gitignore", b"target/\n*.log\n");
+        repo.write("staged.rs", b"fn main() {}\n");
+        repo.git(&["add", ".gitignore", "staged.rs"]);
+        repo.write("new.rs", b"\n");
+        repo.write("debug.log", b"\n");
+        fs::create_dir_all(repo.dir.join("target")).unwrap();
+        repo.write("target/out", b"\n");
+        fs::create_dir_all(repo.dir.join("docs/api")).unwrap();
+        repo.write("docs/api/notes with spaces.md", b"\n");
+
+        let mut untracked = repo.git.untracked_files().unwrap();
+        untracked.sort();
+        assert_eq!(untracked, ["docs/api/notes with spaces.md", "new.rs"]);
+
+        let status = repo.git.status(false).unwrap();
+        assert!(status.contains("staged.rs") && !status.contains("new.rs"));
+        repo.write("staged.rs", b"fn main() { run() }\n");
+        repo.git(&["add", "staged.rs"]);
+        assert_ne!(repo.git.status(false).unwrap(), status);
+        assert!(repo.git.status(true).unwrap().contains("? new.rs"));
+    }
+
+    #[test]
+    fn queries_run_untranslated() {
+        let cmd = Git::new(".").command();
+        let env: Vec<_> = cmd.get_envs().collect();
+        assert_eq!(
+            env,
+            [(
+                std::ffi::OsStr::new("LC_ALL"),
+                Some(std::ffi::OsStr::new("C"))
+            )]
+        );
+    }
+
+    #[test]
+    fn diff_algorithm_is_passed_to_every_diff_query() {
+        let git = Git::new(".");
+        assert!(
+            !git.diff_cached_args(&["-b"], None)
+                .iter()
+                .any(|arg| arg.starts_with("--diff-algorithm"))
+        );
+        let git = git.with_diff_algorithm(Some(DiffAlgorithm::Histogram));
+        assert_eq!(
+            git.diff_cached_args(&["-b"], Some("HEAD~1")),
+            [
+                "diff",
+                "--cached",
+                "-b",
+                "-M50%",
+                "-C50%",
+                "--diff-algorithm=histogram",
+                "HEAD~1"
+            ]
+        );
+    }
+
+    #[test]
+    fn context_lines_set_the_unified_context_of_the_content_diff() {
+        let git = Git::new(".");
+        assert!(git.diff_args(None).contains(&"-U3".to_string()));
+        let git = git.with_context_lines(0);
+        assert_eq!(
+            git.diff_args(None),
+            ["diff", "--cached", "-b", "-U0", "-M50%", "-C50%"]
+        );
+        // The stat doesn't depend on context.
+        assert!(
+            !git.diff_cached_args(&["--stat", "-b"], None)
+                .iter()
+                .any(|arg| arg.starts_with("-U"))
+        );
+    }
+
+    #[test]
+    fn zero_context_hunks_are_budgeted_like_any_other() {
+        let repo = ScratchRepo::new("context");
+        let body: String = (0..40).map(|i| format!("line {i}\n")).collect();
+        repo.write("a.txt", body.as_bytes());
+        repo.git(&["add", "."]);
+        repo.git(&["commit", "-q", "-m", "init"]);
+        repo.write("a.txt", body.replace("line 5\n", "five\n").as_bytes());
+        repo.git(&["add", "."]);
+
+        let git = Git::new(&repo.dir).with_context_lines(0);
+        let (diff, _) = crate::diff::decode_lossy(git.diff_cached(None).unwrap());
+        assert!(diff.contains("@@ -6 +6 @@"), "diff: {diff}");
+        assert!(diff.ends_with("\n-line 5\n+five\n"), "diff: {diff}");
+        let prompt = crate::diff::prompt_diff(
+            diff.clone(),
+            &crate::diff::Budget::chars(3072),
+            &[],
+            &Default::default(),
+            &Default::default(),
+            &[],
+        );
+        assert_eq!(prompt, diff);
+    }
+
+    #[test]
+    fn submodule_bumps_carry_the_submodules_log() {
+        let inner = ScratchRepo::new("sub-inner");
+        inner.write("lib.rs", b"v1\n");
+        inner.git(&["add", "."]);
+        inner.git(&["commit", "-q", "-m", "first"]);
+
+        let repo = ScratchRepo::new("sub-outer");
+        let url = inner.git.dir.to_str().unwrap();
+        let allow = ["-c", "protocol.file.allow=always"];
+        repo.git(&[&allow[..], &["submodule", "add", "-q", url, "vendor/lib"]].concat());
+        repo.git(&["commit", "-q", "-m", "add lib"]);
+
+        for message in ["second", "third"] {
+            inner.write("lib.rs", message.as_bytes());
+            inner.git(&["commit", "-q", "-am", message]);
+        }
+        repo.git(&[&allow[..], &["-C", "vendor/lib", "pull", "-q"]].concat());
+        repo.git(&["add", "vendor/lib"]);
+
+        let changes = repo.git.submodule_changes(None).unwrap();
+        let [change] = &changes[..] else {
+            panic!("changes: {changes:?}");
+        };
+        assert_eq!(change.path, "vendor/lib");
+        assert!(change.is_bump());
+        assert_eq!(change.new.as_ref().unwrap().len(), 40);
+        let log = change.log.as_ref().unwrap();
+        assert_eq!(log.len(), 2);
+        assert!(log[0].ends_with(" third") && log[1].ends_with(" second"));
+
+        // Without a checkout there is no log, but the bump is still known.
+        repo.git(&["submodule", "deinit", "-q", "-f", "vendor/lib"]);
+        let changes = repo.git.submodule_changes(None).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0].is_bump());
+        assert_eq!(changes[0].log, None);
+        assert!(repo.git.binary_changes(None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn moved_files_become_renames_and_a_lone_move_needs_no_model() {
+        let repo = ScratchRepo::new("rename");
+        let body: String = (0..20).map(|i| format!("line {i}\n")).collect();
+        fs::create_dir_all(repo.dir.join("src")).unwrap();
+        repo.write("src/old.rs", body.as_bytes());
+        repo.write("notes.txt", body.as_bytes());
+        repo.git(&["add", "."]);
+        repo.git(&["commit", "-q", "-m", "init"]);
+
+        fs::create_dir_all(repo.dir.join("lib")).unwrap();
+        repo.git(&["mv", "src/old.rs", "lib/new.rs"]);
+        let (diff, _) = crate::diff::decode_lossy(repo.git.diff_cached(None).unwrap());
+        let prompt = crate::diff::prompt_diff(
+            diff.clone(),
+            &crate::diff::Budget::chars(3072),
+            &[],
+            &Default::default(),
+            &Default::default(),
+            &[],
+        );
+        assert_eq!(
+            prompt,
+            "Moved files (contents unchanged):\nrenamed: src/old.rs → lib/new.rs\n"
+        );
+        let files =
+            crate::diff::file_changes(&diff, &[], &Default::default(), &Default::default(), &[]);
+        let commit = crate::move_commit(&files).unwrap();
+        assert_eq!(
+            crate::build_commit_line(&commit, crate::cli::FormatPreset::Conventional),
+            "refactor: move src/old.rs to lib/new.rs"
+        );
+        let stat = repo.git.diff_stat(None).unwrap();
+        assert!(stat.contains("src/old.rs => lib/new.rs"), "stat: {stat}");
+        repo.write("notes.txt", b"changed\n");
+        repo.write("added.txt", b"new\n");
+        repo.git(&["add", "notes.txt", "added.txt"]);
+        let mut entries = repo.git.name_status(None).unwrap();
+        entries.sort();
+        let expected = [
+            ("A", "added.txt"),
+            ("M", "notes.txt"),
+            ("R", "src/old.rs → lib/new.rs"),
+        ];
+        assert_eq!(
+            entries,
+            expected.map(|(status, path)| (status.to_string(), path.to_string()))
+        );
+        repo.git(&["reset", "-q", "notes.txt", "added.txt"]);
+
+        // Without detection the move is a full deletion plus addition.
+        let off = Git::new(&repo.dir).with_similarity(0, 0);
+        let (diff, _) = crate::diff::decode_lossy(off.diff_cached(None).unwrap());
+        assert!(diff.contains("deleted file mode"), "diff: {diff}");
+        assert!(
+            crate::move_commit(&crate::diff::file_changes(
+                &diff,
+                &[],
+                &Default::default(),
+                &Default::default(),
+                &[]
+            ))
+            .is_none()
+        );
+
+        // An edited move stays a rename above the threshold, with its diff.
+        repo.write("lib/new.rs", format!("{body}line 20\n").as_bytes());
+        repo.git(&["add", "."]);
+        let (diff, _) = crate::diff::decode_lossy(repo.git.diff_cached(None).unwrap());
+        assert!(diff.contains("rename from src/old.rs\n"), "diff: {diff}");
+        assert!(diff.contains("+line 20\n"));
+        let files =
+            crate::diff::file_changes(&diff, &[], &Default::default(), &Default::default(), &[]);
+        assert!(crate::move_commit(&files).is_none());
+        let strict = Git::new(&repo.dir).with_similarity(99, 99);
+        let (diff, _) = crate::diff::decode_lossy(strict.diff_cached(None).unwrap());
+        assert!(!diff.contains("rename from"), "diff: {diff}");
+    }
+
+    #[test]
+    fn the_template_and_comment_prefix_follow_the_config() {
+        let repo = ScratchRepo::new("template");
+        assert_eq!(repo.git.comment_prefix().as_deref(), Some("#"));
+        repo.git(&["config", "commit.template", ".gitmessage"]);
+        assert_eq!(
+            repo.git.commit_template(),
+            Some(repo.dir.join(".gitmessage"))
+        );
+        repo.git(&["config", "core.commentChar", ";"]);
+        assert_eq!(repo.git.comment_prefix().as_deref(), Some(";"));
+        repo.git(&["config", "core.commentChar", "auto"]);
+        assert_eq!(repo.git.comment_prefix(), None);
+        repo.git(&["config", "--unset", "core.commentChar"]);
+        repo.git(&["config", "commit.cleanup", "verbatim"]);
+        assert_eq!(repo.git.comment_prefix(), None);
+    }
+
+    #[test]
+    fn head_shortstat_reports_the_final_subject() {
+        let repo = ScratchRepo::new("shortstat");
+        repo.write("a.txt", b"a\nb\n");
+        repo.git(&["add", "."]);
+        repo.git(&["commit", "-q", "-m", "feat: add a"]);
+        let (sha, subject, stat) = repo.git.head_shortstat().unwrap();
+        assert_eq!(sha.len(), 7);
+        assert_eq!(subject, "feat: add a");
+        assert_eq!(stat, "1 file changed, 2 insertions(+)");
+
+        repo.git(&["commit", "-q", "--allow-empty", "-m", "chore: nothing"]);
+        let (_, subject, stat) = repo.git.head_shortstat().unwrap();
+        assert_eq!((subject.as_str(), stat.as_str()), ("chore: nothing", ""));
+    }
+
+    #[test]
+    fn a_branch_is_counted_from_its_merge_base() {
+        let repo = ScratchRepo::new("squash");
+        repo.git(&["commit", "-q", "--allow-empty", "-m", "init"]);
+        repo.git(&["branch", "-q", "trunk"]);
+        repo.write("a.txt", b"a\n");
+        repo.git(&["add", "."]);
+        repo.git(&["commit", "-q", "-m", "feat: add a\n\nWith a body."]);
+        repo.git(&["commit", "-q", "--allow-empty", "-m", "fix: typo"]);
+        assert!(!repo.git.has_staged_changes().unwrap());
+        repo.write("b.txt", b"b\n");
+        repo.git(&["add", "."]);
+        assert!(repo.git.has_staged_changes().unwrap());
+
+        let base = repo.git.merge_base("trunk").unwrap();
+        assert_eq!(base.len(), 40);
+        let range = format!("{base}..HEAD");
+        assert_eq!(
+            repo.git.messages(&range).unwrap(),
+            ["feat: add a\n\nWith a body.", "fix: typo"]
+        );
+        assert_eq!(repo.git.count_commits(&range, &[]).unwrap(), 2);
+        assert_eq!(repo.git.count_commits(&range, &["--merges"]).unwrap(), 0);
+    }
+
+    #[test]
+    fn a_remote_head_names_the_default_branch() {
+        let repo = ScratchRepo::new("remote-head");
+        repo.git(&["commit", "-q", "--allow-empty", "-m", "init"]);
+        repo.git(&["update-ref", "refs/remotes/upstream/develop", "HEAD"]);
+        assert_eq!(repo.git.remote_head("upstream"), None);
+        repo.git(&[
+            "symbolic-ref",
+            "refs/remotes/upstream/HEAD",
+            "refs/remotes/upstream/develop",
+        ]);
+        assert_eq!(
+            repo.git.remote_head("upstream").as_deref(),
+            Some("upstream/develop")
+        );
+        assert_eq!(repo.git.remote_head("origin"), None);
+    }
+
+    #[test]
+    fn subjects_since_the_last_tag() {
+        let repo = ScratchRepo::new("tags");
+        assert_eq!(repo.git.last_tag(), None);
+        for subject in ["feat: first", "fix: second", "feat(api): third"] {
+            repo.git(&["commit", "-q", "--allow-empty", "-m", subject]);
+            if subject == "fix: second" {
+                repo.git(&["tag", "v1.0.0"]);
+            }
+        }
+        assert_eq!(repo.git.last_tag().as_deref(), Some("v1.0.0"));
+        assert_eq!(
+            repo.git.subjects_since(Some("v1.0.0")).unwrap(),
+            ["feat(api): third"]
+        );
+        assert_eq!(repo.git.subjects_since(None).unwrap().len(), 3);
+
+        assert!(repo.git.tag_exists("v1.0.0"));
+        assert!(!repo.git.tag_exists("v2.0.0"));
+        assert!(repo.git.tags_at_head().unwrap().is_empty());
+        repo.git(&["tag", "v1.1.0"]);
+        assert_eq!(repo.git.tags_at_head().unwrap(), ["v1.1.0"]);
+        assert!(repo.git.is_valid_tag_name("v1.2.0-rc.1"));
+        assert!(!repo.git.is_valid_tag_name("v1 .2"));
+        assert!(!repo.git.is_valid_tag_name("v1..2"));
+    }
+
+    #[test]
+    fn staging_includes_untracked_files_unless_disabled() {
+        assert_eq!(staging_args(true, false), ["add", ":/"]);
+        assert_eq!(staging_args(false, false), ["add", "-u", ":/"]);
+        assert_eq!(staging_args(true, true), ["add", "."]);
+        assert_eq!(staging_args(false, true), ["add", "-u", "."]);
+    }
+
+    #[test]
+    fn commit_args_for_review_and_for_watch_mode() {
+        let file = Path::new("/tmp/msg.txt");
+        assert_eq!(
+            CommitOptions {
+                message_file: file,
+                co_authors: &[],
+                amend: false,
+                edit: true,
+                no_verify: false,
+            }
+            .args(),
+            ["commit", "-F", "/tmp/msg.txt", "-e"]
+        );
+        assert_eq!(
+            CommitOptions {
+                message_file: file,
+                co_authors: &[],
+                amend: true,
+                edit: false,
+                no_verify: true,
+            }
+            .args(),
+            ["commit", "--amend", "--no-verify", "-F", "/tmp/msg.txt"]
+        );
+        let co_authors = ["Ada <ada@example.com>".to_string()];
+        assert_eq!(
+            CommitOptions {
+                message_file: file,
+                co_authors: &co_authors,
+                amend: false,
+                edit: false,
+                no_verify: false,
+            }
+            .args(),
+            [
+                "commit",
+                "-F",
+                "/tmp/msg.txt",
+                "--trailer",
+                "Co-authored-by: Ada <ada@example.com>"
+            ]
+        );
+    }
+}
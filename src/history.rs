@@ -0,0 +1,210 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cli::FormatPreset;
+use crate::{Commit, build_commit_line};
+
+// ---------- Commit history ----------
+// Every commit the tool creates is appended to a local JSON Lines file, so
+// what the model suggested can later be compared with what was committed.
+// `git-cmt-rs log` reads it back.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistoryRecord {
+    // RFC 3339, UTC.
+    pub timestamp: String,
+    pub repo: String,
+    // `git hash-object` of the prompt's diff text.
+    pub diff_hash: String,
+    // What the model generated, before editor review.
+    pub commit: Commit,
+    // The `--format` the suggestion is shown in; older records predate it.
+    #[serde(default)]
+    pub format: FormatPreset,
+    // Short SHA and subject of the commit actually created.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub committed: Option<String>,
+}
+
+impl fmt::Display for HistoryRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}  {}  {}",
+            self.timestamp,
+            self.sha.as_deref().unwrap_or("-------"),
+            self.repo
+        )?;
+        let suggested = build_commit_line(&self.commit, self.format);
+        writeln!(f, "  suggested: {suggested}")?;
+        match &self.committed {
+            Some(committed) if *committed != suggested => {
+                writeln!(f, "  committed: {committed}")
+            }
+            Some(_) => writeln!(f, "  committed: (unchanged)"),
+            None => Ok(()),
+        }
+    }
+}
+
+// `$XDG_DATA_HOME/git-cmt-rs/history.jsonl`, or `~/.local/share/...`.
+pub fn history_file(var: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    let data_home = var("XDG_DATA_HOME")
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| var("HOME").map(|home| Path::new(&home).join(".local").join("share")))?;
+    Some(data_home.join("git-cmt-rs").join("history.jsonl"))
+}
+
+pub fn append(path: &Path, record: &HistoryRecord) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    let line = serde_json::to_string(record).context("failed to serialize history record")?;
+    writeln!(file, "{line}").with_context(|| format!("failed to write {}", path.display()))
+}
+
+// The last `n` records, oldest first. A missing file is an empty history;
+// lines that don't parse (e.g. a partial write) are skipped.
+pub fn read_last(path: &Path, n: usize) -> Result<Vec<HistoryRecord>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+    };
+    let records: Vec<HistoryRecord> = text
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    let skip = records.len().saturating_sub(n);
+    Ok(records.into_iter().skip(skip).collect())
+}
+
+pub fn now() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    rfc3339(secs)
+}
+
+// Unix seconds to `YYYY-MM-DDTHH:MM:SSZ` (Howard Hinnant's civil-from-days).
+fn rfc3339(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(message: &str, committed: Option<&str>) -> HistoryRecord {
+        HistoryRecord {
+            timestamp: "2026-10-16T09:30:00Z".into(),
+            repo: "/work/app".into(),
+            diff_hash: "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391".into(),
+            commit: Commit {
+                r#type: "feat".into(),
+                scope: "api".into(),
+                message: message.into(),
+                ..Default::default()
+            },
+            format: FormatPreset::Conventional,
+            sha: Some("abc1234".into()),
+            committed: committed.map(String::from),
+        }
+    }
+
+    #[test]
+    fn timestamps_are_rfc3339_utc() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(rfc3339(1_792_143_045), "2026-10-16T09:30:45Z");
+    }
+
+    #[test]
+    fn history_lives_under_xdg_data_home() {
+        let vars = |pairs: &'static [(&str, &str)]| {
+            move |name: &str| {
+                pairs
+                    .iter()
+                    .find(|(k, _)| *k == name)
+                    .map(|(_, v)| v.to_string())
+            }
+        };
+        assert_eq!(
+            history_file(vars(&[("HOME", "/home/me")])),
+            Some(PathBuf::from(
+                "/home/me/.local/share/git-cmt-rs/history.jsonl"
+            ))
+        );
+        assert_eq!(
+            history_file(vars(&[("HOME", "/home/me"), ("XDG_DATA_HOME", "/data")])),
+            Some(PathBuf::from("/data/git-cmt-rs/history.jsonl"))
+        );
+        assert_eq!(history_file(vars(&[])), None);
+    }
+
+    #[test]
+    fn append_then_read_last_round_trips() {
+        let path = std::env::temp_dir()
+            .join(format!("git-cmt-rs-history-{}", std::process::id()))
+            .join("history.jsonl");
+        let _ = fs::remove_file(&path);
+        assert!(read_last(&path, 10).unwrap().is_empty());
+
+        for n in 0..3 {
+            append(&path, &record(&format!("change {n}"), None)).unwrap();
+        }
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"truncated\n")
+            .unwrap();
+        let last = read_last(&path, 2).unwrap();
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        let messages: Vec<&str> = last.iter().map(|r| r.commit.message.as_str()).collect();
+        assert_eq!(messages, ["change 1", "change 2"]);
+    }
+
+    #[test]
+    fn display_shows_suggested_and_edited_messages() {
+        let shown = record("add login", Some("feat(api): add OAuth login")).to_string();
+        assert_eq!(
+            shown,
+            "2026-10-16T09:30:00Z  abc1234  /work/app\n\
+             \x20 suggested: feat(api): add login\n\
+             \x20 committed: feat(api): add OAuth login\n"
+        );
+        let unchanged = record("add login", Some("feat(api): add login")).to_string();
+        assert!(unchanged.ends_with("  committed: (unchanged)\n"));
+    }
+}
@@ -0,0 +1,232 @@
+use anyhow::{Context, Result, anyhow};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// ---------- prepare-commit-msg hook ----------
+// `git-cmt-rs hook install` (or `init`) puts the generated message into the
+// editor of a plain `git commit`. The script is marked so `install` never
+// overwrites, and `uninstall` never removes, a hook someone else wrote.
+pub const HOOK_NAME: &str = "prepare-commit-msg";
+
+// Also matches scripts written by older versions, which said `init`.
+const MARKER: &str = "# Installed by `git-cmt-rs ";
+
+// All the deciding happens in `git-cmt-rs --hook`, so upgrading the binary
+// upgrades the hook. Failures never stop the commit.
+const HOOK_SCRIPT: &str = r#"#!/bin/sh
+# Installed by `git-cmt-rs hook install`; remove with `git-cmt-rs hook uninstall`.
+# Puts a generated commit message above the one git opens in the editor.
+git-cmt-rs --hook "$1" "$2" "$3" </dev/null 2>/dev/null || true
+"#;
+
+// The message sources git passes as `$2`
+// (https://git-scm.com/docs/githooks#_prepare_commit_msg); empty for a plain
+// `git commit`.
+pub const SOURCES: &[&str] = &["message", "template", "merge", "squash", "commit"];
+
+// Merges and squashes come with a message of their own, as does `commit`
+// (`--amend`, `-c`, `-C`). `hook_skip_sources` in the config file changes
+// this list.
+pub const DEFAULT_SKIP_SOURCES: &[&str] = &["merge", "squash", "commit"];
+
+// `--hook <MSGFILE> [SOURCE] [SHA]`, as the script passes them on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookCall {
+    pub msg_file: PathBuf,
+    pub source: String,
+}
+
+impl HookCall {
+    pub fn from_args(args: &[String]) -> Option<Self> {
+        let (msg_file, rest) = args.split_first()?;
+        Some(HookCall {
+            msg_file: PathBuf::from(msg_file),
+            source: rest.first().cloned().unwrap_or_default(),
+        })
+    }
+
+    // `-m`/`-F` always: the message is already written, and git-cmt-rs's own
+    // commits would otherwise run it a second time.
+    pub fn skipped(&self, skip_sources: &[String]) -> bool {
+        self.source == "message" || skip_sources.contains(&self.source)
+    }
+
+    // The generated message goes above what git put in the file (its
+    // comment block, a template or an earlier message), kept as it was.
+    pub fn prepend(&self, message: &str) -> Result<()> {
+        let path = &self.msg_file;
+        let existing = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let separator = if existing.is_empty() || existing.starts_with('\n') {
+            "\n"
+        } else {
+            "\n\n"
+        };
+        fs::write(path, format!("{message}{separator}{existing}"))
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HookStatus {
+    Installed(PathBuf),
+    // Ours, but written by another version; `install` replaces it.
+    Outdated(PathBuf),
+    Foreign(PathBuf),
+    Missing(PathBuf),
+}
+
+pub fn status(hooks_dir: &Path) -> Result<HookStatus> {
+    let path = hooks_dir.join(HOOK_NAME);
+    Ok(match read_hook(&path)? {
+        None => HookStatus::Missing(path),
+        Some(existing) if !existing.contains(MARKER) => HookStatus::Foreign(path),
+        Some(existing) if existing != HOOK_SCRIPT => HookStatus::Outdated(path),
+        Some(_) => HookStatus::Installed(path),
+    })
+}
+
+// Write the hook into `hooks_dir` and make it executable.
+pub fn install(hooks_dir: &Path) -> Result<PathBuf> {
+    let path = hooks_dir.join(HOOK_NAME);
+    if let Some(existing) = read_hook(&path)?
+        && !existing.contains(MARKER)
+    {
+        return Err(anyhow!(
+            "{} already exists and was not installed by git-cmt-rs; remove it or call \
+             `git-cmt-rs --hook \"$1\" \"$2\" \"$3\"` from it yourself",
+            path.display()
+        ));
+    }
+    fs::create_dir_all(hooks_dir)
+        .with_context(|| format!("failed to create {}", hooks_dir.display()))?;
+    fs::write(&path, HOOK_SCRIPT).with_context(|| format!("failed to write {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("failed to make {} executable", path.display()))?;
+    }
+    Ok(path)
+}
+
+// Remove the hook if it is ours; `None` when there was none to remove.
+pub fn uninstall(hooks_dir: &Path) -> Result<Option<PathBuf>> {
+    let path = hooks_dir.join(HOOK_NAME);
+    match read_hook(&path)? {
+        None => Ok(None),
+        Some(existing) if !existing.contains(MARKER) => Err(anyhow!(
+            "{} was not installed by git-cmt-rs; leaving it in place",
+            path.display()
+        )),
+        Some(_) => {
+            fs::remove_file(&path)
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+            Ok(Some(path))
+        }
+    }
+}
+
+fn read_hook(path: &Path) -> Result<Option<String>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(String::from_utf8_lossy(&bytes).into_owned())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("git-cmt-rs-hook-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn install_and_uninstall_only_touch_our_hook() {
+        let dir = scratch_dir("ours");
+        let path = dir.join(HOOK_NAME);
+        assert_eq!(status(&dir).unwrap(), HookStatus::Missing(path.clone()));
+        assert_eq!(install(&dir).unwrap(), path);
+        let script = fs::read_to_string(&path).unwrap();
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains(r#"git-cmt-rs --hook "$1" "$2" "$3""#));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o111, 0o111);
+        }
+        assert_eq!(status(&dir).unwrap(), HookStatus::Installed(path.clone()));
+
+        // A script from an older version is still ours, and gets replaced.
+        fs::write(&path, "#!/bin/sh\n# Installed by `git-cmt-rs init`; ...\n").unwrap();
+        assert_eq!(status(&dir).unwrap(), HookStatus::Outdated(path.clone()));
+        install(&dir).unwrap();
+        assert_eq!(status(&dir).unwrap(), HookStatus::Installed(path.clone()));
+
+        assert_eq!(uninstall(&dir).unwrap(), Some(path.clone()));
+        assert!(!path.exists());
+        assert_eq!(uninstall(&dir).unwrap(), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_foreign_hook_is_left_alone() {
+        let dir = scratch_dir("foreign");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(HOOK_NAME);
+        fs::write(&path, "#!/bin/sh\necho mine\n").unwrap();
+
+        assert_eq!(status(&dir).unwrap(), HookStatus::Foreign(path.clone()));
+        let err = install(&dir).unwrap_err();
+        assert!(
+            err.to_string().contains("was not installed by git-cmt-rs"),
+            "err: {err}"
+        );
+        assert!(uninstall(&dir).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "#!/bin/sh\necho mine\n");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn hook_calls_skip_sources_and_prepend_the_message() {
+        let skip: Vec<String> = DEFAULT_SKIP_SOURCES.iter().map(|s| s.to_string()).collect();
+        let call = |args: &[&str]| {
+            HookCall::from_args(&args.iter().map(|a| a.to_string()).collect::<Vec<_>>())
+        };
+        assert_eq!(call(&[]), None);
+        let plain = call(&["msg", "", ""]).unwrap();
+        assert!(!plain.skipped(&skip));
+        assert!(!call(&["msg", "template"]).unwrap().skipped(&skip));
+        assert!(call(&["msg", "commit", "HEAD"]).unwrap().skipped(&skip));
+        assert!(!call(&["msg", "commit", "HEAD"]).unwrap().skipped(&[]));
+        assert!(call(&["msg", "message"]).unwrap().skipped(&[]));
+
+        let dir = scratch_dir("prepend");
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("COMMIT_EDITMSG");
+        fs::write(&file, "\n# Please enter the commit message\n").unwrap();
+        let call = HookCall {
+            msg_file: file.clone(),
+            source: String::new(),
+        };
+        call.prepend("feat: add login\n\n- Check the password")
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&file).unwrap(),
+            "feat: add login\n\n- Check the password\n\n# Please enter the commit message\n"
+        );
+        fs::write(&file, "Checklist:\n- [ ] tests\n").unwrap();
+        call.prepend("fix: typo").unwrap();
+        assert_eq!(
+            fs::read_to_string(&file).unwrap(),
+            "fix: typo\n\nChecklist:\n- [ ] tests\n"
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
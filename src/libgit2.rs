@@ -0,0 +1,1040 @@
+use git2::{
+    AttrCheckFlags, AttrValue, Config, Diff, DiffFindOptions, DiffFormat, DiffOptions,
+    DiffStatsFormat, ErrorCode, Index, IndexAddOption, Oid, Repository, RepositoryState,
+    StatusOptions, Tree,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cli::DiffAlgorithm;
+use crate::error::GitCmtError;
+use crate::git::{Backend, CommitOptions, Exec, Git};
+
+type Result<T> = std::result::Result<T, GitCmtError>;
+
+// ---------- libgit2 backend ----------
+// `--git-backend libgit2` (the default): staging, the staged diff, the
+// commit and HEAD/branch/remote lookups in process instead of a `git` per
+// call. Each operation first checks that libgit2 would do exactly what git
+// does; when it wouldn't (a filter driver, a hook to run, the editor, ...)
+// or fails, the operation goes to `Exec`, so messages and exit codes stay
+// git's own. The `try_*` methods return `None` for that.
+pub struct Libgit2 {
+    repo: Repository,
+}
+
+// Set, these point git somewhere `Repository::discover()` doesn't look; a
+// hook run by `git commit` gets `GIT_INDEX_FILE` for a temporary index.
+const REPOSITORY_ENV: &[&str] = &[
+    "GIT_DIR",
+    "GIT_WORK_TREE",
+    "GIT_INDEX_FILE",
+    "GIT_OBJECT_DIRECTORY",
+    "GIT_ALTERNATE_OBJECT_DIRECTORIES",
+    "GIT_COMMON_DIR",
+    "GIT_NAMESPACE",
+];
+
+// Attributes that run something libgit2 can't: clean/smudge drivers such
+// as Git LFS, and re-encoding of the working tree.
+const STAGING_ATTRIBUTES: &[&str] = &["filter", "working-tree-encoding"];
+
+// `git diff` settings libgit2 doesn't read; `git diff --cached` output
+// depends on each when set.
+const DIFF_CONFIG: &[&str] = &[
+    "diff.external",
+    "diff.noprefix",
+    "diff.mnemonicPrefix",
+    "diff.srcPrefix",
+    "diff.dstPrefix",
+    "diff.relative",
+    "diff.suppressBlankEmpty",
+    "diff.orderFile",
+    "diff.interHunkContext",
+    "diff.wsErrorHighlight",
+];
+
+// Where `git commit` would take something other than `user.name`,
+// `user.email` and the clock, or would change the message or the object.
+const COMMIT_CONFIG: &[&str] = &[
+    "commit.gpgSign",
+    "commit.cleanup",
+    "i18n.commitEncoding",
+    "author.name",
+    "author.email",
+    "committer.name",
+    "committer.email",
+];
+const COMMIT_ENV: &[&str] = &[
+    "GIT_AUTHOR_NAME",
+    "GIT_AUTHOR_EMAIL",
+    "GIT_AUTHOR_DATE",
+    "GIT_COMMITTER_NAME",
+    "GIT_COMMITTER_EMAIL",
+    "GIT_COMMITTER_DATE",
+    "EMAIL",
+];
+
+// Hooks `git commit` runs even with `--no-verify`; `pre-commit` and
+// `commit-msg` are added without it.
+const COMMIT_HOOKS: &[&str] = &[
+    "prepare-commit-msg",
+    "post-commit",
+    "post-index-change",
+    "reference-transaction",
+];
+const VERIFY_HOOKS: &[&str] = &["pre-commit", "commit-msg"];
+
+// Index entry flags `git add` treats specially: assume-unchanged, and in
+// the extended flags intent-to-add and skip-worktree (sparse checkouts).
+const ENTRY_VALID: u16 = 0x8000;
+const ENTRY_INTENT_TO_ADD: u16 = 0x2000;
+const ENTRY_SKIP_WORKTREE: u16 = 0x4000;
+const GITLINK_MODE: u32 = 0o160000;
+
+// Git's fallback abbreviation for small repositories.
+const MIN_ABBREV: usize = 7;
+
+impl Libgit2 {
+    // The repository around `dir`; an error outside one, for a bare one, or
+    // when the environment points git elsewhere.
+    pub fn open(dir: &Path) -> Result<Self> {
+        if let Some(name) = REPOSITORY_ENV
+            .iter()
+            .find(|name| std::env::var_os(name).is_some())
+        {
+            return Err(GitCmtError::Git(format!("{name} is set")));
+        }
+        let repo =
+            Repository::discover(dir).map_err(|e| GitCmtError::Git(e.message().to_string()))?;
+        if repo.is_bare() {
+            return Err(GitCmtError::Git("bare repository".into()));
+        }
+        Ok(Libgit2 { repo })
+    }
+
+    fn config(&self) -> Option<Config> {
+        self.repo.config().ok()?.snapshot().ok()
+    }
+
+    // The index as it is on disk, whatever an earlier call (or `git`)
+    // left in memory.
+    fn index(&self) -> Option<Index> {
+        let mut index = self.repo.index().ok()?;
+        index.read(true).ok()?;
+        Some(index)
+    }
+
+    // `dir` below the working tree's root, e.g. `crates/foo`; empty at the
+    // root.
+    fn prefix(&self, git: &Git) -> Option<String> {
+        let root = self.repo.workdir()?.canonicalize().ok()?;
+        let dir = git.dir().canonicalize().ok()?;
+        let prefix = dir.strip_prefix(root).ok()?.to_str()?.replace('\\', "/");
+        // A pathspec, where these would be wildcards.
+        (!prefix.contains(['*', '?', '[', '\\'])).then_some(prefix)
+    }
+
+    fn is_set(config: &Config, key: &str) -> bool {
+        config.get_entry(key).is_ok()
+    }
+
+    fn attribute_is_set(&self, path: &Path, name: &str) -> bool {
+        match self
+            .repo
+            .get_attr_bytes(path, name, AttrCheckFlags::FILE_THEN_INDEX)
+        {
+            Ok(value) => !matches!(
+                AttrValue::from_bytes(value),
+                AttrValue::Unspecified | AttrValue::False
+            ),
+            Err(_) => true,
+        }
+    }
+
+    // `git add` without `-A`'s surprises: no submodules or nested
+    // repositories, no sparse checkout, no entries with special flags, and
+    // no file to stage that needs a filter driver, and no
+    // `post-index-change` hook for `git add` to run.
+    fn try_stage(&self, git: &Git, include_untracked: bool, cwd_only: bool) -> Option<()> {
+        let config = self.config()?;
+        if config.get_bool("core.sparseCheckout").unwrap_or(false)
+            || self.has_hook(&config, &["post-index-change"])
+            || self.repo.workdir()?.join(".gitmodules").exists()
+        {
+            return None;
+        }
+        let mut index = self.index()?;
+        let special = index.iter().any(|entry| {
+            entry.mode == GITLINK_MODE
+                || entry.flags & ENTRY_VALID != 0
+                || entry.flags_extended & (ENTRY_INTENT_TO_ADD | ENTRY_SKIP_WORKTREE) != 0
+        });
+        if special {
+            return None;
+        }
+        let prefix = if cwd_only {
+            self.prefix(git)?
+        } else {
+            String::new()
+        };
+        let pathspec: Vec<&str> = [prefix.as_str()]
+            .into_iter()
+            .filter(|prefix| !prefix.is_empty())
+            .collect();
+
+        let mut options = StatusOptions::new();
+        options
+            .include_untracked(include_untracked)
+            .recurse_untracked_dirs(true)
+            .include_ignored(false);
+        for path in &pathspec {
+            options.pathspec(path);
+        }
+        let statuses = self.repo.statuses(Some(&mut options)).ok()?;
+        for entry in statuses.iter() {
+            let path = entry.path()?;
+            // An untracked directory is a repository of its own, which git
+            // would add as a gitlink.
+            if path.ends_with('/')
+                || STAGING_ATTRIBUTES
+                    .iter()
+                    .any(|name| self.attribute_is_set(Path::new(path), name))
+            {
+                return None;
+            }
+        }
+
+        if include_untracked {
+            index
+                .add_all(&pathspec, IndexAddOption::DEFAULT, None)
+                .ok()?;
+        }
+        index.update_all(&pathspec, None).ok()?;
+        index.write().ok()
+    }
+
+    // `Git::diff_args()` in libgit2 terms, unless an external or textconv
+    // driver, the histogram algorithm or one of `DIFF_CONFIG` is involved.
+    fn try_diff_cached(&self, git: &Git, base: Option<&str>) -> Option<Vec<u8>> {
+        let config = self.config()?;
+        if DIFF_CONFIG.iter().any(|key| Self::is_set(&config, key))
+            || ["GIT_EXTERNAL_DIFF", "GIT_DIFF_OPTS"]
+                .iter()
+                .any(|name| std::env::var_os(name).is_some())
+        {
+            return None;
+        }
+        let algorithm = match git.diff_algorithm() {
+            Some(algorithm) => algorithm,
+            None => match config.get_string("diff.algorithm") {
+                Ok(name) => match name.to_ascii_lowercase().as_str() {
+                    "myers" | "default" => DiffAlgorithm::Myers,
+                    "minimal" => DiffAlgorithm::Minimal,
+                    "patience" => DiffAlgorithm::Patience,
+                    _ => return None,
+                },
+                Err(_) => DiffAlgorithm::Myers,
+            },
+        };
+        let tree = self.base_tree(base)?;
+        let index = self.index()?;
+        // git lists these as `* Unmerged path`.
+        if index.has_conflicts() {
+            return None;
+        }
+
+        let mut options = DiffOptions::new();
+        options
+            .context_lines(git.context_lines())
+            .ignore_whitespace_change(true)
+            .indent_heuristic(config.get_bool("diff.indentHeuristic").unwrap_or(true))
+            .id_abbrev(u16::try_from(self.abbrev_len(&config)?).ok()?);
+        match algorithm {
+            DiffAlgorithm::Myers => {}
+            DiffAlgorithm::Minimal => {
+                options.minimal(true);
+            }
+            DiffAlgorithm::Patience => {
+                options.patience(true);
+            }
+            DiffAlgorithm::Histogram => return None,
+        }
+        let mut diff = self
+            .repo
+            .diff_tree_to_index(tree.as_ref(), Some(&index), Some(&mut options))
+            .ok()?;
+        self.find_renames(
+            &mut diff,
+            &config,
+            git.rename_threshold(),
+            git.copy_threshold(),
+        )?;
+        if self.has_diff_driver(&diff, &config) {
+            return None;
+        }
+
+        let mut patch = Vec::new();
+        diff.print(DiffFormat::Patch, |_, _, line| {
+            if matches!(line.origin(), ' ' | '+' | '-') {
+                patch.push(line.origin() as u8);
+            }
+            patch.extend_from_slice(line.content());
+            true
+        })
+        .ok()?;
+        Some(patch)
+    }
+
+    // The tree `git diff --cached [base]` compares the index with: `base`,
+    // else HEAD's, else (before the first commit) none.
+    fn base_tree(&self, base: Option<&str>) -> Option<Option<Tree<'_>>> {
+        if let Some(base) = base {
+            return Some(Some(
+                self.repo.revparse_single(base).ok()?.peel_to_tree().ok()?,
+            ));
+        }
+        match self.repo.head() {
+            Ok(head) => Some(Some(head.peel_to_tree().ok()?)),
+            Err(e) if e.code() == ErrorCode::UnbornBranch => Some(None),
+            Err(_) => None,
+        }
+    }
+
+    // `-M<n>% -C<n>%` as `Git::detection_args()` passes them; 0 is off.
+    fn find_renames(
+        &self,
+        diff: &mut Diff,
+        config: &Config,
+        rename_threshold: u8,
+        copy_threshold: u8,
+    ) -> Option<()> {
+        if rename_threshold == 0 {
+            return Some(());
+        }
+        let limit = config.get_i32("diff.renameLimit").unwrap_or(1000);
+        let mut find = DiffFindOptions::new();
+        find.renames(true)
+            .rename_threshold(rename_threshold.into())
+            .rename_limit(usize::try_from(limit).ok()?);
+        if copy_threshold > 0 {
+            find.copies(true).copy_threshold(copy_threshold.into());
+        }
+        diff.find_similar(Some(&mut find)).ok()
+    }
+
+    // A `diff` attribute naming a driver with a `textconv` or `command`,
+    // which git would run on that file.
+    fn has_diff_driver(&self, diff: &Diff, config: &Config) -> bool {
+        let paths = diff
+            .deltas()
+            .flat_map(|delta| [delta.old_file().path(), delta.new_file().path()])
+            .flatten();
+        for path in paths {
+            let value = self
+                .repo
+                .get_attr_bytes(path, "diff", AttrCheckFlags::FILE_THEN_INDEX);
+            let driver = match value.map(AttrValue::from_bytes) {
+                Ok(AttrValue::String(driver)) => driver,
+                Ok(AttrValue::Bytes(_)) | Err(_) => return true,
+                Ok(_) => continue,
+            };
+            if ["textconv", "command"]
+                .iter()
+                .any(|key| Self::is_set(config, &format!("diff.{driver}.{key}")))
+            {
+                return true;
+            }
+        }
+        false
+    }
+
+    // `git commit -F <file>` for the plain case: no editor, amend, trailers
+    // or hooks, nothing being concluded (a merge, a `merge --squash`), a
+    // signature from `user.*` alone and something to commit. The summary
+    // git prints after it is printed the same way.
+    fn try_commit(&self, options: &CommitOptions) -> Option<()> {
+        if options.edit || options.amend || !options.co_authors.is_empty() {
+            return None;
+        }
+        let config = self.config()?;
+        if self.repo.state() != RepositoryState::Clean
+            || ["MERGE_MSG", "SQUASH_MSG"]
+                .iter()
+                .any(|name| self.repo.path().join(name).exists())
+            || COMMIT_CONFIG.iter().any(|key| Self::is_set(&config, key))
+            || COMMIT_ENV
+                .iter()
+                .any(|name| std::env::var_os(name).is_some())
+            || self.runs_commit_hooks(&config, options.no_verify)
+        {
+            return None;
+        }
+        let signature = self.repo.signature().ok()?;
+        let message = fs::read_to_string(options.message_file).ok()?;
+        // `--cleanup=whitespace`, git's default without the editor.
+        let message = git2::message_prettify(message, None).ok()?;
+        if message.is_empty() {
+            return None;
+        }
+
+        let mut index = self.index()?;
+        if index.has_conflicts() {
+            return None;
+        }
+        let tree = self.repo.find_tree(index.write_tree().ok()?).ok()?;
+        let parent = match self.repo.head() {
+            Ok(head) => Some(head.peel_to_commit().ok()?),
+            Err(e) if e.code() == ErrorCode::UnbornBranch => None,
+            Err(_) => return None,
+        };
+        // "nothing to commit" is git's to say.
+        let unchanged = match &parent {
+            Some(parent) => parent.tree_id() == tree.id(),
+            None => tree.is_empty(),
+        };
+        if unchanged {
+            return None;
+        }
+        let id = self
+            .repo
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                &message,
+                &tree,
+                &parent.iter().collect::<Vec<_>>(),
+            )
+            .ok()?;
+        let _ = fs::write(self.repo.path().join("COMMIT_EDITMSG"), &message);
+        if let Some(summary) = self.commit_summary(id, &config) {
+            print!("{summary}");
+        }
+        Some(())
+    }
+
+    // Whether any hook `git commit` would run is installed.
+    fn runs_commit_hooks(&self, config: &Config, no_verify: bool) -> bool {
+        let verify = if no_verify { &[][..] } else { VERIFY_HOOKS };
+        let hooks: Vec<&str> = COMMIT_HOOKS.iter().chain(verify).copied().collect();
+        self.has_hook(config, &hooks)
+    }
+
+    // Whether one of `names` is installed where git looks for hooks.
+    fn has_hook(&self, config: &Config, names: &[&str]) -> bool {
+        let dir = match config.get_path("core.hooksPath") {
+            Ok(path) if path.is_relative() => match self.repo.workdir() {
+                Some(root) => root.join(path),
+                None => return true,
+            },
+            Ok(path) => path,
+            Err(e) if e.code() == ErrorCode::NotFound => self.repo.commondir().join("hooks"),
+            Err(_) => return true,
+        };
+        names.iter().any(|name| is_executable(&dir.join(name)))
+    }
+
+    // `[main 3f2a1bc] feat: add paging`, then `--shortstat --summary`
+    // against the parent, as `git commit` prints it.
+    fn commit_summary(&self, id: Oid, config: &Config) -> Option<String> {
+        let commit = self.repo.find_commit(id).ok()?;
+        let head = self.repo.head().ok()?;
+        let branch = if head.is_branch() {
+            head.shorthand()?.to_string()
+        } else {
+            "detached HEAD".to_string()
+        };
+        let root = if commit.parent_count() == 0 {
+            " (root-commit)"
+        } else {
+            ""
+        };
+        let short = self.short_id(id, config)?;
+        let parent_tree = match commit.parent(0) {
+            Ok(parent) => Some(parent.tree().ok()?),
+            Err(_) => None,
+        };
+        let mut diff = self
+            .repo
+            .diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree().ok()?), None)
+            .ok()?;
+        if config.get_bool("diff.renames").unwrap_or(true) {
+            diff.find_similar(Some(DiffFindOptions::new().renames(true)))
+                .ok()?;
+        }
+        let stats = diff
+            .stats()
+            .ok()?
+            .to_buf(
+                DiffStatsFormat::SHORT | DiffStatsFormat::INCLUDE_SUMMARY,
+                80,
+            )
+            .ok()?;
+        Some(format!(
+            "[{branch}{root} {short}] {}\n{}",
+            commit.summary()?,
+            stats.as_str()?
+        ))
+    }
+
+    // `core.abbrev`, or git's default for it: half the bits of the number
+    // of packed objects in hex digits, at least `MIN_ABBREV`.
+    fn abbrev_len(&self, config: &Config) -> Option<usize> {
+        match config.get_string("core.abbrev") {
+            Ok(value) if value == "auto" => {}
+            Ok(value) if ["no", "false", "off"].contains(&value.as_str()) => return Some(40),
+            Ok(value) => return value.parse::<usize>().ok().map(|len| len.clamp(4, 40)),
+            Err(_) => {}
+        }
+        let objects = self.repo.commondir().join("objects");
+        let alternates = fs::read_to_string(objects.join("info/alternates")).unwrap_or_default();
+        let dirs = std::iter::once(objects.clone()).chain(
+            alternates
+                .lines()
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| objects.join(line)),
+        );
+        let count: u64 = dirs.map(|dir| packed_objects(&dir.join("pack"))).sum();
+        let bits = (u64::BITS - count.leading_zeros()) as usize;
+        Some(bits.div_ceil(2).max(MIN_ABBREV))
+    }
+
+    // `%h`: `abbrev_len()` digits, more if that prefix is ambiguous.
+    fn short_id(&self, id: Oid, config: &Config) -> Option<String> {
+        let hex = id.to_string();
+        let odb = self.repo.odb().ok()?;
+        (self.abbrev_len(config)?..hex.len())
+            .find(|&len| {
+                Oid::from_str(&hex[..len])
+                    .and_then(|prefix| odb.exists_prefix(prefix, len))
+                    .is_ok()
+            })
+            .map_or(Some(hex.clone()), |len| Some(hex[..len].to_string()))
+    }
+
+    // `refs/heads/main` as `main`, unless `symbolic-ref --short` would
+    // have to disambiguate it.
+    fn shorten(&self, name: &str, prefix: &str) -> Option<String> {
+        let short = name.strip_prefix(prefix)?;
+        let rules = [
+            short.to_string(),
+            format!("refs/{short}"),
+            format!("refs/tags/{short}"),
+            format!("refs/heads/{short}"),
+            format!("refs/remotes/{short}"),
+            format!("refs/remotes/{short}/HEAD"),
+        ];
+        let ambiguous = rules
+            .iter()
+            .filter(|rule| rule.as_str() != name)
+            .any(|rule| self.repo.find_reference(rule).is_ok());
+        (!ambiguous).then(|| short.to_string())
+    }
+
+    fn try_current_branch(&self) -> Option<String> {
+        let head = self.repo.find_reference("HEAD").ok()?;
+        self.shorten(head.symbolic_target()?, "refs/heads/")
+    }
+
+    fn try_head_commit(&self) -> Option<(String, String)> {
+        // `git log` follows `git replace`; libgit2 doesn't.
+        if self
+            .repo
+            .references_glob("refs/replace/*")
+            .ok()?
+            .next()
+            .is_some()
+        {
+            return None;
+        }
+        let config = self.config()?;
+        let commit = self.repo.head().ok()?.peel_to_commit().ok()?;
+        if commit
+            .message_encoding()
+            .is_some_and(|encoding| !encoding.eq_ignore_ascii_case("utf-8"))
+        {
+            return None;
+        }
+        Some((
+            self.short_id(commit.id(), &config)?,
+            commit.summary()?.to_string(),
+        ))
+    }
+
+    fn try_remotes(&self) -> Option<Vec<String>> {
+        let remotes = self.repo.remotes().ok()?;
+        let mut names: Vec<String> = remotes
+            .iter()
+            .map(|name| name.map(String::from))
+            .collect::<Option<_>>()?;
+        // `git remote` lists them sorted.
+        names.sort();
+        Some(names)
+    }
+
+    fn try_remote_head(&self, remote: &str) -> Option<String> {
+        let head = self
+            .repo
+            .find_reference(&format!("refs/remotes/{remote}/HEAD"))
+            .ok()?;
+        self.shorten(head.symbolic_target()?, "refs/remotes/")
+    }
+}
+
+impl Backend for Libgit2 {
+    fn stage(&self, git: &Git, include_untracked: bool, cwd_only: bool) -> Result<()> {
+        match self.try_stage(git, include_untracked, cwd_only) {
+            Some(()) => Ok(()),
+            None => Exec.stage(git, include_untracked, cwd_only),
+        }
+    }
+
+    fn diff_cached(&self, git: &Git, base: Option<&str>) -> Result<Vec<u8>> {
+        match self.try_diff_cached(git, base) {
+            Some(patch) => Ok(patch),
+            None => Exec.diff_cached(git, base),
+        }
+    }
+
+    fn commit(&self, git: &Git, options: &CommitOptions) -> Result<()> {
+        match self.try_commit(options) {
+            Some(()) => Ok(()),
+            None => Exec.commit(git, options),
+        }
+    }
+
+    fn current_branch(&self, git: &Git) -> Result<String> {
+        match self.try_current_branch() {
+            Some(branch) => Ok(branch),
+            None => Exec.current_branch(git),
+        }
+    }
+
+    fn is_detached(&self, git: &Git) -> bool {
+        self.repo
+            .head_detached()
+            .unwrap_or_else(|_| Exec.is_detached(git))
+    }
+
+    fn head_commit(&self, git: &Git) -> Result<(String, String)> {
+        match self.try_head_commit() {
+            Some(head) => Ok(head),
+            None => Exec.head_commit(git),
+        }
+    }
+
+    fn remotes(&self, git: &Git) -> Result<Vec<String>> {
+        match self.try_remotes() {
+            Some(remotes) => Ok(remotes),
+            None => Exec.remotes(git),
+        }
+    }
+
+    fn branch_remote(&self, git: &Git, branch: &str) -> Option<String> {
+        match self.config() {
+            Some(config) => config
+                .get_string(&format!("branch.{branch}.remote"))
+                .ok()
+                .map(|remote| remote.trim().to_string())
+                .filter(|remote| !remote.is_empty()),
+            None => Exec.branch_remote(git, branch),
+        }
+    }
+
+    fn remote_head(&self, git: &Git, remote: &str) -> Option<String> {
+        match self
+            .repo
+            .find_reference(&format!("refs/remotes/{remote}/HEAD"))
+        {
+            Ok(_) => self
+                .try_remote_head(remote)
+                .or_else(|| Exec.remote_head(git, remote)),
+            Err(e) if e.code() == ErrorCode::NotFound => None,
+            Err(_) => Exec.remote_head(git, remote),
+        }
+    }
+}
+
+// Objects in the pack indexes under `dir`: the last fanout entry of each
+// `.idx`, version 1 or 2. git's `repo_approximate_object_count()` leaves
+// loose objects out too.
+fn packed_objects(dir: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "idx"))
+        .filter_map(|path| fs::read(path).ok())
+        .filter_map(|idx| {
+            let fanout = if idx.starts_with(b"\xfftOc") { 8 } else { 0 };
+            let last = idx.get(fanout + 255 * 4..fanout + 256 * 4)?;
+            Some(u64::from(u32::from_be_bytes(last.try_into().ok()?)))
+        })
+        .sum()
+}
+
+fn is_executable(path: &PathBuf) -> bool {
+    let Ok(metadata) = fs::metadata(path) else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.is_file()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::GitBackend;
+    use crate::scratch::ScratchRepo;
+
+    // The same scratch repository both ways: `repo.git` runs git,
+    // `in_process()` goes through libgit2.
+    impl ScratchRepo {
+        fn in_process(&self) -> Git {
+            Git::new(&self.dir).with_backend(GitBackend::Libgit2).0
+        }
+
+        fn libgit2(&self) -> Libgit2 {
+            Libgit2::open(&self.dir).unwrap()
+        }
+    }
+
+    // One of everything `git diff --cached` has a header for.
+    fn varied_changes(repo: &ScratchRepo) {
+        repo.write("a.txt", b"one\ntwo\nthree\n");
+        repo.write(
+            "moved.rs",
+            b"fn main() {\n    println!(\"hello\");\n}\n// end\n",
+        );
+        repo.write("ws.txt", b"a b\n");
+        repo.write("gone.txt", b"bye\n");
+        repo.write("run.sh", b"echo hi\n");
+        repo.git(&["add", "-A"]);
+        repo.git(&["commit", "-q", "-m", "init"]);
+        repo.write("a.txt", b"one\n2\nthree\nfour");
+        repo.write(
+            "src/main.rs",
+            b"fn main() {\n    println!(\"hello\");\n}\n// end\n",
+        );
+        fs::remove_file(repo.dir.join("moved.rs")).unwrap();
+        repo.write("ws.txt", b"a   b\n");
+        repo.write("bin.dat", b"\0\x01\x02");
+        repo.write("caf\u{e9}.txt", b"x\n");
+        fs::remove_file(repo.dir.join("gone.txt")).unwrap();
+        repo.git(&["update-index", "--chmod=+x", "run.sh"]);
+    }
+
+    fn text(bytes: Vec<u8>) -> String {
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn the_staged_diff_is_the_same_as_gits() {
+        let repo = ScratchRepo::new("lib-diff");
+        varied_changes(&repo);
+        repo.git(&["add", "-A"]);
+        let libgit2 = repo.libgit2();
+        for git in [
+            Git::new(&repo.dir),
+            Git::new(&repo.dir).with_similarity(0, 0),
+            Git::new(&repo.dir).with_context_lines(0),
+            Git::new(&repo.dir).with_diff_algorithm(Some(DiffAlgorithm::Patience)),
+        ] {
+            let ours = libgit2.try_diff_cached(&git, None).unwrap();
+            assert_eq!(text(ours), text(git.diff_cached(None).unwrap()));
+        }
+        let ours = libgit2.try_diff_cached(&repo.git, Some("HEAD")).unwrap();
+        assert_eq!(
+            text(ours),
+            text(repo.git.diff_cached(Some("HEAD")).unwrap())
+        );
+        assert!(
+            text(repo.in_process().diff_cached(None).unwrap()).contains("rename to src/main.rs")
+        );
+    }
+
+    #[test]
+    fn the_first_commit_is_diffed_against_nothing() {
+        let repo = ScratchRepo::new("lib-unborn");
+        repo.write("a.txt", b"hello\n");
+        repo.git(&["add", "a.txt"]);
+        let ours = repo.libgit2().try_diff_cached(&repo.git, None).unwrap();
+        assert_eq!(text(ours), text(repo.git.diff_cached(None).unwrap()));
+    }
+
+    #[test]
+    fn histogram_and_textconv_diffs_go_to_git() {
+        let repo = ScratchRepo::new("lib-diff-fallback");
+        varied_changes(&repo);
+        repo.git(&["add", "-A"]);
+        let libgit2 = repo.libgit2();
+        let histogram = Git::new(&repo.dir).with_diff_algorithm(Some(DiffAlgorithm::Histogram));
+        assert!(libgit2.try_diff_cached(&histogram, None).is_none());
+        assert!(libgit2.try_diff_cached(&repo.git, None).is_some());
+        repo.write(".gitattributes", b"*.txt diff=upper\n");
+        repo.git(&["config", "diff.upper.textconv", "tr a-z A-Z <"]);
+        assert!(libgit2.try_diff_cached(&repo.git, None).is_none());
+        assert!(text(repo.in_process().diff_cached(None).unwrap()).contains("+2\n"));
+    }
+
+    // `git ls-files --stage` after staging with `git` and with libgit2.
+    fn staged_both_ways(repo: &ScratchRepo, include_untracked: bool, cwd_only: bool, in_dir: &str) {
+        let dir = repo.dir.join(in_dir);
+        let exec = Git::new(&dir);
+        exec.stage(include_untracked, cwd_only).unwrap();
+        let by_git = repo.git(&["ls-files", "--stage"]);
+        repo.git(&["reset", "-q"]);
+        Libgit2::open(&dir)
+            .unwrap()
+            .try_stage(&exec, include_untracked, cwd_only)
+            .unwrap();
+        assert_eq!(repo.git(&["ls-files", "--stage"]), by_git);
+        repo.git(&["reset", "-q"]);
+    }
+
+    #[test]
+    fn staging_is_the_same_as_git_add() {
+        let repo = ScratchRepo::new("lib-stage");
+        varied_changes(&repo);
+        repo.git(&["reset", "-q"]);
+        repo.write(".gitignore", b"*.log\n");
+        repo.write("debug.log", b"noise\n");
+        repo.write("src/new.rs", b"// new\n");
+        for (include_untracked, cwd_only, dir) in [
+            (true, false, ""),
+            (false, false, ""),
+            (true, true, "src"),
+            (false, false, "src"),
+        ] {
+            staged_both_ways(&repo, include_untracked, cwd_only, dir);
+        }
+        repo.in_process().stage(true, false).unwrap();
+        assert!(!repo.git(&["ls-files"]).contains("debug.log"));
+    }
+
+    #[test]
+    fn filters_and_submodules_are_staged_by_git() {
+        let repo = ScratchRepo::new("lib-stage-fallback");
+        repo.write("a.txt", b"one\n");
+        assert!(repo.libgit2().try_stage(&repo.git, true, false).is_some());
+        repo.git(&["reset", "-q"]);
+        repo.write(".gitattributes", b"*.bin filter=lfs\n");
+        repo.write("big.bin", b"\0\0");
+        assert!(repo.libgit2().try_stage(&repo.git, true, false).is_none());
+        fs::remove_file(repo.dir.join(".gitattributes")).unwrap();
+        repo.write(".gitmodules", b"");
+        assert!(repo.libgit2().try_stage(&repo.git, true, false).is_none());
+        fs::remove_file(repo.dir.join(".gitmodules")).unwrap();
+        install_hook(&repo, "post-index-change");
+        assert!(repo.libgit2().try_stage(&repo.git, true, false).is_none());
+    }
+
+    fn install_hook(repo: &ScratchRepo, name: &str) {
+        let hook = repo.dir.join(".git/hooks").join(name);
+        repo.write(&format!(".git/hooks/{name}"), b"#!/bin/sh\nexit 0\n");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+    }
+
+    fn message_file(repo: &ScratchRepo, message: &str) -> PathBuf {
+        let path = repo.dir.join(".git").join("test-message");
+        fs::write(&path, message).unwrap();
+        path
+    }
+
+    fn plain(path: &Path) -> CommitOptions<'_> {
+        CommitOptions {
+            message_file: path,
+            co_authors: &[],
+            amend: false,
+            edit: false,
+            no_verify: false,
+        }
+    }
+
+    #[test]
+    fn a_commit_is_the_one_git_would_make() {
+        let repo = ScratchRepo::new("lib-commit");
+        let libgit2 = repo.libgit2();
+        repo.write("a.txt", b"one\n");
+        repo.git(&["add", "-A"]);
+        let path = message_file(&repo, "\n\nfeat: add a   \n\n\n- first\n\n");
+        let options = plain(&path);
+        let summary = {
+            assert!(libgit2.try_commit(&options).is_some());
+            let config = libgit2.config().unwrap();
+            libgit2
+                .commit_summary(libgit2.repo.head().unwrap().target().unwrap(), &config)
+                .unwrap()
+        };
+        let short = repo.git(&["rev-parse", "--short", "HEAD"]);
+        assert_eq!(
+            summary,
+            format!(
+                "[main (root-commit) {}] feat: add a\n 1 file changed, 1 insertion(+)\n create mode 100644 a.txt\n",
+                short.trim()
+            )
+        );
+        assert_eq!(
+            repo.git(&["log", "-1", "--format=%an <%ae>%n%B"]),
+            "test <test@example.com>\nfeat: add a\n\n- first\n\n"
+        );
+        assert_eq!(
+            repo.git(&["reflog", "-1", "--format=%gs"]),
+            "commit (initial): feat: add a\n"
+        );
+        assert_eq!(repo.git(&["status", "--porcelain"]), "");
+
+        // Against git's own summary for the same change.
+        repo.write("a.txt", b"two\n");
+        repo.git(&["add", "-A"]);
+        let by_git = repo.git(&["commit", "-F", path.to_str().unwrap()]);
+        let short = repo.git(&["rev-parse", "--short", "HEAD"]);
+        let config = libgit2.config().unwrap();
+        let head = libgit2.repo.head().unwrap().target().unwrap();
+        assert_eq!(libgit2.commit_summary(head, &config).unwrap(), by_git);
+        assert!(by_git.contains(short.trim()));
+
+        // Nothing staged: git says so.
+        assert!(libgit2.try_commit(&options).is_none());
+    }
+
+    #[test]
+    fn hooks_the_editor_and_trailers_are_left_to_git_commit() {
+        let repo = ScratchRepo::new("lib-commit-fallback");
+        let libgit2 = repo.libgit2();
+        repo.write("a.txt", b"one\n");
+        repo.git(&["add", "-A"]);
+        let path = message_file(&repo, "feat: add a");
+        let co_authors = ["Ada <ada@example.com>".to_string()];
+        for options in [
+            CommitOptions {
+                edit: true,
+                ..plain(&path)
+            },
+            CommitOptions {
+                amend: true,
+                ..plain(&path)
+            },
+            CommitOptions {
+                co_authors: &co_authors,
+                ..plain(&path)
+            },
+        ] {
+            assert!(libgit2.try_commit(&options).is_none());
+        }
+        install_hook(&repo, "pre-commit");
+        assert!(libgit2.try_commit(&plain(&path)).is_none());
+        let no_verify = CommitOptions {
+            no_verify: true,
+            ..plain(&path)
+        };
+        assert!(libgit2.try_commit(&no_verify).is_some());
+        repo.write("a.txt", b"two\n");
+        repo.git(&["add", "-A"]);
+        repo.git(&["config", "commit.gpgSign", "false"]);
+        assert!(libgit2.try_commit(&no_verify).is_none());
+    }
+
+    #[test]
+    fn head_branch_and_remote_lookups_are_the_same_as_gits() {
+        let repo = ScratchRepo::new("lib-lookups");
+        let both = |check: &dyn Fn(&Git) -> String| {
+            let by_git = check(&repo.git);
+            assert_eq!(check(&repo.in_process()), by_git);
+            by_git
+        };
+        let head = |git: &Git| format!("{:?}", git.head_commit().map_err(|e| e.to_string()));
+        let branch = |git: &Git| format!("{:?}", git.current_branch().map_err(|e| e.to_string()));
+        let detached = |git: &Git| git.is_detached().to_string();
+        let remotes = |git: &Git| format!("{:?}", git.remotes().unwrap());
+
+        assert_eq!(branch(&repo.git), "Ok(\"main\")");
+        both(&head);
+        both(&detached);
+        repo.git(&["commit", "-q", "--allow-empty", "-m", "feat: first\n\nbody"]);
+        assert_eq!(
+            both(&head),
+            format!(
+                "Ok(({:?}, \"feat: first\"))",
+                repo.git(&["rev-parse", "--short", "HEAD"]).trim()
+            )
+        );
+        both(&branch);
+        // `symbolic-ref --short` spells out a branch a tag shadows.
+        repo.git(&["tag", "main"]);
+        assert_eq!(both(&branch), "Ok(\"heads/main\")");
+
+        repo.git(&["remote", "add", "upstream", "https://example.com/u.git"]);
+        repo.git(&["remote", "add", "origin", "https://example.com/o.git"]);
+        both(&remotes);
+        repo.git(&["config", "branch.main.remote", "upstream"]);
+        assert_eq!(
+            repo.in_process().branch_remote("main").as_deref(),
+            Some("upstream")
+        );
+        assert_eq!(repo.in_process().branch_remote("other"), None);
+        repo.git(&["update-ref", "refs/remotes/upstream/develop", "HEAD"]);
+        assert_eq!(repo.in_process().remote_head("upstream"), None);
+        repo.git(&[
+            "symbolic-ref",
+            "refs/remotes/upstream/HEAD",
+            "refs/remotes/upstream/develop",
+        ]);
+        assert_eq!(
+            repo.in_process().remote_head("upstream").as_deref(),
+            Some("upstream/develop")
+        );
+
+        repo.git(&["checkout", "-q", "--detach"]);
+        assert_eq!(both(&detached), "true");
+        both(&branch);
+        both(&head);
+    }
+
+    #[test]
+    fn abbreviations_grow_with_the_packed_objects() {
+        let repo = ScratchRepo::new("lib-abbrev");
+        repo.git(&["commit", "-q", "--allow-empty", "-m", "init"]);
+        repo.git(&["gc", "-q"]);
+        let counted = repo.git(&["count-objects", "-v"]);
+        let in_pack = counted
+            .lines()
+            .find_map(|line| line.strip_prefix("in-pack: "))
+            .unwrap();
+        let pack = repo.dir.join(".git/objects/pack");
+        assert_eq!(packed_objects(&pack).to_string(), in_pack);
+        let libgit2 = repo.libgit2();
+        assert_eq!(
+            libgit2.abbrev_len(&libgit2.config().unwrap()),
+            Some(MIN_ABBREV)
+        );
+        repo.git(&["config", "core.abbrev", "12"]);
+        assert_eq!(libgit2.abbrev_len(&libgit2.config().unwrap()), Some(12));
+    }
+
+    #[test]
+    fn a_declined_backend_says_why() {
+        let repo = ScratchRepo::new("lib-declined");
+        let (_, declined) = Git::new(&repo.dir).with_backend(GitBackend::Libgit2);
+        assert!(declined.is_none());
+        repo.git(&["init", "-q", "--bare", "bare.git"]);
+        let (_, declined) = Git::new(repo.dir.join("bare.git")).with_backend(GitBackend::Libgit2);
+        assert_eq!(declined.unwrap().to_string(), "bare repository");
+        let (_, declined) = Git::new(&repo.dir).with_backend(GitBackend::Exec);
+        assert!(declined.is_none());
+    }
+}
@@ -0,0 +1,259 @@
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::client::json_body;
+use super::debug::HttpDebug;
+use super::retry::send_with_retry;
+use super::usage::{Meter, Usage};
+use super::{
+    LlmBackend, Message, ModelList, Task, pr_description, response_text, summary_line,
+    tag_annotation, task_max_tokens, temperature, user_prompt,
+};
+use crate::cli::{FormatPreset, Privacy};
+use crate::config::Config;
+use crate::error::GitCmtError;
+use crate::{Commit, parse_commit};
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+// Prefixed to generation errors, as for the OpenAI flavors.
+const API_NAME: &str = "Anthropic Messages API";
+// The Messages API requires an explicit cap; a commit JSON object is tiny.
+// `--max-tokens` overrides it.
+const MAX_TOKENS: u32 = 256;
+
+// ---------- Anthropic Messages request/response ----------
+#[derive(Debug, Serialize)]
+struct MessagesRequest {
+    model: String,
+    max_tokens: u32,
+    system: String,
+    messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessagesResponse {
+    #[serde(default)]
+    content: Vec<ContentBlock>,
+    #[serde(default)]
+    usage: Option<MessagesUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessagesUsage {
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ContentBlock {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: String,
+}
+
+// ---------- Backend ----------
+// Anthropic has no `response_format`; the system prompt asks for JSON and
+// `parse_commit` tolerates anything wrapped around it.
+pub struct AnthropicBackend {
+    client: reqwest::Client,
+    url: String,
+    models_url: String,
+    api_key: Option<String>,
+    model: String,
+    lang: Option<String>,
+    privacy: Privacy,
+    format: FormatPreset,
+    commit_body: bool,
+    compress: bool,
+    top_p: Option<f32>,
+    max_tokens: u32,
+    retry_budget: Duration,
+    debug: Option<HttpDebug>,
+    meter: Meter,
+}
+
+impl AnthropicBackend {
+    pub(super) fn new(config: &Config, client: reqwest::Client, debug: Option<HttpDebug>) -> Self {
+        AnthropicBackend {
+            client,
+            url: format!("{}/messages", config.base_url),
+            models_url: format!("{}/models", config.base_url),
+            api_key: config.api_key.clone(),
+            model: config.model.clone(),
+            lang: config.lang.clone(),
+            privacy: config.privacy,
+            format: config.format,
+            commit_body: config.body,
+            compress: config.compress_requests,
+            top_p: config.top_p,
+            max_tokens: config.max_tokens.unwrap_or(MAX_TOKENS),
+            retry_budget: config.retry_budget,
+            debug,
+            meter: Meter::new(config),
+        }
+    }
+
+    fn request(&self, req: &MessagesRequest) -> reqwest::RequestBuilder {
+        if let Some(debug) = &self.debug {
+            debug.request(&self.url, req);
+        }
+        json_body(
+            self.authorize(self.client.post(&self.url)),
+            req,
+            self.compress,
+        )
+    }
+
+    // The concatenated text blocks of a `/messages` reply.
+    async fn send(&self, task: Task, diff: &str) -> Result<String> {
+        let req = MessagesRequest {
+            model: self.model.clone(),
+            // Anthropic requires a cap, so there is always one to raise.
+            max_tokens: task_max_tokens(task, Some(self.max_tokens)).unwrap_or(self.max_tokens),
+            system: task.system_prompt(
+                self.lang.as_deref(),
+                self.privacy,
+                self.format,
+                self.commit_body,
+            ),
+            messages: vec![Message::new("user", user_prompt(diff))],
+            temperature: temperature(self.top_p),
+            top_p: self.top_p,
+        };
+
+        let resp = send_with_retry(
+            || self.request(&req),
+            self.retry_budget,
+            self.debug.as_ref(),
+        )
+        .await?;
+        let text = response_text(resp, self.debug.as_ref()).await?;
+        let parsed: MessagesResponse =
+            serde_json::from_str(&text).context("failed to parse LLM response")?;
+        if let Some(usage) = &parsed.usage {
+            self.meter.report(Usage {
+                prompt_tokens: usage.input_tokens,
+                completion_tokens: usage.output_tokens,
+            });
+        }
+
+        let content: String = parsed
+            .content
+            .into_iter()
+            .filter(|block| block.kind == "text")
+            .map(|block| block.text)
+            .collect();
+        if content.trim().is_empty() {
+            return Err(anyhow!("no text content returned"));
+        }
+        Ok(content)
+    }
+
+    fn authorize(&self, builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        let builder = builder.header("anthropic-version", ANTHROPIC_VERSION);
+        match &self.api_key {
+            Some(key) => builder.header("x-api-key", key),
+            None => builder,
+        }
+    }
+}
+
+#[async_trait]
+impl LlmBackend for AnthropicBackend {
+    async fn generate(&self, diff: &str) -> Result<Commit, GitCmtError> {
+        let content = self
+            .send(Task::Commit, diff)
+            .await
+            .map_err(|e| GitCmtError::llm(API_NAME, e))?;
+        parse_commit(&content)
+    }
+
+    async fn summarize(&self, file_diff: &str) -> Result<String> {
+        summary_line(&self.send(Task::Summary, file_diff).await?)
+    }
+
+    async fn describe_pr(&self, log: &str) -> Result<String> {
+        pr_description(&self.send(Task::PrDescription, log).await?)
+    }
+
+    async fn annotate_tag(&self, changes: &str) -> Result<String> {
+        tag_annotation(&self.send(Task::TagAnnotation, changes).await?)
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let resp = send_with_retry(
+            || self.authorize(self.client.get(&self.models_url)),
+            self.retry_budget,
+            self.debug.as_ref(),
+        )
+        .await?;
+        let text = response_text(resp, self.debug.as_ref()).await?;
+        let list: ModelList = serde_json::from_str(&text).context("failed to parse model list")?;
+        // Every Anthropic model is a chat (Messages) model.
+        let mut ids: Vec<String> = list.data.into_iter().map(|m| m.id).collect();
+        ids.sort();
+        Ok(ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::{http_response, mock_server};
+    use super::*;
+
+    fn test_backend(base_url: String) -> AnthropicBackend {
+        AnthropicBackend {
+            client: reqwest::Client::new(),
+            url: format!("{base_url}/messages"),
+            models_url: format!("{base_url}/models"),
+            api_key: Some("sk-ant-test".into()),
+            model: "claude-test".into(),
+            lang: None,
+            privacy: Privacy::Full,
+            format: FormatPreset::Conventional,
+            commit_body: false,
+            compress: false,
+            top_p: None,
+            max_tokens: MAX_TOKENS,
+            retry_budget: Duration::from_secs(5),
+            debug: None,
+            meter: Meter::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn anthropic_sends_auth_headers_and_parses_text_blocks() {
+        let body = serde_json::json!({
+            "content": [
+                { "type": "text", "text": "```json\n{\"type\":\"docs\",\"scope\":\"readme\",\"message\":\"add usage\"}\n```" }
+            ]
+        })
+        .to_string();
+        let (url, requests) = mock_server(vec![http_response("200 OK", "", &body)]).await;
+
+        let commit = test_backend(url).generate("diff").await.unwrap();
+        assert_eq!(commit.r#type, "docs");
+        assert_eq!(commit.scope, "readme");
+
+        let request = requests.lock().unwrap()[0].clone();
+        let lower = request.to_lowercase();
+        assert!(lower.starts_with("post /messages "), "request: {request}");
+        assert!(lower.contains("x-api-key: sk-ant-test"));
+        assert!(lower.contains("anthropic-version: 2023-06-01"));
+        assert!(request.contains(r#""max_tokens":256"#));
+        assert!(request.contains(r#""system":"You are a git commit message generator."#));
+    }
+
+    #[tokio::test]
+    async fn anthropic_without_text_blocks_is_an_error() {
+        let (url, _) = mock_server(vec![http_response("200 OK", "", r#"{"content":[]}"#)]).await;
+        assert!(test_backend(url).generate("diff").await.is_err());
+    }
+}
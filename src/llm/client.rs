@@ -0,0 +1,153 @@
+use anyhow::{Context, Result, anyhow};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use reqwest::header::{CONTENT_ENCODING, CONTENT_TYPE};
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+
+use crate::config::Config;
+
+// ---------- HTTP client ----------
+// One reqwest client shared by every backend, so connection settings live in
+// one place and only affect LLM traffic. Proxy selection already happened in
+// `Config::resolve`; reqwest's own env lookup is turned off to match it.
+// Building fails on a bad CA bundle, so no request is ever attempted with it.
+pub(super) fn build_client(config: &Config) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder().no_proxy();
+    if let Some(path) = &config.ca_cert {
+        for cert in load_ca_certs(path)? {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    if config.insecure {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    if let Some(proxy) = &config.proxy {
+        // reqwest's error would include the URL, credentials and all.
+        let proxy = reqwest::Proxy::all(&proxy.url)
+            .map_err(|_| anyhow!("invalid proxy URL {}", proxy.redacted()))?;
+        builder = builder.proxy(proxy);
+    }
+    builder.build().context("failed to build HTTP client")
+}
+
+// Smaller bodies gain nothing from gzip's overhead.
+const COMPRESS_MIN_BYTES: usize = 1024;
+
+// `builder.json(body)`, or with `--compress-requests` the same JSON gzipped
+// and sent as `Content-Encoding: gzip`. Hosted OpenAI and Ollama reject
+// compressed bodies, hence opt-in, for gateways that accept them.
+pub(super) fn json_body<T: Serialize>(
+    builder: reqwest::RequestBuilder,
+    body: &T,
+    compress: bool,
+) -> reqwest::RequestBuilder {
+    if compress
+        && let Ok(json) = serde_json::to_vec(body)
+        && json.len() >= COMPRESS_MIN_BYTES
+    {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        if encoder.write_all(&json).is_ok()
+            && let Ok(gzipped) = encoder.finish()
+        {
+            return builder
+                .header(CONTENT_TYPE, "application/json")
+                .header(CONTENT_ENCODING, "gzip")
+                .body(gzipped);
+        }
+    }
+    builder.json(body)
+}
+
+fn load_ca_certs(path: &Path) -> Result<Vec<reqwest::Certificate>> {
+    let pem = std::fs::read(path)
+        .with_context(|| format!("failed to read CA certificate {}", path.display()))?;
+    let certs = reqwest::Certificate::from_pem_bundle(&pem)
+        .with_context(|| format!("invalid PEM in CA certificate {}", path.display()))?;
+    if certs.is_empty() {
+        return Err(anyhow!(
+            "no certificates found in CA certificate {}",
+            path.display()
+        ));
+    }
+    Ok(certs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn large_bodies_are_gzipped_only_when_asked() {
+        let body = serde_json::json!({ "diff": "+line\n".repeat(500) });
+        let build = |compress| {
+            json_body(
+                reqwest::Client::new().post("http://localhost/"),
+                &body,
+                compress,
+            )
+            .build()
+            .unwrap()
+        };
+
+        let plain = build(false);
+        assert!(plain.headers().get(CONTENT_ENCODING).is_none());
+
+        let gzipped = build(true);
+        assert_eq!(gzipped.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(gzipped.headers()[CONTENT_TYPE], "application/json");
+        let bytes = gzipped.body().and_then(|b| b.as_bytes()).unwrap();
+        assert!(bytes.len() < plain.body().and_then(|b| b.as_bytes()).unwrap().len());
+        let mut json = String::new();
+        flate2::read::GzDecoder::new(bytes)
+            .read_to_string(&mut json)
+            .unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&json).unwrap(),
+            body
+        );
+
+        let small = json_body(
+            reqwest::Client::new().post("http://localhost/"),
+            &serde_json::json!({ "diff": "x" }),
+            true,
+        )
+        .build()
+        .unwrap();
+        assert!(small.headers().get(CONTENT_ENCODING).is_none());
+    }
+
+    fn write_temp(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("git-cmt-rs-{}-{name}", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn malformed_ca_cert_is_rejected_before_any_request() {
+        let empty = write_temp("empty.pem", "not a certificate\n");
+        let err = load_ca_certs(&empty).unwrap_err();
+        assert!(format!("{err}").contains("no certificates found"), "{err}");
+
+        let garbled = write_temp(
+            "garbled.pem",
+            "-----BEGIN CERTIFICATE-----\nZm9vYmFy\n-----END CERTIFICATE-----\n",
+        );
+        let mut config = crate::config::Config::resolve(
+            &clap::Parser::parse_from(["git-cmt-rs"]),
+            &Default::default(),
+            |_| None,
+        )
+        .unwrap();
+        config.ca_cert = Some(garbled.clone());
+        assert!(build_client(&config).is_err());
+
+        config.ca_cert = Some(std::env::temp_dir().join("git-cmt-rs-missing.pem"));
+        assert!(build_client(&config).is_err());
+
+        let _ = std::fs::remove_file(empty);
+        let _ = std::fs::remove_file(garbled);
+    }
+}
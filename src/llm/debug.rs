@@ -0,0 +1,247 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use crate::config::Config;
+
+// Characters of the diff kept at each end of the user message.
+const ELIDE_KEEP: usize = 200;
+const REDACTED: &str = "[REDACTED]";
+
+// ---------- --debug-http ----------
+// Logs what goes over the wire to stderr or `--log-file`. Redaction is
+// structural: the request is serialized to a `serde_json::Value`, the diff
+// is elided and every string is scrubbed of the API key before printing.
+// The key only ever travels in a header, which is never logged.
+pub(super) struct HttpDebug {
+    sink: Mutex<Box<dyn Write + Send>>,
+    secrets: Vec<String>,
+}
+
+impl HttpDebug {
+    pub(super) fn from_config(config: &Config) -> Result<Option<Self>> {
+        if !config.debug_http {
+            return Ok(None);
+        }
+        let sink: Box<dyn Write + Send> = match &config.log_file {
+            Some(path) => Box::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("failed to open log file {}", path.display()))?,
+            ),
+            None => Box::new(io::stderr()),
+        };
+        let secrets = config.api_key.iter().cloned().collect();
+        Ok(Some(Self::new(sink, secrets)))
+    }
+
+    fn new(sink: Box<dyn Write + Send>, secrets: Vec<String>) -> Self {
+        HttpDebug {
+            sink: Mutex::new(sink),
+            secrets: secrets.into_iter().filter(|s| !s.is_empty()).collect(),
+        }
+    }
+
+    pub(super) fn request(&self, url: &str, body: &impl Serialize) {
+        let mut value = serde_json::to_value(body).unwrap_or(Value::Null);
+        elide_user_messages(&mut value);
+        scrub(&mut value, &self.secrets);
+        let pretty = serde_json::to_string_pretty(&value).unwrap_or_default();
+        self.write(&format!("--> POST {url}\n{pretty}\n"));
+    }
+
+    pub(super) fn response_head(
+        &self,
+        status: reqwest::StatusCode,
+        headers: &reqwest::header::HeaderMap,
+    ) {
+        let mut out = format!("<-- {status}\n");
+        for (name, value) in headers {
+            let name = name.as_str();
+            if is_header_of_interest(name) {
+                let value = String::from_utf8_lossy(value.as_bytes());
+                out.push_str(&format!("{name}: {}\n", self.scrub_text(&value)));
+            }
+        }
+        self.write(&out);
+    }
+
+    pub(super) fn response_body(&self, body: &str) {
+        // Scrub JSON bodies value by value; anything else is plain text.
+        let body = match serde_json::from_str::<Value>(body) {
+            Ok(mut value) => {
+                scrub(&mut value, &self.secrets);
+                serde_json::to_string_pretty(&value).unwrap_or_default()
+            }
+            Err(_) => self.scrub_text(body),
+        };
+        self.write(&format!("{body}\n"));
+    }
+
+    fn scrub_text(&self, text: &str) -> String {
+        scrub_str(text, &self.secrets)
+    }
+
+    fn write(&self, text: &str) {
+        if let Ok(mut sink) = self.sink.lock() {
+            let _ = sink.write_all(text.as_bytes());
+            let _ = sink.flush();
+        }
+    }
+}
+
+fn is_header_of_interest(name: &str) -> bool {
+    matches!(name, "x-request-id" | "request-id" | "retry-after") || name.contains("ratelimit")
+}
+
+// Every backend sends the diff as the content of its `user` message(s):
+// under `messages` for Chat Completions, Anthropic and Ollama, under
+// `input` for the Responses API.
+fn elide_user_messages(value: &mut Value) {
+    let Some(body) = value.as_object_mut() else {
+        return;
+    };
+    let messages = body
+        .iter_mut()
+        .filter(|(key, _)| matches!(key.as_str(), "messages" | "input"))
+        .filter_map(|(_, list)| list.as_array_mut())
+        .flatten();
+    for message in messages {
+        if message.get("role").and_then(Value::as_str) != Some("user") {
+            continue;
+        }
+        if let Some(Value::String(content)) = message.get_mut("content") {
+            *content = elide(content);
+        }
+    }
+}
+
+fn elide(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    if chars.len() <= ELIDE_KEEP * 2 {
+        return text.to_string();
+    }
+    let head: String = chars[..ELIDE_KEEP].iter().collect();
+    let tail: String = chars[chars.len() - ELIDE_KEEP..].iter().collect();
+    format!(
+        "{head}[... {} chars elided ...]{tail}",
+        chars.len() - ELIDE_KEEP * 2
+    )
+}
+
+// Replace every occurrence of a secret in every string, key or value.
+fn scrub(value: &mut Value, secrets: &[String]) {
+    match value {
+        Value::String(s) => *s = scrub_str(s, secrets),
+        Value::Array(items) => items.iter_mut().for_each(|v| scrub(v, secrets)),
+        Value::Object(map) => {
+            for (key, mut v) in std::mem::take(map) {
+                scrub(&mut v, secrets);
+                map.insert(scrub_str(&key, secrets), v);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn scrub_str(text: &str, secrets: &[String]) -> String {
+    secrets.iter().fold(text.to_string(), |text, secret| {
+        text.replace(secret.as_str(), REDACTED)
+    })
+}
+
+#[cfg(test)]
+pub(super) mod capture {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use super::HttpDebug;
+
+    // Sink that keeps everything written so tests can inspect the log.
+    #[derive(Clone, Default)]
+    pub struct Captured(pub Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        pub fn text(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+        }
+    }
+
+    pub fn debug_with_secret(secret: &str) -> (HttpDebug, Captured) {
+        let captured = Captured::default();
+        let debug = HttpDebug::new(Box::new(captured.clone()), vec![secret.to_string()]);
+        (debug, captured)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::capture::debug_with_secret;
+    use super::*;
+
+    #[test]
+    fn elide_keeps_both_ends_of_long_text() {
+        assert_eq!(elide("short"), "short");
+        let long = format!("{}{}{}", "a".repeat(200), "b".repeat(50), "c".repeat(200));
+        let elided = elide(&long);
+        assert!(elided.starts_with(&"a".repeat(200)));
+        assert!(elided.ends_with(&"c".repeat(200)));
+        assert!(elided.contains("[... 50 chars elided ...]"));
+        assert!(!elided.contains('b'));
+    }
+
+    #[test]
+    fn request_log_elides_diff_and_scrubs_key() {
+        let key = "sk-live-0123456789";
+        let (debug, captured) = debug_with_secret(key);
+        let diff = format!("+OPENAI_API_KEY={key}\n{}\n+end {key}", "x".repeat(1000));
+        let body = serde_json::json!({
+            "model": "m",
+            "messages": [
+                { "role": "system", "content": "prompt" },
+                { "role": "user", "content": diff }
+            ],
+            key: key
+        });
+        debug.request("https://example.test/chat/completions", &body);
+
+        let log = captured.text();
+        assert!(!log.contains(key), "key leaked: {log}");
+        assert!(log.contains("[REDACTED]"));
+        assert!(log.contains("chars elided"));
+        assert!(log.contains("\"content\": \"prompt\""));
+    }
+
+    #[test]
+    fn responses_input_is_elided_too() {
+        let (debug, captured) = debug_with_secret("sk-unused");
+        let body = serde_json::json!({
+            "model": "m",
+            "input": [
+                { "role": "system", "content": "prompt" },
+                { "role": "user", "content": "x".repeat(1000) }
+            ]
+        });
+        debug.request("https://example.test/responses", &body);
+
+        let log = captured.text();
+        assert!(log.contains("[... 600 chars elided ...]"), "log: {log}");
+        assert!(log.contains("\"content\": \"prompt\""));
+    }
+}
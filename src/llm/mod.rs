@@ -0,0 +1,616 @@
+mod anthropic;
+mod client;
+mod debug;
+mod ollama;
+mod openai;
+mod responses;
+mod retry;
+mod summarize;
+mod usage;
+
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::Commit;
+use crate::cli::{Api, FormatPreset, Privacy, Provider};
+use crate::config::Config;
+use crate::error::GitCmtError;
+use debug::HttpDebug;
+
+pub use anthropic::AnthropicBackend;
+pub use ollama::OllamaBackend;
+pub use openai::OpenAiBackend;
+pub use responses::ResponsesBackend;
+pub use summarize::Summarizer;
+pub use usage::{count_prompt_tokens, diff_budget, estimate_cost};
+
+// ---------- Backend abstraction ----------
+// A provider turns a diff into a `Commit`. Adding a provider means adding a
+// struct that implements this trait and a match arm in `BackendFactory`; the
+// main flow only ever sees `dyn LlmBackend`.
+#[async_trait]
+pub trait LlmBackend: Send + Sync {
+    async fn generate(&self, diff: &str) -> Result<Commit, GitCmtError>;
+
+    // One plain sentence describing a single file's diff; the map step of
+    // `--summarize-large-diffs`.
+    async fn summarize(&self, file_diff: &str) -> Result<String>;
+
+    // A Markdown pull request description for a branch's `git log -p`, for
+    // `--pr-description`.
+    async fn describe_pr(&self, log: &str) -> Result<String>;
+
+    // The message of an annotated `--tag`, from the commit's prompt or the
+    // subjects since the previous tag.
+    async fn annotate_tag(&self, changes: &str) -> Result<String>;
+
+    // Model IDs the provider offers for chat, sorted, for `git-cmt-rs models`.
+    async fn list_models(&self) -> Result<Vec<String>>;
+}
+
+pub struct BackendFactory;
+
+impl BackendFactory {
+    pub fn from_config(config: &Config) -> Result<Box<dyn LlmBackend>> {
+        let client = client::build_client(config)?;
+        let debug = HttpDebug::from_config(config)?;
+        Ok(match config.provider {
+            Provider::OpenAi | Provider::OpenRouter if config.api == Api::Responses => {
+                Box::new(ResponsesBackend::new(config, client, debug))
+            }
+            Provider::OpenAi | Provider::OpenRouter => {
+                Box::new(OpenAiBackend::new(config, client, debug))
+            }
+            Provider::Anthropic => Box::new(AnthropicBackend::new(config, client, debug)),
+            Provider::Ollama => Box::new(OllamaBackend::new(config, client, debug)),
+        })
+    }
+}
+
+// ---------- Shared prompt ----------
+// `{types}` is the `--format` preset's type list; `{body}` is `BODY_FIELD`
+// with `--body`, else nothing.
+const SYSTEM_PROMPT: &str = r#"You are a git commit message generator.
+Analyze changes and output JSON with:
+- type: {types}
+- scope: affected component (optional)
+- message: clear description (50 chars max)
+- breaking: true only if the change breaks users: removed or renamed public APIs, changed CLI flags, changed schemas or file formats; otherwise false
+- breaking_description: when breaking, what breaks and how to migrate (one sentence); otherwise ""
+{body}Return ONLY valid JSON, no other text."#;
+
+const BODY_FIELD: &str = "- body: array of 2 to 4 bullet points saying what changed and why \
+(72 chars max each, no leading dash)\n";
+
+// Appended for `--names-only`, where the model never sees file contents.
+const NAMES_ONLY_PROMPT: &str = "You only see file names, change statuses and line counts, \
+not the contents of the files. Base the message on those and on any hint from the author; \
+do not guess at details you cannot see, and prefer a general description when unsure.";
+
+// Appended for `--brief`, where even statuses and line counts are left out.
+const BRIEF_PROMPT: &str = "You only see the names of the changed files: not their contents, \
+change statuses or line counts, so you lack the full context of the change. Base the message \
+on the file names and on any hint from the author, and keep it general rather than guess.";
+
+const SUMMARY_PROMPT: &str = r#"You summarize the change to one file in a git diff.
+Reply with a single plain sentence saying what changed and why.
+No JSON, no markdown, no file name prefix."#;
+
+const PR_DESCRIPTION_PROMPT: &str = r#"You write pull request descriptions from the commits on a branch, given as `git log -p`.
+Reply in Markdown with exactly these sections:
+## Summary
+One or two sentences on what the branch does and why.
+## Changes
+A bullet list of the notable changes, grouped by area.
+## Testing
+How the changes were or should be tested; say so if the commits don't show it.
+No other text before or after."#;
+
+const TAG_ANNOTATION_PROMPT: &str = r#"You write the message of an annotated git tag marking a release, from the changes it covers.
+Reply in plain text: a first line of at most 72 characters summing up the release, then, if there is more than one notable change, a blank line and a short bullet list ("- ") of them.
+No Markdown headings, no code fences, no other text."#;
+
+// A description is a few paragraphs; the commit message's default cap
+// would cut it off.
+const PR_DESCRIPTION_MIN_TOKENS: u32 = 1024;
+
+// What a request asks the model for. Summaries, PR descriptions and tag
+// annotations are plain text: no structured-output mode and no streaming.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Task {
+    Commit,
+    Summary,
+    PrDescription,
+    TagAnnotation,
+}
+
+impl Task {
+    // `lang` is the `--lang` code; summaries are never shown to the user, so
+    // they stay in English. Names-only prompts never reach the summary step.
+    fn system_prompt(
+        self,
+        lang: Option<&str>,
+        privacy: Privacy,
+        format: FormatPreset,
+        body: bool,
+    ) -> String {
+        match self {
+            Task::Summary => return SUMMARY_PROMPT.to_string(),
+            Task::PrDescription => return PR_DESCRIPTION_PROMPT.to_string(),
+            Task::TagAnnotation => return TAG_ANNOTATION_PROMPT.to_string(),
+            Task::Commit => {}
+        }
+        let formatter = format.formatter();
+        let mut prompt = SYSTEM_PROMPT
+            .replace("{types}", &formatter.types().join("|"))
+            .replace("{body}", if body { BODY_FIELD } else { "" });
+        if let Some(instructions) = formatter.instructions() {
+            prompt = format!("{prompt}\n{instructions}");
+        }
+        match privacy {
+            Privacy::Full => {}
+            Privacy::NamesOnly => prompt = format!("{prompt}\n{NAMES_ONLY_PROMPT}"),
+            Privacy::Brief => prompt = format!("{prompt}\n{BRIEF_PROMPT}"),
+        }
+        if let Some(code) = lang {
+            prompt = format!("{prompt}\n{}", language_instruction(code));
+        }
+        prompt
+    }
+}
+
+// `--max-tokens` as configured, raised to `PR_DESCRIPTION_MIN_TOKENS` for a
+// PR description; no cap stays no cap.
+fn task_max_tokens(task: Task, configured: Option<u32>) -> Option<u32> {
+    match task {
+        Task::PrDescription => configured.map(|max| max.max(PR_DESCRIPTION_MIN_TOKENS)),
+        Task::Commit | Task::Summary | Task::TagAnnotation => configured,
+    }
+}
+
+// ---------- Sampling ----------
+// Greedy decoding (temperature 0) unless `--top-p` is set; then only `top_p`
+// is sent, since providers advise tuning one or the other, not both.
+pub(super) fn temperature(top_p: Option<f32>) -> Option<f32> {
+    top_p.is_none().then_some(0.0)
+}
+
+// Penalties default to 0, which is also the API default, so they are only
+// sent when set.
+pub(super) fn is_zero(penalty: &f32) -> bool {
+    *penalty == 0.0
+}
+
+// ---------- Languages ----------
+// English names for common BCP 47 primary language subtags, used to tell the
+// model which language `--lang` asks for. Codes outside the table are still
+// sent, by code; main warns about them.
+const LANGUAGES: &[(&str, &str)] = &[
+    ("af", "Afrikaans"),
+    ("ar", "Arabic"),
+    ("be", "Belarusian"),
+    ("bg", "Bulgarian"),
+    ("bn", "Bengali"),
+    ("ca", "Catalan"),
+    ("cs", "Czech"),
+    ("cy", "Welsh"),
+    ("da", "Danish"),
+    ("de", "German"),
+    ("el", "Greek"),
+    ("en", "English"),
+    ("eo", "Esperanto"),
+    ("es", "Spanish"),
+    ("et", "Estonian"),
+    ("eu", "Basque"),
+    ("fa", "Persian"),
+    ("fi", "Finnish"),
+    ("fil", "Filipino"),
+    ("fr", "French"),
+    ("ga", "Irish"),
+    ("gl", "Galician"),
+    ("he", "Hebrew"),
+    ("hi", "Hindi"),
+    ("hr", "Croatian"),
+    ("hu", "Hungarian"),
+    ("hy", "Armenian"),
+    ("id", "Indonesian"),
+    ("is", "Icelandic"),
+    ("it", "Italian"),
+    ("ja", "Japanese"),
+    ("ka", "Georgian"),
+    ("kk", "Kazakh"),
+    ("ko", "Korean"),
+    ("lt", "Lithuanian"),
+    ("lv", "Latvian"),
+    ("mk", "Macedonian"),
+    ("mr", "Marathi"),
+    ("ms", "Malay"),
+    ("nb", "Norwegian Bokmål"),
+    ("nl", "Dutch"),
+    ("nn", "Norwegian Nynorsk"),
+    ("no", "Norwegian"),
+    ("pl", "Polish"),
+    ("pt", "Portuguese"),
+    ("ro", "Romanian"),
+    ("ru", "Russian"),
+    ("sk", "Slovak"),
+    ("sl", "Slovenian"),
+    ("sq", "Albanian"),
+    ("sr", "Serbian"),
+    ("sv", "Swedish"),
+    ("sw", "Swahili"),
+    ("ta", "Tamil"),
+    ("te", "Telugu"),
+    ("th", "Thai"),
+    ("tl", "Tagalog"),
+    ("tr", "Turkish"),
+    ("uk", "Ukrainian"),
+    ("ur", "Urdu"),
+    ("uz", "Uzbek"),
+    ("vi", "Vietnamese"),
+    ("zh", "Chinese"),
+];
+
+// The language named by a BCP 47 code's primary subtag (`pt-BR` ->
+// Portuguese), or None if it isn't in `LANGUAGES`.
+pub fn language_name(code: &str) -> Option<&'static str> {
+    let primary = code.trim().split(['-', '_']).next()?.to_ascii_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|(subtag, _)| *subtag == primary)
+        .map(|(_, name)| *name)
+}
+
+// Only `message` is localized: `type` stays one of the listed keywords and
+// `scope` names code, so both stay ASCII and the JSON shape is unchanged.
+fn language_instruction(code: &str) -> String {
+    let code = code.trim();
+    let language = match language_name(code) {
+        Some(name) if code.contains(['-', '_']) => format!("{name} ({code})"),
+        Some(name) => name.to_string(),
+        None => format!("the language with BCP 47 code {code:?}"),
+    };
+    format!(
+        "Write the commit message in {language}. Only the message field is translated; \
+         type and scope stay in English ASCII."
+    )
+}
+
+// The first non-empty line of a summary reply, minus any list marker.
+fn summary_line(content: &str) -> Result<String> {
+    content
+        .lines()
+        .map(|line| line.trim().trim_start_matches(['-', '*']).trim())
+        .find(|line| !line.is_empty())
+        .map(String::from)
+        .ok_or_else(|| anyhow!("empty summary returned"))
+}
+
+// A PR description reply, minus a ```markdown fence some models wrap it in.
+fn pr_description(content: &str) -> Result<String> {
+    let text = unfenced(content);
+    if text.is_empty() {
+        return Err(anyhow!("empty PR description returned"));
+    }
+    Ok(format!("{text}\n"))
+}
+
+// A tag message: the reply without a fence, trailing spaces or blank
+// lines at either end.
+fn tag_annotation(content: &str) -> Result<String> {
+    let text = unfenced(content);
+    if text.is_empty() {
+        return Err(anyhow!("empty tag annotation returned"));
+    }
+    let lines: Vec<&str> = text.lines().map(str::trim_end).collect();
+    Ok(format!("{}\n", lines.join("\n")))
+}
+
+// The reply trimmed and, when the model wrapped all of it in a ``` fence
+// anyway, without the fence.
+fn unfenced(content: &str) -> &str {
+    let text = content.trim();
+    match text.strip_prefix("```") {
+        Some(fenced) => fenced
+            .split_once('\n')
+            .map_or("", |(_, rest)| rest)
+            .trim_end()
+            .trim_end_matches("```")
+            .trim(),
+        None => text,
+    }
+}
+
+fn user_prompt(diff: &str) -> String {
+    format!("Changes:\n{diff}")
+}
+
+fn commit_schema(format: FormatPreset, body: bool) -> serde_json::Value {
+    let mut schema = serde_json::json!({
+        "type": "object",
+        "additionalProperties": false,
+        "required": ["type", "scope", "message", "breaking", "breaking_description"],
+        "properties": {
+            "type":   { "type": "string", "enum": format.formatter().types() },
+            "scope":  { "type": "string" },
+            "message":{ "type": "string", "maxLength": 50 },
+            "breaking": { "type": "boolean" },
+            "breaking_description": { "type": "string" }
+        }
+    });
+    if body {
+        if let Some(required) = schema["required"].as_array_mut() {
+            required.push("body".into());
+        }
+        schema["properties"]["body"] = serde_json::json!({
+            "type": "array",
+            "items": { "type": "string", "maxLength": 72 }
+        });
+    }
+    schema
+}
+
+// Chat message shape shared by the OpenAI, Anthropic and Ollama APIs.
+#[derive(Debug, Serialize)]
+struct Message {
+    role: String,
+    content: String,
+}
+
+impl Message {
+    fn new(role: &str, content: impl Into<String>) -> Self {
+        Message {
+            role: role.into(),
+            content: content.into(),
+        }
+    }
+}
+
+// Read a successful response body, logging it under `--debug-http`.
+async fn response_text(resp: reqwest::Response, debug: Option<&HttpDebug>) -> Result<String> {
+    let text = resp.text().await.context("failed to read LLM response")?;
+    if let Some(debug) = debug {
+        debug.response_body(&text);
+    }
+    Ok(text)
+}
+
+// Shape shared by OpenAI-compatible and Anthropic `GET /models`.
+#[derive(Debug, Deserialize)]
+struct ModelList {
+    data: Vec<ModelEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelEntry {
+    id: String,
+}
+
+// A non-retryable HTTP failure. Kept as a typed error so backends can
+// downcast and react to specific statuses (e.g. response_format fallback).
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: reqwest::StatusCode,
+    pub body: String,
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "LLM request failed with status {}: {}",
+            self.status, self.body
+        )
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn language_codes_name_their_primary_subtag() {
+        assert_eq!(language_name("fr"), Some("French"));
+        assert_eq!(language_name("pt-BR"), Some("Portuguese"));
+        assert_eq!(language_name("ZH_tw"), Some("Chinese"));
+        assert_eq!(language_name("xx"), None);
+
+        assert!(
+            Task::Commit
+                .system_prompt(Some("de"), Privacy::Full, FormatPreset::Conventional, false)
+                .ends_with(
+                    "Write the commit message in German. Only the message field is translated; \
+             type and scope stay in English ASCII."
+                )
+        );
+        assert!(
+            Task::Commit
+                .system_prompt(
+                    Some("pt-BR"),
+                    Privacy::Full,
+                    FormatPreset::Conventional,
+                    false
+                )
+                .contains("in Portuguese (pt-BR).")
+        );
+        assert!(
+            Task::Commit
+                .system_prompt(
+                    Some("tlh"),
+                    Privacy::Full,
+                    FormatPreset::Conventional,
+                    false
+                )
+                .contains(r#"in the language with BCP 47 code "tlh"."#)
+        );
+        assert_eq!(
+            Task::Commit.system_prompt(None, Privacy::Full, FormatPreset::Conventional, false),
+            SYSTEM_PROMPT
+                .replace("{types}", "feat|fix|docs|style|refactor|test|chore|revert")
+                .replace("{body}", "")
+        );
+        assert_eq!(
+            Task::Summary.system_prompt(
+                Some("fr"),
+                Privacy::Full,
+                FormatPreset::Conventional,
+                false
+            ),
+            SUMMARY_PROMPT
+        );
+    }
+
+    #[test]
+    fn format_presets_set_the_prompt_types_and_schema() {
+        let prompt = Task::Commit.system_prompt(None, Privacy::Full, FormatPreset::Angular, false);
+        assert!(prompt.contains("- type: build|ci|docs|feat|fix|perf|refactor|test|revert\n"));
+        assert!(prompt.contains("Follow Angular's commit guidelines"));
+        let schema = commit_schema(FormatPreset::Angular, false);
+        assert_eq!(schema["properties"]["type"]["enum"][1], "ci");
+        let schema = commit_schema(FormatPreset::Emoji, false);
+        assert_eq!(schema["properties"]["type"]["enum"][6], "chore");
+    }
+
+    #[test]
+    fn body_is_only_asked_for_with_the_flag() {
+        let prompt = |body| {
+            Task::Commit.system_prompt(None, Privacy::Full, FormatPreset::Conventional, body)
+        };
+        assert!(!prompt(false).contains("- body:"));
+        assert!(prompt(true).contains("otherwise \"\"\n- body: array of 2 to 4 bullet points"));
+        assert!(prompt(true).ends_with("\nReturn ONLY valid JSON, no other text."));
+        assert!(commit_schema(FormatPreset::Conventional, false)["properties"]["body"].is_null());
+        let schema = commit_schema(FormatPreset::Conventional, true);
+        assert_eq!(schema["required"][5], "body");
+        assert_eq!(schema["properties"]["breaking"]["type"], "boolean");
+        assert_eq!(schema["properties"]["body"]["items"]["maxLength"], 72);
+    }
+
+    #[test]
+    fn names_only_prompt_says_contents_are_not_shown() {
+        let prompt = Task::Commit.system_prompt(
+            Some("fr"),
+            Privacy::NamesOnly,
+            FormatPreset::Conventional,
+            false,
+        );
+        assert!(prompt.starts_with("You are a git commit message generator."));
+        assert!(prompt.contains(NAMES_ONLY_PROMPT));
+        assert!(prompt.ends_with("type and scope stay in English ASCII."));
+        let brief =
+            Task::Commit.system_prompt(None, Privacy::Brief, FormatPreset::Conventional, false);
+        assert!(brief.ends_with(BRIEF_PROMPT));
+        assert!(!brief.contains(NAMES_ONLY_PROMPT));
+        assert_eq!(
+            Task::Summary.system_prompt(
+                None,
+                Privacy::NamesOnly,
+                FormatPreset::Conventional,
+                false
+            ),
+            SUMMARY_PROMPT
+        );
+    }
+
+    #[test]
+    fn pr_description_replies_lose_their_fence() {
+        let reply = "```markdown\n## Summary\nAdds paging.\n```\n";
+        assert_eq!(pr_description(reply).unwrap(), "## Summary\nAdds paging.\n");
+        assert_eq!(
+            pr_description("  ## Summary\nx  ").unwrap(),
+            "## Summary\nx\n"
+        );
+        assert!(pr_description("```\n```").is_err());
+    }
+
+    #[test]
+    fn tag_annotations_are_plain_text() {
+        let reply = "```\nRelease 1.2: paging and retries   \n\n- add paging\n```";
+        assert_eq!(
+            tag_annotation(reply).unwrap(),
+            "Release 1.2: paging and retries\n\n- add paging\n"
+        );
+        assert!(tag_annotation("  \n").is_err());
+        assert_eq!(
+            Task::TagAnnotation.system_prompt(
+                None,
+                Privacy::Full,
+                FormatPreset::Conventional,
+                true
+            ),
+            TAG_ANNOTATION_PROMPT
+        );
+    }
+
+    #[test]
+    fn pr_descriptions_get_their_own_prompt_and_room_to_answer() {
+        let prompt =
+            Task::PrDescription.system_prompt(Some("fr"), Privacy::Full, FormatPreset::Emoji, true);
+        assert_eq!(prompt, PR_DESCRIPTION_PROMPT);
+        assert!(prompt.contains("## Summary") && prompt.contains("## Testing"));
+        assert_eq!(task_max_tokens(Task::PrDescription, Some(256)), Some(1024));
+        assert_eq!(task_max_tokens(Task::PrDescription, Some(4096)), Some(4096));
+        assert_eq!(task_max_tokens(Task::PrDescription, None), None);
+        assert_eq!(task_max_tokens(Task::Commit, Some(256)), Some(256));
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Minimal scripted HTTP server: answers the Nth connection with the Nth
+    // raw response and records each raw request (headers + body).
+    pub async fn mock_server(responses: Vec<String>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut sock, _) = listener.accept().await.unwrap();
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 4096];
+                // Read headers, then the Content-Length body.
+                loop {
+                    let n = sock.read(&mut chunk).await.unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+                    let text = String::from_utf8_lossy(&buf);
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let len = text[..end]
+                            .lines()
+                            .find_map(|l| {
+                                l.to_lowercase()
+                                    .strip_prefix("content-length:")
+                                    .map(|v| v.trim().parse::<usize>().unwrap())
+                            })
+                            .unwrap_or(0);
+                        if buf.len() >= end + 4 + len {
+                            break;
+                        }
+                    }
+                    if n == 0 {
+                        break;
+                    }
+                }
+                seen.lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(&buf).into_owned());
+                sock.write_all(response.as_bytes()).await.unwrap();
+                sock.shutdown().await.unwrap();
+            }
+        });
+        (format!("http://{addr}"), requests)
+    }
+
+    pub fn http_response(status: &str, extra_headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n{extra_headers}\r\n{body}",
+            body.len()
+        )
+    }
+}
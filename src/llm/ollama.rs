@@ -0,0 +1,285 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::client::json_body;
+use super::debug::HttpDebug;
+use super::retry::send_with_retry;
+use super::usage::{Meter, Usage};
+use super::{
+    LlmBackend, Message, Task, is_zero, pr_description, response_text, summary_line,
+    tag_annotation, task_max_tokens, temperature, user_prompt,
+};
+use crate::cli::{FormatPreset, Privacy};
+use crate::config::Config;
+use crate::error::GitCmtError;
+use crate::{Commit, parse_commit};
+
+// Prefixed to generation errors, as for the OpenAI flavors.
+const API_NAME: &str = "Ollama API";
+
+// ---------- Ollama native /api/chat request/response ----------
+#[derive(Debug, Serialize)]
+struct ChatRequest {
+    model: String,
+    messages: Vec<Message>,
+    stream: bool,
+    // Ollama's JSON mode; constrains output to a single JSON value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<&'static str>,
+    options: Options,
+}
+
+#[derive(Debug, Serialize)]
+struct Options {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "is_zero")]
+    frequency_penalty: f32,
+    #[serde(skip_serializing_if = "is_zero")]
+    presence_penalty: f32,
+    // Ollama's name for a completion-token cap.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_predict: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatResponse {
+    message: ResponseMessage,
+    // Ollama reports token counts as top-level eval counters.
+    #[serde(default)]
+    prompt_eval_count: Option<u64>,
+    #[serde(default)]
+    eval_count: Option<u64>,
+}
+
+// `GET /api/tags`: the models pulled locally.
+#[derive(Debug, Deserialize)]
+struct TagList {
+    #[serde(default)]
+    models: Vec<Tag>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Tag {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponseMessage {
+    #[serde(default)]
+    content: String,
+}
+
+// ---------- Backend ----------
+// Talks to Ollama's native API (`OLLAMA_HOST`), so it works without the
+// OpenAI-compatible `/v1` layer. No auth: Ollama doesn't use any.
+pub struct OllamaBackend {
+    client: reqwest::Client,
+    url: String,
+    tags_url: String,
+    model: String,
+    lang: Option<String>,
+    privacy: Privacy,
+    format: FormatPreset,
+    commit_body: bool,
+    compress: bool,
+    top_p: Option<f32>,
+    frequency_penalty: f32,
+    presence_penalty: f32,
+    max_tokens: Option<u32>,
+    retry_budget: Duration,
+    debug: Option<HttpDebug>,
+    meter: Meter,
+}
+
+impl OllamaBackend {
+    pub(super) fn new(config: &Config, client: reqwest::Client, debug: Option<HttpDebug>) -> Self {
+        OllamaBackend {
+            client,
+            url: format!("{}/api/chat", config.base_url),
+            tags_url: format!("{}/api/tags", config.base_url),
+            model: config.model.clone(),
+            lang: config.lang.clone(),
+            privacy: config.privacy,
+            format: config.format,
+            commit_body: config.body,
+            compress: config.compress_requests,
+            top_p: config.top_p,
+            frequency_penalty: config.frequency_penalty,
+            presence_penalty: config.presence_penalty,
+            max_tokens: config.max_tokens,
+            retry_budget: config.retry_budget,
+            debug,
+            meter: Meter::new(config),
+        }
+    }
+
+    fn request(&self, req: &ChatRequest) -> reqwest::RequestBuilder {
+        if let Some(debug) = &self.debug {
+            debug.request(&self.url, req);
+        }
+        json_body(self.client.post(&self.url), req, self.compress)
+    }
+
+    // The assistant message content from `/api/chat`.
+    async fn chat(&self, task: Task, diff: &str) -> Result<String> {
+        let req = ChatRequest {
+            model: self.model.clone(),
+            messages: vec![
+                Message::new(
+                    "system",
+                    task.system_prompt(
+                        self.lang.as_deref(),
+                        self.privacy,
+                        self.format,
+                        self.commit_body,
+                    ),
+                ),
+                Message::new("user", user_prompt(diff)),
+            ],
+            stream: false,
+            format: (task == Task::Commit).then_some("json"),
+            options: Options {
+                temperature: temperature(self.top_p),
+                top_p: self.top_p,
+                frequency_penalty: self.frequency_penalty,
+                presence_penalty: self.presence_penalty,
+                num_predict: task_max_tokens(task, self.max_tokens),
+            },
+        };
+
+        let resp = send_with_retry(
+            || self.request(&req),
+            self.retry_budget,
+            self.debug.as_ref(),
+        )
+        .await?;
+        let text = response_text(resp, self.debug.as_ref()).await?;
+        let parsed: ChatResponse =
+            serde_json::from_str(&text).context("failed to parse LLM response")?;
+        if let (Some(prompt_tokens), Some(completion_tokens)) =
+            (parsed.prompt_eval_count, parsed.eval_count)
+        {
+            self.meter.report(Usage {
+                prompt_tokens,
+                completion_tokens,
+            });
+        }
+
+        Ok(parsed.message.content)
+    }
+}
+
+#[async_trait]
+impl LlmBackend for OllamaBackend {
+    async fn generate(&self, diff: &str) -> Result<Commit, GitCmtError> {
+        let content = self
+            .chat(Task::Commit, diff)
+            .await
+            .map_err(|e| GitCmtError::llm(API_NAME, e))?;
+        parse_commit(&content)
+    }
+
+    async fn summarize(&self, file_diff: &str) -> Result<String> {
+        summary_line(&self.chat(Task::Summary, file_diff).await?)
+    }
+
+    async fn describe_pr(&self, log: &str) -> Result<String> {
+        pr_description(&self.chat(Task::PrDescription, log).await?)
+    }
+
+    async fn annotate_tag(&self, changes: &str) -> Result<String> {
+        tag_annotation(&self.chat(Task::TagAnnotation, changes).await?)
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let resp = send_with_retry(
+            || self.client.get(&self.tags_url),
+            self.retry_budget,
+            self.debug.as_ref(),
+        )
+        .await?;
+        let text = response_text(resp, self.debug.as_ref()).await?;
+        let tags: TagList = serde_json::from_str(&text).context("failed to parse model list")?;
+        let mut names: Vec<String> = tags.models.into_iter().map(|m| m.name).collect();
+        names.sort();
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::{http_response, mock_server};
+    use super::*;
+
+    #[tokio::test]
+    async fn ollama_uses_native_chat_endpoint_in_json_mode() {
+        let body = serde_json::json!({
+            "model": "qwen2.5-coder",
+            "message": { "role": "assistant", "content": r#"{"type":"chore","scope":"deps","message":"bump"}"# },
+            "done": true
+        })
+        .to_string();
+        let (url, requests) = mock_server(vec![http_response("200 OK", "", &body)]).await;
+
+        let backend = OllamaBackend {
+            client: reqwest::Client::new(),
+            url: format!("{url}/api/chat"),
+            tags_url: format!("{url}/api/tags"),
+            model: "qwen2.5-coder".into(),
+            lang: None,
+            privacy: Privacy::Full,
+            format: FormatPreset::Conventional,
+            commit_body: false,
+            compress: false,
+            top_p: None,
+            frequency_penalty: 0.0,
+            presence_penalty: 0.0,
+            max_tokens: None,
+            retry_budget: Duration::from_secs(5),
+            debug: None,
+            meter: Meter::default(),
+        };
+        let commit = backend.generate("diff").await.unwrap();
+        assert_eq!(commit.r#type, "chore");
+        assert_eq!(commit.message, "bump");
+
+        let request = requests.lock().unwrap()[0].clone();
+        assert!(request.starts_with("POST /api/chat "), "request: {request}");
+        assert!(request.contains(r#""format":"json""#));
+        assert!(request.contains(r#""stream":false"#));
+        assert!(!request.to_lowercase().contains("authorization:"));
+    }
+
+    #[tokio::test]
+    async fn ollama_lists_local_tags() {
+        let body = r#"{"models":[{"name":"qwen2.5-coder:7b"},{"name":"llama3.1:8b"}]}"#;
+        let (url, requests) = mock_server(vec![http_response("200 OK", "", body)]).await;
+
+        let backend = OllamaBackend {
+            client: reqwest::Client::new(),
+            url: format!("{url}/api/chat"),
+            tags_url: format!("{url}/api/tags"),
+            model: "qwen2.5-coder".into(),
+            lang: None,
+            privacy: Privacy::Full,
+            format: FormatPreset::Conventional,
+            commit_body: false,
+            compress: false,
+            top_p: None,
+            frequency_penalty: 0.0,
+            presence_penalty: 0.0,
+            max_tokens: None,
+            retry_budget: Duration::from_secs(5),
+            debug: None,
+            meter: Meter::default(),
+        };
+        let models = backend.list_models().await.unwrap();
+        assert_eq!(models, ["llama3.1:8b", "qwen2.5-coder:7b"]);
+        assert!(requests.lock().unwrap()[0].starts_with("GET /api/tags "));
+    }
+}
@@ -0,0 +1,1122 @@
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::io::{self, IsTerminal, Write};
+use std::time::Duration;
+
+use super::client::json_body;
+use super::debug::HttpDebug;
+use super::retry::send_with_retry;
+use super::usage::{Meter, Usage};
+use super::{
+    ApiError, LlmBackend, Message, ModelList, Task, commit_schema, is_zero, pr_description,
+    response_text, summary_line, tag_annotation, task_max_tokens, temperature, user_prompt,
+};
+use crate::cli::{FormatPreset, MaxTokensField, Privacy, Provider};
+use crate::config::Config;
+use crate::error::GitCmtError;
+use crate::{Commit, parse_commit};
+
+const OPENROUTER_REFERER: &str = "https://github.com/AaronSaikovski/git-cmt-rs";
+// Prefixed to every error so it's clear which OpenAI API flavor failed.
+const API_NAME: &str = "Chat Completions API";
+
+// ---------- OpenAI Chat Completions request/response ----------
+#[derive(Debug, Serialize)]
+struct ChatRequest {
+    model: String,
+    messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "is_zero")]
+    frequency_penalty: f32,
+    #[serde(skip_serializing_if = "is_zero")]
+    presence_penalty: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<ResponseFormat>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    // At most one of these is set, per `MaxTokensField`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_completion_tokens: Option<u32>,
+}
+
+impl ChatRequest {
+    fn set_max_tokens(&mut self, max: Option<u32>, field: MaxTokensField) {
+        (self.max_tokens, self.max_completion_tokens) = match field {
+            MaxTokensField::MaxTokens => (max, None),
+            MaxTokensField::MaxCompletionTokens => (None, max),
+        };
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ResponseFormat {
+    JsonObject,
+    JsonSchema { json_schema: JsonSchema },
+}
+
+#[derive(Debug, Serialize)]
+struct JsonSchema {
+    name: String,
+    schema: serde_json::Value,
+    strict: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatResponse {
+    choices: Vec<Choice>,
+    #[serde(default)]
+    usage: Option<ChatUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatUsage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Choice {
+    message: ChoiceMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChoiceMessage {
+    content: String,
+}
+
+// Streaming (`stream: true`) responses arrive as SSE `data:` events, each
+// carrying an incremental `delta` instead of a full `message`.
+#[derive(Debug, Deserialize)]
+struct StreamChunk {
+    #[serde(default)]
+    choices: Vec<StreamChoice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamChoice {
+    #[serde(default)]
+    delta: StreamDelta,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct StreamDelta {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+// Default is `json_object` so the tool works against Ollama and most local
+// proxies; hosted OpenAI users can opt back into strict schema validation
+// with `json_schema`.
+fn build_response_format(
+    raw: Option<&str>,
+    schema: serde_json::Value,
+) -> Result<Option<ResponseFormat>> {
+    match raw.unwrap_or("json_object").trim().to_lowercase().as_str() {
+        "json_object" => Ok(Some(ResponseFormat::JsonObject)),
+        "json_schema" => Ok(Some(ResponseFormat::JsonSchema {
+            json_schema: JsonSchema {
+                name: "commit_message".into(),
+                schema,
+                strict: true,
+            },
+        })),
+        "none" => Ok(None),
+        other => Err(anyhow!(
+            "OPENAI_RESPONSE_FORMAT must be one of: json_object, json_schema, none (got: {other:?})"
+        )),
+    }
+}
+
+// A 400 whose body complains about `response_format` / `json_schema` means
+// the endpoint doesn't support the structured-output mode we asked for.
+fn rejects_response_format(status: reqwest::StatusCode, body: &str) -> bool {
+    if status != reqwest::StatusCode::BAD_REQUEST {
+        return false;
+    }
+    let body = body.to_lowercase();
+    body.contains("response_format") || body.contains("json_schema")
+}
+
+// Next, more widely supported mode to try after `current` was rejected:
+// json_schema -> json_object -> none (`parse_commit` copes with free text).
+fn downgrade_response_format(current: &ResponseFormat) -> Option<ResponseFormat> {
+    match current {
+        ResponseFormat::JsonSchema { .. } => Some(ResponseFormat::JsonObject),
+        ResponseFormat::JsonObject => None,
+    }
+}
+
+// The `OPENAI_RESPONSE_FORMAT` value that selects `format`.
+fn response_format_name(format: Option<&ResponseFormat>) -> &'static str {
+    match format {
+        Some(ResponseFormat::JsonSchema { .. }) => "json_schema",
+        Some(ResponseFormat::JsonObject) => "json_object",
+        None => "none",
+    }
+}
+
+// ---------- Auth ----------
+// Headers every OpenAI-style request carries; shared with `ResponsesBackend`.
+#[derive(Debug, Default)]
+pub(super) struct OpenAiAuth {
+    pub(super) api_key: Option<String>,
+    // OpenRouter requires an `HTTP-Referer` header for abuse prevention.
+    pub(super) referer: Option<&'static str>,
+    // `OpenAI-Organization` / `OpenAI-Project` routing for team accounts.
+    pub(super) org: Option<String>,
+    pub(super) project: Option<String>,
+}
+
+impl OpenAiAuth {
+    pub(super) fn new(config: &Config) -> Self {
+        OpenAiAuth {
+            api_key: config.api_key.clone(),
+            referer: (config.provider == Provider::OpenRouter).then_some(OPENROUTER_REFERER),
+            org: config.org.clone(),
+            project: config.project.clone(),
+        }
+    }
+
+    pub(super) fn apply(&self, mut builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        if let Some(key) = &self.api_key {
+            builder = builder.bearer_auth(key);
+        }
+        if let Some(referer) = self.referer {
+            builder = builder.header(reqwest::header::REFERER, referer);
+        }
+        if let Some(org) = &self.org {
+            builder = builder.header("OpenAI-Organization", org);
+        }
+        if let Some(project) = &self.project {
+            builder = builder.header("OpenAI-Project", project);
+        }
+        builder
+    }
+}
+
+// ---------- Backend ----------
+// Chat Completions client for hosted OpenAI, OpenRouter, and any
+// OpenAI-compatible endpoint (Ollama's /v1, MLX, LiteLLM, vLLM, ...).
+pub struct OpenAiBackend {
+    client: reqwest::Client,
+    url: String,
+    models_url: String,
+    auth: OpenAiAuth,
+    model: String,
+    lang: Option<String>,
+    privacy: Privacy,
+    format: FormatPreset,
+    commit_body: bool,
+    compress: bool,
+    top_p: Option<f32>,
+    frequency_penalty: f32,
+    presence_penalty: f32,
+    response_format: Option<String>,
+    response_format_fallback: bool,
+    stream: bool,
+    max_tokens: Option<u32>,
+    max_tokens_field: MaxTokensField,
+    retry_budget: Duration,
+    debug: Option<HttpDebug>,
+    meter: Meter,
+}
+
+impl OpenAiBackend {
+    pub(super) fn new(config: &Config, client: reqwest::Client, debug: Option<HttpDebug>) -> Self {
+        OpenAiBackend {
+            client,
+            url: format!("{}/chat/completions", config.base_url),
+            models_url: format!("{}/models", config.base_url),
+            auth: OpenAiAuth::new(config),
+            model: config.model.clone(),
+            lang: config.lang.clone(),
+            privacy: config.privacy,
+            format: config.format,
+            commit_body: config.body,
+            compress: config.compress_requests,
+            top_p: config.top_p,
+            frequency_penalty: config.frequency_penalty,
+            presence_penalty: config.presence_penalty,
+            response_format: config.response_format.clone(),
+            response_format_fallback: config.response_format_fallback,
+            stream: config.stream,
+            max_tokens: config.max_tokens,
+            max_tokens_field: config.max_tokens_field,
+            retry_budget: config.retry_budget,
+            debug,
+            meter: Meter::new(config),
+        }
+    }
+
+    fn request(&self, req: &ChatRequest) -> reqwest::RequestBuilder {
+        if let Some(debug) = &self.debug {
+            debug.request(&self.url, req);
+        }
+        json_body(
+            self.auth.apply(self.client.post(&self.url)),
+            req,
+            self.compress,
+        )
+    }
+
+    // The raw assistant content from `/chat/completions`.
+    async fn complete(&self, task: Task, diff: &str) -> Result<String> {
+        let commit = task == Task::Commit;
+        let mut req = ChatRequest {
+            model: self.model.clone(),
+            messages: vec![
+                Message::new(
+                    "system",
+                    task.system_prompt(
+                        self.lang.as_deref(),
+                        self.privacy,
+                        self.format,
+                        self.commit_body,
+                    ),
+                ),
+                Message::new("user", user_prompt(diff)),
+            ],
+            temperature: temperature(self.top_p),
+            top_p: self.top_p,
+            frequency_penalty: self.frequency_penalty,
+            presence_penalty: self.presence_penalty,
+            response_format: if commit {
+                build_response_format(
+                    self.response_format.as_deref(),
+                    commit_schema(self.format, self.commit_body),
+                )?
+            } else {
+                None
+            },
+            stream: self.stream && commit,
+            max_tokens: None,
+            max_completion_tokens: None,
+        };
+        req.set_max_tokens(
+            task_max_tokens(task, self.max_tokens),
+            self.max_tokens_field,
+        );
+
+        // Modes the endpoint rejected, for the one warning once one works.
+        let mut rejected = Vec::new();
+        let resp = loop {
+            let err = match send_with_retry(
+                || self.request(&req),
+                self.retry_budget,
+                self.debug.as_ref(),
+            )
+            .await
+            {
+                Ok(resp) => break resp,
+                Err(err) => err,
+            };
+
+            // Many OpenAI-compatible servers, some OpenAI regions and older
+            // models (`unsupported_response_format`), and some Azure API
+            // versions 400 on `json_schema`; step down json_schema ->
+            // json_object -> none rather than failing the whole run, unless
+            // `response_format_fallback = false`.
+            if self.response_format_fallback
+                && let Some(api) = err.downcast_ref::<ApiError>()
+                && rejects_response_format(api.status, &api.body)
+                && let Some(current) = &req.response_format
+            {
+                rejected.push(response_format_name(Some(current)));
+                req.response_format = downgrade_response_format(current);
+                continue;
+            }
+
+            return Err(err);
+        };
+
+        if !rejected.is_empty() {
+            let name = response_format_name(req.response_format.as_ref());
+            eprintln!(
+                "Warning: endpoint rejected response_format={}; used {name} instead. Set OPENAI_RESPONSE_FORMAT={name} to skip probing next time.",
+                rejected.join(", ")
+            );
+        }
+
+        // Servers that don't support streaming may ignore `stream: true` and
+        // answer with a regular JSON body; only take the SSE path when they
+        // actually sent an event stream.
+        if self.stream && is_event_stream(&resp) {
+            let content = read_streamed_content(resp).await?;
+            if let Some(debug) = &self.debug {
+                debug.response_body(&content);
+            }
+            return Ok(content);
+        }
+
+        let text = response_text(resp, self.debug.as_ref()).await?;
+        let parsed: ChatResponse =
+            serde_json::from_str(&text).context("failed to parse LLM response")?;
+        if let Some(usage) = &parsed.usage {
+            self.meter.report(Usage {
+                prompt_tokens: usage.prompt_tokens,
+                completion_tokens: usage.completion_tokens,
+            });
+        }
+        parsed
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message.content)
+            .ok_or_else(|| anyhow!("no choices returned"))
+    }
+}
+
+// `/models` on hosted OpenAI also lists embeddings, audio, image and
+// moderation models; keep only what can serve chat completions.
+fn is_chat_model(id: &str) -> bool {
+    const NON_CHAT: [&str; 10] = [
+        "embedding",
+        "whisper",
+        "tts",
+        "dall-e",
+        "davinci",
+        "babbage",
+        "moderation",
+        "transcribe",
+        "image",
+        "realtime",
+    ];
+    let id = id.to_lowercase();
+    !NON_CHAT.iter().any(|marker| id.contains(marker))
+}
+
+#[async_trait]
+impl LlmBackend for OpenAiBackend {
+    async fn generate(&self, diff: &str) -> Result<Commit, GitCmtError> {
+        let content = self
+            .complete(Task::Commit, diff)
+            .await
+            .map_err(|e| GitCmtError::llm(API_NAME, e))?;
+        // Model should have returned strict JSON per schema, but local models
+        // (e.g. Gemma via Ollama) often wrap it in markdown fences or stray text.
+        parse_commit(&content)
+    }
+
+    async fn summarize(&self, file_diff: &str) -> Result<String> {
+        let content = self
+            .complete(Task::Summary, file_diff)
+            .await
+            .map_err(|e| anyhow!("{API_NAME}: {e:#}"))?;
+        summary_line(&content)
+    }
+
+    async fn describe_pr(&self, log: &str) -> Result<String> {
+        let content = self
+            .complete(Task::PrDescription, log)
+            .await
+            .map_err(|e| anyhow!("{API_NAME}: {e:#}"))?;
+        pr_description(&content)
+    }
+
+    async fn annotate_tag(&self, changes: &str) -> Result<String> {
+        let content = self
+            .complete(Task::TagAnnotation, changes)
+            .await
+            .map_err(|e| anyhow!("{API_NAME}: {e:#}"))?;
+        tag_annotation(&content)
+    }
+
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let resp = send_with_retry(
+            || self.auth.apply(self.client.get(&self.models_url)),
+            self.retry_budget,
+            self.debug.as_ref(),
+        )
+        .await?;
+        let text = response_text(resp, self.debug.as_ref()).await?;
+        let list: ModelList = serde_json::from_str(&text).context("failed to parse model list")?;
+        let mut ids: Vec<String> = list
+            .data
+            .into_iter()
+            .map(|m| m.id)
+            .filter(|id| is_chat_model(id))
+            .collect();
+        ids.sort();
+        Ok(ids)
+    }
+}
+
+// ---------- Streaming ----------
+const SPINNER_FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+fn is_event_stream(resp: &reqwest::Response) -> bool {
+    resp.headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/event-stream"))
+}
+
+// Consume an SSE chat-completions stream, accumulating `delta.content` while
+// keeping a spinner and running character count on stderr.
+async fn read_streamed_content(mut resp: reqwest::Response) -> Result<String> {
+    let mut decoder = SseDecoder::default();
+    let mut progress = Progress::new();
+    let mut content = String::new();
+    let mut ticker = tokio::time::interval(Duration::from_millis(100));
+
+    'outer: loop {
+        tokio::select! {
+            chunk = resp.chunk() => {
+                let Some(chunk) = chunk.context("failed to read LLM response stream")? else {
+                    break;
+                };
+                for data in decoder.push(&chunk) {
+                    if data == "[DONE]" {
+                        break 'outer;
+                    }
+                    if let Some(delta) = parse_stream_delta(&data)? {
+                        content.push_str(&delta);
+                    }
+                }
+                progress.render(content.chars().count());
+            }
+            _ = ticker.tick() => progress.render(content.chars().count()),
+        }
+    }
+
+    progress.finish();
+    Ok(content)
+}
+
+// Extract the content delta from a single SSE `data:` payload. Chunks with no
+// content (role announcements, finish markers) yield `None`.
+fn parse_stream_delta(data: &str) -> Result<Option<String>> {
+    let chunk: StreamChunk = serde_json::from_str(data)
+        .with_context(|| format!("failed to parse LLM stream chunk (raw: {data:?})"))?;
+    Ok(chunk
+        .choices
+        .into_iter()
+        .next()
+        .and_then(|c| c.delta.content))
+}
+
+// Incremental Server-Sent Events decoder. Network chunks don't respect line
+// (or UTF-8) boundaries, so bytes are buffered until a full line arrives;
+// only `data:` fields are returned, comments and other fields are skipped.
+#[derive(Debug, Default)]
+struct SseDecoder {
+    buf: Vec<u8>,
+}
+
+impl SseDecoder {
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buf.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(pos) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if let Some(data) = line.strip_prefix("data:") {
+                events.push(data.trim_start().to_string());
+            }
+        }
+        events
+    }
+}
+
+// Spinner plus live character count on stderr. Silent when stderr isn't a
+// terminal so redirected logs don't fill up with carriage returns.
+struct Progress {
+    enabled: bool,
+    frame: usize,
+}
+
+impl Progress {
+    fn new() -> Self {
+        Self {
+            enabled: io::stderr().is_terminal(),
+            frame: 0,
+        }
+    }
+
+    fn render(&mut self, chars: usize) {
+        if !self.enabled {
+            return;
+        }
+        let spinner = SPINNER_FRAMES[self.frame % SPINNER_FRAMES.len()];
+        self.frame += 1;
+        eprint!("\r{spinner} Receiving response... {chars} chars");
+        let _ = io::stderr().flush();
+    }
+
+    fn finish(&self) {
+        if self.enabled {
+            eprint!("\r{:60}\r", "");
+            let _ = io::stderr().flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::debug::capture::debug_with_secret;
+    use super::super::mock::{http_response, mock_server};
+    use super::*;
+
+    fn empty_schema() -> serde_json::Value {
+        serde_json::json!({})
+    }
+
+    // ---------- build_response_format ----------
+
+    #[test]
+    fn response_format_default_is_json_object() {
+        let f = build_response_format(None, empty_schema()).unwrap();
+        assert!(matches!(f, Some(ResponseFormat::JsonObject)));
+    }
+
+    #[test]
+    fn response_format_explicit_json_object() {
+        let f = build_response_format(Some("json_object"), empty_schema()).unwrap();
+        assert!(matches!(f, Some(ResponseFormat::JsonObject)));
+    }
+
+    #[test]
+    fn response_format_json_schema_has_strict_and_name() {
+        let f = build_response_format(Some("json_schema"), empty_schema()).unwrap();
+        match f {
+            Some(ResponseFormat::JsonSchema { json_schema }) => {
+                assert_eq!(json_schema.name, "commit_message");
+                assert!(json_schema.strict);
+            }
+            other => panic!("expected JsonSchema variant, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn response_format_none_returns_no_payload() {
+        let f = build_response_format(Some("none"), empty_schema()).unwrap();
+        assert!(f.is_none());
+    }
+
+    #[test]
+    fn response_format_is_case_insensitive_and_trimmed() {
+        let f = build_response_format(Some("  JSON_Object  "), empty_schema()).unwrap();
+        assert!(matches!(f, Some(ResponseFormat::JsonObject)));
+    }
+
+    #[test]
+    fn response_format_unknown_value_lists_valid_choices() {
+        let err = build_response_format(Some("garbage"), empty_schema()).unwrap_err();
+        let msg = format!("{err}");
+        assert!(msg.contains("json_object"), "msg: {msg}");
+        assert!(msg.contains("json_schema"), "msg: {msg}");
+        assert!(msg.contains("none"), "msg: {msg}");
+        assert!(msg.contains("garbage"), "msg: {msg}");
+    }
+
+    // ---------- response_format fallback ----------
+
+    #[test]
+    fn rejects_response_format_on_400_mentioning_it() {
+        let body = r#"{"error":{"message":"Invalid parameter: 'response_format.json_schema' is not supported"}}"#;
+        assert!(rejects_response_format(
+            reqwest::StatusCode::BAD_REQUEST,
+            body
+        ));
+        assert!(rejects_response_format(
+            reqwest::StatusCode::BAD_REQUEST,
+            "unknown field RESPONSE_FORMAT"
+        ));
+    }
+
+    #[test]
+    fn rejects_response_format_ignores_other_errors() {
+        assert!(!rejects_response_format(
+            reqwest::StatusCode::BAD_REQUEST,
+            "model not found"
+        ));
+        assert!(!rejects_response_format(
+            reqwest::StatusCode::UNAUTHORIZED,
+            "response_format"
+        ));
+    }
+
+    #[test]
+    fn downgrade_steps_schema_to_object_to_none() {
+        let schema = build_response_format(Some("json_schema"), empty_schema())
+            .unwrap()
+            .unwrap();
+        let object = downgrade_response_format(&schema);
+        assert!(matches!(object, Some(ResponseFormat::JsonObject)));
+        assert!(downgrade_response_format(&object.unwrap()).is_none());
+    }
+
+    #[test]
+    fn response_format_names_round_trip_through_builder() {
+        for name in ["json_object", "json_schema", "none"] {
+            let f = build_response_format(Some(name), empty_schema()).unwrap();
+            assert_eq!(response_format_name(f.as_ref()), name);
+        }
+    }
+
+    // ---------- ResponseFormat wire format (regression guard for the enum tag) ----------
+
+    #[test]
+    fn json_object_serializes_with_type_only() {
+        let v = serde_json::to_value(ResponseFormat::JsonObject).unwrap();
+        assert_eq!(v, serde_json::json!({ "type": "json_object" }));
+    }
+
+    #[test]
+    fn json_schema_serializes_with_nested_schema() {
+        let rf = ResponseFormat::JsonSchema {
+            json_schema: JsonSchema {
+                name: "commit_message".into(),
+                schema: serde_json::json!({ "type": "object" }),
+                strict: true,
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(rf).unwrap(),
+            serde_json::json!({
+                "type": "json_schema",
+                "json_schema": {
+                    "name": "commit_message",
+                    "schema": { "type": "object" },
+                    "strict": true,
+                }
+            })
+        );
+    }
+
+    // ---------- Streaming ----------
+
+    #[test]
+    fn sse_decoder_reassembles_lines_split_across_chunks() {
+        let mut d = SseDecoder::default();
+        assert!(d.push(b"data: {\"a\"").is_empty());
+        assert_eq!(
+            d.push(b":1}\r\n\ndata: [DONE]\n"),
+            vec!["{\"a\":1}", "[DONE]"]
+        );
+    }
+
+    #[test]
+    fn sse_decoder_skips_comments_and_other_fields() {
+        let mut d = SseDecoder::default();
+        let events = d.push(b": keep-alive\nevent: message\ndata: x\n");
+        assert_eq!(events, vec!["x"]);
+    }
+
+    #[test]
+    fn sse_decoder_handles_utf8_split_mid_character() {
+        let mut d = SseDecoder::default();
+        let bytes = "data: é\n".as_bytes();
+        assert!(d.push(&bytes[..7]).is_empty());
+        assert_eq!(d.push(&bytes[7..]), vec!["é"]);
+    }
+
+    #[test]
+    fn stream_delta_extracts_content() {
+        let data = r#"{"choices":[{"index":0,"delta":{"content":"{\"ty"}}]}"#;
+        assert_eq!(parse_stream_delta(data).unwrap().as_deref(), Some("{\"ty"));
+    }
+
+    #[test]
+    fn stream_delta_without_content_is_none() {
+        let data = r#"{"choices":[{"index":0,"delta":{"role":"assistant"}}]}"#;
+        assert_eq!(parse_stream_delta(data).unwrap(), None);
+        assert_eq!(parse_stream_delta(r#"{"choices":[]}"#).unwrap(), None);
+    }
+
+    #[test]
+    fn chat_request_omits_stream_unless_enabled() {
+        let mut req = ChatRequest {
+            model: "m".into(),
+            messages: vec![],
+            temperature: Some(0.0),
+            top_p: None,
+            frequency_penalty: 0.0,
+            presence_penalty: 0.0,
+            response_format: None,
+            stream: false,
+            max_tokens: None,
+            max_completion_tokens: None,
+        };
+        let v = serde_json::to_value(&req).unwrap();
+        assert!(v.get("stream").is_none());
+        req.stream = true;
+        let v = serde_json::to_value(&req).unwrap();
+        assert_eq!(v["stream"], serde_json::json!(true));
+    }
+
+    #[test]
+    fn chat_request_omits_penalties_unless_nonzero() {
+        let mut req = ChatRequest {
+            model: "m".into(),
+            messages: vec![],
+            temperature: Some(0.0),
+            top_p: None,
+            frequency_penalty: 0.0,
+            presence_penalty: 0.0,
+            response_format: None,
+            stream: false,
+            max_tokens: None,
+            max_completion_tokens: None,
+        };
+        let v = serde_json::to_value(&req).unwrap();
+        assert!(v.get("frequency_penalty").is_none());
+        assert!(v.get("presence_penalty").is_none());
+        req.frequency_penalty = 0.5;
+        req.presence_penalty = -1.0;
+        let v = serde_json::to_value(&req).unwrap();
+        assert_eq!(v["frequency_penalty"], 0.5);
+        assert_eq!(v["presence_penalty"], -1.0);
+    }
+
+    #[test]
+    fn chat_request_max_tokens_field_is_switchable() {
+        let mut req = ChatRequest {
+            model: "m".into(),
+            messages: vec![],
+            temperature: Some(0.0),
+            top_p: None,
+            frequency_penalty: 0.0,
+            presence_penalty: 0.0,
+            response_format: None,
+            stream: false,
+            max_tokens: None,
+            max_completion_tokens: None,
+        };
+        req.set_max_tokens(None, MaxTokensField::MaxTokens);
+        let v = serde_json::to_value(&req).unwrap();
+        assert!(v.get("max_tokens").is_none());
+        assert!(v.get("max_completion_tokens").is_none());
+
+        req.set_max_tokens(Some(128), MaxTokensField::MaxTokens);
+        let v = serde_json::to_value(&req).unwrap();
+        assert_eq!(v["max_tokens"], 128);
+        assert!(v.get("max_completion_tokens").is_none());
+
+        req.set_max_tokens(Some(128), MaxTokensField::MaxCompletionTokens);
+        let v = serde_json::to_value(&req).unwrap();
+        assert!(v.get("max_tokens").is_none());
+        assert_eq!(v["max_completion_tokens"], 128);
+    }
+
+    fn test_backend(base_url: String, retry_budget: Duration) -> OpenAiBackend {
+        OpenAiBackend {
+            client: reqwest::Client::new(),
+            url: format!("{base_url}/chat/completions"),
+            models_url: format!("{base_url}/models"),
+            auth: OpenAiAuth::default(),
+            model: "test-model".into(),
+            lang: None,
+            privacy: Privacy::Full,
+            format: FormatPreset::Conventional,
+            commit_body: false,
+            compress: false,
+            top_p: None,
+            frequency_penalty: 0.0,
+            presence_penalty: 0.0,
+            response_format: None,
+            response_format_fallback: true,
+            stream: false,
+            max_tokens: None,
+            max_tokens_field: MaxTokensField::MaxTokens,
+            retry_budget,
+            debug: None,
+            meter: Meter::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn openai_retries_429_then_succeeds() {
+        let ok_body = serde_json::json!({
+            "choices": [{ "message": { "content": r#"{"type":"fix","scope":"","message":"retry"}"# } }]
+        })
+        .to_string();
+        let (url, hits) = mock_server(vec![
+            http_response("429 Too Many Requests", "Retry-After: 0\r\n", "{}"),
+            http_response("200 OK", "", &ok_body),
+        ])
+        .await;
+
+        let commit = test_backend(url, Duration::from_secs(5))
+            .generate("diff")
+            .await
+            .unwrap();
+        assert_eq!(commit.message, "retry");
+        assert_eq!(hits.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn debug_http_never_logs_the_api_key() {
+        let key = "sk-proj-LEAKED0123456789";
+        let ok_body = serde_json::json!({
+            "choices": [{ "message": { "content": format!(r#"{{"type":"fix","scope":"","message":"{key}"}}"#) } }]
+        })
+        .to_string();
+        let (url, requests) = mock_server(vec![
+            http_response(
+                "429 Too Many Requests",
+                "Retry-After: 0\r\nx-request-id: req_123\r\nx-ratelimit-remaining-requests: 0\r\n",
+                &format!("invalid key {key}"),
+            ),
+            http_response("200 OK", "", &ok_body),
+        ])
+        .await;
+
+        let (debug, captured) = debug_with_secret(key);
+        let backend = OpenAiBackend {
+            auth: OpenAiAuth {
+                api_key: Some(key.into()),
+                ..OpenAiAuth::default()
+            },
+            debug: Some(debug),
+            ..test_backend(url, Duration::from_secs(5))
+        };
+        // The diff itself leaks the key, e.g. a committed `.env`.
+        let diff = format!("+OPENAI_API_KEY={key}\n{}", "+filler\n".repeat(100));
+        backend.generate(&diff).await.unwrap();
+
+        // The key really was sent (header and diff), but never logged.
+        assert!(requests.lock().unwrap()[0].contains(key));
+        let log = captured.text();
+        assert!(!log.contains(key), "key leaked into debug log:\n{log}");
+        assert!(log.contains("--> POST "));
+        assert!(log.contains("<-- 429 Too Many Requests"));
+        assert!(log.contains("x-request-id: req_123"));
+        assert!(log.contains("x-ratelimit-remaining-requests: 0"));
+        assert!(log.contains("chars elided"));
+        assert!(log.contains("<-- 200 OK"));
+    }
+
+    #[tokio::test]
+    async fn openai_gives_up_when_budget_is_exhausted() {
+        let (url, hits) = mock_server(vec![http_response(
+            "429 Too Many Requests",
+            "Retry-After: 30\r\n",
+            "slow down",
+        )])
+        .await;
+
+        let err = test_backend(url, Duration::from_secs(1))
+            .generate("diff")
+            .await
+            .unwrap_err();
+        let msg = format!("{err}");
+        assert!(msg.contains("retry budget"), "msg: {msg}");
+        assert!(msg.contains("slow down"), "msg: {msg}");
+        assert_eq!(hits.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn openai_does_not_retry_client_errors() {
+        let (url, hits) = mock_server(vec![http_response("401 Unauthorized", "", "bad key")]).await;
+
+        let err = test_backend(url, Duration::from_secs(5))
+            .generate("diff")
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, GitCmtError::Api { status: 401, .. }),
+            "err: {err}"
+        );
+        assert!(format!("{err}").starts_with("Chat Completions API: "));
+        assert_eq!(hits.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn openai_falls_back_when_json_schema_is_rejected() {
+        let ok_body = serde_json::json!({
+            "choices": [{ "message": { "content": r#"{"type":"feat","scope":"","message":"fallback"}"# } }]
+        })
+        .to_string();
+        let (url, requests) = mock_server(vec![
+            http_response(
+                "400 Bad Request",
+                "",
+                r#"{"error":{"message":"response_format json_schema unsupported"}}"#,
+            ),
+            http_response("200 OK", "", &ok_body),
+        ])
+        .await;
+
+        let mut backend = test_backend(url, Duration::from_secs(5));
+        backend.response_format = Some("json_schema".into());
+        let commit = backend.generate("diff").await.unwrap();
+        assert_eq!(commit.message, "fallback");
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].contains(r#""type":"json_schema""#));
+        assert!(requests[1].contains(r#""type":"json_object""#));
+    }
+
+    #[tokio::test]
+    async fn unsupported_response_format_steps_down_to_none_or_fails_when_disabled() {
+        let ok_body = serde_json::json!({
+            "choices": [{ "message": { "content": r#"{"type":"fix","scope":"","message":"plain"}"# } }]
+        })
+        .to_string();
+        let rejected = r#"{"error":{"code":"unsupported_response_format","message":"Not supported with this model."}}"#;
+        let (url, requests) = mock_server(vec![
+            http_response("400 Bad Request", "", rejected),
+            http_response("400 Bad Request", "", rejected),
+            http_response("200 OK", "", &ok_body),
+        ])
+        .await;
+        let mut backend = test_backend(url, Duration::from_secs(5));
+        backend.response_format = Some("json_schema".into());
+        assert_eq!(backend.generate("diff").await.unwrap().message, "plain");
+        {
+            let requests = requests.lock().unwrap();
+            assert!(requests[1].contains(r#""type":"json_object""#));
+            assert!(!requests[2].contains("response_format"));
+        }
+
+        let (url, requests) =
+            mock_server(vec![http_response("400 Bad Request", "", rejected)]).await;
+        let mut backend = test_backend(url, Duration::from_secs(5));
+        backend.response_format = Some("json_schema".into());
+        backend.response_format_fallback = false;
+        let err = backend.generate("diff").await.unwrap_err();
+        assert!(
+            format!("{err}").contains("unsupported_response_format"),
+            "err: {err}"
+        );
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn top_p_is_sent_instead_of_temperature() {
+        let ok_body = serde_json::json!({
+            "choices": [{ "message": { "content": r#"{"type":"fix","scope":"","message":"x"}"# } }]
+        })
+        .to_string();
+        let (url, requests) = mock_server(vec![
+            http_response("200 OK", "", &ok_body),
+            http_response("200 OK", "", &ok_body),
+        ])
+        .await;
+        let mut backend = test_backend(url, Duration::from_secs(5));
+        backend.generate("diff").await.unwrap();
+        backend.top_p = Some(0.9);
+        backend.generate("diff").await.unwrap();
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].contains(r#""temperature":0.0"#));
+        assert!(!requests[0].contains("top_p"));
+        assert!(requests[1].contains(r#""top_p":0.9"#));
+        assert!(!requests[1].contains("temperature"));
+    }
+
+    #[tokio::test]
+    async fn openrouter_sends_referer_header() {
+        let ok_body = serde_json::json!({
+            "choices": [{ "message": { "content": r#"{"type":"fix","scope":"","message":"x"}"# } }]
+        })
+        .to_string();
+        let (url, requests) = mock_server(vec![http_response("200 OK", "", &ok_body)]).await;
+
+        let mut backend = test_backend(url, Duration::from_secs(5));
+        backend.auth.referer = Some(OPENROUTER_REFERER);
+        backend.generate("diff").await.unwrap();
+        let request = requests.lock().unwrap()[0].to_lowercase();
+        assert!(request.contains("referer: https://github.com/aaronsaikovski/git-cmt-rs"));
+        assert!(!request.contains("openai-organization:"));
+        assert!(!request.contains("openai-project:"));
+    }
+
+    #[tokio::test]
+    async fn org_and_project_headers_are_sent_when_set() {
+        let ok_body = serde_json::json!({
+            "choices": [{ "message": { "content": r#"{"type":"fix","scope":"","message":"x"}"# } }]
+        })
+        .to_string();
+        let (url, requests) = mock_server(vec![http_response("200 OK", "", &ok_body)]).await;
+
+        let mut backend = test_backend(url, Duration::from_secs(5));
+        backend.auth.org = Some("org-abc123".into());
+        backend.auth.project = Some("proj_xyz".into());
+        backend.generate("diff").await.unwrap();
+        let request = requests.lock().unwrap()[0].to_lowercase();
+        assert!(request.contains("openai-organization: org-abc123"));
+        assert!(request.contains("openai-project: proj_xyz"));
+    }
+
+    #[tokio::test]
+    async fn summaries_are_plain_text_requests() {
+        let ok_body = serde_json::json!({
+            "choices": [{ "message": { "content": "\n- Adds a retry loop around uploads.\nExtra detail." } }]
+        })
+        .to_string();
+        let (url, requests) = mock_server(vec![http_response("200 OK", "", &ok_body)]).await;
+
+        let mut backend = test_backend(url, Duration::from_secs(5));
+        backend.stream = true;
+        let summary = backend.summarize("diff").await.unwrap();
+        assert_eq!(summary, "Adds a retry loop around uploads.");
+        let request = requests.lock().unwrap()[0].clone();
+        assert!(!request.contains("response_format"), "request: {request}");
+        assert!(!request.contains(r#""stream""#));
+        assert!(request.contains("You summarize the change to one file"));
+    }
+
+    #[tokio::test]
+    async fn lang_localizes_commit_messages_only() {
+        let ok_body = serde_json::json!({
+            "choices": [{ "message": { "content": r#"{"type":"fix","scope":"api","message":"corrige les tentatives"}"# } }]
+        })
+        .to_string();
+        let (url, requests) = mock_server(vec![
+            http_response("200 OK", "", &ok_body),
+            http_response("200 OK", "", &ok_body),
+        ])
+        .await;
+
+        let backend = OpenAiBackend {
+            lang: Some("fr".into()),
+            privacy: Privacy::Full,
+            format: FormatPreset::Conventional,
+            commit_body: false,
+            compress: false,
+            ..test_backend(url, Duration::from_secs(5))
+        };
+        let commit = backend.generate("diff").await.unwrap();
+        assert_eq!(commit.message, "corrige les tentatives");
+        backend.summarize("diff").await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert!(
+            requests[0].contains("Write the commit message in French."),
+            "request: {}",
+            requests[0]
+        );
+        assert!(requests[0].contains(r#""response_format""#));
+        assert!(!requests[1].contains("French"));
+    }
+
+    #[tokio::test]
+    async fn list_models_keeps_chat_models_sorted() {
+        let body = serde_json::json!({
+            "data": [
+                { "id": "gpt-4o-mini" },
+                { "id": "text-embedding-3-small" },
+                { "id": "gpt-4.1" },
+                { "id": "whisper-1" },
+                { "id": "dall-e-3" }
+            ]
+        })
+        .to_string();
+        let (url, requests) = mock_server(vec![http_response("200 OK", "", &body)]).await;
+
+        let models = test_backend(url, Duration::from_secs(5))
+            .list_models()
+            .await
+            .unwrap();
+        assert_eq!(models, ["gpt-4.1", "gpt-4o-mini"]);
+        assert!(requests.lock().unwrap()[0].starts_with("GET /models "));
+    }
+}
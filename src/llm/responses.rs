@@ -0,0 +1,391 @@
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::client::json_body;
+use super::debug::HttpDebug;
+use super::openai::OpenAiAuth;
+use super::retry::send_with_retry;
+use super::usage::{Meter, Usage};
+use super::{
+    LlmBackend, Message, ModelList, Task, commit_schema, pr_description, response_text,
+    summary_line, tag_annotation, task_max_tokens, temperature, user_prompt,
+};
+use crate::cli::{FormatPreset, Privacy};
+use crate::config::Config;
+use crate::error::GitCmtError;
+use crate::{Commit, parse_commit};
+
+// Prefixed to every error so it's clear which OpenAI API flavor failed.
+const API_NAME: &str = "OpenAI Responses API";
+
+// ---------- OpenAI Responses request/response ----------
+#[derive(Debug, Serialize)]
+struct ResponsesRequest {
+    model: String,
+    input: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<TextConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_output_tokens: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+struct TextConfig {
+    format: TextFormat,
+}
+
+// Unlike chat completions, the schema fields sit directly on `format`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum TextFormat {
+    JsonObject,
+    JsonSchema {
+        name: String,
+        schema: serde_json::Value,
+        strict: bool,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponsesResponse {
+    #[serde(default)]
+    output: Vec<OutputItem>,
+    #[serde(default)]
+    usage: Option<ResponsesUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OutputItem {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    content: Vec<OutputContent>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OutputContent {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    refusal: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResponsesUsage {
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+// Same `OPENAI_RESPONSE_FORMAT` values as chat completions, but strict
+// `json_schema` is the default: only hosted OpenAI-style servers implement
+// this API, and they all support it.
+fn build_text_format(raw: Option<&str>, schema: serde_json::Value) -> Result<Option<TextConfig>> {
+    let format = match raw.unwrap_or("json_schema").trim().to_lowercase().as_str() {
+        "json_schema" => TextFormat::JsonSchema {
+            name: "commit_message".into(),
+            schema,
+            strict: true,
+        },
+        "json_object" => TextFormat::JsonObject,
+        "none" => return Ok(None),
+        other => {
+            return Err(anyhow!(
+                "OPENAI_RESPONSE_FORMAT must be one of: json_object, json_schema, none (got: {other:?})"
+            ));
+        }
+    };
+    Ok(Some(TextConfig { format }))
+}
+
+// The assistant's text. Reasoning models emit a `reasoning` item before the
+// `message`, so search for the message rather than trusting `output[0]`.
+fn output_text(resp: &ResponsesResponse) -> Result<String> {
+    let content = resp
+        .output
+        .iter()
+        .filter(|item| item.kind == "message")
+        .flat_map(|item| &item.content);
+    let mut text = String::new();
+    for part in content {
+        match part.kind.as_str() {
+            "output_text" => text.push_str(&part.text),
+            "refusal" => return Err(anyhow!("model refused: {}", part.refusal)),
+            _ => {}
+        }
+    }
+    if text.trim().is_empty() {
+        return Err(anyhow!("no output text returned"));
+    }
+    Ok(text)
+}
+
+// ---------- Backend ----------
+// Sibling of `OpenAiBackend` for `api = "responses"`: `/responses` with
+// `text.format` structured outputs, which some newer models require.
+pub struct ResponsesBackend {
+    client: reqwest::Client,
+    url: String,
+    models_url: String,
+    auth: OpenAiAuth,
+    model: String,
+    lang: Option<String>,
+    privacy: Privacy,
+    format: FormatPreset,
+    commit_body: bool,
+    compress: bool,
+    top_p: Option<f32>,
+    response_format: Option<String>,
+    max_tokens: Option<u32>,
+    retry_budget: Duration,
+    debug: Option<HttpDebug>,
+    meter: Meter,
+}
+
+impl ResponsesBackend {
+    pub(super) fn new(config: &Config, client: reqwest::Client, debug: Option<HttpDebug>) -> Self {
+        ResponsesBackend {
+            client,
+            url: format!("{}/responses", config.base_url),
+            models_url: format!("{}/models", config.base_url),
+            auth: OpenAiAuth::new(config),
+            model: config.model.clone(),
+            lang: config.lang.clone(),
+            privacy: config.privacy,
+            format: config.format,
+            commit_body: config.body,
+            compress: config.compress_requests,
+            top_p: config.top_p,
+            response_format: config.response_format.clone(),
+            max_tokens: config.max_tokens,
+            retry_budget: config.retry_budget,
+            debug,
+            meter: Meter::new(config),
+        }
+    }
+
+    fn request(&self, req: &ResponsesRequest) -> reqwest::RequestBuilder {
+        if let Some(debug) = &self.debug {
+            debug.request(&self.url, req);
+        }
+        json_body(
+            self.auth.apply(self.client.post(&self.url)),
+            req,
+            self.compress,
+        )
+    }
+
+    async fn respond(&self, task: Task, diff: &str) -> Result<String> {
+        let req = ResponsesRequest {
+            model: self.model.clone(),
+            input: vec![
+                Message::new(
+                    "system",
+                    task.system_prompt(
+                        self.lang.as_deref(),
+                        self.privacy,
+                        self.format,
+                        self.commit_body,
+                    ),
+                ),
+                Message::new("user", user_prompt(diff)),
+            ],
+            temperature: temperature(self.top_p),
+            top_p: self.top_p,
+            text: match task {
+                Task::Commit => build_text_format(
+                    self.response_format.as_deref(),
+                    commit_schema(self.format, self.commit_body),
+                )?,
+                Task::Summary | Task::PrDescription | Task::TagAnnotation => None,
+            },
+            max_output_tokens: task_max_tokens(task, self.max_tokens),
+        };
+
+        let resp = send_with_retry(
+            || self.request(&req),
+            self.retry_budget,
+            self.debug.as_ref(),
+        )
+        .await?;
+        let text = response_text(resp, self.debug.as_ref()).await?;
+        let parsed: ResponsesResponse =
+            serde_json::from_str(&text).context("failed to parse LLM response")?;
+        if let Some(usage) = &parsed.usage {
+            self.meter.report(Usage {
+                prompt_tokens: usage.input_tokens,
+                completion_tokens: usage.output_tokens,
+            });
+        }
+        output_text(&parsed)
+    }
+}
+
+#[async_trait]
+impl LlmBackend for ResponsesBackend {
+    async fn generate(&self, diff: &str) -> Result<Commit, GitCmtError> {
+        let content = self
+            .respond(Task::Commit, diff)
+            .await
+            .map_err(|e| GitCmtError::llm(API_NAME, e))?;
+        parse_commit(&content)
+    }
+
+    async fn summarize(&self, file_diff: &str) -> Result<String> {
+        let content = self
+            .respond(Task::Summary, file_diff)
+            .await
+            .map_err(|e| anyhow!("{API_NAME}: {e:#}"))?;
+        summary_line(&content)
+    }
+
+    async fn describe_pr(&self, log: &str) -> Result<String> {
+        let content = self
+            .respond(Task::PrDescription, log)
+            .await
+            .map_err(|e| anyhow!("{API_NAME}: {e:#}"))?;
+        pr_description(&content)
+    }
+
+    async fn annotate_tag(&self, changes: &str) -> Result<String> {
+        let content = self
+            .respond(Task::TagAnnotation, changes)
+            .await
+            .map_err(|e| anyhow!("{API_NAME}: {e:#}"))?;
+        tag_annotation(&content)
+    }
+
+    // The Responses API shares `/models` with chat completions.
+    async fn list_models(&self) -> Result<Vec<String>> {
+        let resp = send_with_retry(
+            || self.auth.apply(self.client.get(&self.models_url)),
+            self.retry_budget,
+            self.debug.as_ref(),
+        )
+        .await?;
+        let text = response_text(resp, self.debug.as_ref()).await?;
+        let list: ModelList = serde_json::from_str(&text).context("failed to parse model list")?;
+        let mut ids: Vec<String> = list.data.into_iter().map(|m| m.id).collect();
+        ids.sort();
+        Ok(ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::{http_response, mock_server};
+    use super::*;
+
+    fn test_backend(base_url: String) -> ResponsesBackend {
+        ResponsesBackend {
+            client: reqwest::Client::new(),
+            url: format!("{base_url}/responses"),
+            models_url: format!("{base_url}/models"),
+            auth: OpenAiAuth {
+                api_key: Some("sk-test".into()),
+                ..OpenAiAuth::default()
+            },
+            model: "gpt-5-mini".into(),
+            lang: None,
+            privacy: Privacy::Full,
+            format: FormatPreset::Conventional,
+            commit_body: false,
+            compress: false,
+            top_p: None,
+            response_format: None,
+            max_tokens: Some(256),
+            retry_budget: Duration::from_secs(5),
+            debug: None,
+            meter: Meter::default(),
+        }
+    }
+
+    #[test]
+    fn text_format_defaults_to_flat_json_schema() {
+        let v = serde_json::to_value(
+            build_text_format(None, commit_schema(FormatPreset::Conventional, false)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(v["format"]["type"], "json_schema");
+        assert_eq!(v["format"]["name"], "commit_message");
+        assert_eq!(v["format"]["strict"], true);
+        assert!(v["format"]["schema"]["properties"]["type"].is_object());
+
+        let v = serde_json::to_value(
+            build_text_format(
+                Some("json_object"),
+                commit_schema(FormatPreset::Conventional, false),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            v,
+            serde_json::json!({ "format": { "type": "json_object" } })
+        );
+        assert!(
+            build_text_format(
+                Some("none"),
+                commit_schema(FormatPreset::Conventional, false)
+            )
+            .unwrap()
+            .is_none()
+        );
+        assert!(
+            build_text_format(
+                Some("xml"),
+                commit_schema(FormatPreset::Conventional, false)
+            )
+            .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn responses_skips_reasoning_items_and_parses_message_text() {
+        let body = serde_json::json!({
+            "output": [
+                { "type": "reasoning", "summary": [] },
+                { "type": "message", "role": "assistant", "content": [
+                    { "type": "output_text", "text": r#"{"type":"feat","scope":"api","message":"add responses transport"}"# }
+                ]}
+            ],
+            "usage": { "input_tokens": 120, "output_tokens": 20 }
+        })
+        .to_string();
+        let (url, requests) = mock_server(vec![http_response("200 OK", "", &body)]).await;
+
+        let commit = test_backend(url).generate("diff").await.unwrap();
+        assert_eq!(commit.r#type, "feat");
+        assert_eq!(commit.message, "add responses transport");
+
+        let request = requests.lock().unwrap()[0].clone();
+        assert!(
+            request.starts_with("POST /responses "),
+            "request: {request}"
+        );
+        assert!(request.contains(r#""input":[{"role":"system""#));
+        assert!(request.contains(r#""max_output_tokens":256"#));
+        assert!(request.contains(r#""text":{"format":{"type":"json_schema""#));
+    }
+
+    #[tokio::test]
+    async fn responses_errors_name_the_api_flavor() {
+        let (url, _) = mock_server(vec![http_response(
+            "400 Bad Request",
+            "",
+            r#"{"error":{"message":"unknown model"}}"#,
+        )])
+        .await;
+        let err = test_backend(url).generate("diff").await.unwrap_err();
+        let msg = format!("{err}");
+        assert!(msg.starts_with("OpenAI Responses API: "), "msg: {msg}");
+        assert!(msg.contains("unknown model"), "msg: {msg}");
+    }
+}
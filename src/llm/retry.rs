@@ -0,0 +1,231 @@
+use anyhow::{Context, Result, anyhow};
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::ApiError;
+use super::debug::HttpDebug;
+
+// ---------- Retries ----------
+// Send the request produced by `build`, retrying 429s and 5xx until `budget`
+// would be exceeded. Other failures come back as an `ApiError`. With
+// `--debug-http`, every response's status line is logged here and failure
+// bodies too; callers log successful bodies once they've read them.
+pub(super) async fn send_with_retry(
+    build: impl Fn() -> reqwest::RequestBuilder,
+    budget: Duration,
+    debug: Option<&HttpDebug>,
+) -> Result<reqwest::Response> {
+    let started = Instant::now();
+    let mut attempt = 0;
+    loop {
+        let resp = build().send().await.context("LLM request failed")?;
+        if let Some(debug) = debug {
+            debug.response_head(resp.status(), resp.headers());
+        }
+        if resp.status().is_success() {
+            return Ok(resp);
+        }
+
+        let status = resp.status();
+        let headers = resp.headers().clone();
+        let body = resp.text().await.unwrap_or_default();
+        if let Some(debug) = debug {
+            debug.response_body(&body);
+        }
+
+        if let Some(delay) = retry_delay(status, &headers, attempt) {
+            if started.elapsed() + delay > budget {
+                return Err(anyhow!(
+                    "LLM request failed with status {} after {} attempt(s); retry budget of {}s exhausted: {}",
+                    status,
+                    attempt + 1,
+                    budget.as_secs(),
+                    body
+                ));
+            }
+            wait_with_countdown(delay, status).await;
+            attempt += 1;
+            continue;
+        }
+
+        return Err(ApiError { status, body }.into());
+    }
+}
+
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+// How long to wait before retrying a failed request, or `None` when the
+// failure isn't worth retrying. 429s honor the server's reset hints; 5xx
+// back off exponentially. Other 4xx fail fast.
+fn retry_delay(
+    status: reqwest::StatusCode,
+    headers: &reqwest::header::HeaderMap,
+    attempt: u32,
+) -> Option<Duration> {
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        Some(rate_limit_reset(headers).unwrap_or_else(|| backoff(attempt)))
+    } else if status.is_server_error() {
+        Some(backoff(attempt))
+    } else {
+        None
+    }
+}
+
+// Read `Retry-After` (seconds) or, failing that, the longest of OpenAI's
+// `x-ratelimit-reset-requests` / `x-ratelimit-reset-tokens` durations.
+fn rate_limit_reset(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+    if let Some(secs) = header("retry-after").and_then(|v| v.trim().parse::<f64>().ok())
+        && secs.is_finite()
+        && secs >= 0.0
+    {
+        return Some(Duration::from_secs_f64(secs));
+    }
+
+    ["x-ratelimit-reset-requests", "x-ratelimit-reset-tokens"]
+        .into_iter()
+        .filter_map(|name| header(name).and_then(parse_reset_duration))
+        .max()
+}
+
+// Parse OpenAI-style reset durations such as `1s`, `20ms`, `6m0s`, `1h2m3.5s`.
+fn parse_reset_duration(raw: &str) -> Option<Duration> {
+    let mut total = Duration::ZERO;
+    let mut rest = raw.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let value: f64 = rest[..split].parse().ok()?;
+        rest = &rest[split..];
+        let (secs_per_unit, unit_len) = if rest.starts_with("ms") {
+            (0.001, 2)
+        } else if rest.starts_with('h') {
+            (3600.0, 1)
+        } else if rest.starts_with('m') {
+            (60.0, 1)
+        } else if rest.starts_with('s') {
+            (1.0, 1)
+        } else {
+            return None;
+        };
+        total += Duration::from_secs_f64(value * secs_per_unit);
+        rest = &rest[unit_len..];
+    }
+    Some(total)
+}
+
+// Exponential backoff (1s, 2s, 4s, ... capped at 30s) plus up to 50% jitter
+// so concurrent clients don't retry in lockstep.
+fn backoff(attempt: u32) -> Duration {
+    let base = Duration::from_secs(1u64 << attempt.min(5)).min(MAX_BACKOFF);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    base + base.mul_f64(f64::from(nanos % 1000) / 2000.0)
+}
+
+// Sleep for `delay`, counting down on stderr once per second.
+async fn wait_with_countdown(delay: Duration, status: reqwest::StatusCode) {
+    let reason = if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        "Rate limited".to_string()
+    } else {
+        format!("Server error ({status})")
+    };
+
+    if !io::stderr().is_terminal() {
+        eprintln!("{reason}; retrying in {:.1}s...", delay.as_secs_f64());
+        tokio::time::sleep(delay).await;
+        return;
+    }
+
+    let deadline = tokio::time::Instant::now() + delay;
+    loop {
+        let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        eprint!("\r{reason}; retrying in {}s...   ", remaining.as_secs() + 1);
+        let _ = io::stderr().flush();
+        tokio::time::sleep(remaining.min(Duration::from_secs(1))).await;
+    }
+    eprint!("\r{:60}\r", "");
+    let _ = io::stderr().flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> reqwest::header::HeaderMap {
+        let mut map = reqwest::header::HeaderMap::new();
+        for (k, v) in pairs {
+            map.insert(*k, v.parse().unwrap());
+        }
+        map
+    }
+
+    #[test]
+    fn retry_after_seconds_is_honored_on_429() {
+        let d = retry_delay(
+            reqwest::StatusCode::TOO_MANY_REQUESTS,
+            &headers(&[("retry-after", "7")]),
+            0,
+        );
+        assert_eq!(d, Some(Duration::from_secs(7)));
+    }
+
+    #[test]
+    fn ratelimit_reset_headers_take_the_longest() {
+        let h = headers(&[
+            ("x-ratelimit-reset-requests", "1s"),
+            ("x-ratelimit-reset-tokens", "6m0s"),
+        ]);
+        assert_eq!(rate_limit_reset(&h), Some(Duration::from_secs(360)));
+    }
+
+    #[test]
+    fn reset_durations_parse_mixed_units() {
+        assert_eq!(
+            parse_reset_duration("20ms"),
+            Some(Duration::from_millis(20))
+        );
+        assert_eq!(
+            parse_reset_duration("1h2m3.5s"),
+            Some(Duration::from_secs_f64(3723.5))
+        );
+        assert_eq!(parse_reset_duration("soon"), None);
+        assert_eq!(parse_reset_duration(""), None);
+    }
+
+    #[test]
+    fn server_errors_back_off_exponentially_with_jitter() {
+        let empty = headers(&[]);
+        for attempt in 0..4 {
+            let d = retry_delay(reqwest::StatusCode::BAD_GATEWAY, &empty, attempt).unwrap();
+            let base = Duration::from_secs(1 << attempt);
+            assert!(
+                d >= base && d <= base.mul_f64(1.5),
+                "attempt {attempt}: {d:?}"
+            );
+        }
+        assert!(backoff(20) <= MAX_BACKOFF.mul_f64(1.5));
+    }
+
+    #[test]
+    fn client_errors_fail_fast() {
+        let empty = headers(&[]);
+        for status in [
+            reqwest::StatusCode::BAD_REQUEST,
+            reqwest::StatusCode::UNAUTHORIZED,
+            reqwest::StatusCode::NOT_FOUND,
+        ] {
+            assert_eq!(retry_delay(status, &empty, 0), None);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use super::LlmBackend;
+use crate::diff::FileChange;
+
+// Summary requests in flight at once during the map step.
+const MAX_CONCURRENT_SUMMARIES: usize = 4;
+
+// ---------- Map-reduce summaries ----------
+// `--summarize-large-diffs`: instead of truncating a diff that is too large,
+// ask for one sentence per file (concurrently, at most
+// `MAX_CONCURRENT_SUMMARIES` at a time) and send those lines in place of the
+// diff. Summaries are cached by blob IDs for the rest of the run, so a file
+// that comes up again is never paid for twice.
+pub struct Summarizer {
+    backend: Arc<dyn LlmBackend>,
+    permits: Arc<Semaphore>,
+    cache: Mutex<HashMap<String, String>>,
+}
+
+impl Summarizer {
+    pub fn new(backend: Arc<dyn LlmBackend>) -> Self {
+        Summarizer {
+            backend,
+            permits: Arc::new(Semaphore::new(MAX_CONCURRENT_SUMMARIES)),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // The text that replaces the diff in the prompt: one line per file, in
+    // diff order. A file whose summary request fails is described by its
+    // stat line instead, with a warning.
+    pub async fn summarize(&self, files: &[FileChange]) -> String {
+        let mut pending = JoinSet::new();
+        {
+            let cache = self.cache.lock().unwrap();
+            let mut queued = HashSet::new();
+            for file in files.iter().filter(|file| file.note.is_none()) {
+                if cache.contains_key(&file.key) || !queued.insert(&file.key) {
+                    continue;
+                }
+                let backend = self.backend.clone();
+                let permits = self.permits.clone();
+                let (key, diff) = (file.key.clone(), file.diff.clone());
+                pending.spawn(async move {
+                    let _permit = permits.acquire_owned().await;
+                    (key, backend.summarize(&diff).await)
+                });
+            }
+        }
+
+        let mut failed = HashMap::new();
+        while let Some(joined) = pending.join_next().await {
+            // A panicked task has no key to report; its file falls back below.
+            let Ok((key, result)) = joined else {
+                continue;
+            };
+            match result {
+                Ok(summary) => {
+                    self.cache.lock().unwrap().insert(key, summary);
+                }
+                Err(e) => {
+                    failed.insert(key, e);
+                }
+            }
+        }
+
+        let cache = self.cache.lock().unwrap();
+        let mut out = String::from("Per-file summaries (the full diff was too large to send):\n");
+        for file in files {
+            let line = match (&file.note, cache.get(&file.key)) {
+                (Some(note), _) => note.clone(),
+                (None, Some(summary)) => format!("{}: {summary}", file.path),
+                (None, None) => {
+                    if let Some(e) = failed.remove(&file.key) {
+                        eprintln!(
+                            "Could not summarize {}: {e:#}; using its stat line",
+                            file.path
+                        );
+                    }
+                    file.stat.clone()
+                }
+            };
+            out.push_str("- ");
+            out.push_str(&line);
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Commit;
+    use anyhow::{Result, anyhow};
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    // Summarizes each diff as its first line; fails on diffs containing
+    // "boom". Tracks calls and the most requests seen in flight at once.
+    #[derive(Default)]
+    struct FakeBackend {
+        calls: AtomicUsize,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl LlmBackend for FakeBackend {
+        async fn generate(&self, _diff: &str) -> Result<Commit, crate::error::GitCmtError> {
+            unreachable!("the map step only summarizes")
+        }
+
+        async fn summarize(&self, file_diff: &str) -> Result<String> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            if file_diff.contains("boom") {
+                return Err(anyhow!("server error"));
+            }
+            Ok(file_diff.lines().next().unwrap_or_default().to_string())
+        }
+
+        async fn describe_pr(&self, _log: &str) -> Result<String> {
+            unreachable!("the map step only summarizes")
+        }
+
+        async fn annotate_tag(&self, _changes: &str) -> Result<String> {
+            unreachable!("the map step only summarizes")
+        }
+
+        async fn list_models(&self) -> Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn change(path: &str, key: &str, diff: &str) -> FileChange {
+        FileChange {
+            path: path.into(),
+            key: key.into(),
+            diff: diff.into(),
+            stat: format!("{path}: 1 insertion, 0 deletions"),
+            note: None,
+            rename: None,
+            generated: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn files_are_summarized_once_each_with_stat_fallback() {
+        let backend = Arc::new(FakeBackend::default());
+        let summarizer = Summarizer::new(backend.clone());
+        let mut lock = change("Cargo.lock", "c", "lock diff");
+        lock.note = Some("Cargo.lock: 40 insertions, 2 deletions".into());
+        let files = [
+            change("src/a.rs", "a", "Adds a."),
+            // Same blobs as src/a.rs: reuses its summary.
+            change("src/copy.rs", "a", "Adds a."),
+            change("src/b.rs", "b", "boom"),
+            lock,
+        ];
+
+        let out = summarizer.summarize(&files).await;
+        assert_eq!(
+            out,
+            "Per-file summaries (the full diff was too large to send):\n\
+             - src/a.rs: Adds a.\n\
+             - src/copy.rs: Adds a.\n\
+             - src/b.rs: 1 insertion, 0 deletions\n\
+             - Cargo.lock: 40 insertions, 2 deletions\n"
+        );
+        assert_eq!(backend.calls.load(Ordering::SeqCst), 2);
+
+        // Cached summaries aren't requested again; failures are retried.
+        summarizer.summarize(&files).await;
+        assert_eq!(backend.calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_are_bounded() {
+        let backend = Arc::new(FakeBackend::default());
+        let files: Vec<FileChange> = (0..10)
+            .map(|i| change(&format!("f{i}"), &i.to_string(), &format!("change {i}")))
+            .collect();
+        Summarizer::new(backend.clone()).summarize(&files).await;
+        assert_eq!(backend.calls.load(Ordering::SeqCst), 10);
+        let max = backend.max_in_flight.load(Ordering::SeqCst);
+        assert!((2..=MAX_CONCURRENT_SUMMARIES).contains(&max), "max: {max}");
+    }
+}
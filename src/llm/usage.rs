@@ -0,0 +1,522 @@
+use std::collections::HashMap;
+use std::fmt;
+use tiktoken_rs::CoreBPE;
+use tiktoken_rs::tokenizer::{Tokenizer, get_tokenizer};
+
+use super::{Task, user_prompt};
+use crate::cli::{FormatPreset, Privacy, Provider};
+use crate::config::{Config, Price};
+use crate::diff::Budget;
+
+// Rates assumed for models missing from `PRICES` and `[prices]`.
+const FALLBACK_PRICE_MODEL: &str = "gpt-4.1-mini";
+// Completion tokens assumed when `--max-tokens 0` sends no cap.
+const UNCAPPED_COMPLETION_TOKENS: u32 = 256;
+
+// Tokens the chat format adds around each message (role and separators).
+const PER_MESSAGE_OVERHEAD: usize = 4;
+
+// Input plus output tokens each model accepts, matched by model-name prefix
+// (longest wins) like `PRICES`.
+const CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4o", 128_000),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-3.5-turbo", 16_385),
+    ("o3", 200_000),
+    ("o4-mini", 200_000),
+    ("claude", 200_000),
+];
+// Unknown models are mostly local ones, and Ollama and llama.cpp default to
+// a context of a few thousand tokens.
+const DEFAULT_CONTEXT_WINDOW: usize = 4096;
+// Left free for what follows the budgeted diff in the prompt: the `Summary:`
+// stat, the changed-file list and the untracked list.
+const SECTION_RESERVE: usize = 1024;
+
+// Built-in list prices in USD per million tokens, matched by model-name
+// prefix (longest wins). `[prices]` in the config file overrides these.
+const PRICES: &[(&str, Price)] = &[
+    (
+        "gpt-4.1",
+        Price {
+            input: 2.00,
+            output: 8.00,
+        },
+    ),
+    (
+        "gpt-4.1-mini",
+        Price {
+            input: 0.40,
+            output: 1.60,
+        },
+    ),
+    (
+        "gpt-4.1-nano",
+        Price {
+            input: 0.10,
+            output: 0.40,
+        },
+    ),
+    (
+        "gpt-4o",
+        Price {
+            input: 2.50,
+            output: 10.00,
+        },
+    ),
+    (
+        "gpt-4o-mini",
+        Price {
+            input: 0.15,
+            output: 0.60,
+        },
+    ),
+    (
+        "o4-mini",
+        Price {
+            input: 1.10,
+            output: 4.40,
+        },
+    ),
+    (
+        "claude-3-5-haiku",
+        Price {
+            input: 0.80,
+            output: 4.00,
+        },
+    ),
+    (
+        "claude-3-5-sonnet",
+        Price {
+            input: 3.00,
+            output: 15.00,
+        },
+    ),
+    (
+        "claude-sonnet-4",
+        Price {
+            input: 3.00,
+            output: 15.00,
+        },
+    ),
+];
+
+// ---------- Prompt size ----------
+// Counted before sending: exactly with tiktoken for models it recognizes,
+// otherwise with a bytes/4 estimate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PromptTokens {
+    pub count: usize,
+    pub exact: bool,
+}
+
+impl fmt::Display for PromptTokens {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "≈ {} prompt tokens", format_tokens(self.count as u64))?;
+        if !self.exact {
+            write!(f, " (bytes/4 estimate)")?;
+        }
+        Ok(())
+    }
+}
+
+// `lang` (the `--lang` code), names-only privacy, the `--format` preset and
+// `--body` add lines to the system prompt.
+pub fn count_prompt_tokens(
+    model: &str,
+    lang: Option<&str>,
+    privacy: Privacy,
+    format: FormatPreset,
+    body: bool,
+    diff: &str,
+) -> PromptTokens {
+    let messages = [
+        Task::Commit.system_prompt(lang, privacy, format, body),
+        user_prompt(diff),
+    ];
+    let bpe = bpe_for(model);
+    let count = messages
+        .iter()
+        .map(|text| count_tokens(bpe, text) + PER_MESSAGE_OVERHEAD)
+        .sum();
+    PromptTokens {
+        count,
+        exact: bpe.is_some(),
+    }
+}
+
+// The one counting rule, shared by the estimate and the diff budget.
+fn count_tokens(bpe: Option<&CoreBPE>, text: &str) -> usize {
+    match bpe {
+        Some(bpe) => bpe.encode_with_special_tokens(text).len(),
+        None => text.len().div_ceil(4),
+    }
+}
+
+fn bpe_for(model: &str) -> Option<&'static CoreBPE> {
+    Some(match get_tokenizer(base_model_name(model))? {
+        Tokenizer::O200kBase => tiktoken_rs::o200k_base_singleton(),
+        Tokenizer::Cl100kBase => tiktoken_rs::cl100k_base_singleton(),
+        Tokenizer::P50kBase => tiktoken_rs::p50k_base_singleton(),
+        Tokenizer::P50kEdit => tiktoken_rs::p50k_edit_singleton(),
+        Tokenizer::R50kBase | Tokenizer::Gpt2 => tiktoken_rs::r50k_base_singleton(),
+    })
+}
+
+// OpenRouter-style names carry a vendor prefix (`openai/gpt-4o-mini`).
+fn base_model_name(model: &str) -> &str {
+    model.rsplit('/').next().unwrap_or(model)
+}
+
+// ---------- Diff budget ----------
+pub fn context_window(model: &str) -> usize {
+    let name = base_model_name(model);
+    CONTEXT_WINDOWS
+        .iter()
+        .filter(|(prefix, _)| name.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map_or(DEFAULT_CONTEXT_WINDOW, |&(_, window)| window)
+}
+
+// What the diff may use: `--max-prompt-tokens`, or else the model's context
+// window less the completion, minus the system prompt and `SECTION_RESERVE`.
+// `--max-diff-chars` switches to the old character budget instead.
+pub fn diff_budget(config: &Config) -> Budget {
+    if let Some(max) = config.max_diff_chars {
+        return Budget::chars(max);
+    }
+    let completion = config.max_tokens.unwrap_or(UNCAPPED_COMPLETION_TOKENS) as usize;
+    let total = config
+        .max_prompt_tokens
+        .unwrap_or_else(|| context_window(&config.model).saturating_sub(completion));
+    let fixed = count_prompt_tokens(
+        &config.model,
+        config.lang.as_deref(),
+        config.privacy,
+        config.format,
+        config.body,
+        "",
+    )
+    .count;
+    let bpe = bpe_for(&config.model);
+    Budget::new(
+        total.saturating_sub(fixed + SECTION_RESERVE),
+        "tokens",
+        move |text| count_tokens(bpe, text),
+    )
+}
+
+// 950 -> "950", 1834 -> "1.8k", 2_500_000 -> "2.5M".
+pub fn format_tokens(n: u64) -> String {
+    if n < 1000 {
+        n.to_string()
+    } else if n < 1_000_000 {
+        format!("{:.1}k", n as f64 / 1000.0)
+    } else {
+        format!("{:.1}M", n as f64 / 1_000_000.0)
+    }
+}
+
+// ---------- Usage and cost ----------
+// Token totals as reported by the API after the fact.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+fn price_for(model: &str, overrides: &HashMap<String, Price>) -> Option<Price> {
+    let name = base_model_name(model);
+    if let Some(price) = overrides.get(model).or_else(|| overrides.get(name)) {
+        return Some(*price);
+    }
+    PRICES
+        .iter()
+        .filter(|(prefix, _)| name.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, price)| *price)
+}
+
+// ---------- Pre-send estimate ----------
+// Worst case before sending: the counted prompt plus a completion that uses
+// the whole `--max-tokens` cap. Models without a known price are estimated
+// at gpt-4.1-mini rates and say so.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostEstimate {
+    pub prompt: PromptTokens,
+    pub completion_tokens: u32,
+    pub usd: f64,
+    pub rates: String,
+}
+
+impl CostEstimate {
+    pub fn cents(&self) -> f64 {
+        self.usd * 100.0
+    }
+}
+
+impl fmt::Display for CostEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Estimated cost: ≈ ${:.4} ({} + up to {} completion tokens at {} rates)",
+            self.usd,
+            self.prompt,
+            format_tokens(self.completion_tokens as u64),
+            self.rates
+        )
+    }
+}
+
+// None for local providers, where there is nothing to bill.
+pub fn estimate_cost(config: &Config, prompt: PromptTokens) -> Option<CostEstimate> {
+    if config.provider == Provider::Ollama {
+        return None;
+    }
+    let (price, rates) = match price_for(&config.model, &config.prices) {
+        Some(price) => (price, config.model.clone()),
+        None => (
+            price_for(FALLBACK_PRICE_MODEL, &HashMap::new())?,
+            format!("assumed {FALLBACK_PRICE_MODEL}"),
+        ),
+    };
+    let completion_tokens = config.max_tokens.unwrap_or(UNCAPPED_COMPLETION_TOKENS);
+    let usd =
+        (prompt.count as f64 * price.input + completion_tokens as f64 * price.output) / 1_000_000.0;
+    Some(CostEstimate {
+        prompt,
+        completion_tokens,
+        usd,
+        rates,
+    })
+}
+
+// Prints what each run consumed. Held by every backend; local providers
+// report tokens only, since there is nothing to bill.
+#[derive(Debug, Default)]
+pub(super) struct Meter {
+    model: String,
+    prices: HashMap<String, Price>,
+    local: bool,
+}
+
+impl Meter {
+    pub(super) fn new(config: &Config) -> Self {
+        Meter {
+            model: config.model.clone(),
+            prices: config.prices.clone(),
+            local: config.provider == Provider::Ollama,
+        }
+    }
+
+    pub(super) fn report(&self, usage: Usage) {
+        eprintln!("{}", self.summary(usage));
+    }
+
+    fn summary(&self, usage: Usage) -> String {
+        let tokens = format!(
+            "Tokens: {} prompt + {} completion",
+            format_tokens(usage.prompt_tokens),
+            format_tokens(usage.completion_tokens)
+        );
+        if self.local {
+            return tokens;
+        }
+        match price_for(&self.model, &self.prices) {
+            Some(price) => {
+                let cost = (usage.prompt_tokens as f64 * price.input
+                    + usage.completion_tokens as f64 * price.output)
+                    / 1_000_000.0;
+                format!("{tokens} (≈ ${cost:.4} at {} rates)", self.model)
+            }
+            None => format!(
+                "{tokens} (no price known for {}; add it under [prices] in the config file)",
+                self.model
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_openai_models_exactly_and_others_by_estimate() {
+        let diff = "+fn main() {}\n".repeat(50);
+        let exact = count_prompt_tokens(
+            "gpt-4.1-mini",
+            None,
+            Privacy::Full,
+            FormatPreset::Conventional,
+            false,
+            &diff,
+        );
+        assert!(exact.exact);
+        assert!(
+            count_prompt_tokens(
+                "openai/gpt-4o-mini",
+                None,
+                Privacy::Full,
+                FormatPreset::Conventional,
+                false,
+                &diff
+            )
+            .exact
+        );
+        // The --lang line is part of the prompt.
+        assert!(
+            count_prompt_tokens(
+                "gpt-4.1-mini",
+                Some("fr"),
+                Privacy::Full,
+                FormatPreset::Conventional,
+                false,
+                &diff
+            )
+            .count
+                > exact.count
+        );
+
+        let estimate = count_prompt_tokens(
+            "llama3.1",
+            None,
+            Privacy::Full,
+            FormatPreset::Conventional,
+            false,
+            &diff,
+        );
+        assert!(!estimate.exact);
+        let bytes = Task::Commit
+            .system_prompt(None, Privacy::Full, FormatPreset::Conventional, false)
+            .len()
+            + user_prompt(&diff).len();
+        assert!(estimate.count >= bytes / 4);
+        assert!(format!("{estimate}").contains("estimate"));
+    }
+
+    #[test]
+    fn token_formatting() {
+        assert_eq!(format_tokens(950), "950");
+        assert_eq!(format_tokens(1834), "1.8k");
+        assert_eq!(format_tokens(2_500_000), "2.5M");
+        let shown = PromptTokens {
+            count: 1834,
+            exact: true,
+        };
+        assert_eq!(shown.to_string(), "≈ 1.8k prompt tokens");
+    }
+
+    #[test]
+    fn prices_match_longest_prefix_and_config_overrides_win() {
+        let none = HashMap::new();
+        assert_eq!(
+            price_for("gpt-4.1-mini-2025-04-14", &none).unwrap().input,
+            0.40
+        );
+        assert_eq!(price_for("gpt-4.1", &none).unwrap().input, 2.00);
+        assert_eq!(price_for("openai/gpt-4o-mini", &none).unwrap().output, 0.60);
+        assert!(price_for("llama3.1", &none).is_none());
+
+        let overrides = HashMap::from([(
+            "gpt-4.1-mini".to_string(),
+            Price {
+                input: 1.0,
+                output: 2.0,
+            },
+        )]);
+        assert_eq!(price_for("gpt-4.1-mini", &overrides).unwrap().input, 1.0);
+    }
+
+    #[test]
+    fn summary_includes_cost_when_priced() {
+        let meter = Meter {
+            model: "gpt-4o-mini".into(),
+            ..Meter::default()
+        };
+        let usage = Usage {
+            prompt_tokens: 1_000_000,
+            completion_tokens: 1_000_000,
+        };
+        assert_eq!(
+            meter.summary(usage),
+            "Tokens: 1.0M prompt + 1.0M completion (≈ $0.7500 at gpt-4o-mini rates)"
+        );
+
+        let local = Meter {
+            model: "qwen2.5-coder".into(),
+            local: true,
+            ..Meter::default()
+        };
+        assert!(!local.summary(usage).contains('$'));
+    }
+
+    fn config(args: &[&str]) -> Config {
+        use clap::Parser;
+        let cli =
+            crate::cli::Cli::parse_from(std::iter::once("git-cmt-rs").chain(args.iter().copied()));
+        Config::resolve(&cli, &crate::config::FileConfig::default(), |_| None).unwrap()
+    }
+
+    #[test]
+    fn diff_budget_is_the_context_window_less_the_fixed_prompt_and_reserves() {
+        assert_eq!(context_window("gpt-4.1-mini-2025-04-14"), 1_047_576);
+        assert_eq!(context_window("openai/gpt-4o-mini"), 128_000);
+        assert_eq!(context_window("claude-sonnet-4-20250514"), 200_000);
+        assert_eq!(context_window("qwen2.5-coder"), DEFAULT_CONTEXT_WINDOW);
+
+        let fixed = |config: &Config| {
+            count_prompt_tokens(
+                &config.model,
+                None,
+                Privacy::Full,
+                config.format,
+                config.body,
+                "",
+            )
+            .count
+                + SECTION_RESERVE
+        };
+        let mut c = config(&["--max-tokens", "100"]);
+        c.model = "llama3.1".into();
+        let budget = diff_budget(&c);
+        assert_eq!(budget.unit, "tokens");
+        assert_eq!(budget.limit, 4096 - 100 - fixed(&c));
+
+        let c = config(&["--max-prompt-tokens", "8000"]);
+        assert_eq!(diff_budget(&c).limit, 8000 - fixed(&c));
+        // Too small to leave anything for the diff.
+        assert_eq!(
+            diff_budget(&config(&["--max-prompt-tokens", "10"])).limit,
+            0
+        );
+
+        let budget = diff_budget(&config(&["--max-diff-chars", "3072"]));
+        assert_eq!((budget.limit, budget.unit), (3072, "chars"));
+    }
+
+    #[test]
+    fn estimate_assumes_a_full_completion_and_falls_back_to_default_rates() {
+        let prompt = PromptTokens {
+            count: 1_000_000,
+            exact: true,
+        };
+        let estimate = estimate_cost(&config(&["--max-tokens", "0"]), prompt).unwrap();
+        assert_eq!(estimate.completion_tokens, UNCAPPED_COMPLETION_TOKENS);
+        assert_eq!(estimate.rates, "gpt-4.1-mini");
+        assert!((estimate.cents() - (40.0 + 256.0 * 1.6 / 10_000.0)).abs() < 1e-9);
+
+        let mut unknown = config(&["--max-tokens", "100"]);
+        unknown.model = "some-gateway-model".into();
+        let estimate = estimate_cost(&unknown, prompt).unwrap();
+        assert_eq!(estimate.completion_tokens, 100);
+        assert!(
+            estimate
+                .to_string()
+                .contains("at assumed gpt-4.1-mini rates")
+        );
+
+        assert!(estimate_cost(&config(&["--provider", "ollama"]), prompt).is_none());
+    }
+}
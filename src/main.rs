@@ -1,13 +1,55 @@
+mod cache;
+mod changelog;
+mod cli;
+mod config;
+mod diff;
+mod error;
+mod exclude;
+mod format;
+mod git;
+mod history;
+mod hook;
+mod libgit2;
+mod llm;
+mod merge;
+mod redact;
+#[cfg(test)]
+mod scratch;
+mod select;
+mod squash;
+mod status;
+
 use anyhow::{Context, Result, anyhow};
+use clap::{ColorChoice, CommandFactory, FromArgMatches, ValueEnum};
 use serde::{Deserialize, Serialize};
-use std::env;
-use std::io::{self, Write};
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::task::JoinHandle;
 
-const MAX_DIFF_CHARS: usize = 3072;
+use cli::{
+    Cli, CliCommand, ConfigAction, FormatPreset, GitBackend, HookAction, Privacy, TicketStyle,
+};
+use config::{Config, FileConfig, Source};
+use diff::{Budget, Changes, FileChange, Generated, SubmoduleChange};
+use error::GitCmtError;
+use exclude::Excludes;
+use git::{CommitOptions, Git};
+use hook::HookStatus;
+use llm::{BackendFactory, LlmBackend, Summarizer};
+use merge::Merge;
+use redact::Redactor;
+use squash::Squash;
+use status::{Entry, Staging};
+
+// `git hash-object -t tree /dev/null`: diffing against it shows everything.
+const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
 
 // ---------- Domain types ----------
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 struct Commit {
     #[serde(default)]
     r#type: String, // feat, fix, docs, etc.
@@ -15,65 +57,664 @@ struct Commit {
     scope: String, // optional component
     #[serde(default)]
     message: String, // 50 chars max per prompt
+    // A breaking change: `!` after the type, and a `BREAKING CHANGE:`
+    // footer when there is a description.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    breaking: bool,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    breaking_description: String,
+    // Bullet points for the commit body (`--body`), without their `- `. A
+    // plain string (older models, older history records) is split into lines.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_body",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    body: Vec<String>,
+}
+
+fn deserialize_body<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = Option::<serde_json::Value>::deserialize(deserializer)?;
+    Ok(value.as_ref().map(body_from_value).unwrap_or_default())
+}
+
+// An array holds one bullet per element; a string one per line. Bullet
+// markers and blank entries are dropped either way.
+fn body_from_value(value: &serde_json::Value) -> Vec<String> {
+    let lines: Vec<String> = match value {
+        serde_json::Value::Array(items) => items.iter().map(value_to_string).collect(),
+        serde_json::Value::String(text) => text.lines().map(String::from).collect(),
+        _ => Vec::new(),
+    };
+    lines
+        .iter()
+        .map(|line| line.trim().trim_start_matches(['-', '*', '•']).trim())
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect()
 }
 
 // ---------- Git ----------
-fn stage_all_changes() -> Result<()> {
+// Run from a subdirectory, `git add .` would quietly stage only that subtree,
+// so say which of the two is happening. Silent at the root, where they agree.
+fn staging_scope(git: &Git, cwd_only: bool) -> Option<String> {
+    let prefix = git.prefix().ok().filter(|prefix| !prefix.is_empty())?;
+    Some(if cwd_only {
+        format!("In {prefix}; staging only this subtree (--cwd)")
+    } else {
+        format!("In {prefix}; staging the whole repository (use --cwd for this subtree only)")
+    })
+}
+
+// Before `git add` takes the whole tree: list what it would pick up and ask.
+// `Ok(false)` when declined; nothing has been touched then.
+fn confirm_staging(config: &Config, git: &Git) -> Result<bool> {
+    let prefix = config
+        .cwd_only
+        .then(|| git.prefix().ok())
+        .flatten()
+        .filter(|prefix| !prefix.is_empty());
+    let files = status::to_stage(
+        &git.status_entries()?,
+        &Staging {
+            staged: true,
+            include_untracked: config.include_untracked,
+            cwd_prefix: prefix.as_deref(),
+        },
+    );
+    // Nothing new to add; the diff (or its absence) speaks for itself.
+    if files.is_empty() {
+        return Ok(true);
+    }
+    eprint!("{}", status::stage_preview(&files));
+    confirm(&format!("Stage these {} files?", files.len()))
+}
+
+// Re-stage exactly the files a hook touched, wherever the tool was run from.
+fn restage(paths: &[String]) -> Result<(), GitCmtError> {
     let status = Command::new("git")
-        .args(["add", "."])
+        .args(["add", "--"])
+        .args(paths.iter().map(|path| git::top_pathspec(path)))
         .status()
-        .context("failed to run `git add .`")?;
-
+        .map_err(|e| GitCmtError::Git(format!("failed to run `git add`: {e}")))?;
     if !status.success() {
-        return Err(anyhow!("git add failed with status: {}", status));
+        return Err(GitCmtError::Git(format!(
+            "git add failed with status: {status}"
+        )));
     }
+    Ok(())
+}
 
+// Take files back out of the index, leaving their changes in the tree.
+fn unstage(paths: &[String]) -> Result<(), GitCmtError> {
+    let status = Command::new("git")
+        .args(["reset", "-q", "--"])
+        .args(paths.iter().map(|path| git::top_pathspec(path)))
+        .status()
+        .map_err(|e| GitCmtError::Git(format!("failed to run `git reset`: {e}")))?;
+    if !status.success() {
+        return Err(GitCmtError::Git(format!(
+            "git reset failed with status: {status}"
+        )));
+    }
     Ok(())
 }
 
-fn get_staged_changes() -> Result<String> {
-    let output = Command::new("git")
-        .args(["diff", "--cached", "-b"])
-        .output()
-        .context("failed to run `git diff --cached -b`")?;
+// `--select`: stage the picked files and unstage staged ones left unpicked,
+// so the index holds exactly the choice. `None` when there was nothing to
+// pick from; otherwise how many files were picked.
+fn stage_selected(config: &Config, git: &Git) -> Result<Option<usize>> {
+    let root = PathBuf::from(git.toplevel()?);
+    let excludes = Excludes::load(&root, &config.prompt_exclude)?;
+    // No counts before the first commit, which has no HEAD to diff against.
+    let numstat = git.worktree_numstat().unwrap_or_default();
+    let candidates = select::candidates(&git.status_entries()?, &numstat, &excludes);
+    if candidates.is_empty() {
+        return Ok(None);
+    }
+    let picked = select::choose(&candidates)?;
+    if picked.is_empty() {
+        return Ok(Some(0));
+    }
+    let (chosen, left): (Vec<_>, Vec<_>) = candidates
+        .into_iter()
+        .enumerate()
+        .partition(|(i, _)| picked.contains(i));
+    let unstaged: Vec<String> = left
+        .into_iter()
+        .filter(|(_, candidate)| candidate.staged)
+        .map(|(_, candidate)| candidate.path)
+        .collect();
+    if !unstaged.is_empty() {
+        unstage(&unstaged)?;
+    }
+    let paths: Vec<String> = chosen.into_iter().map(|(_, c)| c.path).collect();
+    restage(&paths)?;
+    Ok(Some(paths.len()))
+}
 
-    if !output.status.success() {
-        return Err(anyhow!("git diff failed with status: {}", output.status));
+// A formatter in a pre-commit hook (rustfmt, prettier) fixes the staged files
+// and then fails the commit, leaving its fixes unstaged. When a failed commit
+// left files among `paths` changed that weren't before (`dirty_before`),
+// offer to stage them for one more try; `--hook-retry` doesn't ask. The
+// hook's own output has already gone to the terminal.
+fn restage_after_hook(
+    git: &Git,
+    paths: &[String],
+    dirty_before: &[String],
+    auto: bool,
+) -> Result<bool> {
+    let changed: Vec<String> = git
+        .unstaged_changes(paths)?
+        .into_iter()
+        .filter(|path| !dirty_before.contains(path))
+        .collect();
+    if changed.is_empty() {
+        return Ok(false);
+    }
+    eprintln!(
+        "The commit failed after a hook modified {} staged file(s): {}",
+        changed.len(),
+        changed.join(", ")
+    );
+    if !auto && !confirm("Re-stage them and retry the commit once?")? {
+        return Ok(false);
+    }
+    restage(&changed)?;
+    eprintln!("Re-staged {} file(s); retrying the commit", changed.len());
+    Ok(true)
+}
+
+// Exit codes for the pre-flight checks, so scripts can tell them from an
+// ordinary failure (1): the shell's "command not found" and the code git
+// itself exits with outside a repository.
+const EXIT_NO_GIT: i32 = 127;
+const EXIT_NOT_A_REPO: i32 = 128;
+
+// Before any staging or network activity: git must be installed and the
+// current directory inside a repository. Otherwise every later step fails
+// with its own, less helpful, message.
+fn preflight(verbose: bool) {
+    let git = Git::cwd();
+    match git.version() {
+        Ok(version) if verbose => eprintln!("Using {version}"),
+        Ok(_) => {}
+        Err(GitCmtError::Io(e)) if e.kind() == io::ErrorKind::NotFound => {
+            eprintln!("git was not found on PATH; install git or add it to PATH.");
+            std::process::exit(EXIT_NO_GIT);
+        }
+        Err(e) => {
+            eprintln!("Failed to run git: {e}");
+            std::process::exit(EXIT_NO_GIT);
+        }
+    }
+    if !git.is_repository() {
+        eprintln!("Not a git repository (or any parent); run git-cmt-rs inside one.");
+        std::process::exit(EXIT_NOT_A_REPO);
+    }
+}
+
+// `git` on `backend`. libgit2 that can't open a repository git can use
+// leaves the work to `git`: silently by default, with a warning when
+// `source` (a flag or the config file) asked for libgit2.
+fn with_git_backend(git: Git, backend: GitBackend, source: &Source) -> Git {
+    let (git, declined) = git.with_backend(backend);
+    if let Some(e) = declined
+        && *source != Source::Default
+        && git.is_repository()
+    {
+        eprintln!(
+            "Warning: libgit2, chosen by {source}, can't open this repository ({e}); using git instead."
+        );
+    }
+    git
+}
+
+// The repository in the current directory, set up as `config` says.
+fn configured_git(config: &Config) -> Git {
+    let git = Git::cwd()
+        .with_similarity(config.rename_threshold, config.copy_threshold)
+        .with_diff_algorithm(config.diff_algorithm)
+        .with_context_lines(config.context_lines);
+    let source = config
+        .sources
+        .get("git_backend")
+        .unwrap_or(&Source::Default);
+    with_git_backend(git, config.git_backend, source)
+}
+
+// For the subcommands handled before the config is loaded: only
+// `--git-backend` on the command line applies.
+fn cli_git(cli: &Cli) -> Git {
+    let source = match cli.git_backend {
+        Some(_) => Source::Flag("--git-backend"),
+        None => Source::Default,
+    };
+    with_git_backend(Git::cwd(), cli.git_backend.unwrap_or_default(), &source)
+}
+
+// `-C`/`--repo`: the directory to run in, checked before anything else
+// happens. Returned as given; the caller changes into it.
+fn repo_dir(path: &Path) -> Result<&Path, GitCmtError> {
+    if !path.is_dir() {
+        return Err(GitCmtError::Git(format!(
+            "{} is not a directory",
+            path.display()
+        )));
+    }
+    if !Git::new(path).is_repository() {
+        return Err(GitCmtError::Git(format!(
+            "{} is not in a git repository",
+            path.display()
+        )));
+    }
+    Ok(path)
+}
+
+// `--color` / `--no-color`, the last one given winning, else NO_COLOR
+// (https://no-color.org): any non-empty value turns colour off. Taken from
+// the raw arguments, as clap colours its help and errors while parsing.
+fn color_choice(args: &[String], no_color: Option<&str>) -> ColorChoice {
+    let flag = args
+        .iter()
+        .take_while(|arg| *arg != "--")
+        .filter_map(|arg| match arg.as_str() {
+            "--color" => Some(ColorChoice::Always),
+            "--no-color" => Some(ColorChoice::Never),
+            _ => None,
+        })
+        .last();
+    match (flag, no_color) {
+        (Some(choice), _) => choice,
+        (None, Some(value)) if !value.is_empty() => ColorChoice::Never,
+        (None, _) => ColorChoice::Auto,
+    }
+}
+
+// Where the diff to summarise comes from. `Git` and `Amend` stage and read
+// the index; the others take a pre-computed diff as-is. `Amend` describes
+// what the amended commit will contain: HEAD's own changes plus the index.
+// `Squash` is the index against a base commit, for the `squash` subcommand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum DiffSource {
+    Git,
+    Amend,
+    Squash(String),
+    Stdin,
+    File(PathBuf),
+}
+
+impl DiffSource {
+    fn from_cli(cli: &Cli) -> Self {
+        if cli.diff_stdin {
+            DiffSource::Stdin
+        } else if let Some(path) = &cli.diff_file {
+            DiffSource::File(path.clone())
+        } else if cli.amend {
+            DiffSource::Amend
+        } else {
+            DiffSource::Git
+        }
+    }
+
+    fn stages(&self) -> bool {
+        matches!(self, DiffSource::Git | DiffSource::Amend)
+    }
+}
+
+/// A diff from stdin or a patch file is only turned into a printed message
+/// unless `--commit` asks for the commit too; generating it needs no
+/// repository at all.
+fn print_only(cli: &Cli) -> bool {
+    matches!(
+        DiffSource::from_cli(cli),
+        DiffSource::Stdin | DiffSource::File(_)
+    ) && !cli.commit
+}
+
+impl std::fmt::Display for DiffSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiffSource::Git => write!(f, "staged changes"),
+            DiffSource::Amend => write!(f, "HEAD plus staged changes"),
+            DiffSource::Squash(base) => write!(f, "the commits since {base}"),
+            DiffSource::Stdin => write!(f, "stdin"),
+            DiffSource::File(path) => write!(f, "{}", path.display()),
+        }
     }
+}
+
+// Fetch the diff from `source`, then apply the shared truncation. Stdin and
+// file sources (`git stash show -p`, patches from email, CI artifacts) let
+// the tool summarise changes that were never staged.
+fn get_staged_changes(
+    git: &Git,
+    source: &DiffSource,
+    budget: &Budget,
+    ignore: &[String],
+    generated: &Generated,
+    exclude: &Excludes,
+    redactor: &Redactor,
+) -> Result<Changes> {
+    let base = match source {
+        DiffSource::Amend if has_parent_commit() => Some("HEAD~1"),
+        DiffSource::Amend => Some(EMPTY_TREE),
+        DiffSource::Squash(base) => Some(base.as_str()),
+        _ => None,
+    };
+    let mut binaries = Vec::new();
+    let mut submodules = Vec::new();
+    let mut stat = None;
+    let mut names = Vec::new();
+    let diff = match source {
+        DiffSource::Git | DiffSource::Amend | DiffSource::Squash(_) => {
+            binaries = git.binary_changes(base)?;
+            submodules = git.submodule_changes(base)?;
+            stat = Some(git.diff_stat(base)?);
+            names = git.name_status(base)?;
+            git.diff_cached(base)?
+        }
+        DiffSource::Stdin => {
+            let mut diff = Vec::new();
+            io::stdin()
+                .read_to_end(&mut diff)
+                .context("failed to read diff from stdin")?;
+            diff
+        }
+        DiffSource::File(path) => fs::read(path)
+            .with_context(|| format!("failed to read diff file {}", path.display()))?,
+    };
 
-    let mut diff = String::from_utf8(output.stdout).context("git output was not valid UTF-8")?;
+    let (diff, lossy) = diff::decode_lossy(diff);
+    if lossy > 0 {
+        eprintln!(
+            "Warning: {lossy} byte(s) of the diff were not valid UTF-8 and were replaced with U+FFFD in the prompt; the commit itself is unaffected."
+        );
+    }
 
     if diff.trim().is_empty() {
-        return Err(anyhow!("no staged changes found"));
+        return Err(match source {
+            DiffSource::Git | DiffSource::Stdin => GitCmtError::NothingToCommit.into(),
+            DiffSource::Amend => anyhow!("no changes found in HEAD or the index"),
+            DiffSource::Squash(base) => anyhow!("the commits since {base} change nothing"),
+            other => anyhow!("no diff found in {other}"),
+        });
     }
 
-    if diff.chars().count() > MAX_DIFF_CHARS {
-        let truncated: String = diff.chars().take(MAX_DIFF_CHARS).collect();
-        diff = format!("{truncated}\n... (truncated)");
+    // Before anything is split off for the prompt or per-file summaries.
+    let (diff, found) = redactor.redact(&diff);
+    if let Some(summary) = redact::summary(&found) {
+        eprintln!("{summary}. Use --no-redact to send them as-is.");
     }
 
-    Ok(diff)
+    let stats = diff::parse_diff_stats(&diff);
+    let files = diff::file_changes(&diff, ignore, generated, exclude, &binaries);
+    let diff = diff::prompt_diff(diff, budget, ignore, generated, exclude, &binaries);
+    Ok(Changes::new(stat, diff)
+        .with_stats(Some(stats))
+        .with_files(files)
+        .with_files_changed(&names)
+        .with_submodules(submodules))
 }
 
-fn current_branch() -> Result<String> {
-    let output = Command::new("git")
-        .args(["symbolic-ref", "--short", "HEAD"])
-        .output()
-        .context("failed to run `git symbolic-ref`")?;
-    if !output.status.success() {
-        return Err(anyhow!(
-            "could not determine current branch (detached HEAD?)"
-        ));
+// `--names-only`: the same staged changes as `get_staged_changes()`, but only
+// `--name-status` and `--stat` are read, so file contents never reach the
+// prompt; `--brief` reads `--name-only` alone. A pre-computed diff has no
+// such metadata to fall back on.
+fn get_staged_names(git: &Git, source: &DiffSource, privacy: Privacy) -> Result<Changes> {
+    let base = match source {
+        DiffSource::Git => None,
+        DiffSource::Amend if has_parent_commit() => Some("HEAD~1"),
+        DiffSource::Amend => Some(EMPTY_TREE),
+        DiffSource::Squash(base) => Some(base.as_str()),
+        other => {
+            let mode = privacy
+                .to_possible_value()
+                .expect("no skipped privacy values");
+            return Err(anyhow!(
+                "{} privacy can't be used with a diff from {other}; \
+                 it reads file names from git",
+                mode.get_name()
+            ));
+        }
+    };
+    let empty = || match source {
+        DiffSource::Amend => anyhow!("no changes found in HEAD or the index"),
+        DiffSource::Squash(base) => anyhow!("the commits since {base} change nothing"),
+        _ => GitCmtError::NothingToCommit.into(),
+    };
+    if privacy == Privacy::Brief {
+        let paths = git.name_only(base)?;
+        if paths.is_empty() {
+            return Err(empty());
+        }
+        return Ok(Changes::brief(&paths));
+    }
+    let names = git.name_status(base)?;
+    if names.is_empty() {
+        return Err(empty());
+    }
+    Ok(Changes::names_only(Some(git.diff_stat(base)?), &names))
+}
+
+// `--context-commits`: started on the blocking pool before the diff is read,
+// so `git log` runs while `git diff` does. Recent subjects are only style
+// examples; failing to read them is not worth stopping for.
+fn spawn_recent_subjects(n: usize) -> Option<JoinHandle<Vec<String>>> {
+    (n > 0).then(|| {
+        tokio::task::spawn_blocking(move || match Git::cwd().recent_subjects(n) {
+            Ok(subjects) => subjects,
+            Err(e) => {
+                eprintln!("Warning: could not read recent commits: {e}");
+                Vec::new()
+            }
+        })
+    })
+}
+
+async fn recent_subjects(handle: Option<JoinHandle<Vec<String>>>) -> Vec<String> {
+    match handle {
+        Some(handle) => handle.await.unwrap_or_default(),
+        None => Vec::new(),
+    }
+}
+
+// Left-out changes listed when there is nothing to commit.
+const MAX_SKIPPED_LISTED: usize = 50;
+
+// The staged diff came back empty. A clean tree is not a failure (exit 0);
+// otherwise every change that staging left out is listed with the reason,
+// and the exit code for the failed run is returned.
+fn nothing_to_commit(git: &Git, config: &Config, staged: bool) -> i32 {
+    let entries = match git.status_entries() {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Failed to get diff from git: no staged changes found ({e})");
+            return 1;
+        }
+    };
+    if entries
+        .iter()
+        .all(|entry| matches!(entry, Entry::Ignored { .. }))
+    {
+        eprintln!("nothing to commit, working tree clean");
+        return 0;
+    }
+
+    let prefix = config
+        .cwd_only
+        .then(|| git.prefix().ok())
+        .flatten()
+        .filter(|prefix| !prefix.is_empty());
+    let skipped = status::skipped(
+        &entries,
+        &Staging {
+            staged,
+            include_untracked: config.include_untracked,
+            cwd_prefix: prefix.as_deref(),
+        },
+    );
+    eprintln!(
+        "No staged changes found, but {} changed file(s) were left out:",
+        skipped.len()
+    );
+    for (path, reason) in skipped.iter().take(MAX_SKIPPED_LISTED) {
+        eprintln!("  {path}: {reason}");
+    }
+    if skipped.len() > MAX_SKIPPED_LISTED {
+        eprintln!("  ... and {} more", skipped.len() - MAX_SKIPPED_LISTED);
+    }
+    1
+}
+
+// Without `--stat-threshold`, a diff this many times the budget switches to
+// names-only.
+const STAT_THRESHOLD_FACTOR: usize = 3;
+
+// (size, threshold) when the whole diff is so far over the budget that the
+// trimmed version would mostly be file headers: file names and counts say
+// more then. `--summarize-large-diffs` has its own answer to large diffs.
+fn auto_stat(config: &Config, budget: &Budget, changes: &Changes) -> Option<(usize, usize)> {
+    if !config.auto_stat || config.summarize_large_diffs || config.privacy != Privacy::Full {
+        return None;
+    }
+    let threshold = config
+        .stat_threshold
+        .unwrap_or(budget.limit.saturating_mul(STAT_THRESHOLD_FACTOR));
+    let size = changes.full_size(budget);
+    (size > threshold).then_some((size, threshold))
+}
+
+// No patterns at all with `--no-redact` / `redact = false`.
+fn redactor(config: &Config) -> Result<Redactor> {
+    if config.redact {
+        Redactor::new(&config.redact_patterns)
+    } else {
+        Ok(Redactor::default())
+    }
+}
+
+// `git push <args>`. Stderr reaches the terminal as git writes it and is
+// teed, so a missing upstream can be recognised afterwards; it runs
+// untranslated for that. Behind the pipe git only reports progress when
+// asked to, so a terminal gets `--progress`.
+fn git_push(args: &[String]) -> Result<(std::process::ExitStatus, String), GitCmtError> {
+    let mut command = Command::new("git");
+    command.arg("push");
+    if io::stderr().is_terminal() {
+        command.arg("--progress");
+    }
+    let mut child = command
+        .args(args)
+        .env("LC_ALL", "C")
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| GitCmtError::Git(format!("failed to run `git push`: {e}")))?;
+    let pipe = child.stderr.take().expect("stderr is piped");
+    let seen = tee(pipe, io::stderr())?;
+    let status = child.wait()?;
+    Ok((status, String::from_utf8_lossy(&seen).into_owned()))
+}
+
+// Copy `from` to `to` chunk by chunk as it arrives, keeping a copy.
+fn tee(mut from: impl Read, mut to: impl Write) -> io::Result<Vec<u8>> {
+    let mut seen = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = match from.read(&mut chunk) {
+            Ok(0) => return Ok(seen),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        to.write_all(&chunk[..n])?;
+        to.flush()?;
+        seen.extend_from_slice(&chunk[..n]);
     }
-    Ok(String::from_utf8(output.stdout)
-        .context("git output was not valid UTF-8")?
-        .trim()
-        .to_string())
 }
 
-fn has_upstream() -> bool {
+// "fatal: The current branch <name> has no upstream branch."
+fn needs_upstream(stderr: &str) -> bool {
+    stderr.contains("has no upstream branch")
+}
+
+// Where a new branch goes without asking: `origin`, or the only remote there
+// is. `None` when there are several to choose from (or none at all).
+fn default_remote(remotes: &[String]) -> Option<&str> {
+    match remotes {
+        [only] => Some(only),
+        _ => remotes.iter().find(|r| *r == "origin").map(String::as_str),
+    }
+}
+
+// Several remotes and none of them `origin`: ask. `None` when stdin closes.
+fn choose_remote(remotes: &[String]) -> Result<Option<String>> {
+    if let Some(remote) = default_remote(remotes) {
+        return Ok(Some(remote.to_string()));
+    }
+    loop {
+        eprint!("Push to which remote? ({}): ", remotes.join(", "));
+        io::stderr().flush()?;
+
+        let mut input = String::new();
+        let read = io::stdin()
+            .read_line(&mut input)
+            .context("failed to read user input")?;
+        if read == 0 {
+            eprintln!();
+            return Ok(None);
+        }
+        match remotes.iter().find(|r| *r == input.trim()) {
+            Some(remote) => return Ok(Some(remote.clone())),
+            None => eprintln!("Please answer one of: {}", remotes.join(", ")),
+        }
+    }
+}
+
+// `branch.<name>.remote` may be `.`, the repository itself, which `git
+// remote` doesn't list.
+fn remote_exists(remote: &str, remotes: &[String]) -> bool {
+    remote == "." || remotes.iter().any(|r| r == remote)
+}
+
+// `--branch` on a detached HEAD. A bare name only works as a destination if
+// the remote branch already exists, so spell out the full ref.
+fn detached_push_refspec(branch: &str) -> String {
+    if branch.starts_with("refs/") {
+        format!("HEAD:{branch}")
+    } else {
+        format!("HEAD:refs/heads/{branch}")
+    }
+}
+
+// `created 3f2a1bc feat(cli): add --dry-run (4 files changed, +120 −8)`
+// from `git show --shortstat`'s `4 files changed, 120 insertions(+), 8
+// deletions(-)`; just the SHA and subject when there is no stat line.
+fn commit_summary(verb: &str, sha: &str, subject: &str, shortstat: &str) -> String {
+    let line = format!("{verb} {sha} {subject}");
+    let mut parts = shortstat.split(", ");
+    let Some(files) = parts.next().filter(|files| !files.is_empty()) else {
+        return line;
+    };
+    let (mut insertions, mut deletions) = ("0", "0");
+    for part in parts {
+        let count = part.split_whitespace().next().unwrap_or("0");
+        if part.ends_with("(+)") {
+            insertions = count;
+        } else if part.ends_with("(-)") {
+            deletions = count;
+        }
+    }
+    format!("{line} ({files}, +{insertions} \u{2212}{deletions})")
+}
+
+fn has_parent_commit() -> bool {
     Command::new("git")
-        .args(["rev-parse", "--abbrev-ref", "--symbolic-full-name", "@{u}"])
+        .args(["rev-parse", "--verify", "--quiet", "HEAD~1"])
         .stdout(Stdio::null())
         .stderr(Stdio::null())
         .status()
@@ -81,168 +722,132 @@ fn has_upstream() -> bool {
         .unwrap_or(false)
 }
 
-// ---------- OpenAI Chat Completions request/response ----------
-#[derive(Debug, Serialize)]
-struct ChatRequest {
-    model: String,
-    messages: Vec<Message>,
-    temperature: f32,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    response_format: Option<ResponseFormat>,
-}
-
-#[derive(Debug, Serialize)]
-struct Message {
-    role: String,
-    content: String,
-}
-
-#[derive(Debug, Serialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
-enum ResponseFormat {
-    JsonObject,
-    JsonSchema { json_schema: JsonSchema },
-}
-
-#[derive(Debug, Serialize)]
-struct JsonSchema {
-    name: String,
-    schema: serde_json::Value,
-    strict: bool,
-}
-
-#[derive(Debug, Deserialize)]
-struct ChatResponse {
-    choices: Vec<Choice>,
-}
-
-#[derive(Debug, Deserialize)]
-struct Choice {
-    message: ChoiceMessage,
-}
-
-#[derive(Debug, Deserialize)]
-struct ChoiceMessage {
-    content: String,
-}
-
-// Default is `json_object` so the tool works against Ollama and most local
-// proxies; hosted OpenAI users can opt back into strict schema validation
-// with `json_schema`.
-fn build_response_format(
-    raw: Option<&str>,
-    schema: serde_json::Value,
-) -> Result<Option<ResponseFormat>> {
-    match raw.unwrap_or("json_object").trim().to_lowercase().as_str() {
-        "json_object" => Ok(Some(ResponseFormat::JsonObject)),
-        "json_schema" => Ok(Some(ResponseFormat::JsonSchema {
-            json_schema: JsonSchema {
-                name: "commit_message".into(),
-                schema,
-                strict: true,
-            },
-        })),
-        "none" => Ok(None),
-        other => Err(anyhow!(
-            "OPENAI_RESPONSE_FORMAT must be one of: json_object, json_schema, none (got: {other:?})"
-        )),
-    }
-}
-
-// ---------- LLM ----------
-async fn generate_message(changes: &str) -> Result<Commit> {
-    // API key is optional: local backends like Ollama ignore auth, and some
-    // proxies reject an empty `Authorization: Bearer` header.
-    let api_key = env::var("OPENAI_API_KEY").ok().filter(|k| !k.is_empty());
-    let base =
-        env::var("OPENAI_BASE_URL").unwrap_or_else(|_| "https://api.openai.com/v1".to_string());
-    let model = env::var("OPENAI_MODEL").unwrap_or_else(|_| "gpt-4.1-mini".to_string());
-
-    // System + user messages; user holds the diff.
-    let system = r#"You are a git commit message generator.
-Analyze changes and output JSON with:
-- type: feat|fix|docs|style|refactor|test|chore
-- scope: affected component (optional)
-- message: clear description (50 chars max)
-Return ONLY valid JSON, no other text."#;
-
-    let user = format!("Changes:\n{changes}");
-
-    let schema = serde_json::json!({
-        "type": "object",
-        "additionalProperties": false,
-        "required": ["type", "scope", "message"],
-        "properties": {
-            "type":   { "type": "string", "enum": ["feat","fix","docs","style","refactor","test","chore"] },
-            "scope":  { "type": "string" },
-            "message":{ "type": "string", "maxLength": 50 }
+// `git-cmt-rs undo`: drop the last commit. `--soft` keeps its changes staged
+// so the tool can simply be re-run; `--hard` also throws them away.
+fn undo_last_commit(git: &Git, hard: bool) -> Result<()> {
+    let (sha, subject) = git.head_commit()?;
+    if !has_parent_commit() {
+        return Err(anyhow!(
+            "{sha} is the first commit in this repository; nothing to reset to"
+        ));
+    }
+
+    let mode = if hard { "--hard" } else { "--soft" };
+    if hard {
+        eprintln!(
+            "WARNING: `git reset --hard HEAD~1` discards commit {sha} and ALL uncommitted changes in the working tree."
+        );
+        if !confirm("Really discard them?")? {
+            eprintln!("Undo cancelled.");
+            return Ok(());
         }
-    });
+    }
 
-    let response_format_raw = env::var("OPENAI_RESPONSE_FORMAT").ok();
-    let req = ChatRequest {
-        model,
-        messages: vec![
-            Message {
-                role: "system".into(),
-                content: system.into(),
-            },
-            Message {
-                role: "user".into(),
-                content: user,
-            },
-        ],
-        temperature: 0.0,
-        response_format: build_response_format(response_format_raw.as_deref(), schema)?,
-    };
-
-    let client = reqwest::Client::new();
-    let mut req_builder = client.post(format!("{base}/chat/completions"));
-    if let Some(key) = api_key {
-        req_builder = req_builder.bearer_auth(key);
-    }
-    let resp = req_builder
-        .json(&req)
-        .send()
-        .await
-        .context("LLM request failed")?;
+    let status = Command::new("git")
+        .args(["reset", mode, "HEAD~1"])
+        .status()
+        .context("failed to run `git reset`")?;
+    if !status.success() {
+        return Err(anyhow!("git reset failed with status: {status}"));
+    }
+
+    if hard {
+        eprintln!("Undid commit {sha}: {subject} (changes discarded)");
+    } else {
+        eprintln!("Undid commit {sha}: {subject} (changes are still staged)");
+    }
+    Ok(())
+}
+
+// ---------- Revert ----------
+// `git-cmt-rs revert <SHA>`: git applies the reverse of the commit to the
+// index, the model explains why it is being undone, and the commit ends with
+// git's own `This reverts commit <sha>.` line.
+struct Revert {
+    sha: String,
+    short: String,
+    subject: String,
+}
+
+impl Revert {
+    fn footer(&self) -> String {
+        format!("This reverts commit {}.", self.sha)
+    }
+}
+
+fn start_revert(rev: &str) -> Result<Revert> {
+    let output = Command::new("git")
+        .args(["log", "-1", "--format=%H%n%h%n%s", rev, "--"])
+        .output()
+        .context("failed to run `git log`")?;
+    if !output.status.success() {
+        return Err(anyhow!("{rev:?} is not a commit in this repository"));
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    let mut lines = text.lines().map(String::from);
+    let revert = Revert {
+        sha: lines.next().unwrap_or_default(),
+        short: lines.next().unwrap_or_default(),
+        subject: lines.next().unwrap_or_default(),
+    };
 
-    if !resp.status().is_success() {
-        let status = resp.status();
-        let text = resp.text().await.unwrap_or_default();
+    let status = Command::new("git")
+        .args(["revert", "--no-commit", &revert.sha])
+        .status()
+        .context("failed to run `git revert`")?;
+    if !status.success() {
         return Err(anyhow!(
-            "LLM request failed with status {}: {}",
-            status,
-            text
+            "git revert --no-commit {} failed with status: {status}; \
+             resolve the conflicts or run `git revert --abort`",
+            revert.short
         ));
     }
+    Ok(revert)
+}
 
-    let parsed: ChatResponse = resp.json().await.context("failed to parse LLM response")?;
-
-    let content = parsed
-        .choices
+// A merge waiting to be committed, with both sides' logs; `None` outside
+// one. Files still marked unmerged have to be resolved and added first.
+fn merge_in_progress(git: &Git) -> Result<Option<Merge>> {
+    let Some(head) = git.merge_head() else {
+        return Ok(None);
+    };
+    let unmerged: Vec<String> = git
+        .status_entries()?
         .into_iter()
-        .next()
-        .ok_or_else(|| anyhow!("no choices returned"))?
-        .message
-        .content;
-
-    // Model should have returned strict JSON per schema, but local models
-    // (e.g. Gemma via Ollama) often wrap it in markdown fences or stray text.
-    parse_commit(&content)
+        .filter_map(|entry| match entry {
+            Entry::Unmerged { path } => Some(path),
+            _ => None,
+        })
+        .collect();
+    if !unmerged.is_empty() {
+        return Err(anyhow!(
+            "resolve the conflicts in {} and `git add` them first",
+            unmerged.join(", ")
+        ));
+    }
+    let merge_msg = fs::read_to_string(git.git_path("MERGE_MSG")?).unwrap_or_default();
+    let ours = git.current_branch().unwrap_or_else(|_| "HEAD".into());
+    let limit = merge::MAX_LOG_LINES;
+    Ok(Some(Merge::new(&merge_msg, &head, &ours).with_logs(
+        git.log_oneline("HEAD..MERGE_HEAD", limit)?,
+        git.log_oneline("MERGE_HEAD..HEAD", limit)?,
+    )))
 }
 
 // Parse a `Commit` from raw model output. Tries the text as-is first, then
 // falls back to extracting the first balanced JSON object/array embedded in
 // surrounding prose / markdown code fences and coercing it into a `Commit`.
-fn parse_commit(content: &str) -> Result<Commit> {
+fn parse_commit(content: &str) -> Result<Commit, GitCmtError> {
     let trimmed = content.trim();
 
     // Fast path: strict `{ "type", "scope", "message" }` object. Require a
     // meaningful field so odd-keyed objects (e.g. `{"_type": ...}`) that
     // deserialize into an all-empty `Commit` fall through to coercion below.
+    // A `[type, scope, message]` array fills the struct positionally too;
+    // a fourth element is a flattened key/value list, not a body.
     if let Ok(commit) = serde_json::from_str::<Commit>(trimmed)
         && (!commit.r#type.trim().is_empty() || !commit.message.trim().is_empty())
+        && (commit.body.is_empty() || trimmed.starts_with('{'))
     {
         return Ok(commit);
     }
@@ -265,18 +870,17 @@ fn parse_commit(content: &str) -> Result<Commit> {
         return Ok(commit);
     }
 
-    Err(anyhow!("failed to parse commit JSON (raw: {content:?})"))
+    Err(GitCmtError::ParseError(format!(
+        "failed to parse commit JSON (raw: {content:?})"
+    )))
 }
 
 // Coerce a generic JSON value into a `Commit`. Accepts a JSON object or a
 // flattened `[key, value, key, value, ...]` array, tolerating decorated keys
 // like `_type`. Returns `None` if no commit-shaped fields are present.
 fn commit_from_value(value: &serde_json::Value) -> Option<Commit> {
-    let pairs: Vec<(String, String)> = match value {
-        serde_json::Value::Object(map) => map
-            .iter()
-            .map(|(k, v)| (k.clone(), value_to_string(v)))
-            .collect(),
+    let pairs: Vec<(String, &serde_json::Value)> = match value {
+        serde_json::Value::Object(map) => map.iter().map(|(k, v)| (k.clone(), v)).collect(),
         // An array of objects is a list of commit candidates (some models emit
         // one per file). Coerce the first that yields a commit-shaped value.
         serde_json::Value::Array(items) if items.iter().any(serde_json::Value::is_object) => {
@@ -287,7 +891,7 @@ fn commit_from_value(value: &serde_json::Value) -> Option<Commit> {
         serde_json::Value::Array(items) => items
             .chunks(2)
             .filter(|pair| pair.len() == 2)
-            .map(|pair| (value_to_string(&pair[0]), value_to_string(&pair[1])))
+            .map(|pair| (value_to_string(&pair[0]), &pair[1]))
             .collect(),
         _ => return None,
     };
@@ -297,17 +901,20 @@ fn commit_from_value(value: &serde_json::Value) -> Option<Commit> {
     for (key, val) in pairs {
         match normalize_key(&key).as_str() {
             "type" => {
-                commit.r#type = val;
+                commit.r#type = value_to_string(val);
                 matched = true;
             }
             "scope" => {
-                commit.scope = val;
+                commit.scope = value_to_string(val);
                 matched = true;
             }
             "message" => {
-                commit.message = val;
+                commit.message = value_to_string(val);
                 matched = true;
             }
+            "body" => commit.body = body_from_value(val),
+            "breaking" => commit.breaking = is_true(val),
+            "breakingdescription" => commit.breaking_description = value_to_string(val),
             _ => {}
         }
     }
@@ -338,6 +945,15 @@ fn value_to_string(value: &serde_json::Value) -> String {
     }
 }
 
+// Local models sometimes quote booleans.
+fn is_true(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Bool(b) => *b,
+        serde_json::Value::String(s) => s.trim().eq_ignore_ascii_case("true"),
+        _ => false,
+    }
+}
+
 // Find the first balanced JSON object or array in `s`. String and escape aware,
 // so delimiters inside JSON string values don't throw off the depth count.
 // Skips leading garbage like a stray `{` that never closes.
@@ -384,108 +1000,2109 @@ fn match_delimited(bytes: &[u8], start: usize, open: u8, close: u8) -> Option<us
     None
 }
 
-fn build_commit_line(commit: &Commit) -> String {
-    let mut out = commit.r#type.trim().to_string();
-    if !commit.scope.trim().is_empty() {
-        out.push('(');
-        out.push_str(commit.scope.trim());
-        out.push(')');
+// A lone pure rename (no edits) gets `refactor: move <from> to <to>`
+// without asking the model. Copies and anything else go through the LLM.
+fn move_commit(files: &[FileChange]) -> Option<Commit> {
+    let [file] = files else {
+        return None;
+    };
+    let rename = file.rename.as_ref().filter(|rename| !rename.copy)?;
+    Some(Commit {
+        r#type: "refactor".into(),
+        scope: String::new(),
+        message: format!("move {} to {}", rename.from, rename.to),
+        ..Default::default()
+    })
+}
+
+// Nothing but submodule bumps staged: `chore(deps): bump <path> to <sha>`
+// without asking the model, whether or not the submodules are checked out.
+// Added or removed submodules go through the LLM.
+fn bump_commit(files: &[FileChange], submodules: &[SubmoduleChange]) -> Option<Commit> {
+    let only_bumps = !submodules.is_empty()
+        && submodules.iter().all(SubmoduleChange::is_bump)
+        && files.iter().all(|file| {
+            submodules
+                .iter()
+                .any(|submodule| submodule.path == file.path)
+        });
+    if !only_bumps {
+        return None;
     }
-    out.push_str(": ");
-    out.push_str(commit.message.trim());
-    out
+    let bumps: Vec<String> = submodules
+        .iter()
+        .map(|submodule| {
+            let new = submodule.new.as_deref().unwrap_or_default();
+            format!("{} to {}", submodule.path, diff::short_sha(new))
+        })
+        .collect();
+    Some(Commit {
+        r#type: "chore".into(),
+        scope: "deps".into(),
+        message: format!("bump {}", bumps.join(", ")),
+        ..Default::default()
+    })
 }
 
-fn confirm_push() -> Result<bool> {
-    loop {
-        eprint!("Push commit to remote? (y/n): ");
-        io::stderr().flush()?;
+// Tells apart the message files of one process (watch mode, parallel tests).
+static MESSAGE_FILES: AtomicUsize = AtomicUsize::new(0);
 
-        let mut input = String::new();
-        io::stdin()
-            .read_line(&mut input)
-            .context("failed to read user input")?;
+// The message for `git commit -F`, exactly as `build_commit_message()` made
+// it, in a temporary file removed on drop. A file keeps multi-line messages
+// and quotes intact where a `-m` argument would go through a shell's quoting
+// on Windows.
+struct MessageFile {
+    path: PathBuf,
+}
 
-        match input.trim().to_lowercase().as_str() {
-            "y" | "yes" => return Ok(true),
-            "n" | "no" => return Ok(false),
-            _ => {
-                eprintln!("Please answer 'y' or 'n'");
-                continue;
+impl MessageFile {
+    fn create(message: &str) -> io::Result<Self> {
+        let n = MESSAGE_FILES.fetch_add(1, Ordering::Relaxed);
+        let path =
+            std::env::temp_dir().join(format!("git-cmt-rs-message-{}-{n}.txt", std::process::id()));
+        fs::write(&path, message)?;
+        Ok(MessageFile { path })
+    }
+
+    // The file for `git commit -F <file> -e`, which skips `commit.template`
+    // and a pending MERGE_MSG or SQUASH_MSG. With `edit` they follow the
+    // message as comments, for reference while reviewing; git strips them
+    // when the editor closes. Without it the message is written as is.
+    fn for_commit(git: &Git, message: &str, edit: bool) -> io::Result<Self> {
+        let Some(prefix) = edit.then(|| git.comment_prefix()).flatten() else {
+            return Self::create(message);
+        };
+        let template = git
+            .commit_template()
+            .map(|path| (format!("commit.template ({})", path.display()), path));
+        let pending = ["MERGE_MSG", "SQUASH_MSG"]
+            .into_iter()
+            .filter_map(|name| Some((name.to_string(), git.git_path(name).ok()?)));
+        let context: Vec<(String, String)> = template
+            .into_iter()
+            .chain(pending)
+            .filter_map(|(label, path)| Some((label, fs::read_to_string(path).ok()?)))
+            .collect();
+        Self::create(&editor_buffer(message, &prefix, &context))
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+// `message`, then each `(label, text)` with every line behind `prefix`;
+// blank texts are left out.
+fn editor_buffer(message: &str, prefix: &str, context: &[(String, String)]) -> String {
+    let mut buffer = message.to_string();
+    for (label, text) in context {
+        let text = text.trim_end();
+        if text.trim().is_empty() {
+            continue;
+        }
+        buffer.push_str(&format!("\n\n{prefix} {label}:"));
+        for line in text.lines() {
+            match line.trim_end() {
+                "" => buffer.push_str(&format!("\n{prefix}")),
+                line => buffer.push_str(&format!("\n{prefix} {line}")),
             }
         }
     }
+    buffer
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    match stage_all_changes() {
-        Ok(_) => eprintln!("Staged all changes with `git add .`"),
-        Err(e) => {
-            eprintln!("Failed to stage changes: {e}");
-            std::process::exit(1);
-        }
-    };
+impl Drop for MessageFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
 
-    let changes = match get_staged_changes() {
-        Ok(d) => d,
+// `--wip`: the staged changes committed under `Config::wip_message`, with
+// hooks skipped unless `allow_no_verify` forbids it. Returns the exit code.
+fn wip_commit(git: &Git, config: &Config, staged: bool) -> i32 {
+    match git.name_status(None) {
+        Ok(names) if names.is_empty() => return nothing_to_commit(git, config, staged),
+        Ok(_) => {}
         Err(e) => {
-            eprintln!("Failed to get staged changes: {e}");
-            std::process::exit(1);
+            eprintln!("Failed to get diff from git: {e}");
+            return 1;
         }
-    };
-
-    eprintln!("Staged diff found; generating message for changes...");
-
-    let commit = match generate_message(&changes).await {
-        Ok(c) => c,
+    }
+    if !config.allow_no_verify {
+        eprintln!("Running git hooks for the WIP commit (allow_no_verify = false)");
+    }
+    let message_file = match MessageFile::create(&config.wip_message) {
+        Ok(file) => file,
         Err(e) => {
-            eprintln!("Failed to generate commit message: {e}");
-            std::process::exit(1);
+            eprintln!("Failed to write the commit message: {e}");
+            return 1;
         }
     };
-
+    let options = CommitOptions {
+        message_file: message_file.path(),
+        co_authors: &config.co_authors,
+        amend: false,
+        edit: false,
+        no_verify: config.allow_no_verify,
+    };
+    if let Err(e) = git.commit(&options) {
+        eprintln!("Failed to create WIP commit: {e}");
+        return 1;
+    }
     eprintln!(
-        "Parsed commit: type='{}', scope='{}', message='{}'",
-        commit.r#type, commit.scope, commit.message
+        "WIP commit created: {}. Not pushed; `git-cmt-rs undo` takes it back.",
+        config.wip_message
     );
+    0
+}
 
-    let line = build_commit_line(&commit);
+// The ticket the message should reference: `--ticket`, else the first
+// `ticket_pattern` match in `branch`. A detached HEAD (`branch` None) has no
+// name to search, so it gets none.
+fn ticket(config: &Config, branch: Option<&str>) -> Option<String> {
+    if let Some(ticket) = &config.ticket {
+        return Some(ticket.clone());
+    }
+    let found = config.ticket_pattern.as_ref()?.find(branch?)?;
+    Some(found.as_str().to_string())
+}
 
-    // Run: git commit -e -m "<line>"
-    let status = Command::new("git")
-        .args(["commit", "-e", "-m", &line])
-        .status()
-        .context("failed to run `git commit`")?;
+// `ticket()` for the current branch, when there is a pattern to look for.
+fn branch_ticket(config: &Config, git: &Git) -> Option<String> {
+    let branch = (config.ticket_pattern.is_some() && !git.is_detached())
+        .then(|| git.current_branch().ok())
+        .flatten();
+    ticket(config, branch.as_deref())
+}
 
-    if !status.success() {
-        return Err(anyhow!("git commit failed with status: {status}"));
-    }
+// Git's convention for the subject line; a ticket suffix must not be what
+// pushes it past.
+const MAX_SUBJECT_WIDTH: usize = 72;
 
-    eprintln!("Commit created successfully.");
+fn build_commit_line(commit: &Commit, format: FormatPreset) -> String {
+    format.formatter().subject(commit)
+}
 
-    // Ask for confirmation before pushing
-    let should_push = match confirm_push() {
-        Ok(confirmed) => confirmed,
+// The subject, then (with a body) a blank line and one `- ` bullet per
+// entry, wrapped at `width` with continuation lines under the text. A
+// breaking change's description is the last paragraph, as the
+// `BREAKING CHANGE:` footer. A ticket ends the subject as ` (PROJ-1234)`
+// or joins the footers as `Refs: PROJ-1234`; one that would push the
+// subject past `MAX_SUBJECT_WIDTH` joins the footers instead.
+fn build_commit_message(
+    commit: &Commit,
+    format: FormatPreset,
+    width: usize,
+    mut ticket: Option<(&str, TicketStyle)>,
+) -> String {
+    let bullets: Vec<String> = commit
+        .body
+        .iter()
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(|item| format!("- {item}"))
+        .collect();
+    let mut subject = build_commit_line(commit, format);
+    if let Some((id, TicketStyle::Subject)) = ticket
+        && !subject.contains(id)
+    {
+        let suffixed = format!("{subject} ({id})");
+        if suffixed.chars().count() <= MAX_SUBJECT_WIDTH {
+            subject = suffixed;
+        } else {
+            ticket = Some((id, TicketStyle::Footer));
+        }
+    }
+    let mut paragraphs = vec![subject];
+    if !bullets.is_empty() {
+        paragraphs.push(wrap_body(&bullets.join("\n"), width));
+    }
+    let mut footers = Vec::new();
+    let description = commit.breaking_description.trim();
+    if commit.breaking && !description.is_empty() {
+        footers.push(wrap_body(&format!("BREAKING CHANGE: {description}"), width));
+    }
+    if let Some((id, TicketStyle::Footer)) = ticket {
+        footers.push(format!("Refs: {id}"));
+    }
+    if !footers.is_empty() {
+        paragraphs.push(footers.join("\n"));
+    }
+    paragraphs.join("\n\n")
+}
+
+// Hard-wrap a commit body at `width` columns (git's convention is 72) with
+// `textwrap`, line by line. Only over-long lines are split, at spaces (never
+// at hyphens): blank lines and short lines stay as they are, a `- `/`* `
+// bullet continues under its text, and a word longer than `width` (a URL, a
+// path) is never broken. The subject line is built separately and never
+// wrapped. `width` 0 turns wrapping off.
+fn wrap_body(text: &str, width: usize) -> String {
+    let mut out = Vec::new();
+    for line in text.trim().lines().map(str::trim_end) {
+        if width == 0 || line.chars().count() <= width {
+            out.push(line.to_string());
+            continue;
+        }
+        let content = line.trim_start();
+        let indent = &line[..line.len() - content.len()];
+        let hang = match content.get(..2) {
+            Some("- " | "* ") => format!("{indent}  "),
+            _ => indent.to_string(),
+        };
+        let options = textwrap::Options::new(width)
+            .initial_indent(indent)
+            .subsequent_indent(&hang)
+            .break_words(false)
+            .word_splitter(textwrap::WordSplitter::NoHyphenation);
+        out.push(textwrap::fill(content, options));
+    }
+    out.join("\n")
+}
+
+// How to push. Amending rewrites a commit that may already be on the
+// remote, so it needs a force push; `--force-with-lease` refuses if someone
+// else pushed in the meantime. Plain `--force` is only used when asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PushMode {
+    Normal,
+    ForceWithLease,
+    Force,
+}
+
+impl PushMode {
+    fn from_cli(cli: &Cli) -> Self {
+        if cli.force {
+            PushMode::Force
+        } else if cli.amend {
+            PushMode::ForceWithLease
+        } else {
+            PushMode::Normal
+        }
+    }
+
+    fn flag(self) -> Option<&'static str> {
+        match self {
+            PushMode::Normal => None,
+            PushMode::ForceWithLease => Some("--force-with-lease"),
+            PushMode::Force => Some("--force"),
+        }
+    }
+
+    fn warning(self) -> Option<&'static str> {
+        match self {
+            PushMode::Normal => None,
+            PushMode::ForceWithLease => Some(
+                "WARNING: force-pushing with --force-with-lease. This rewrites the remote branch; \
+                 it is refused if someone else has pushed to it since your last fetch.",
+            ),
+            PushMode::Force => Some(
+                "WARNING: force-pushing with --force. This overwrites the remote branch \
+                 unconditionally and can discard commits other people have pushed.",
+            ),
+        }
+    }
+}
+
+// `upstream` is the `remote/branch` a new branch is about to track.
+fn confirm_push(upstream: Option<&str>, tags: &[String]) -> Result<bool> {
+    confirm(&push_question(upstream, tags))
+}
+
+fn push_question(upstream: Option<&str>, tags: &[String]) -> String {
+    let tags = match tags {
+        [] => String::new(),
+        [tag] => format!(" with tag {tag}"),
+        tags => format!(" with tags {}", tags.join(", ")),
+    };
+    match upstream {
+        Some(upstream) => format!("Push{tags} and set upstream to {upstream}?"),
+        None => format!("Push commit{tags} to remote?"),
+    }
+}
+
+// An annotated tag on the new commit: named after itself for
+// `--tag-version`, with a generated message for `--tag`.
+fn create_tag(name: &str, message: &str) -> Result<(), GitCmtError> {
+    let message_file = MessageFile::create(message)?;
+    let status = Command::new("git")
+        .args(["tag", "-a", name, "-F"])
+        .arg(message_file.path())
+        .status()
+        .map_err(|e| GitCmtError::Git(format!("failed to run `git tag`: {e}")))?;
+    if !status.success() {
+        return Err(GitCmtError::Git(format!(
+            "git tag failed with status: {status}"
+        )));
+    }
+    Ok(())
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    loop {
+        eprint!("{question} (y/n): ");
+        io::stderr().flush()?;
+
+        let mut input = String::new();
+        let read = io::stdin()
+            .read_line(&mut input)
+            .context("failed to read user input")?;
+        // stdin is closed (or was the diff, with --diff-stdin): treat as "no".
+        if read == 0 {
+            eprintln!();
+            return Ok(false);
+        }
+
+        match input.trim().to_lowercase().as_str() {
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => {
+                eprintln!("Please answer 'y' or 'n'");
+                continue;
+            }
+        }
+    }
+}
+
+// `--copy`: the message on the system clipboard (X11 or Wayland on Linux).
+fn copy_to_clipboard(text: &str) -> Result<(), arboard::Error> {
+    arboard::Clipboard::new()?.set_text(text)
+}
+
+// ---------- Upstream check ----------
+// Before the push question the upstream is fetched and compared with HEAD.
+// A branch that is behind would have its push rejected, so the question
+// becomes: rebase onto the upstream and push, push anyway, or skip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BehindAnswer {
+    Rebase,
+    Push,
+    Skip,
+}
+
+// `(ahead, behind)` of `upstream` once its remote has been fetched.
+fn fetch_upstream(git: &Git, upstream: &str) -> Result<(usize, usize)> {
+    let remote = git
+        .current_branch()
+        .ok()
+        .and_then(|branch| git.branch_remote(&branch))
+        .or_else(|| {
+            upstream
+                .split_once('/')
+                .map(|(remote, _)| remote.to_string())
+        })
+        .ok_or_else(|| anyhow!("no remote for {upstream}"))?;
+    git.fetch(&remote)
+        .with_context(|| format!("git fetch {remote} failed"))?;
+    Ok(git.ahead_behind(upstream)?)
+}
+
+// Closed stdin is a skip, like a "no" to the push question.
+fn ask_behind(upstream: &str) -> Result<BehindAnswer> {
+    loop {
+        eprint!("Rebase onto {upstream} and push, push anyway, or skip the push? (r/p/s): ");
+        io::stderr().flush()?;
+
+        let mut input = String::new();
+        let read = io::stdin()
+            .read_line(&mut input)
+            .context("failed to read user input")?;
+        if read == 0 {
+            eprintln!();
+            return Ok(BehindAnswer::Skip);
+        }
+        match behind_answer(&input) {
+            Some(answer) => return Ok(answer),
+            None => eprintln!("Please answer 'r', 'p' or 's'"),
+        }
+    }
+}
+
+fn behind_answer(input: &str) -> Option<BehindAnswer> {
+    match input.trim().to_lowercase().as_str() {
+        "r" | "rebase" => Some(BehindAnswer::Rebase),
+        "p" | "push" => Some(BehindAnswer::Push),
+        "s" | "skip" | "n" | "no" => Some(BehindAnswer::Skip),
+        _ => None,
+    }
+}
+
+// `git rebase --autostash <upstream>`, with git's output shown. A conflict
+// is left to the user, with what to do next.
+fn rebase_onto(upstream: &str) -> Result<()> {
+    let status = Command::new("git")
+        .args(["rebase", "--autostash", upstream])
+        .status()
+        .context("failed to run `git rebase`")?;
+    if !status.success() {
+        return Err(anyhow!(
+            "the rebase onto {upstream} stopped. Nothing was pushed.\n\
+             Resolve the conflicts, `git add` them and run `git rebase --continue`, then `git push`; \
+             or run `git rebase --abort` to go back to your commit as it was."
+        ));
+    }
+    Ok(())
+}
+
+// `--confirm-message`: the last word on the message, separate from the push
+// question. `Edit` commits through the editor even with `--no-edit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MessageAnswer {
+    Commit,
+    Edit,
+    Cancel,
+}
+
+// Without a terminal to ask (CI, a piped diff) the answer is yes, so
+// scripts aren't left waiting; closed stdin mid-prompt is a no.
+fn confirm_message(message: &str) -> Result<MessageAnswer> {
+    if !io::stdin().is_terminal() {
+        return Ok(MessageAnswer::Commit);
+    }
+    eprintln!("\n{message}\n");
+    loop {
+        eprint!("Commit with this message? (y/n/e): ");
+        io::stderr().flush()?;
+
+        let mut input = String::new();
+        let read = io::stdin()
+            .read_line(&mut input)
+            .context("failed to read user input")?;
+        if read == 0 {
+            eprintln!();
+            return Ok(MessageAnswer::Cancel);
+        }
+        match message_answer(&input) {
+            Some(answer) => return Ok(answer),
+            None => eprintln!("Please answer 'y', 'n' or 'e'"),
+        }
+    }
+}
+
+fn message_answer(input: &str) -> Option<MessageAnswer> {
+    match input.trim().to_lowercase().as_str() {
+        "y" | "yes" => Some(MessageAnswer::Commit),
+        "e" | "edit" => Some(MessageAnswer::Edit),
+        "n" | "no" => Some(MessageAnswer::Cancel),
+        _ => None,
+    }
+}
+
+// `--interactive`: one prompt per field with the model's value in brackets.
+// Enter (or closed stdin) keeps it; `-` clears the optional scope.
+fn review_commit(commit: &Commit) -> Result<Commit> {
+    Ok(Commit {
+        r#type: ask_field("Type", &commit.r#type, false)?,
+        scope: ask_field("Scope", &commit.scope, true)?,
+        message: ask_field("Message", &commit.message, false)?,
+        ..commit.clone()
+    })
+}
+
+fn ask_field(label: &str, value: &str, clearable: bool) -> Result<String> {
+    eprint!("{label} [{}]: ", value.trim());
+    io::stderr().flush()?;
+
+    let mut input = String::new();
+    let read = io::stdin()
+        .read_line(&mut input)
+        .context("failed to read user input")?;
+    if read == 0 {
+        eprintln!();
+    }
+    Ok(field_answer(&input, value, clearable))
+}
+
+fn field_answer(input: &str, value: &str, clearable: bool) -> String {
+    match input.trim() {
+        "" => value.trim().to_string(),
+        "-" if clearable => String::new(),
+        answer => answer.to_string(),
+    }
+}
+
+// `git-cmt-rs hook install|uninstall|status`, and `init [--uninstall]`.
+fn manage_hook(git: &Git, action: HookAction) -> Result<()> {
+    let hooks_dir = git.hooks_dir()?;
+    match action {
+        HookAction::Install => {
+            let path = hook::install(&hooks_dir)?;
+            eprintln!(
+                "Installed {}; `git commit` now opens the editor with a generated message.",
+                path.display()
+            );
+        }
+        HookAction::Uninstall => match hook::uninstall(&hooks_dir)? {
+            Some(path) => eprintln!("Removed {}", path.display()),
+            None => eprintln!("No git-cmt-rs {} hook installed.", hook::HOOK_NAME),
+        },
+        HookAction::Status => match hook::status(&hooks_dir)? {
+            HookStatus::Installed(path) => println!("installed: {}", path.display()),
+            HookStatus::Outdated(path) => println!(
+                "installed by another git-cmt-rs version: {} (`git-cmt-rs hook install` updates it)",
+                path.display()
+            ),
+            HookStatus::Foreign(path) => println!(
+                "another {} hook is in place, not managed by git-cmt-rs: {}",
+                hook::HOOK_NAME,
+                path.display()
+            ),
+            HookStatus::Missing(path) => println!("not installed: {}", path.display()),
+        },
+    }
+    Ok(())
+}
+
+// Append the commit just created to the history file. Called after a
+// successful `git commit`, so HEAD is the new commit.
+fn record_history(git: &Git, prompt: &str, commit: Commit, format: FormatPreset) -> Result<()> {
+    let path = history::history_file(|name| std::env::var(name).ok())
+        .ok_or_else(|| anyhow!("neither XDG_DATA_HOME nor HOME is set"))?;
+    let (sha, subject) = git.head_commit()?;
+    let record = history::HistoryRecord {
+        timestamp: history::now(),
+        repo: git.toplevel()?,
+        diff_hash: git.hash_object(prompt)?,
+        commit,
+        format,
+        sha: Some(sha),
+        committed: Some(subject),
+    };
+    history::append(&path, &record)
+}
+
+// Where the message cache lives and the key for `prompt` under the current
+// settings. None when the cache is off or there is no git directory to keep
+// it in (`--diff-file` outside a repository).
+fn cache_slot(config: &Config, prompt: &str) -> Option<(PathBuf, String)> {
+    if config.cache_minutes == 0 {
+        return None;
+    }
+    let path = Git::cwd().git_path(cache::CACHE_FILE).ok()?;
+    Some((path, cache::key(&cache_settings(config), prompt)))
+}
+
+// Every setting that changes what the model is asked or how it samples.
+fn cache_settings(config: &Config) -> String {
+    format!(
+        "{:?} {:?} {} {:?} {:?} {} {:?} {:?} {} {}",
+        config.provider,
+        config.api,
+        config.model,
+        config.format,
+        config.privacy,
+        config.body,
+        config.lang,
+        config.top_p,
+        config.frequency_penalty,
+        config.presence_penalty
+    )
+}
+
+// `--changelog`: prepend a section for the commits since the last tag to
+// CHANGELOG.md and stage it. Returns the section heading, or None when
+// there were no commits to list.
+fn update_changelog(version: Option<&str>) -> Result<Option<String>> {
+    let git = Git::cwd();
+    let tag = git.last_tag();
+    let subjects = git.subjects_since(tag.as_deref())?;
+    let version = version.or(tag.as_deref()).unwrap_or("Unreleased");
+    let date = &history::now()[..10];
+    let Some(section) = changelog::section(version, date, &subjects) else {
+        return Ok(None);
+    };
+
+    let path = PathBuf::from(git.toplevel()?).join(changelog::CHANGELOG_FILE);
+    let existing = match fs::read_to_string(&path) {
+        Ok(text) => Some(text),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+    };
+    fs::write(&path, changelog::prepend(existing.as_deref(), &section))
+        .with_context(|| format!("failed to write {}", path.display()))?;
+
+    let status = Command::new("git")
+        .arg("add")
+        .arg(&path)
+        .status()
+        .context("failed to run `git add`")?;
+    if !status.success() {
+        return Err(anyhow!("git add failed with status: {status}"));
+    }
+    Ok(section.lines().next().map(String::from))
+}
+
+// `git-cmt-rs log`: the last `n` history entries, oldest first.
+fn print_history(n: usize) -> Result<()> {
+    let path = history::history_file(|name| std::env::var(name).ok())
+        .ok_or_else(|| anyhow!("neither XDG_DATA_HOME nor HOME is set"))?;
+    let records = history::read_last(&path, n)?;
+    if records.is_empty() {
+        eprintln!("No history yet ({}).", path.display());
+    }
+    for record in records {
+        println!("{record}");
+    }
+    Ok(())
+}
+
+// Newest first; enough for a release, short of a whole history untagged.
+const MAX_TAG_SUBJECTS: usize = 100;
+
+// `--tag`: the message for its annotated tag, from the prompt the commit's
+// message was written from or, with `--tag-from-history`, the subjects since
+// the previous tag (the new tag isn't made yet).
+async fn annotate_tag(config: &Config, git: &Git, prompt: &str, history: bool) -> Result<String> {
+    let changes = if history {
+        let since = git.last_tag();
+        let subjects = git.subjects_since(since.as_deref())?;
+        let since = since.map_or_else(
+            || "the first commit".to_string(),
+            |tag| format!("tag {tag}"),
+        );
+        let list: String = subjects
+            .iter()
+            .take(MAX_TAG_SUBJECTS)
+            .map(|subject| format!("- {subject}\n"))
+            .collect();
+        format!("Commits since {since}:\n{list}")
+    } else {
+        prompt.to_string()
+    };
+    eprintln!("Generating the tag message...");
+    BackendFactory::from_config(config)?
+        .annotate_tag(&changes)
+        .await
+}
+
+// Where `--pr-description` leaves its output, in the git directory.
+const PR_DESCRIPTION_FILE: &str = "PR_DESCRIPTION.md";
+
+// `--pr-description`: the branch's commits since `pr_base` as `git log -p`,
+// redacted and trimmed to the diff budget like a staged diff, described in
+// Markdown. Written to `.git/PR_DESCRIPTION.md` and printed on stdout.
+async fn write_pr_description(config: &Config, git: &Git, redactor: &Redactor) -> Result<()> {
+    let log = git
+        .log_patch(&config.pr_base)
+        .with_context(|| format!("failed to read the commits since {}", config.pr_base))?;
+    let (log, _) = diff::decode_lossy(log);
+    if log.trim().is_empty() {
+        return Err(anyhow!("no commits in {}..HEAD", config.pr_base));
+    }
+    let (log, found) = redactor.redact(&log);
+    if let Some(summary) = redact::summary(&found) {
+        eprintln!("{summary}. Use --no-redact to send them as-is.");
+    }
+    let log = diff::truncate_diff_smart(&log, &llm::diff_budget(config));
+    eprintln!(
+        "Generating a PR description for the commits since {}...",
+        config.pr_base
+    );
+    let backend = BackendFactory::from_config(config)?;
+    let description = backend.describe_pr(&log).await?;
+    let path = git.git_path(PR_DESCRIPTION_FILE)?;
+    fs::write(&path, &description)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    print!("{description}");
+    eprintln!("PR description written to {}", path.display());
+    Ok(())
+}
+
+// `git-cmt-rs models`: one model ID per line on stdout.
+async fn list_models(config: &Config) {
+    let models = match BackendFactory::from_config(config) {
+        Ok(backend) => backend.list_models().await,
+        Err(e) => Err(e),
+    };
+    match models {
+        Ok(models) if models.is_empty() => eprintln!("No models available."),
+        Ok(models) => models.iter().for_each(|m| println!("{m}")),
+        Err(e) => {
+            eprintln!("Failed to list models: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+// Set up the backend, run the `--summarize-large-diffs` map step if needed,
+// check the estimate against the limits, then ask for the message. Returns
+// the prompt that was sent with the commit, or None when the user declined
+// to send. Errors are left to the caller: a normal run exits, `watch` skips
+// the changes and keeps polling.
+async fn generate_commit(
+    config: &Config,
+    mut changes: Changes,
+) -> Result<Option<(String, Commit)>> {
+    // Keyed on the changes before any summarizing, which would differ
+    // from run to run.
+    let cached = cache_slot(config, &changes.text());
+    if let Some((path, key)) = &cached
+        && let Some(entry) = cache::load(path)
+        && let Some(commit) = entry.lookup(key, cache::now(), config.cache_minutes)
+    {
+        eprintln!(
+            "Reusing the message generated {} min ago for the same changes (--no-cache to ask again).",
+            entry.age_minutes(cache::now())
+        );
+        return Ok(Some((changes.text(), commit.clone())));
+    }
+
+    let backend: Arc<dyn LlmBackend> = BackendFactory::from_config(config)
+        .map_err(|e| anyhow!("Failed to set up LLM client: {e}"))?
+        .into();
+
+    // The map step runs before the estimate, so the estimate and
+    // --max-prompt-tokens apply to the prompt that is actually sent.
+    let mut summarized = false;
+    if config.summarize_large_diffs {
+        let sendable: Vec<&str> = changes
+            .files
+            .iter()
+            .filter(|file| file.note.is_none())
+            .map(|file| file.diff.as_str())
+            .collect();
+        let full_tokens = llm::count_prompt_tokens(
+            &config.model,
+            config.lang.as_deref(),
+            config.privacy,
+            config.format,
+            config.body,
+            &sendable.concat(),
+        );
+        if !sendable.is_empty() && full_tokens.count > config.summarize_above {
+            eprintln!(
+                "Full diff is {full_tokens}, above --summarize-above {}; summarizing {} files one by one...",
+                config.summarize_above,
+                sendable.len()
+            );
+            changes.diff = Summarizer::new(backend.clone())
+                .summarize(&changes.files)
+                .await;
+            summarized = true;
+        }
+    }
+
+    let prompt = changes.text();
+    let prompt_tokens = llm::count_prompt_tokens(
+        &config.model,
+        config.lang.as_deref(),
+        config.privacy,
+        config.format,
+        config.body,
+        &prompt,
+    );
+    // Exactly what goes out as the user message, after filtering,
+    // truncation and redaction, for telling why a message came out poorly.
+    if config.verbose {
+        eprintln!(
+            "--- Prompt ({} chars, {prompt_tokens}) ---",
+            prompt.chars().count()
+        );
+        eprint!("{prompt}");
+        if !prompt.ends_with('\n') {
+            eprintln!();
+        }
+        eprintln!("--- End of prompt ---");
+    }
+    let estimate = llm::estimate_cost(config, prompt_tokens);
+    if let Some(estimate) = &estimate {
+        eprintln!("{estimate}");
+    }
+    if let Some(max) = config.max_prompt_tokens
+        && prompt_tokens.count > max
+    {
+        return Err(anyhow!(
+            "Refusing to send: {prompt_tokens} exceeds --max-prompt-tokens {max}. \
+             Stage fewer changes or use --summarize-large-diffs."
+        ));
+    }
+
+    if let (Some(estimate), Some(limit)) = (&estimate, config.cost_warn_above)
+        && estimate.cents() > limit
+    {
+        let question = format!(
+            "Estimated cost {:.2}¢ exceeds --cost-warn-above {limit}¢. Send anyway?",
+            estimate.cents()
+        );
+        match confirm(&question) {
+            Ok(true) => {}
+            Ok(false) if summarized => {
+                eprintln!(
+                    "Request cancelled; per-file summaries were sent, but no commit message was requested. Changes remain staged."
+                );
+                return Ok(None);
+            }
+            Ok(false) => {
+                eprintln!("Request cancelled; nothing was sent. Changes remain staged.");
+                return Ok(None);
+            }
+            Err(e) => return Err(anyhow!("Failed to read confirmation: {e}")),
+        }
+    }
+
+    let commit = backend
+        .generate(&prompt)
+        .await
+        .map_err(|e| anyhow!("Failed to generate commit message: {e}"))?;
+    if let Some((path, key)) = cached
+        && let Err(e) = cache::store(&path, &cache::CacheEntry::new(key, commit.clone()))
+    {
+        eprintln!("Warning: failed to cache the message: {e}");
+    }
+    Ok(Some((prompt, commit)))
+}
+
+// The message for `changes`, its scope taken from `[scope_map]` when a
+// changed path matches. None when the user declined to send the request.
+async fn message_for(config: &Config, changes: Changes) -> Result<Option<(String, Commit)>> {
+    let paths: Vec<&str> = changes.paths.iter().map(String::as_str).collect();
+    let mapped = diff::resolve_scope(&paths, &config.scope_map);
+    let Some((prompt, mut commit)) = fixed_or_generated(config, changes).await? else {
+        return Ok(None);
+    };
+    if let Some(scope) = mapped
+        && scope != commit.scope
+    {
+        eprintln!("Using scope '{scope}' from [scope_map]");
+        commit.scope = scope;
+    }
+    Ok(Some((prompt, commit)))
+}
+
+// Fixed for a lone file move or submodule bumps, otherwise from the model.
+async fn fixed_or_generated(config: &Config, changes: Changes) -> Result<Option<(String, Commit)>> {
+    let fixed = move_commit(&changes.files)
+        .map(|commit| ("Only a file move is staged", commit))
+        .or_else(|| {
+            bump_commit(&changes.files, &changes.submodules)
+                .map(|commit| ("Only submodule updates are staged", commit))
+        });
+    match fixed {
+        // The fixed message is English; --lang goes to the model instead.
+        Some((what, commit)) if config.lang.is_none() => {
+            eprintln!("{what}; no request needed.");
+            Ok(Some((changes.text(), commit)))
+        }
+        _ => generate_commit(config, changes).await,
+    }
+}
+
+// ---------- Watch mode ----------
+// `git-cmt-rs watch`: check `git status` every `interval` seconds and, when
+// the tree has changed, stage, generate and commit with no editor and no
+// push prompt. A state that couldn't be committed (whitespace-only diff,
+// declined estimate, failed request, failing hook) is skipped until the tree
+// changes again.
+const AUTO_PREFIX: &str = "[auto] ";
+
+async fn watch(config: &Config, interval: u64, max_commits: usize) -> Result<()> {
+    let git = configured_git(config);
+    let root = PathBuf::from(git.toplevel()?);
+    let redactor = redactor(config)?;
+    let budget = llm::diff_budget(config);
+    let mut ticker = tokio::time::interval(std::time::Duration::from_secs(interval));
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    eprintln!(
+        "Watching {} every {interval}s for up to {max_commits} auto-commits (Ctrl-C to stop)...",
+        root.display()
+    );
+    if let Some(scope) = staging_scope(&git, config.cwd_only) {
+        eprintln!("{scope}");
+    }
+
+    let mut made = 0;
+    let mut skipped = None;
+    while made < max_commits {
+        ticker.tick().await;
+        if git.status(config.include_untracked)?.is_empty() {
+            continue;
+        }
+        // Wait out a rebase or cherry-pick rather than commit into it.
+        if git.operation_in_progress()?.is_some() {
+            continue;
+        }
+        git.stage(config.include_untracked, config.cwd_only)?;
+        let state = git.status(config.include_untracked)?;
+        if skipped.as_ref() == Some(&state) {
+            continue;
+        }
+
+        let excludes = Excludes::load(&root, &config.prompt_exclude)?;
+        let recent = spawn_recent_subjects(config.context_commits);
+        let changes = match config.privacy {
+            Privacy::NamesOnly | Privacy::Brief => {
+                get_staged_names(&git, &DiffSource::Git, config.privacy)
+            }
+            Privacy::Full => get_staged_changes(
+                &git,
+                &DiffSource::Git,
+                &budget,
+                &config.prompt_ignore,
+                &config.generated,
+                &excludes,
+                &redactor,
+            ),
+        };
+        let changes = match changes {
+            Ok(changes) => changes
+                .with_recent_commits(&recent_subjects(recent).await)
+                .with_hint(config.hint.as_deref()),
+            Err(e) => {
+                eprintln!("Skipping these changes: {e}");
+                skipped = Some(state);
+                continue;
+            }
+        };
+        let (prompt, commit) = match message_for(config, changes).await {
+            Ok(Some(generated)) => generated,
+            Ok(None) => {
+                skipped = Some(state);
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Skipping these changes: {e:#}");
+                skipped = Some(state);
+                continue;
+            }
+        };
+
+        let ticket = branch_ticket(config, &git);
+        let message = format!(
+            "{AUTO_PREFIX}{}",
+            build_commit_message(
+                &commit,
+                config.format,
+                config.body_wrap_width,
+                ticket.as_deref().map(|id| (id, config.ticket_style)),
+            )
+        );
+        let message_file = MessageFile::create(&message)?;
+        if let Err(e) = git.commit(&CommitOptions {
+            message_file: message_file.path(),
+            co_authors: &config.co_authors,
+            amend: false,
+            edit: false,
+            no_verify: config.no_verify,
+        }) {
+            eprintln!("Skipping these changes: {e}");
+            skipped = Some(state);
+            continue;
+        }
+        made += 1;
+        let subject = message.lines().next().unwrap_or_default();
+        eprintln!("Auto-commit {made}/{max_commits}: {subject}");
+        if let Err(e) = record_history(&git, &prompt, commit, config.format) {
+            eprintln!("Could not record commit history: {e}");
+        }
+    }
+    eprintln!("Reached --max-auto-commits {max_commits}; stopping.");
+    Ok(())
+}
+
+// ---------- Squash ----------
+// `git-cmt-rs squash [BASE]`: one message for every commit since the branch
+// left BASE (by default the remote's default branch, else `pr_base`), from
+// their combined diff and their messages, printed on stdout.
+// `--apply` replaces the commits with it: `git reset --soft` to the merge
+// base, then a commit with the message (in the editor unless `--no-edit`).
+async fn squash(
+    config: &Config,
+    base: Option<&str>,
+    apply: bool,
+    force: bool,
+    edit: bool,
+) -> Result<()> {
+    let git = configured_git(config);
+    let default_branch = base.is_none().then(|| default_branch(&git)).flatten();
+    let name = base
+        .or(default_branch.as_deref())
+        .unwrap_or(&config.pr_base);
+    let merge_base = git.merge_base(name).with_context(|| {
+        format!("no common ancestor with {name}; name the base, e.g. `git-cmt-rs squash main`")
+    })?;
+    let range = format!("{merge_base}..HEAD");
+    let count = git.count_commits(&range, &[])?;
+    if count == 0 {
+        return Err(anyhow!("no commits since {name} to squash"));
+    }
+
+    // Checked before the request, so a refused squash costs nothing.
+    let staged = git.has_staged_changes()?;
+    if apply {
+        if let Some(operation) = git.operation_in_progress()? {
+            return Err(anyhow!(
+                "there is {operation} in progress; finish or abort it first"
+            ));
+        }
+        if staged {
+            return Err(anyhow!(
+                "the index has staged changes, which the squashed commit would take in; \
+                 commit or unstage them first"
+            ));
+        }
+        let merges = git.count_commits(&range, &["--merges"])?;
+        if merges > 0 && !force {
+            return Err(anyhow!(
+                "the commits since {name} include {}; squashing flattens them into one \
+                 commit. Use --force to squash anyway",
+                diff::plural(merges, "merge commit")
+            ));
+        }
+        let pushed = count - git.count_commits(&range, &["--not", "--remotes"])?;
+        if pushed > 0 && !force {
+            return Err(anyhow!(
+                "{pushed} of the {} since {name} {} already on a remote; squashing rewrites \
+                 them and the next push needs --force-with-lease. Use --force to squash anyway",
+                diff::plural(count, "commit"),
+                if pushed == 1 { "is" } else { "are" }
+            ));
+        }
+    } else if staged {
+        eprintln!("Warning: staged changes are described too, though no commit holds them yet.");
+    }
+
+    let mut config = config.clone();
+    config.body = true;
+    let root = PathBuf::from(git.toplevel()?);
+    let excludes = Excludes::load(&root, &config.prompt_exclude)?;
+    let source = DiffSource::Squash(merge_base.clone());
+    let changes = match config.privacy {
+        Privacy::NamesOnly | Privacy::Brief => get_staged_names(&git, &source, config.privacy)?,
+        Privacy::Full => get_staged_changes(
+            &git,
+            &source,
+            &llm::diff_budget(&config),
+            &config.prompt_ignore,
+            &config.generated,
+            &excludes,
+            &redactor(&config)?,
+        )?,
+    };
+    let changes = changes
+        .with_squash(Squash::new(name, git.messages(&range)?).prompt())
+        .with_hint(config.hint.as_deref());
+    eprintln!(
+        "Squashing {} since {name}; generating the squashed message...",
+        diff::plural(count, "commit")
+    );
+    let Some((prompt, commit)) = message_for(&config, changes).await? else {
+        return Ok(());
+    };
+    let ticket = branch_ticket(&config, &git);
+    let message = build_commit_message(
+        &commit,
+        config.format,
+        config.body_wrap_width,
+        ticket.as_deref().map(|id| (id, config.ticket_style)),
+    );
+    if !apply {
+        println!("{message}");
+        eprintln!("Nothing changed; run again with --apply to squash the commits into one.");
+        return Ok(());
+    }
+
+    let (head, _) = git.head_commit()?;
+    let status = Command::new("git")
+        .args(["reset", "--soft", &merge_base])
+        .status()
+        .context("failed to run `git reset`")?;
+    if !status.success() {
+        return Err(anyhow!("git reset failed with status: {status}"));
+    }
+    let message_file = MessageFile::for_commit(&git, &message, edit)
+        .context("failed to write the commit message to a temporary file")?;
+    let options = CommitOptions {
+        message_file: message_file.path(),
+        co_authors: &config.co_authors,
+        amend: false,
+        edit,
+        no_verify: config.no_verify,
+    };
+    if let Err(e) = git.commit(&options) {
+        eprintln!(
+            "The branch is at {name}'s merge base with every change staged; \
+             `git reset --soft {head}` puts the commits back."
+        );
+        return Err(e.into());
+    }
+    drop(message_file);
+    match git.head_shortstat() {
+        Ok((sha, subject, stat)) => {
+            eprintln!("{}", commit_summary("created", &sha, &subject, &stat))
+        }
+        Err(_) => eprintln!("Commit created successfully."),
+    }
+    eprintln!(
+        "Replaced {}; the old tip was {head}.",
+        diff::plural(count, "commit")
+    );
+    if let Err(e) = record_history(&git, &prompt, commit, config.format) {
+        eprintln!("Could not record commit history: {e}");
+    }
+    Ok(())
+}
+
+// The branch `refs/remotes/<remote>/HEAD` points at, trying the current
+// branch's remote, then origin, then the rest; None when no remote has one.
+fn default_branch(git: &Git) -> Option<String> {
+    let mut remotes = git.remotes().unwrap_or_default();
+    let own = git
+        .current_branch()
+        .ok()
+        .and_then(|branch| git.branch_remote(&branch));
+    for first in ["origin"].into_iter().map(String::from).chain(own) {
+        if let Some(at) = remotes.iter().position(|remote| *remote == first) {
+            let remote = remotes.remove(at);
+            remotes.insert(0, remote);
+        }
+    }
+    remotes.iter().find_map(|remote| git.remote_head(remote))
+}
+
+// ---------- config validate ----------
+// `git-cmt-rs config validate`: one line per check so a broken setup shows up
+// before the first commit. A check is skipped when one it depends on failed.
+// Returns whether every check passed (warnings don't count).
+async fn validate_config(cli: &Cli) -> bool {
+    let var = |name: &str| std::env::var(name).ok();
+    let mut passed = true;
+    let mut report = |status: &str, check: &str, detail: String| {
+        passed &= status != "FAIL";
+        println!("{:<7}{check}: {detail}", format!("[{status}]"));
+    };
+
+    let file = match FileConfig::find(var) {
+        None => {
+            report(
+                "ok",
+                "config file",
+                "none found; using environment and defaults".into(),
+            );
+            FileConfig::default()
+        }
+        Some(path) => match FileConfig::read(&path) {
+            Ok(file) => {
+                report("ok", "config file", path.display().to_string());
+                file
+            }
+            Err(e) => {
+                report("FAIL", "config file", format!("{e:#}"));
+                return false;
+            }
+        },
+    };
+
+    let config = match Config::resolve(cli, &file, var) {
+        Ok(config) => {
+            let provider = config
+                .provider
+                .to_possible_value()
+                .map(|v| v.get_name().to_string());
+            report(
+                "ok",
+                "settings",
+                format!(
+                    "{} at {}, model {}",
+                    provider.unwrap_or_default(),
+                    config.base_url,
+                    config.model
+                ),
+            );
+            config
+        }
+        Err(e) => {
+            report("FAIL", "settings", format!("{e:#}"));
+            return false;
+        }
+    };
+
+    let key_missing = match (config.api_key_env(), &config.api_key) {
+        (None, _) => {
+            report("ok", "api key", "not needed for this provider".into());
+            false
+        }
+        (Some((name, _)), Some(_)) => {
+            report("ok", "api key", format!("set ({name})"));
+            false
+        }
+        (Some((name, true)), None) => {
+            report("FAIL", "api key", format!("{name} is not set"));
+            true
+        }
+        (Some((name, false)), None) => {
+            report(
+                "warn",
+                "api key",
+                format!("{name} is not set; fine if this endpoint needs none"),
+            );
+            false
+        }
+    };
+    if key_missing {
+        report("skip", "connection", "needs an API key".into());
+        return passed;
+    }
+
+    let models = match BackendFactory::from_config(&config) {
+        Ok(backend) => backend.list_models().await,
+        Err(e) => Err(e),
+    };
+    match models {
+        Ok(models) => {
+            report(
+                "ok",
+                "connection",
+                format!("{} answered with {} models", config.base_url, models.len()),
+            );
+            if !models.is_empty() && !model_listed(&config.model, &models) {
+                report(
+                    "warn",
+                    "model",
+                    format!("{} is not in the provider's model list", config.model),
+                );
+            }
+        }
+        Err(e) => report("FAIL", "connection", format!("{e:#}")),
+    }
+    passed
+}
+
+// Ollama lists models with their tag; `qwen2.5-coder` means `:latest`.
+fn model_listed(model: &str, models: &[String]) -> bool {
+    models
+        .iter()
+        .any(|m| m == model || *m == format!("{model}:latest"))
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args_os()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    let color = color_choice(&args, std::env::var("NO_COLOR").ok().as_deref());
+    // Prompts are coloured through `console`; on auto it checks the terminal.
+    if color != ColorChoice::Auto {
+        let enabled = color == ColorChoice::Always;
+        dialoguer::console::set_colors_enabled(enabled);
+        dialoguer::console::set_colors_enabled_stderr(enabled);
+    }
+    let matches = Cli::command().color(color).get_matches_from(&args);
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    // `-C`: the rest runs there, as `git -C` does, so every git command,
+    // the repository's config file and `.gitcmtignore` follow without
+    // being told.
+    if let Some(path) = &cli.repo {
+        let entered = repo_dir(path).and_then(|dir| Ok(std::env::set_current_dir(dir)?));
+        if let Err(e) = entered {
+            eprintln!("Can't run in {}: {e}", path.display());
+            std::process::exit(EXIT_NOT_A_REPO);
+        }
+    }
+
+    // `undo` only talks to git, so a broken LLM config shouldn't block it.
+    if let Some(CliCommand::Undo { hard }) = &cli.command {
+        preflight(cli.verbose);
+        let git = cli_git(&cli);
+        if let Err(e) = undo_last_commit(&git, *hard) {
+            eprintln!("Failed to undo last commit: {e}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // `hook` (and `init`) only touch a file in the hooks directory.
+    let hook_action = match &cli.command {
+        Some(CliCommand::Hook { action }) => Some(*action),
+        Some(CliCommand::Init { uninstall: false }) => Some(HookAction::Install),
+        Some(CliCommand::Init { uninstall: true }) => Some(HookAction::Uninstall),
+        _ => None,
+    };
+    if let Some(action) = hook_action {
+        preflight(cli.verbose);
+        if let Err(e) = manage_hook(&cli_git(&cli), action) {
+            eprintln!("Failed to update the {} hook: {e}", hook::HOOK_NAME);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // `log` only reads the local history file.
+    if let Some(CliCommand::Log { last }) = &cli.command {
+        if let Err(e) = print_history(*last) {
+            eprintln!("Failed to read commit history: {e}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // `config validate` reports a broken config file or setting as a failed
+    // check instead of stopping at it.
+    if let Some(CliCommand::Config {
+        action: ConfigAction::Validate,
+    }) = &cli.command
+    {
+        if !validate_config(&cli).await {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // Resolve configuration before touching the index so a bad setting fails
+    // without side effects.
+    let mut config = match Config::load(&cli) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Invalid configuration: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    match &cli.command {
+        Some(CliCommand::Config { action }) => {
+            match action {
+                ConfigAction::Show => print!("{}", config.render()),
+                ConfigAction::Validate => unreachable!("handled before Config::load"),
+            }
+            return Ok(());
+        }
+        Some(CliCommand::Models) => {
+            list_models(&config).await;
+            return Ok(());
+        }
+        Some(
+            CliCommand::Undo { .. }
+            | CliCommand::Revert { .. }
+            | CliCommand::Hook { .. }
+            | CliCommand::Init { .. }
+            | CliCommand::Log { .. }
+            | CliCommand::Watch { .. }
+            | CliCommand::Squash { .. },
+        )
+        | None => {}
+    }
+    let print_only = print_only(&cli);
+    if !print_only {
+        preflight(config.verbose);
+    }
+
+    // `--hook`, from the prepare-commit-msg hook: sources that come with a
+    // message of their own are left alone before any work is done.
+    let hook_call = hook::HookCall::from_args(&cli.hook);
+    if let Some(call) = &hook_call
+        && call.skipped(&config.hook_skip_sources)
+    {
+        return Ok(());
+    }
+
+    if config.insecure {
+        eprintln!(
+            "WARNING: TLS certificate verification is DISABLED for LLM requests (--insecure). \
+             Anyone on the network path can read or alter your diff and the response."
+        );
+    }
+
+    if config.no_verify {
+        eprintln!(
+            "WARNING: --no-verify: git hooks (pre-commit, commit-msg, pre-push) will be skipped for this commit and push."
+        );
+    }
+
+    if let Some(code) = &config.lang
+        && llm::language_name(code).is_none()
+    {
+        eprintln!(
+            "Warning: unrecognised language code {code:?} for --lang; asking the model for it as given."
+        );
+    }
+
+    if config.verbose
+        && let Some(proxy) = &config.proxy
+    {
+        eprintln!("Using proxy {proxy}");
+    }
+
+    if let Some(CliCommand::Watch {
+        interval,
+        max_auto_commits,
+    }) = &cli.command
+    {
+        if let Err(e) = watch(&config, *interval, *max_auto_commits).await {
+            eprintln!("Watch stopped: {e}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(CliCommand::Squash { base, apply, force }) = &cli.command {
+        if let Err(e) = squash(&config, base.as_deref(), *apply, *force, !cli.no_edit).await {
+            eprintln!("Can't squash: {e:#}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let source = DiffSource::from_cli(&cli);
+    let git = configured_git(&config);
+
+    // A tag that can't be created would only fail once the commit is made.
+    // The two conflict, so at most one is set.
+    let new_tag = cli
+        .tag_version
+        .as_ref()
+        .map(|tag| (tag, "--tag-version"))
+        .or(cli.tag.as_ref().map(|tag| (tag, "--tag")));
+    if let Some((tag, flag)) = new_tag {
+        if !git.is_valid_tag_name(tag) {
+            eprintln!("{flag} {tag:?} is not a valid tag name");
+            std::process::exit(1);
+        }
+        if git.tag_exists(tag) {
+            eprintln!("Tag {tag} already exists; pick another {flag}");
+            std::process::exit(1);
+        }
+    }
+
+    // A commit made now would land inside the operation's sequence. Dry
+    // runs and hook calls commit nothing themselves.
+    if source.stages()
+        && !(cli.dry_run || cli.message_only || hook_call.is_some() || cli.allow_in_progress)
+    {
+        match git.operation_in_progress() {
+            Ok(None) => {}
+            Ok(Some(operation)) => {
+                let command = operation.command();
+                eprintln!(
+                    "There is {operation} in progress; committing now would land in the middle of it.\n\
+                     Resolve any conflicts and run `git {command} --continue`, or `git {command} --abort` \
+                     to give up on it. Use --allow-in-progress to commit anyway."
+                );
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Warning: could not check for a rebase or cherry-pick in progress: {e}")
+            }
+        }
+    }
+
+    // The revert is its own diff source: nothing else is staged, and it
+    // can't be left half-done by a dry run.
+    let revert = match &cli.command {
+        Some(CliCommand::Revert { commit }) => {
+            if cli.amend
+                || cli.wip
+                || cli.dry_run
+                || cli.message_only
+                || cli.changelog
+                || cli.select
+                || !source.stages()
+            {
+                eprintln!(
+                    "revert can't be combined with --amend, --wip, --dry-run, --message-only, --changelog, --select, --diff-stdin or --diff-file"
+                );
+                std::process::exit(1);
+            }
+            match start_revert(commit) {
+                Ok(revert) => {
+                    eprintln!(
+                        "Reverting {} ({:?}) in the index with `git revert --no-commit`",
+                        revert.short, revert.subject
+                    );
+                    Some(revert)
+                }
+                Err(e) => {
+                    eprintln!("Failed to revert {commit}: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        _ => None,
+    };
+
+    // Concluding a merge: the index already holds the merge result, so
+    // nothing else is staged, and the message describes the merge with a
+    // body on how any conflicts were resolved.
+    let merge = if matches!(source, DiffSource::Git) && hook_call.is_none() {
+        match merge_in_progress(&git) {
+            Ok(Some(merge)) => {
+                if cli.wip || cli.changelog || cli.select {
+                    eprintln!(
+                        "A merge is in progress; --wip, --changelog and --select can't be used until it is committed"
+                    );
+                    std::process::exit(1);
+                }
+                eprintln!(
+                    "Merge of {} '{}' in progress; committing the index as it is",
+                    merge.kind, merge.theirs
+                );
+                config.body = true;
+                Some(merge)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                eprintln!("A merge is in progress: {e:#}");
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    // Before staging, so the updated CHANGELOG.md is part of the diff the
+    // message is written for.
+    if cli.changelog {
+        match update_changelog(cli.changelog_version.as_deref()) {
+            Ok(Some(heading)) => eprintln!("Updated {} with {heading}", changelog::CHANGELOG_FILE),
+            Ok(None) => eprintln!(
+                "No commits since the last tag; {} left unchanged.",
+                changelog::CHANGELOG_FILE
+            ),
+            Err(e) => {
+                eprintln!("Failed to update {}: {e}", changelog::CHANGELOG_FILE);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // A dry run changes nothing, the index included.
+    let dry_run = cli.dry_run || cli.message_only || print_only || hook_call.is_some();
+    let no_stage = cli.no_stage || dry_run;
+    // `git revert` and `git merge` have already staged everything the
+    // commit should hold.
+    let stages = source.stages() && revert.is_none() && merge.is_none();
+    if stages && dry_run {
+        eprintln!("Dry run; generating a message for the index as it is");
+    } else if stages && cli.no_stage {
+        eprintln!("Skipping staging (--no-stage); committing the index as it is");
+    } else if stages && cli.select {
+        match stage_selected(&config, &git) {
+            Ok(Some(0)) => {
+                eprintln!("No files selected; nothing staged or committed.");
+                return Ok(());
+            }
+            Ok(Some(n)) => eprintln!("Staged {n} selected file(s)"),
+            // Nothing changed; the empty diff below says so.
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("Failed to stage the selected files: {e:#}");
+                std::process::exit(1);
+            }
+        }
+    } else if stages {
+        if let Some(scope) = staging_scope(&git, config.cwd_only) {
+            eprintln!("{scope}");
+        }
+        if !(cli.force_stage || cli.yes) {
+            match confirm_staging(&config, &git) {
+                Ok(true) => {}
+                Ok(false) => {
+                    eprintln!("Staging cancelled; nothing staged or committed.");
+                    if cli.changelog {
+                        eprintln!(
+                            "{} was already updated; `git checkout -- {0}` undoes it.",
+                            changelog::CHANGELOG_FILE
+                        );
+                    }
+                    return Ok(());
+                }
+                Err(e) => {
+                    eprintln!("Failed to list the changes to stage: {e:#}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        let args = git::staging_args(config.include_untracked, config.cwd_only).join(" ");
+        match git.stage(config.include_untracked, config.cwd_only) {
+            Ok(_) if config.include_untracked => eprintln!("Staged all changes with `git {args}`"),
+            Ok(_) => {
+                eprintln!("Staged tracked changes with `git {args}`; untracked files left out")
+            }
+            Err(e) => {
+                eprintln!("Failed to stage changes: {e}");
+                std::process::exit(1);
+            }
+        };
+    }
+
+    // No diff is read and no model asked; the commit is never pushed.
+    if cli.wip {
+        std::process::exit(wip_commit(&git, &config, !no_stage));
+    }
+
+    // `.gitcmtignore` lives at the repository root; outside a repository
+    // (e.g. `--diff-file` on a plain patch) it is looked for here.
+    let root = git
+        .toplevel()
+        .map_or_else(|_| PathBuf::from("."), PathBuf::from);
+    let excludes = match Excludes::load(&root, &config.prompt_exclude) {
+        Ok(excludes) => excludes,
+        Err(e) => {
+            eprintln!("Failed to load prompt exclusions: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    let redactor = match redactor(&config) {
+        Ok(redactor) => redactor,
+        Err(e) => {
+            eprintln!("Failed to load redaction patterns: {e:#}");
+            std::process::exit(1);
+        }
+    };
+
+    let budget = llm::diff_budget(&config);
+    if config.verbose {
+        eprintln!("Diff budget: {} {}", budget.limit, budget.unit);
+    }
+
+    let recent = spawn_recent_subjects(config.context_commits);
+    let changes = match config.privacy {
+        Privacy::NamesOnly | Privacy::Brief => get_staged_names(&git, &source, config.privacy),
+        Privacy::Full => get_staged_changes(
+            &git,
+            &source,
+            &budget,
+            &config.prompt_ignore,
+            &config.generated,
+            &excludes,
+            &redactor,
+        ),
+    };
+    let recent = recent_subjects(recent).await;
+    let mut changes = match changes {
+        Ok(d) => d
+            .with_recent_commits(&recent)
+            .with_hint(config.hint.as_deref()),
+        Err(e)
+            if source.stages()
+                && matches!(e.downcast_ref(), Some(GitCmtError::NothingToCommit)) =>
+        {
+            std::process::exit(nothing_to_commit(&git, &config, !no_stage));
+        }
         Err(e) => {
-            eprintln!("Error during push confirmation: {e}");
+            eprintln!("Failed to get diff from {source}: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    // Only git sources have the name and stat queries to fall back on.
+    if let Some((size, threshold)) = auto_stat(&config, &budget, &changes)
+        && source.stages()
+    {
+        match get_staged_names(&git, &source, Privacy::NamesOnly) {
+            Ok(names) => {
+                eprintln!(
+                    "Diff is {size} {unit}, over --stat-threshold {threshold}; switching to --stat-only. \
+                     Use --no-auto-stat to send the trimmed diff instead.",
+                    unit = budget.unit
+                );
+                changes = names
+                    .with_recent_commits(&recent)
+                    .with_hint(config.hint.as_deref());
+                config.privacy = Privacy::NamesOnly;
+            }
+            Err(e) => eprintln!("Warning: could not switch to --stat-only: {e}"),
+        }
+    }
+
+    if let Some(revert) = &revert {
+        changes = changes.with_revert(&revert.short, &revert.subject);
+    }
+    if let Some(merge) = &merge {
+        changes = changes.with_merge(merge.prompt());
+    }
+
+    // Without staging, new files are easy to forget: name them to the user
+    // and to the model, which would otherwise credit the commit with them.
+    // A pre-computed diff says nothing about the working tree.
+    if no_stage && source.stages() {
+        match git.untracked_files() {
+            Ok(untracked) => {
+                changes = changes.with_untracked(&untracked);
+                if let Some(list) = &changes.untracked {
+                    eprint!("{list}");
+                }
+            }
+            Err(e) => eprintln!("Warning: could not list untracked files: {e}"),
+        }
+    }
+
+    match source {
+        DiffSource::Git => eprintln!("Staged diff found; generating message for changes..."),
+        DiffSource::Amend => {
+            eprintln!("Amending HEAD; generating message for its changes plus the index...")
+        }
+        ref other => eprintln!("Read diff from {other}; generating message for changes..."),
+    }
+    match config.privacy {
+        Privacy::Full => {}
+        Privacy::NamesOnly => {
+            eprintln!("Names only: sending file names and change counts, no file contents.")
+        }
+        Privacy::Brief => eprintln!("Brief: sending only the changed file names."),
+    }
+
+    if config.verbose
+        && let Some(stat) = &changes.stat
+    {
+        eprint!("{stat}");
+    }
+    // Why each summarized file was taken for generated, to debug the
+    // `generated_*` settings.
+    if config.verbose {
+        for file in &changes.files {
+            if let Some(reason) = &file.generated {
+                eprintln!("Generated file, diff omitted: {} ({reason})", file.path);
+            }
+        }
+    }
+
+    // For telling what a failing hook changed, after `changes` is spent.
+    let paths = changes.paths.clone();
+    let generated = message_for(&config, changes).await.unwrap_or_else(|e| {
+        eprintln!("{e:#}");
+        std::process::exit(1);
+    });
+    let Some((prompt, mut commit)) = generated else {
+        if revert.is_some() {
+            eprintln!("The revert is still applied to the index; `git revert --abort` undoes it.");
+        }
+        return Ok(());
+    };
+    if revert.is_some() {
+        commit.r#type = "revert".into();
+    }
+    // The subject is always git's; the model writes the body.
+    if let Some(merge) = &merge {
+        commit.r#type = "merge".into();
+        commit.scope.clear();
+        commit.message = merge.subject();
+        commit.breaking = false;
+    }
+
+    eprintln!(
+        "Parsed commit: type='{}', scope='{}', message='{}'",
+        commit.r#type, commit.scope, commit.message
+    );
+
+    // The history keeps what the model suggested, so review a copy.
+    let mut reviewed = if cli.interactive {
+        match review_commit(&commit) {
+            Ok(reviewed) => reviewed,
+            Err(e) => {
+                eprintln!("Failed to review commit message: {e}");
+                std::process::exit(1);
+            }
+        }
+    } else {
+        commit.clone()
+    };
+    if cli.breaking && !reviewed.breaking {
+        eprintln!("Marking the commit as a breaking change (--breaking)");
+        reviewed.breaking = true;
+    }
+
+    let ticket = branch_ticket(&config, &git);
+    let mut message = build_commit_message(
+        &reviewed,
+        config.format,
+        config.body_wrap_width,
+        ticket.as_deref().map(|id| (id, config.ticket_style)),
+    );
+    if let Some(revert) = &revert {
+        message = format!("{message}\n\n{}", revert.footer());
+    }
+    // Before the editor, so a clipboard failure is seen and the generated
+    // message is there to paste whatever happens to the commit.
+    if cli.copy {
+        match copy_to_clipboard(&message) {
+            Ok(()) => eprintln!("Commit message copied to clipboard"),
+            Err(e) => eprintln!("Warning: could not copy the message to the clipboard: {e}"),
+        }
+    }
+    if let Some(call) = &hook_call {
+        if let Err(e) = call.prepend(&message) {
+            eprintln!("Failed to write the commit message: {e}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if dry_run {
+        if print_only && !cli.message_only {
+            eprintln!(
+                "Diff from {source}; nothing committed (use --commit to commit). The message is:"
+            );
+        } else if !cli.message_only {
+            eprintln!("Dry run; nothing committed or pushed. The message would be:");
+        }
+        println!("{message}");
+        return Ok(());
+    }
+    let mut edit = !(cli.no_edit || cli.interactive);
+    if cli.confirm_message && !cli.yes {
+        match confirm_message(&message) {
+            Ok(MessageAnswer::Commit) => {}
+            Ok(MessageAnswer::Edit) => edit = true,
+            Ok(MessageAnswer::Cancel) => {
+                eprintln!("Commit cancelled; the changes are still staged.");
+                if revert.is_some() {
+                    eprintln!("`git revert --abort` undoes the revert in the index.");
+                }
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("Error during message confirmation: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+    let message_file = MessageFile::for_commit(&git, &message, edit)
+        .context("failed to write the commit message to a temporary file")?;
+    let options = CommitOptions {
+        message_file: message_file.path(),
+        co_authors: &config.co_authors,
+        amend: cli.amend,
+        edit,
+        no_verify: config.no_verify,
+    };
+    let dirty_before = git.unstaged_changes(&paths).unwrap_or_default();
+    if let Err(e) = git.commit(&options) {
+        match restage_after_hook(&git, &paths, &dirty_before, cli.hook_retry) {
+            Ok(true) => git.commit(&options)?,
+            Ok(false) => return Err(e.into()),
+            Err(check) => {
+                eprintln!("Could not check for files modified by hooks: {check}");
+                return Err(e.into());
+            }
+        }
+    }
+    // Gone before any later `process::exit`, which would skip the drop.
+    drop(message_file);
+
+    // From HEAD rather than `message`, which the editor may have changed.
+    let verb = if cli.amend { "amended" } else { "created" };
+    match git.head_shortstat() {
+        Ok((sha, subject, stat)) => eprintln!("{}", commit_summary(verb, &sha, &subject, &stat)),
+        Err(_) => eprintln!("Commit {verb} successfully."),
+    }
+
+    if let Err(e) = record_history(&git, &prompt, commit, config.format) {
+        eprintln!("Could not record commit history: {e}");
+    }
+
+    if let Some(tag) = &cli.tag_version {
+        if let Err(e) = create_tag(tag, tag) {
+            eprintln!("Failed to tag the commit: {e}");
+            std::process::exit(1);
+        }
+        eprintln!("Tagged the commit {tag}");
+    }
+    if let Some(tag) = &cli.tag {
+        let annotation = match annotate_tag(&config, &git, &prompt, cli.tag_from_history).await {
+            Ok(annotation) => annotation,
+            Err(e) => {
+                eprintln!("Could not generate the tag message ({e:#}); using the tag name.");
+                tag.clone()
+            }
+        };
+        if let Err(e) = create_tag(tag, &annotation) {
+            eprintln!("Failed to tag the commit: {e}");
             std::process::exit(1);
         }
+        eprintln!("Tagged the commit {tag}:\n{}", annotation.trim_end());
+    }
+
+    // After the commit, so the branch's log includes it. The commit stands
+    // whatever happens here.
+    if cli.pr_description
+        && let Err(e) = write_pr_description(&config, &git, &redactor).await
+    {
+        eprintln!("Could not write the PR description: {e:#}");
+    }
+
+    // Nowhere to push in a local-only repository; the commit stands.
+    let remotes = git.remotes().unwrap_or_default();
+    if remotes.is_empty() {
+        eprintln!("No remotes configured; skipping push.");
+        return Ok(());
+    }
+
+    // A detached HEAD has no branch to push; without `--branch` there is
+    // nowhere sensible to send the commit, so don't offer to.
+    let detached = git.is_detached();
+    match (&cli.branch, detached) {
+        (None, true) => {
+            let sha = git.head_commit().map(|(sha, _)| sha).unwrap_or_default();
+            eprintln!(
+                "Detached HEAD; skipping push. The new commit is {sha}: \
+                 `git branch <name> {sha}` keeps it, or re-run with --branch <name> to push it."
+            );
+            return Ok(());
+        }
+        (Some(branch), false) => {
+            eprintln!(
+                "Note: --branch {branch} only applies on a detached HEAD; pushing the current branch."
+            )
+        }
+        _ => {}
+    }
+
+    // `--remote`, else the branch's own `branch.<name>.remote`. A name that
+    // isn't configured would only fail after the prompt, so stop here.
+    let named_remote = match &cli.remote {
+        Some(remote) => Some((remote.clone(), "--remote".to_string())),
+        None if !detached => git.current_branch().ok().and_then(|branch| {
+            git.branch_remote(&branch)
+                .map(|remote| (remote, format!("branch.{branch}.remote")))
+        }),
+        None => None,
+    };
+    if let Some((remote, from)) = &named_remote
+        && !remote_exists(remote, &remotes)
+    {
+        eprintln!(
+            "Remote '{remote}' (from {from}) does not exist; configured remotes: {}",
+            remotes.join(", ")
+        );
+        std::process::exit(1);
+    }
+
+    // `--push-tags`: the tag just made, else whatever already points at the
+    // new commit.
+    let push_tags = match (&cli.tag_version, cli.push_tags) {
+        (_, false) => Vec::new(),
+        (Some(tag), true) => vec![tag.clone()],
+        (None, true) => git.tags_at_head().unwrap_or_default(),
+    };
+    if cli.push_tags && push_tags.is_empty() {
+        eprintln!("--push-tags: no tags point at the new commit; pushing the branch only.");
+    }
+    // `--tag`: the annotated tag goes with the branch, via `--follow-tags`.
+    let asked_tags: Vec<String> = push_tags.iter().chain(&cli.tag).cloned().collect();
+
+    // Warn before asking, so the answer is an informed one.
+    let push_mode = PushMode::from_cli(&cli);
+    if let Some(warning) = push_mode.warning() {
+        eprintln!("{warning}");
+    }
+
+    // A branch that has never been pushed gets its upstream set on the way,
+    // so the next plain `git push` (or `git pull`) knows where to go.
+    let set_upstream = !detached && config.auto_set_upstream && git.upstream().is_none();
+    // The push names its remote when it has to; otherwise git picks.
+    let tag_remote = named_remote.as_ref().map(|(remote, _)| remote.clone());
+    let remote = if detached || set_upstream || cli.remote.is_some() {
+        let remote = match named_remote {
+            Some((remote, _)) => Some(remote),
+            None => choose_remote(&remotes)?,
+        };
+        if remote.is_none() {
+            eprintln!("Push cancelled. Commit saved locally.");
+            return Ok(());
+        }
+        remote
+    } else {
+        None
+    };
+
+    // Ask for confirmation before pushing
+    let upstream = match (&remote, set_upstream) {
+        (Some(remote), true) => Some(format!("{remote}/{}", git.current_branch()?)),
+        _ => None,
     };
+    // Only a push to the upstream can be checked against it.
+    let tracking = (config.fetch_before_push && remote.is_none() && !detached)
+        .then(|| git.upstream())
+        .flatten();
+    let mut answered = false;
+    if let Some(tracking) = &tracking {
+        match fetch_upstream(&git, tracking) {
+            Ok((0, 0)) => {}
+            Ok((ahead, 0)) => eprintln!(
+                "Your branch is {} ahead of {tracking}.",
+                diff::plural(ahead, "commit")
+            ),
+            Ok((ahead, behind)) => {
+                eprintln!(
+                    "{tracking} has {} you don't have; your branch is {} ahead of it.",
+                    diff::plural(behind, "new commit"),
+                    diff::plural(ahead, "commit")
+                );
+                match ask_behind(tracking)? {
+                    BehindAnswer::Rebase => {
+                        if let Err(e) = rebase_onto(tracking) {
+                            eprintln!("Commit saved locally, but {e}");
+                            std::process::exit(1);
+                        }
+                        eprintln!("Rebased onto {tracking}");
+                        if !asked_tags.is_empty() {
+                            eprintln!(
+                                "Note: {} still point(s) at the commit as it was before the rebase; \
+                                 `git tag -f -a <tag>` moves a tag to the rebased one.",
+                                asked_tags.join(", ")
+                            );
+                        }
+                    }
+                    BehindAnswer::Push => {}
+                    BehindAnswer::Skip => {
+                        eprintln!("Push skipped. Commit saved locally.");
+                        return Ok(());
+                    }
+                }
+                answered = true;
+            }
+            Err(e) => eprintln!("Warning: could not check {tracking} for new commits: {e:#}"),
+        }
+    }
+    let should_push = answered
+        || match confirm_push(upstream.as_deref(), &asked_tags) {
+            Ok(confirmed) => confirmed,
+            Err(e) => {
+                eprintln!("Error during push confirmation: {e}");
+                std::process::exit(1);
+            }
+        };
 
     if !should_push {
         eprintln!("Push cancelled. Commit saved locally.");
         return Ok(());
     }
 
-    let mut push_cmd = Command::new("git");
-    push_cmd.arg("push");
-    if !has_upstream() {
-        let branch = current_branch()?;
-        eprintln!("No upstream set; pushing with `--set-upstream origin {branch}`");
-        push_cmd.args(["--set-upstream", "origin", &branch]);
+    // Tags go in a push of their own, so they need a remote even when the
+    // branch push leaves it to git.
+    let tag_remote = remote
+        .clone()
+        .or(tag_remote)
+        .or_else(|| default_remote(&remotes).map(String::from))
+        .unwrap_or_else(|| "origin".into());
+
+    let mut push_args: Vec<String> = push_mode.flag().into_iter().map(String::from).collect();
+    if config.no_verify {
+        push_args.push("--no-verify".into());
+    }
+    if cli.tag.is_some() {
+        push_args.push("--follow-tags".into());
+    }
+    if let Some(remote) = &remote {
+        match (&cli.branch, detached) {
+            (Some(branch), true) => {
+                eprintln!("Detached HEAD; pushing it to {remote} as {branch}");
+                push_args.extend([remote.clone(), detached_push_refspec(branch)]);
+            }
+            _ if set_upstream => push_args.extend(["-u".into(), remote.clone(), "HEAD".into()]),
+            _ => push_args.extend([remote.clone(), "HEAD".into()]),
+        }
+    }
+    let (mut status, stderr) = git_push(&push_args)?;
+    if !status.success() && config.auto_set_upstream && needs_upstream(&stderr) {
+        let branch = git.current_branch()?;
+        let remote = remote
+            .or_else(|| default_remote(&remotes).map(String::from))
+            .unwrap_or_else(|| "origin".into());
+        eprintln!("No upstream set; retrying with `git push --set-upstream {remote} {branch}`");
+        push_args.extend(["--set-upstream".into(), remote, branch]);
+        (status, _) = git_push(&push_args)?;
     }
-    let status = push_cmd.status().context("failed to run `git push`")?;
 
     if !status.success() {
         return Err(anyhow!("git push failed with status: {status}"));
@@ -493,93 +3110,506 @@ async fn main() -> Result<()> {
 
     eprintln!("Changes pushed successfully!");
 
+    if !push_tags.is_empty() {
+        let mut tag_args: Vec<String> = Vec::new();
+        if config.no_verify {
+            tag_args.push("--no-verify".into());
+        }
+        tag_args.push(tag_remote.clone());
+        tag_args.extend(push_tags.iter().map(|tag| format!("refs/tags/{tag}")));
+        let (status, _) = git_push(&tag_args)?;
+        if !status.success() {
+            return Err(anyhow!(
+                "git push of {} to {tag_remote} failed with status: {status}",
+                push_tags.join(", ")
+            ));
+        }
+        eprintln!("Pushed {} to {tag_remote}", push_tags.join(", "));
+    }
+
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::scratch::ScratchRepo;
+    use clap::Parser;
+
+    #[test]
+    fn the_last_color_flag_wins_over_no_color() {
+        let args = |list: &[&str]| -> Vec<String> { list.iter().map(|a| a.to_string()).collect() };
+        assert_eq!(
+            color_choice(&args(&["git-cmt-rs"]), None),
+            ColorChoice::Auto
+        );
+        assert_eq!(
+            color_choice(&args(&["git-cmt-rs"]), Some("")),
+            ColorChoice::Auto
+        );
+        assert_eq!(
+            color_choice(&args(&["git-cmt-rs"]), Some("1")),
+            ColorChoice::Never
+        );
+        assert_eq!(
+            color_choice(&args(&["git-cmt-rs", "--color"]), Some("1")),
+            ColorChoice::Always
+        );
+        assert_eq!(
+            color_choice(&args(&["git-cmt-rs", "--color", "-v", "--no-color"]), None),
+            ColorChoice::Never
+        );
+        assert_eq!(
+            color_choice(&args(&["git-cmt-rs", "--", "--color"]), None),
+            ColorChoice::Auto
+        );
+        let cli = Cli::parse_from(["git-cmt-rs", "--no-color", "--color"]);
+        assert!(cli.color && !cli.no_color);
+    }
 
-    fn empty_schema() -> serde_json::Value {
-        serde_json::json!({})
+    // ---------- DiffSource ----------
+
+    #[test]
+    fn diff_source_amend_stages_and_external_sources_win() {
+        let cli = Cli::parse_from(["git-cmt-rs", "--amend"]);
+        assert_eq!(DiffSource::from_cli(&cli), DiffSource::Amend);
+        assert!(DiffSource::Amend.stages());
+        let cli = Cli::parse_from(["git-cmt-rs", "--amend", "--diff-stdin"]);
+        assert_eq!(DiffSource::from_cli(&cli), DiffSource::Stdin);
+        assert!(!DiffSource::Stdin.stages());
     }
 
-    // ---------- build_response_format ----------
+    // ---------- PushMode ----------
 
     #[test]
-    fn response_format_default_is_json_object() {
-        let f = build_response_format(None, empty_schema()).unwrap();
-        assert!(matches!(f, Some(ResponseFormat::JsonObject)));
+    fn push_mode_uses_lease_after_amend_and_force_only_when_asked() {
+        let mode = |args: &[&str]| {
+            let mut argv = vec!["git-cmt-rs"];
+            argv.extend_from_slice(args);
+            PushMode::from_cli(&Cli::parse_from(argv))
+        };
+        assert_eq!(mode(&[]), PushMode::Normal);
+        assert_eq!(mode(&[]).flag(), None);
+        assert_eq!(mode(&["--amend"]).flag(), Some("--force-with-lease"));
+        assert_eq!(mode(&["--amend", "--force"]).flag(), Some("--force"));
+        assert_eq!(mode(&["--force"]).flag(), Some("--force"));
+        assert!(mode(&[]).warning().is_none());
+        assert!(mode(&["--amend"]).warning().is_some());
+        assert!(mode(&["--force"]).warning().is_some());
     }
 
     #[test]
-    fn response_format_explicit_json_object() {
-        let f = build_response_format(Some("json_object"), empty_schema()).unwrap();
-        assert!(matches!(f, Some(ResponseFormat::JsonObject)));
+    fn detached_pushes_name_the_full_ref() {
+        assert_eq!(
+            detached_push_refspec("fix/flaky"),
+            "HEAD:refs/heads/fix/flaky"
+        );
+        assert_eq!(
+            detached_push_refspec("refs/heads/main"),
+            "HEAD:refs/heads/main"
+        );
     }
 
     #[test]
-    fn response_format_json_schema_has_strict_and_name() {
-        let f = build_response_format(Some("json_schema"), empty_schema()).unwrap();
-        match f {
-            Some(ResponseFormat::JsonSchema { json_schema }) => {
-                assert_eq!(json_schema.name, "commit_message");
-                assert!(json_schema.strict);
-            }
-            other => panic!("expected JsonSchema variant, got {other:?}"),
+    fn the_commit_summary_condenses_the_shortstat() {
+        assert_eq!(
+            commit_summary(
+                "created",
+                "3f2a1bc",
+                "feat(cli): add --dry-run",
+                "4 files changed, 120 insertions(+), 8 deletions(-)"
+            ),
+            "created 3f2a1bc feat(cli): add --dry-run (4 files changed, +120 \u{2212}8)"
+        );
+        assert_eq!(
+            commit_summary(
+                "amended",
+                "3f2a1bc",
+                "docs: typo",
+                "1 file changed, 1 deletion(-)"
+            ),
+            "amended 3f2a1bc docs: typo (1 file changed, +0 \u{2212}1)"
+        );
+        assert_eq!(
+            commit_summary("created", "3f2a1bc", "merge: branch 'x' into main", ""),
+            "created 3f2a1bc merge: branch 'x' into main"
+        );
+    }
+
+    #[test]
+    fn a_missing_upstream_is_recognised_from_push_stderr() {
+        let stderr = "fatal: The current branch feature/x has no upstream branch.\n\
+                      To push the current branch and set the remote as upstream, use\n";
+        assert!(needs_upstream(stderr));
+        assert!(!needs_upstream(
+            "! [rejected]        main -> main (fetch first)\n"
+        ));
+    }
+
+    #[test]
+    fn new_branches_go_to_origin_or_the_only_remote() {
+        let remotes = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(default_remote(&remotes(&["upstream"])), Some("upstream"));
+        assert_eq!(
+            default_remote(&remotes(&["fork", "origin"])),
+            Some("origin")
+        );
+        assert_eq!(default_remote(&remotes(&["fork", "upstream"])), None);
+        assert_eq!(default_remote(&[]), None);
+    }
+
+    #[test]
+    fn interactive_answers_keep_replace_or_clear() {
+        assert_eq!(field_answer("\n", " feat ", false), "feat");
+        assert_eq!(field_answer("fix\n", "feat", false), "fix");
+        assert_eq!(field_answer("-\n", "auth", true), "");
+        assert_eq!(field_answer("-\n", "add login", false), "-");
+        let cli = Cli::parse_from(["git-cmt-rs", "--interactive"]);
+        assert!(cli.interactive && !cli.no_edit);
+    }
+
+    #[test]
+    fn message_confirmation_answers_commit_edit_or_cancel() {
+        assert_eq!(message_answer("y\n"), Some(MessageAnswer::Commit));
+        assert_eq!(message_answer(" YES "), Some(MessageAnswer::Commit));
+        assert_eq!(message_answer("e\n"), Some(MessageAnswer::Edit));
+        assert_eq!(message_answer("n"), Some(MessageAnswer::Cancel));
+        assert_eq!(message_answer(""), None);
+        assert_eq!(message_answer("maybe"), None);
+        assert!(Cli::try_parse_from(["git-cmt-rs", "--confirm-message", "--dry-run"]).is_err());
+    }
+
+    #[test]
+    fn the_push_question_names_the_tags() {
+        assert_eq!(push_question(None, &[]), "Push commit to remote?");
+        assert_eq!(
+            push_question(None, &["v1.2.0".into()]),
+            "Push commit with tag v1.2.0 to remote?"
+        );
+        assert_eq!(
+            push_question(Some("origin/feature"), &["v1".into(), "v1.2".into()]),
+            "Push with tags v1, v1.2 and set upstream to origin/feature?"
+        );
+    }
+
+    #[test]
+    fn behind_the_upstream_answers_rebase_push_or_skip() {
+        assert_eq!(behind_answer("r\n"), Some(BehindAnswer::Rebase));
+        assert_eq!(behind_answer(" Push "), Some(BehindAnswer::Push));
+        assert_eq!(behind_answer("s"), Some(BehindAnswer::Skip));
+        assert_eq!(behind_answer("n"), Some(BehindAnswer::Skip));
+        assert_eq!(behind_answer(""), None);
+        assert_eq!(behind_answer("y"), None);
+    }
+
+    #[test]
+    fn generated_tags_replace_tag_version_and_push_tags() {
+        let cli = Cli::parse_from(["git-cmt-rs", "--tag", "v1.3.0", "--tag-from-history"]);
+        assert_eq!(cli.tag.as_deref(), Some("v1.3.0"));
+        for argv in [
+            &["git-cmt-rs", "--tag", "v1", "--tag-version", "v1"][..],
+            &["git-cmt-rs", "--tag", "v1", "--push-tags"],
+            &["git-cmt-rs", "--tag", "v1", "--dry-run"],
+            &["git-cmt-rs", "--tag-from-history"],
+        ] {
+            assert!(Cli::try_parse_from(argv).is_err(), "{argv:?}");
         }
     }
 
     #[test]
-    fn response_format_none_returns_no_payload() {
-        let f = build_response_format(Some("none"), empty_schema()).unwrap();
-        assert!(f.is_none());
+    fn named_remotes_must_be_configured() {
+        let remotes = ["origin".to_string(), "fork".to_string()];
+        assert!(remote_exists("fork", &remotes));
+        assert!(remote_exists(".", &remotes));
+        assert!(!remote_exists("upstream", &remotes));
+        assert!(!remote_exists("origin", &[]));
+    }
+
+    #[test]
+    fn repo_must_be_an_existing_repository() {
+        let cli = Cli::parse_from(["git-cmt-rs", "-C", "../other", "log"]);
+        assert_eq!(cli.repo, Some(PathBuf::from("../other")));
+        let missing = std::env::temp_dir().join("git-cmt-rs-no-such-dir");
+        let err = repo_dir(&missing).unwrap_err();
+        assert!(err.to_string().ends_with("is not a directory"));
+    }
+
+    #[test]
+    fn diff_source_defaults_to_git() {
+        let cli = Cli::parse_from(["git-cmt-rs"]);
+        assert_eq!(DiffSource::from_cli(&cli), DiffSource::Git);
+    }
+
+    #[test]
+    fn diff_source_from_flags() {
+        let cli = Cli::parse_from(["git-cmt-rs", "--diff-stdin"]);
+        assert_eq!(DiffSource::from_cli(&cli), DiffSource::Stdin);
+        let cli = Cli::parse_from(["git-cmt-rs", "--diff-file", "fix.patch"]);
+        assert_eq!(
+            DiffSource::from_cli(&cli),
+            DiffSource::File(PathBuf::from("fix.patch"))
+        );
+    }
+
+    #[test]
+    fn stdin_prints_unless_commit_is_given() {
+        let cli = Cli::parse_from(["git-cmt-rs", "--stdin", "--print"]);
+        assert_eq!(DiffSource::from_cli(&cli), DiffSource::Stdin);
+        assert!(cli.message_only && !cli.commit);
+        let cli = Cli::parse_from(["git-cmt-rs", "--stdin", "--commit"]);
+        assert!(cli.diff_stdin && cli.commit);
+        assert!(Cli::try_parse_from(["git-cmt-rs", "--commit"]).is_err());
+        assert!(Cli::try_parse_from(["git-cmt-rs", "--stdin", "--commit", "--print"]).is_err());
+        assert!(print_only(&Cli::parse_from(["git-cmt-rs", "--stdin"])));
+        assert!(!print_only(&Cli::parse_from([
+            "git-cmt-rs",
+            "--stdin",
+            "--commit"
+        ])));
+        assert!(!print_only(&Cli::parse_from(["git-cmt-rs"])));
+    }
+
+    #[tokio::test]
+    async fn a_diff_file_outside_a_repository_is_only_printed() {
+        use llm::mock::{http_response, mock_server};
+
+        let dir = std::env::temp_dir().join(format!("git-cmt-rs-no-repo-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let patch = dir.join("change.patch");
+        fs::write(
+            &patch,
+            "diff --git a/a.rs b/a.rs\n--- a/a.rs\n+++ b/a.rs\n@@ -1 +1 @@\n-fn a() {}\n+fn b() {}\n",
+        )
+        .unwrap();
+        let body = serde_json::json!({
+            "message": { "role": "assistant", "content": r#"{"type":"refactor","scope":"","message":"rename a to b"}"# },
+            "done": true
+        })
+        .to_string();
+        let (url, _) = mock_server(vec![http_response("200 OK", "", &body)]).await;
+
+        let cli = Cli::parse_from([
+            "git-cmt-rs",
+            "--diff-file",
+            patch.to_str().unwrap(),
+            "--provider",
+            "ollama",
+            "--no-cache",
+        ]);
+        assert!(print_only(&cli));
+        assert!(Cli::try_parse_from(["git-cmt-rs", "--diff-file", "x.patch", "--commit"]).is_ok());
+        let config = Config::resolve(&cli, &FileConfig::default(), |name| {
+            (name == "OLLAMA_HOST").then(|| url.clone())
+        })
+        .unwrap();
+        let git = Git::new(&dir);
+        let changes = get_staged_changes(
+            &git,
+            &DiffSource::from_cli(&cli),
+            &Budget::chars(10_000),
+            &[],
+            &Generated::default(),
+            &Excludes::default(),
+            &Redactor::default(),
+        )
+        .unwrap();
+        let (_, commit) = message_for(&config, changes).await.unwrap().unwrap();
+        let committed = git.is_repository();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(commit.message, "rename a to b");
+        assert!(!committed);
+    }
+
+    #[test]
+    fn diff_stdin_and_diff_file_conflict() {
+        assert!(
+            Cli::try_parse_from(["git-cmt-rs", "--diff-stdin", "--diff-file", "x.patch"]).is_err()
+        );
     }
 
     #[test]
-    fn response_format_is_case_insensitive_and_trimmed() {
-        let f = build_response_format(Some("  JSON_Object  "), empty_schema()).unwrap();
-        assert!(matches!(f, Some(ResponseFormat::JsonObject)));
+    fn diff_file_is_read_and_truncated() {
+        let path = std::env::temp_dir().join(format!("git-cmt-rs-{}.patch", std::process::id()));
+        fs::write(&path, "y".repeat(200)).unwrap();
+        let diff = get_staged_changes(
+            &Git::cwd(),
+            &DiffSource::File(path.clone()),
+            &Budget::chars(100),
+            &[],
+            &Generated::default(),
+            &Excludes::default(),
+            &Redactor::default(),
+        );
+        fs::remove_file(&path).unwrap();
+        let changes = diff.unwrap();
+        assert_eq!(changes.stat, None);
+        assert!(
+            changes
+                .text()
+                .ends_with("... (truncated: 100 chars dropped)")
+        );
     }
 
     #[test]
-    fn response_format_unknown_value_lists_valid_choices() {
-        let err = build_response_format(Some("garbage"), empty_schema()).unwrap_err();
-        let msg = format!("{err}");
-        assert!(msg.contains("json_object"), "msg: {msg}");
-        assert!(msg.contains("json_schema"), "msg: {msg}");
-        assert!(msg.contains("none"), "msg: {msg}");
-        assert!(msg.contains("garbage"), "msg: {msg}");
+    fn names_only_needs_git_for_the_file_list() {
+        let cli = Cli::parse_from(["git-cmt-rs", "--names-only", "--hint", "why"]);
+        assert!(cli.names_only);
+        let err =
+            get_staged_names(&Git::cwd(), &DiffSource::Stdin, Privacy::NamesOnly).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "names-only privacy can't be used with a diff from stdin; it reads file names from git"
+        );
+        let err = get_staged_names(&Git::cwd(), &DiffSource::Stdin, Privacy::Brief).unwrap_err();
+        assert!(err.to_string().starts_with("brief privacy can't be used"));
+        assert!(Cli::try_parse_from(["git-cmt-rs", "--brief", "--names-only"]).is_err());
     }
 
-    // ---------- ResponseFormat wire format (regression guard for the enum tag) ----------
+    #[test]
+    fn the_cache_key_covers_the_sampling_settings_and_api() {
+        let settings = |args: &[&str]| {
+            let mut argv = vec!["git-cmt-rs", "--provider", "openai"];
+            argv.extend_from_slice(args);
+            let cli = Cli::parse_from(argv);
+            cache_settings(&Config::resolve(&cli, &FileConfig::default(), |_| None).unwrap())
+        };
+        let default = settings(&[]);
+        for args in [
+            ["--top-p", "0.9"],
+            ["--frequency-penalty", "0.5"],
+            ["--presence-penalty", "0.5"],
+            ["--api", "responses"],
+        ] {
+            assert_ne!(settings(&args), default, "{args:?}");
+        }
+    }
 
     #[test]
-    fn json_object_serializes_with_type_only() {
-        let v = serde_json::to_value(ResponseFormat::JsonObject).unwrap();
-        assert_eq!(v, serde_json::json!({ "type": "json_object" }));
+    fn tee_passes_everything_on_and_keeps_a_copy() {
+        let text =
+            "x".repeat(10_000) + "\nfatal: The current branch main has no upstream branch.\n";
+        let mut terminal = Vec::new();
+        let seen = tee(text.as_bytes(), &mut terminal).unwrap();
+        assert_eq!(terminal, text.as_bytes());
+        assert_eq!(seen, text.as_bytes());
+    }
+
+    // `watch` keeps polling after a failed round, so this must not exit.
+    #[tokio::test]
+    async fn a_refused_request_is_an_error_for_the_caller() {
+        let cli = Cli::parse_from([
+            "git-cmt-rs",
+            "--provider",
+            "ollama",
+            "--max-prompt-tokens",
+            "1",
+            "--no-cache",
+        ]);
+        let config = Config::resolve(&cli, &FileConfig::default(), |_| None).unwrap();
+        let changes = Changes::new(None, "diff --git a/a.rs b/a.rs\n+fn main() {}\n".into());
+        let err = generate_commit(&config, changes).await.unwrap_err();
+        assert!(
+            err.to_string().starts_with("Refusing to send:"),
+            "error: {err}"
+        );
     }
 
     #[test]
-    fn json_schema_serializes_with_nested_schema() {
-        let rf = ResponseFormat::JsonSchema {
-            json_schema: JsonSchema {
-                name: "commit_message".into(),
-                schema: serde_json::json!({ "type": "object" }),
-                strict: true,
-            },
+    fn auto_stat_switches_once_the_whole_diff_is_three_budgets() {
+        let config = |args: &[&str]| {
+            let mut argv = vec!["git-cmt-rs"];
+            argv.extend_from_slice(args);
+            Config::resolve(&Cli::parse_from(argv), &FileConfig::default(), |_| None).unwrap()
         };
+        let diff = format!(
+            "diff --git a/a.rs b/a.rs\n--- a/a.rs\n+++ b/a.rs\n@@ -1 +1 @@\n{}",
+            "+x\n".repeat(100)
+        );
+        let changes = Changes::new(None, String::new()).with_files(diff::file_changes(
+            &diff,
+            &[],
+            &Generated::default(),
+            &Excludes::default(),
+            &[],
+        ));
+        let size = diff.len();
         assert_eq!(
-            serde_json::to_value(rf).unwrap(),
-            serde_json::json!({
-                "type": "json_schema",
-                "json_schema": {
-                    "name": "commit_message",
-                    "schema": { "type": "object" },
-                    "strict": true,
-                }
-            })
+            auto_stat(&config(&[]), &Budget::chars(100), &changes),
+            Some((size, 300))
+        );
+        assert_eq!(
+            auto_stat(&config(&[]), &Budget::chars(size / 3 + 1), &changes),
+            None
+        );
+        let threshold = ["--stat-threshold", "1000"];
+        assert_eq!(
+            auto_stat(&config(&threshold), &Budget::chars(100), &changes),
+            None
+        );
+        for args in [
+            ["--no-auto-stat"],
+            ["--summarize-large-diffs"],
+            ["--names-only"],
+        ] {
+            assert_eq!(
+                auto_stat(&config(&args), &Budget::chars(100), &changes),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn pure_submodule_bumps_need_no_model() {
+        let diff = "diff --git a/vendor/lib b/vendor/lib\nindex 1111111..2222222 160000\n\
+                    --- a/vendor/lib\n+++ b/vendor/lib\n@@ -1 +1 @@\n\
+                    -Subproject commit 1111111111111111111111111111111111111111\n\
+                    +Subproject commit 2222222222222222222222222222222222222222\n";
+        let files = diff::file_changes(diff, &[], &Generated::default(), &Excludes::default(), &[]);
+        let mut bump = SubmoduleChange {
+            path: "vendor/lib".into(),
+            old: Some("1".repeat(40)),
+            new: Some("2".repeat(40)),
+            log: None,
+        };
+        let commit = bump_commit(&files, std::slice::from_ref(&bump)).unwrap();
+        assert_eq!(
+            build_commit_line(&commit, FormatPreset::Conventional),
+            "chore(deps): bump vendor/lib to 2222222"
+        );
+        assert!(bump_commit(&files, &[]).is_none());
+
+        // Another file, or a submodule being added, needs the model.
+        let other = diff::file_changes(
+            &format!(
+                "{diff}diff --git a/a.rs b/a.rs\n--- a/a.rs\n+++ b/a.rs\n@@ -1 +1 @@\n-x\n+y\n"
+            ),
+            &[],
+            &Generated::default(),
+            &Excludes::default(),
+            &[],
         );
+        assert!(bump_commit(&other, std::slice::from_ref(&bump)).is_none());
+        bump.old = None;
+        assert!(bump_commit(&files, &[bump]).is_none());
+    }
+
+    #[test]
+    fn empty_diff_file_is_an_error() {
+        let path =
+            std::env::temp_dir().join(format!("git-cmt-rs-empty-{}.patch", std::process::id()));
+        fs::write(&path, "\n  \n").unwrap();
+        let err = get_staged_changes(
+            &Git::cwd(),
+            &DiffSource::File(path.clone()),
+            &Budget::chars(100),
+            &[],
+            &Generated::default(),
+            &Excludes::default(),
+            &Redactor::default(),
+        )
+        .unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(format!("{err}").contains("no diff found"), "err: {err}");
     }
 
     // ---------- build_commit_line ----------
@@ -590,8 +3620,12 @@ mod tests {
             r#type: "feat".into(),
             scope: "auth".into(),
             message: "add login".into(),
+            ..Default::default()
         };
-        assert_eq!(build_commit_line(&c), "feat(auth): add login");
+        assert_eq!(
+            build_commit_line(&c, FormatPreset::Conventional),
+            "feat(auth): add login"
+        );
     }
 
     #[test]
@@ -600,8 +3634,12 @@ mod tests {
             r#type: "fix".into(),
             scope: "".into(),
             message: "off-by-one".into(),
+            ..Default::default()
         };
-        assert_eq!(build_commit_line(&c), "fix: off-by-one");
+        assert_eq!(
+            build_commit_line(&c, FormatPreset::Conventional),
+            "fix: off-by-one"
+        );
     }
 
     #[test]
@@ -610,8 +3648,12 @@ mod tests {
             r#type: "chore".into(),
             scope: "   ".into(),
             message: "tidy".into(),
+            ..Default::default()
         };
-        assert_eq!(build_commit_line(&c), "chore: tidy");
+        assert_eq!(
+            build_commit_line(&c, FormatPreset::Conventional),
+            "chore: tidy"
+        );
     }
 
     #[test]
@@ -620,8 +3662,376 @@ mod tests {
             r#type: "  docs  ".into(),
             scope: "  readme  ".into(),
             message: "  fix typo  ".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            build_commit_line(&c, FormatPreset::Conventional),
+            "docs(readme): fix typo"
+        );
+    }
+
+    #[test]
+    fn message_file_holds_the_message_byte_for_byte_until_dropped() {
+        let commit = Commit {
+            r#type: "feat".into(),
+            scope: "i18n".into(),
+            message: "add \"naïve\" café → 日本語 🎉".into(),
+            body: vec!["Keep 'quotes' and $vars as typed.".into()],
+            ..Default::default()
+        };
+        let message = build_commit_message(&commit, FormatPreset::Conventional, 72, None);
+        for message in [
+            message.clone(),
+            format!("{message}\n"),
+            format!("{message}\n\n"),
+        ] {
+            let file = MessageFile::create(&message).unwrap();
+            let path = file.path().to_path_buf();
+            assert_eq!(fs::read(&path).unwrap(), message.as_bytes());
+            drop(file);
+            assert!(!path.exists());
+        }
+        assert!(!message.ends_with('\n'));
+        let a = MessageFile::create("a").unwrap();
+        let b = MessageFile::create("b").unwrap();
+        assert_ne!(a.path(), b.path());
+    }
+
+    #[test]
+    fn watch_interval_must_be_positive() {
+        let cli = Cli::parse_from(["git-cmt-rs", "watch"]);
+        assert!(matches!(
+            cli.command,
+            Some(CliCommand::Watch {
+                interval: 30,
+                max_auto_commits: 10
+            })
+        ));
+        let cli = Cli::parse_from([
+            "git-cmt-rs",
+            "watch",
+            "--interval",
+            "5",
+            "--max-auto-commits",
+            "2",
+        ]);
+        assert!(matches!(
+            cli.command,
+            Some(CliCommand::Watch {
+                interval: 5,
+                max_auto_commits: 2
+            })
+        ));
+        assert!(Cli::try_parse_from(["git-cmt-rs", "watch", "--interval", "0"]).is_err());
+    }
+
+    #[test]
+    fn squash_force_only_goes_with_apply() {
+        let cli = Cli::parse_from(["git-cmt-rs", "squash"]);
+        assert!(matches!(
+            cli.command,
+            Some(CliCommand::Squash {
+                base: None,
+                apply: false,
+                force: false
+            })
+        ));
+        let cli = Cli::parse_from(["git-cmt-rs", "squash", "develop", "--apply", "--force"]);
+        assert!(matches!(
+            cli.command,
+            Some(CliCommand::Squash { base: Some(ref base), apply: true, force: true }) if base == "develop"
+        ));
+        assert!(Cli::try_parse_from(["git-cmt-rs", "squash", "--force"]).is_err());
+    }
+
+    // ---------- wrap_body ----------
+
+    #[test]
+    fn body_lines_wrap_at_the_width_without_splitting_words() {
+        let body = "Retries now back off exponentially with jitter so that many clients do not hammer the API at once.\n\
+                    \n\
+                    - Honour Retry-After and the x-ratelimit-reset headers when the server sends them back.\n\
+                    Short line stays.\n\
+                    See https://example.com/a/very/long/url/that/is/longer/than/the/wrap/width/on/its/own for details.";
+        assert_eq!(
+            wrap_body(body, 40),
+            "Retries now back off exponentially with\n\
+             jitter so that many clients do not\n\
+             hammer the API at once.\n\
+             \n\
+             - Honour Retry-After and the\n\
+             \x20 x-ratelimit-reset headers when the\n\
+             \x20 server sends them back.\n\
+             Short line stays.\n\
+             See\n\
+             https://example.com/a/very/long/url/that/is/longer/than/the/wrap/width/on/its/own\n\
+             for details."
+        );
+        assert!(
+            wrap_body(body, 72)
+                .lines()
+                .all(|l| l.chars().count() <= 72 || !l.contains(' '))
+        );
+        assert_eq!(wrap_body(body, 0), body);
+        assert_eq!(wrap_body("  \n", 72), "");
+        assert_eq!(
+            wrap_body("Use the rate-limit-reset header", 20),
+            "Use the\nrate-limit-reset\nheader"
+        );
+    }
+
+    #[test]
+    fn body_comes_from_model_output_when_present() {
+        let c =
+            parse_commit(r#"{"type":"fix","scope":"","message":"retry","body":"Longer text."}"#)
+                .unwrap();
+        assert_eq!(c.body, ["Longer text."]);
+        let c = parse_commit(r#"{"type":"fix","scope":"","message":"retry"}"#).unwrap();
+        assert!(c.body.is_empty());
+        assert!(!serde_json::to_string(&c).unwrap().contains("body"));
+
+        let c = parse_commit(
+            r#"{"type":"fix","scope":"","message":"retry","body":["- Back off on 429", "", "Cap at 60s"]}"#,
+        )
+        .unwrap();
+        assert_eq!(c.body, ["Back off on 429", "Cap at 60s"]);
+        let c = parse_commit(r#"{"_type":"fix","message":"retry","body":"- one\n- two"}"#).unwrap();
+        assert_eq!(c.body, ["one", "two"]);
+        let c = parse_commit(r#"{"type":"fix","scope":"","message":"retry","body":null}"#).unwrap();
+        assert!(c.body.is_empty());
+    }
+
+    // ---------- build_commit_message ----------
+
+    #[test]
+    fn breaking_changes_end_with_the_footer() {
+        let mut c = Commit {
+            r#type: "feat".into(),
+            message: "drop --legacy".into(),
+            breaking: true,
+            breaking_description: "The --legacy flag is gone; use --format instead.".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            build_commit_message(&c, FormatPreset::Conventional, 72, None),
+            "feat!: drop --legacy\n\nBREAKING CHANGE: The --legacy flag is gone; use --format instead."
+        );
+        c.scope = "cli".into();
+        c.body = vec!["Remove the flag and its code path".into()];
+        assert_eq!(
+            build_commit_message(&c, FormatPreset::Conventional, 72, None),
+            "feat(cli)!: drop --legacy\n\n- Remove the flag and its code path\n\n\
+             BREAKING CHANGE: The --legacy flag is gone; use --format instead."
+        );
+        // `--breaking` on a change the model didn't flag: the marker, no footer.
+        c.breaking_description.clear();
+        c.body.clear();
+        assert_eq!(
+            build_commit_message(&c, FormatPreset::Conventional, 72, None),
+            "feat(cli)!: drop --legacy"
+        );
+        c.breaking = false;
+        c.breaking_description = "ignored".into();
+        assert_eq!(
+            build_commit_message(&c, FormatPreset::Conventional, 72, None),
+            "feat(cli): drop --legacy"
+        );
+    }
+
+    #[test]
+    fn a_ticket_ends_the_subject_or_joins_the_footers() {
+        let mut c = Commit {
+            r#type: "fix".into(),
+            scope: "auth".into(),
+            message: "handle expired tokens".into(),
+            ..Default::default()
+        };
+        let subject = Some(("PROJ-1234", TicketStyle::Subject));
+        let footer = Some(("PROJ-1234", TicketStyle::Footer));
+        assert_eq!(
+            build_commit_message(&c, FormatPreset::Conventional, 72, subject),
+            "fix(auth): handle expired tokens (PROJ-1234)"
+        );
+        assert_eq!(
+            build_commit_message(&c, FormatPreset::Conventional, 72, footer),
+            "fix(auth): handle expired tokens\n\nRefs: PROJ-1234"
+        );
+        c.breaking = true;
+        c.breaking_description = "Tokens are no longer refreshed.".into();
+        assert_eq!(
+            build_commit_message(&c, FormatPreset::Conventional, 72, footer),
+            "fix(auth)!: handle expired tokens\n\n\
+             BREAKING CHANGE: Tokens are no longer refreshed.\nRefs: PROJ-1234"
+        );
+        // A subject that already names it isn't given it twice.
+        c.breaking = false;
+        c.message = "handle PROJ-1234 expired tokens".into();
+        assert_eq!(
+            build_commit_message(&c, FormatPreset::Conventional, 72, subject),
+            "fix(auth): handle PROJ-1234 expired tokens"
+        );
+        // One that would make the subject too long goes in the footers.
+        c.message = "handle expired tokens when the refresh endpoint is down".into();
+        assert_eq!(
+            build_commit_message(&c, FormatPreset::Conventional, 72, subject),
+            "fix(auth): handle expired tokens when the refresh endpoint is down\n\n\
+             Refs: PROJ-1234"
+        );
+    }
+
+    #[test]
+    fn the_ticket_is_the_first_match_in_the_branch_unless_given() {
+        let config = |args: &[&str]| {
+            let mut argv = vec!["git-cmt-rs"];
+            argv.extend_from_slice(args);
+            let file = FileConfig {
+                ticket_pattern: Some(r"[A-Z]+-\d+".into()),
+                ..Default::default()
+            };
+            Config::resolve(&Cli::parse_from(argv), &file, |_| None).unwrap()
+        };
+        let c = config(&[]);
+        assert_eq!(
+            ticket(&c, Some("PROJ-1234-fix-login-OPS-7")).as_deref(),
+            Some("PROJ-1234")
+        );
+        assert_eq!(
+            ticket(&c, Some("fix/OPS-7-and-OPS-8")).as_deref(),
+            Some("OPS-7")
+        );
+        assert_eq!(ticket(&c, Some("main")), None);
+        // Detached HEAD: no branch name, no ticket, no error.
+        assert_eq!(ticket(&c, None), None);
+        let c = config(&["--ticket", "SEC-9"]);
+        assert_eq!(ticket(&c, Some("PROJ-1234-fix")).as_deref(), Some("SEC-9"));
+        assert_eq!(ticket(&c, None).as_deref(), Some("SEC-9"));
+    }
+
+    #[test]
+    fn a_detached_head_has_no_branch_ticket() {
+        let repo = ScratchRepo::new("ticket");
+        repo.git(&["checkout", "-q", "-b", "PROJ-1234-fix-login"]);
+        repo.git(&["commit", "-q", "--allow-empty", "-m", "init"]);
+        repo.git(&["checkout", "-q", "--detach"]);
+        let file = FileConfig {
+            ticket_pattern: Some(r"[A-Z]+-\d+".into()),
+            ..Default::default()
+        };
+        let config = Config::resolve(&Cli::parse_from(["git-cmt-rs"]), &file, |_| None).unwrap();
+        assert_eq!(branch_ticket(&config, &repo.git), None);
+    }
+
+    #[test]
+    fn the_editor_buffer_comments_out_the_context() {
+        let context = [
+            (
+                "commit.template (.gitmessage)".to_string(),
+                "[TICKET] Subject\n\nWhy:\n".to_string(),
+            ),
+            ("MERGE_MSG".to_string(), " \n\n".to_string()),
+            (
+                "SQUASH_MSG".to_string(),
+                "Squashed commit of the following:\n\n    fix: typo\n".to_string(),
+            ),
+        ];
+        assert_eq!(
+            editor_buffer("fix: handle empty input", "#", &context),
+            "fix: handle empty input\n\n\
+             # commit.template (.gitmessage):\n# [TICKET] Subject\n#\n# Why:\n\n\
+             # SQUASH_MSG:\n# Squashed commit of the following:\n#\n#     fix: typo"
+        );
+        assert_eq!(editor_buffer("fix: x", ";", &[]), "fix: x");
+    }
+
+    #[test]
+    fn only_an_edited_message_file_shows_the_template_and_squash_message() {
+        let repo = ScratchRepo::new("buffer");
+        repo.write(".gitmessage", b"Why:\n");
+        repo.git(&["config", "commit.template", ".gitmessage"]);
+        let git = &repo.git;
+        fs::write(git.git_path("SQUASH_MSG").unwrap(), "fix: typo\n").unwrap();
+
+        let edited = MessageFile::for_commit(git, "feat: add paging", true).unwrap();
+        let template = repo.dir.join(".gitmessage");
+        assert_eq!(
+            fs::read_to_string(edited.path()).unwrap(),
+            format!(
+                "feat: add paging\n\n# commit.template ({}):\n# Why:\n\n# SQUASH_MSG:\n# fix: typo",
+                template.display()
+            )
+        );
+        let plain = MessageFile::for_commit(git, "feat: add paging", false).unwrap();
+        assert_eq!(
+            fs::read_to_string(plain.path()).unwrap(),
+            "feat: add paging"
+        );
+    }
+
+    #[test]
+    fn breaking_fields_are_parsed_leniently() {
+        let c = parse_commit(
+            r#"{"type":"feat","scope":"","message":"x","breaking":true,"breaking_description":"Gone."}"#,
+        )
+        .unwrap();
+        assert!(c.breaking);
+        assert_eq!(c.breaking_description, "Gone.");
+        // A quoted boolean fails the strict path and is coerced.
+        let c =
+            parse_commit(r#"{"type":"feat","scope":"","message":"x","breaking":"true"}"#).unwrap();
+        assert!(c.breaking);
+        let c = parse_commit(r#"{"type":"feat","scope":"","message":"x"}"#).unwrap();
+        assert!(!c.breaking);
+        assert!(!serde_json::to_string(&c).unwrap().contains("breaking"));
+    }
+
+    #[test]
+    fn commit_message_without_a_body_is_the_subject() {
+        let c = Commit {
+            r#type: "fix".into(),
+            message: "retry".into(),
+            body: vec!["  ".into()],
+            ..Default::default()
         };
-        assert_eq!(build_commit_line(&c), "docs(readme): fix typo");
+        assert_eq!(
+            build_commit_message(&c, FormatPreset::Conventional, 72, None),
+            "fix: retry"
+        );
+    }
+
+    #[test]
+    fn commit_message_bullets_follow_a_blank_line_and_wrap() {
+        let mut c = Commit {
+            r#type: "fix".into(),
+            scope: "api".into(),
+            message: "retry on 429".into(),
+            body: vec!["Honour Retry-After".into()],
+            ..Default::default()
+        };
+        assert_eq!(
+            build_commit_message(&c, FormatPreset::Conventional, 72, None),
+            "fix(api): retry on 429\n\n- Honour Retry-After"
+        );
+        c.body = vec![
+            "Back off exponentially with jitter between attempts".into(),
+            "Stop once the retry budget is spent".into(),
+        ];
+        assert_eq!(
+            build_commit_message(&c, FormatPreset::Github, 30, None),
+            "[fix] retry on 429\n\n\
+             - Back off exponentially with\n  jitter between attempts\n\
+             - Stop once the retry budget\n  is spent"
+        );
+    }
+
+    // ---------- config validate ----------
+
+    #[test]
+    fn model_listed_accepts_an_implied_latest_tag() {
+        let models = ["gpt-4o".to_string(), "qwen2.5-coder:latest".to_string()];
+        assert!(model_listed("gpt-4o", &models));
+        assert!(model_listed("qwen2.5-coder", &models));
+        assert!(!model_listed("qwen2.5-coder:7b", &models));
+        assert!(!model_listed("gpt-4", &models));
     }
 
     // ---------- Commit deserialization (model output parsing) ----------
@@ -680,7 +4090,10 @@ mod tests {
 
     #[test]
     fn parse_commit_errors_on_no_json() {
-        assert!(parse_commit("I could not generate a commit message.").is_err());
+        assert!(matches!(
+            parse_commit("I could not generate a commit message."),
+            Err(GitCmtError::ParseError(_))
+        ));
     }
 
     #[test]
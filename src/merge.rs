@@ -0,0 +1,159 @@
+// ---------- Merges in progress ----------
+// With MERGE_HEAD present the commit concludes a merge. Git's MERGE_MSG says
+// what is being merged and which files conflicted, and each side's log since
+// the merge base says what the two branches brought. Git access lives in
+// main; this module only does the text.
+
+// Commits listed per side; a long-lived branch would otherwise crowd out
+// the diff.
+pub const MAX_LOG_LINES: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Merge {
+    // What MERGE_MSG calls the other side: `branch`, `remote-tracking
+    // branch`, `tag` or `commit`.
+    pub kind: String,
+    pub theirs: String,
+    pub ours: String,
+    pub conflicts: Vec<String>,
+    // `git log --oneline` of each side since the merge base, newest first.
+    pub theirs_log: Vec<String>,
+    pub ours_log: Vec<String>,
+}
+
+impl Merge {
+    // `merge_msg` is `.git/MERGE_MSG` (empty if missing); `head` the
+    // MERGE_HEAD commit, named when the message doesn't say.
+    pub fn new(merge_msg: &str, head: &str, ours: &str) -> Self {
+        let (kind, theirs) = parse_title(merge_msg.lines().next().unwrap_or_default())
+            .unwrap_or_else(|| ("commit".into(), head.chars().take(7).collect()));
+        Merge {
+            kind,
+            theirs,
+            ours: ours.to_string(),
+            conflicts: parse_conflicts(merge_msg),
+            theirs_log: Vec::new(),
+            ours_log: Vec::new(),
+        }
+    }
+
+    pub fn with_logs(self, theirs_log: Vec<String>, ours_log: Vec<String>) -> Self {
+        Merge {
+            theirs_log,
+            ours_log,
+            ..self
+        }
+    }
+
+    // The subject's description, after `merge: `.
+    pub fn subject(&self) -> String {
+        format!("{} '{}' into {}", self.kind, self.theirs, self.ours)
+    }
+
+    // Added to the prompt: the diff alone reads like one big feature.
+    pub fn prompt(&self) -> String {
+        let mut text = format!(
+            "This change concludes a merge of {} '{}' into {}; the diff is what the merge \
+             brings into {2}.\n",
+            self.kind, self.theirs, self.ours
+        );
+        for (title, log) in [
+            (format!("Commits from {}:", self.theirs), &self.theirs_log),
+            (
+                format!("Commits on {} since the merge base:", self.ours),
+                &self.ours_log,
+            ),
+        ] {
+            if !log.is_empty() {
+                text.push_str(&format!("{title}\n"));
+                log.iter()
+                    .for_each(|line| text.push_str(&format!("  {line}\n")));
+            }
+        }
+        if self.conflicts.is_empty() {
+            text.push_str(
+                "There were no conflicts. The subject is fixed; write a body of one or \
+                 two bullets on what the merge brings.\n",
+            );
+        } else {
+            text.push_str("Files that conflicted, resolved in this diff:\n");
+            self.conflicts
+                .iter()
+                .for_each(|path| text.push_str(&format!("  {path}\n")));
+            text.push_str(
+                "The subject is fixed; write a body of one bullet per conflicted file \
+                 saying how the conflict was resolved.\n",
+            );
+        }
+        text
+    }
+}
+
+// `Merge branch 'feature-x' into main` -> ("branch", "feature-x"). Also
+// `remote-tracking branch`, `tag` and `commit`, and `of <url>` after a pull.
+fn parse_title(line: &str) -> Option<(String, String)> {
+    let rest = line.strip_prefix("Merge ")?;
+    let (kind, rest) = rest.split_once(" '")?;
+    let (name, _) = rest.split_once('\'')?;
+    Some((kind.to_string(), name.to_string()))
+}
+
+// The `Conflicts:` list git adds to MERGE_MSG, commented out (`#\tpath`) by
+// current git and plain (`\tpath`) by old versions.
+fn parse_conflicts(merge_msg: &str) -> Vec<String> {
+    merge_msg
+        .lines()
+        .map(|line| line.trim_start_matches('#'))
+        .skip_while(|line| line.trim() != "Conflicts:")
+        .skip(1)
+        .take_while(|line| line.starts_with('\t'))
+        .map(|line| line.trim().to_string())
+        .filter(|path| !path.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MERGE_MSG: &str = "Merge branch 'feature-x' into develop
+
+# Conflicts:
+#\tsrc/main.rs
+#\tREADME.md
+#
+# It looks like you may be committing a merge.
+";
+
+    #[test]
+    fn merge_msg_names_the_branch_and_the_conflicts() {
+        let merge = Merge::new(MERGE_MSG, "0123456789abcdef", "develop");
+        assert_eq!(merge.subject(), "branch 'feature-x' into develop");
+        assert_eq!(merge.conflicts, ["src/main.rs", "README.md"]);
+
+        let old = Merge::new(
+            "Merge remote-tracking branch 'origin/main'\n\nConflicts:\n\tCargo.lock\n",
+            "0123456789abcdef",
+            "main",
+        );
+        assert_eq!(
+            old.subject(),
+            "remote-tracking branch 'origin/main' into main"
+        );
+        assert_eq!(old.conflicts, ["Cargo.lock"]);
+
+        let bare = Merge::new("", "0123456789abcdef", "main");
+        assert_eq!(bare.subject(), "commit '0123456' into main");
+        assert!(bare.conflicts.is_empty());
+    }
+
+    #[test]
+    fn the_prompt_lists_both_logs_and_the_conflicts() {
+        let merge = Merge::new(MERGE_MSG, "0123456", "develop")
+            .with_logs(vec!["abc1234 feat: add x".into()], Vec::new());
+        let prompt = merge.prompt();
+        assert!(prompt.contains("Commits from feature-x:\n  abc1234 feat: add x\n"));
+        assert!(!prompt.contains("Commits on develop"));
+        assert!(prompt.contains("resolved in this diff:\n  src/main.rs\n  README.md\n"));
+    }
+}
@@ -0,0 +1,54 @@
+// ---------- Scratch repositories for tests ----------
+// A real repository in the temp dir for tests that need git itself: created
+// on branch `main` with a test identity, removed again on drop. Names only
+// need to be unique among the tests, which share one process id.
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::git::Git;
+
+pub struct ScratchRepo {
+    pub dir: PathBuf,
+    pub git: Git,
+}
+
+impl ScratchRepo {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("git-cmt-rs-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let repo = ScratchRepo {
+            git: Git::new(&dir),
+            dir,
+        };
+        repo.git(&["init", "-q", "-b", "main"]);
+        repo.git(&["config", "user.name", "test"]);
+        repo.git(&["config", "user.email", "test@example.com"]);
+        repo
+    }
+
+    // Runs git in the repository, unlocalized, and returns its stdout.
+    pub fn git(&self, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .current_dir(&self.dir)
+            .env("LC_ALL", "C")
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {args:?}");
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    pub fn write(&self, path: &str, bytes: &[u8]) {
+        let path = self.dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, bytes).unwrap();
+    }
+}
+
+impl Drop for ScratchRepo {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
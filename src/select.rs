@@ -0,0 +1,147 @@
+use anyhow::{Result, anyhow};
+use dialoguer::MultiSelect;
+use std::io::{self, IsTerminal};
+
+use crate::exclude::Excludes;
+use crate::git::LineCounts;
+use crate::status::Entry;
+
+// ---------- --select ----------
+// Pick which changed files to stage instead of `git add :/` taking them
+// all. Every change starts checked except files kept out of the prompt by
+// `.gitcmtignore` / `prompt_exclude`, which are usually not what the commit
+// is about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub path: String,
+    // What the list shows: status, path and line counts.
+    pub label: String,
+    pub checked: bool,
+    // Already in the index, so leaving it unchecked means unstaging it.
+    pub staged: bool,
+}
+
+// One candidate per stageable change, in `git status` order. Unmerged
+// files need resolving first and ignored ones aren't changes at all;
+// `numstat` has the line counts for tracked files (`None` for binaries).
+pub fn candidates(
+    entries: &[Entry],
+    numstat: &[(String, LineCounts)],
+    excludes: &Excludes,
+) -> Vec<Candidate> {
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let (status, path, staged) = match entry {
+                Entry::Changed {
+                    index,
+                    worktree,
+                    path,
+                    ..
+                } => (status_name(*index, *worktree), path, *index != '.'),
+                Entry::Untracked { path } => ("new", path, false),
+                Entry::Unmerged { .. } | Entry::Ignored { .. } => return None,
+            };
+            let counts = match numstat.iter().find(|(p, _)| p == path) {
+                Some((_, Some((added, deleted)))) => format!(" (+{added} -{deleted})"),
+                Some((_, None)) => " (binary)".to_string(),
+                None => String::new(),
+            };
+            let excluded = excludes.is_excluded(path);
+            let note = if excluded { " [prompt-excluded]" } else { "" };
+            Some(Candidate {
+                path: path.clone(),
+                label: format!("{status:<8} {path}{counts}{note}"),
+                checked: !excluded,
+                staged,
+            })
+        })
+        .collect()
+}
+
+// The worktree side wins: it is what staging the file will take.
+fn status_name(index: char, worktree: char) -> &'static str {
+    let code = if worktree == '.' { index } else { worktree };
+    match code {
+        'A' => "added",
+        'D' => "deleted",
+        'R' => "renamed",
+        'C' => "copied",
+        'T' => "retyped",
+        _ => "modified",
+    }
+}
+
+// The indexes of the checked candidates, or none when nothing was chosen or
+// the list was dismissed (Esc / q). Without a terminal the prompt could
+// never be answered, so that is an error rather than a hang.
+pub fn choose(candidates: &[Candidate]) -> Result<Vec<usize>> {
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        return Err(anyhow!(
+            "--select needs a terminal to pick files in; stage them yourself and use --no-stage"
+        ));
+    }
+    Ok(MultiSelect::new()
+        .with_prompt("Files to stage (Space toggles, Enter confirms, Esc cancels)")
+        .items_checked(
+            candidates
+                .iter()
+                .map(|candidate| (candidate.label.as_str(), candidate.checked)),
+        )
+        .interact_opt()?
+        .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changed(index: char, worktree: char, path: &str) -> Entry {
+        Entry::Changed {
+            index,
+            worktree,
+            submodule: false,
+            path: path.into(),
+        }
+    }
+
+    #[test]
+    fn everything_but_prompt_excluded_files_starts_checked() {
+        let entries = [
+            changed('.', 'M', "src/main.rs"),
+            changed('A', '.', "src/new.rs"),
+            changed('.', 'D', "old.rs"),
+            Entry::Untracked {
+                path: "fixtures/users.json".into(),
+            },
+            Entry::Unmerged {
+                path: "conflict.rs".into(),
+            },
+            Entry::Ignored {
+                path: "target/x".into(),
+            },
+        ];
+        let numstat = [
+            ("src/main.rs".to_string(), Some((12, 3))),
+            ("old.rs".to_string(), Some((0, 40))),
+            ("src/new.rs".to_string(), None),
+        ];
+        let excludes = Excludes::parse(["fixtures/"]);
+        let list = candidates(&entries, &numstat, &excludes);
+        let labels: Vec<&str> = list.iter().map(|c| c.label.as_str()).collect();
+        assert_eq!(
+            labels,
+            [
+                "modified src/main.rs (+12 -3)",
+                "added    src/new.rs (binary)",
+                "deleted  old.rs (+0 -40)",
+                "new      fixtures/users.json [prompt-excluded]",
+            ]
+        );
+        let checked: Vec<bool> = list.iter().map(|c| c.checked).collect();
+        assert_eq!(checked, [true, true, true, false]);
+        let staged: Vec<bool> = list.iter().map(|c| c.staged).collect();
+        assert_eq!(staged, [false, true, false, false]);
+        assert_eq!(list[3].path, "fixtures/users.json");
+    }
+}
@@ -0,0 +1,95 @@
+// ---------- Squashing a branch ----------
+// `git-cmt-rs squash`: one message for every commit since the base. The
+// combined diff goes through the usual pipeline; the messages of the commits
+// being replaced say what the branch set out to do. Git access lives in
+// main; this module only does the text.
+
+use crate::diff::plural;
+
+// Messages listed, oldest first; a long-lived branch would otherwise crowd
+// out the diff.
+pub const MAX_MESSAGES: usize = 50;
+// Lines kept of each message; the subject and the start of the body say
+// enough.
+pub const MAX_MESSAGE_LINES: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Squash {
+    // The base as the user named it (or the default branch), for the prompt.
+    pub base: String,
+    // Full messages of the commits in `base..HEAD`, oldest first.
+    pub messages: Vec<String>,
+}
+
+impl Squash {
+    pub fn new(base: &str, messages: Vec<String>) -> Self {
+        Squash {
+            base: base.to_string(),
+            messages,
+        }
+    }
+
+    // Added to the prompt: without it the model would only see one diff.
+    pub fn prompt(&self) -> String {
+        let mut text = format!(
+            "This change squashes {} since {} into one; the diff is their combined effect.\n\
+             Their messages, oldest first:\n",
+            plural(self.messages.len(), "commit"),
+            self.base
+        );
+        for message in self.messages.iter().take(MAX_MESSAGES) {
+            let mut lines = message
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .take(MAX_MESSAGE_LINES);
+            text.push_str(&format!("- {}\n", lines.next().unwrap_or_default()));
+            lines.for_each(|line| text.push_str(&format!("  {line}\n")));
+        }
+        if let Some(more) = self.messages.len().checked_sub(MAX_MESSAGES + 1) {
+            text.push_str(&format!("(and {} more)\n", more + 1));
+        }
+        text.push_str(
+            "Write one message for the combined change, not a list of the commits. \
+             Leave out fixups and anything a later commit undid.\n",
+        );
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_prompt_lists_each_message_oldest_first() {
+        let squash = Squash::new(
+            "main",
+            vec![
+                "feat(api): add paging\n\nCursor based.\n".into(),
+                "fix: typo".into(),
+            ],
+        );
+        let prompt = squash.prompt();
+        assert!(prompt.starts_with("This change squashes 2 commits since main into one"));
+        assert!(
+            prompt
+                .contains("oldest first:\n- feat(api): add paging\n  Cursor based.\n- fix: typo\n")
+        );
+        assert!(!prompt.contains("more)"));
+    }
+
+    #[test]
+    fn a_single_commit_is_not_plural() {
+        let prompt = Squash::new("main", vec!["fix: typo".into()]).prompt();
+        assert!(prompt.starts_with("This change squashes 1 commit since main into one"));
+    }
+
+    #[test]
+    fn long_branches_are_cut_to_the_first_messages() {
+        let messages = (0..MAX_MESSAGES + 3).map(|i| format!("fix: {i}")).collect();
+        let prompt = Squash::new("origin/main", messages).prompt();
+        assert!(prompt.contains(&format!("- fix: {}\n", MAX_MESSAGES - 1)));
+        assert!(!prompt.contains(&format!("- fix: {MAX_MESSAGES}\n")));
+        assert!(prompt.contains("(and 3 more)\n"));
+    }
+}
@@ -0,0 +1,342 @@
+// ---------- git status --porcelain=v2 ----------
+// Parsed `git status --porcelain=v2 -z` output, used to explain an empty
+// staged diff (a clean tree is one thing, changes that staging left out are
+// another) and to preview what staging will take.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    // `1` (ordinary) and `2` (renamed/copied) records. `index` and `worktree`
+    // are the X and Y status letters, '.' when that side is unchanged.
+    Changed {
+        index: char,
+        worktree: char,
+        submodule: bool,
+        path: String,
+    },
+    // `u`: a merge conflict that hasn't been resolved.
+    Unmerged {
+        path: String,
+    },
+    // `?`
+    Untracked {
+        path: String,
+    },
+    // `!`, only present with `--ignored`.
+    Ignored {
+        path: String,
+    },
+}
+
+impl Entry {
+    pub fn path(&self) -> &str {
+        match self {
+            Entry::Changed { path, .. }
+            | Entry::Unmerged { path }
+            | Entry::Untracked { path }
+            | Entry::Ignored { path } => path,
+        }
+    }
+}
+
+pub fn parse(porcelain: &str) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut records = porcelain.split('\0');
+    while let Some(record) = records.next() {
+        let entry = match record.split_once(' ') {
+            // 1 XY sub mH mI mW hH hI path
+            Some(("1", rest)) => changed(rest, 7),
+            // 2 XY sub mH mI mW hH hI Xscore path, then the original path
+            Some(("2", rest)) => {
+                records.next();
+                changed(rest, 8)
+            }
+            // u XY sub m1 m2 m3 mW h1 h2 h3 path
+            Some(("u", rest)) => rest.splitn(10, ' ').nth(9).map(|path| Entry::Unmerged {
+                path: path.to_string(),
+            }),
+            Some(("?", path)) => Some(Entry::Untracked {
+                path: path.to_string(),
+            }),
+            Some(("!", path)) => Some(Entry::Ignored {
+                path: path.to_string(),
+            }),
+            // `#` headers and the empty record after the last NUL.
+            _ => None,
+        };
+        entries.extend(entry);
+    }
+    entries
+}
+
+// `XY sub ...` with the path after `fields` more space-separated fields.
+fn changed(rest: &str, fields: usize) -> Option<Entry> {
+    let mut parts = rest.splitn(fields + 1, ' ');
+    let mut xy = parts.next()?.chars();
+    let (index, worktree) = (xy.next()?, xy.next()?);
+    let submodule = parts.next()?.starts_with('S');
+    let path = parts.nth(fields - 2)?;
+    Some(Entry::Changed {
+        index,
+        worktree,
+        submodule,
+        path: path.to_string(),
+    })
+}
+
+// How the index was staged, to say why a change didn't make it in.
+pub struct Staging<'a> {
+    pub staged: bool,
+    pub include_untracked: bool,
+    // `--cwd` in a subdirectory: the root-relative prefix that was staged.
+    pub cwd_prefix: Option<&'a str>,
+}
+
+// Each entry that is a change but isn't in the staged diff, with the reason.
+// Called once the diff has come back empty, so staged entries can only be
+// whitespace-only changes (the diff is taken with `-b`).
+pub fn skipped(entries: &[Entry], staging: &Staging) -> Vec<(String, String)> {
+    let outside = |path: &str| {
+        staging
+            .cwd_prefix
+            .filter(|prefix| !path.starts_with(prefix))
+            .map(|prefix| format!("outside {prefix} (--cwd)"))
+    };
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let reason = match entry {
+                Entry::Ignored { .. } => return None,
+                Entry::Unmerged { .. } => "unmerged; resolve the conflict first".to_string(),
+                Entry::Changed { index, .. } if *index != '.' => {
+                    "whitespace-only change; the diff ignores whitespace".to_string()
+                }
+                Entry::Changed {
+                    submodule: true, ..
+                } => "submodule has uncommitted changes; commit inside it first".to_string(),
+                Entry::Changed { path, .. } => match outside(path) {
+                    _ if !staging.staged => "not staged (--no-stage)".to_string(),
+                    Some(reason) => reason,
+                    None => "not staged".to_string(),
+                },
+                Entry::Untracked { path } => match outside(path) {
+                    _ if !staging.staged => "untracked (--no-stage)".to_string(),
+                    _ if !staging.include_untracked => {
+                        "untracked (--no-include-untracked)".to_string()
+                    }
+                    Some(reason) => reason,
+                    None => "untracked".to_string(),
+                },
+            };
+            Some((entry.path().to_string(), reason))
+        })
+        .collect()
+}
+
+// The staging preview lists this many files before counting the rest.
+const MAX_STAGE_LISTED: usize = 20;
+
+// What `git add` is about to take: the status letter and path of every
+// worktree change and, with `include_untracked`, every new file (`A`),
+// within the `--cwd` prefix if there is one. Unmerged files and dirty
+// submodules are left out, as `skipped()` explains.
+pub fn to_stage(entries: &[Entry], staging: &Staging) -> Vec<(char, String)> {
+    let inside = |path: &str| {
+        staging
+            .cwd_prefix
+            .is_none_or(|prefix| path.starts_with(prefix))
+    };
+    entries
+        .iter()
+        .filter_map(|entry| match entry {
+            Entry::Changed {
+                worktree,
+                submodule: false,
+                path,
+                ..
+            } if *worktree != '.' => Some((*worktree, path)),
+            Entry::Untracked { path } if staging.include_untracked => Some(('A', path)),
+            _ => None,
+        })
+        .filter(|(_, path)| inside(path))
+        .map(|(status, path)| (status, path.clone()))
+        .collect()
+}
+
+// Counts by kind, then the files themselves, capped at `MAX_STAGE_LISTED`.
+pub fn stage_preview(files: &[(char, String)]) -> String {
+    let count = |wanted: &[char]| {
+        files
+            .iter()
+            .filter(|(status, _)| wanted.contains(status))
+            .count()
+    };
+    let counts: Vec<String> = [
+        (count(&['M', 'T']), "modified"),
+        (count(&['A']), "new"),
+        (count(&['D']), "deleted"),
+    ]
+    .into_iter()
+    .filter(|(n, _)| *n > 0)
+    .map(|(n, kind)| format!("{n} {kind}"))
+    .collect();
+    let mut out = format!(
+        "Staging will pick up {} file(s): {}\n",
+        files.len(),
+        counts.join(", ")
+    );
+    for (status, path) in files.iter().take(MAX_STAGE_LISTED) {
+        out.push_str(&format!("  {status} {path}\n"));
+    }
+    if files.len() > MAX_STAGE_LISTED {
+        out.push_str(&format!(
+            "  ... and {} more\n",
+            files.len() - MAX_STAGE_LISTED
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = concat!(
+        "# branch.oid 1f2e3d\0",
+        "1 .M N... 100644 100644 100644 3b18e5 3b18e5 src/main.rs\0",
+        "1 M. N... 100644 100644 100644 3b18e5 9c1f2a docs/a b.md\0",
+        "2 R. N... 100644 100644 100644 3b18e5 3b18e5 R100 new.rs\0old.rs\0",
+        "1 .M S.M. 160000 160000 160000 5d4c3b 5d4c3b vendor/lib\0",
+        "u UU N... 100644 100644 100644 100644 aa11 bb22 cc33 conflict.rs\0",
+        "? notes/todo.txt\0",
+        "! target/\0",
+    );
+
+    #[test]
+    fn porcelain_v2_records_are_parsed() {
+        let entries = parse(SAMPLE);
+        let paths: Vec<&str> = entries.iter().map(Entry::path).collect();
+        assert_eq!(
+            paths,
+            [
+                "src/main.rs",
+                "docs/a b.md",
+                "new.rs",
+                "vendor/lib",
+                "conflict.rs",
+                "notes/todo.txt",
+                "target/"
+            ]
+        );
+        assert_eq!(
+            entries[0],
+            Entry::Changed {
+                index: '.',
+                worktree: 'M',
+                submodule: false,
+                path: "src/main.rs".into()
+            }
+        );
+        assert!(matches!(entries[2], Entry::Changed { index: 'R', .. }));
+        assert!(matches!(
+            entries[3],
+            Entry::Changed {
+                submodule: true,
+                ..
+            }
+        ));
+        assert!(parse("").is_empty());
+    }
+
+    #[test]
+    fn the_staging_preview_lists_what_git_add_takes() {
+        let entries = parse(SAMPLE);
+        let mut staging = Staging {
+            staged: true,
+            include_untracked: true,
+            cwd_prefix: None,
+        };
+        let files = to_stage(&entries, &staging);
+        assert_eq!(
+            files,
+            [
+                ('M', "src/main.rs".to_string()),
+                ('A', "notes/todo.txt".to_string())
+            ]
+        );
+        assert_eq!(
+            stage_preview(&files),
+            "Staging will pick up 2 file(s): 1 modified, 1 new\n  M src/main.rs\n  A notes/todo.txt\n"
+        );
+        staging.include_untracked = false;
+        staging.cwd_prefix = Some("docs/");
+        assert!(to_stage(&entries, &staging).is_empty());
+
+        let many: Vec<(char, String)> = (0..25).map(|i| ('D', format!("old/{i}.rs"))).collect();
+        let preview = stage_preview(&many);
+        assert!(preview.starts_with("Staging will pick up 25 file(s): 25 deleted\n"));
+        assert!(preview.ends_with("  D old/19.rs\n  ... and 5 more\n"));
+    }
+
+    #[test]
+    fn skipped_changes_say_why() {
+        let entries = parse(SAMPLE);
+        let staging = Staging {
+            staged: true,
+            include_untracked: false,
+            cwd_prefix: Some("src/"),
+        };
+        let skipped = skipped(&entries, &staging);
+        let reason = |path: &str| {
+            skipped
+                .iter()
+                .find(|(p, _)| p == path)
+                .map(|(_, reason)| reason.as_str())
+        };
+        assert_eq!(reason("src/main.rs"), Some("not staged"));
+        assert_eq!(
+            reason("docs/a b.md"),
+            Some("whitespace-only change; the diff ignores whitespace")
+        );
+        assert_eq!(
+            reason("vendor/lib"),
+            Some("submodule has uncommitted changes; commit inside it first")
+        );
+        assert_eq!(
+            reason("conflict.rs"),
+            Some("unmerged; resolve the conflict first")
+        );
+        assert_eq!(
+            reason("notes/todo.txt"),
+            Some("untracked (--no-include-untracked)")
+        );
+        assert_eq!(reason("target/"), None);
+
+        let entries = parse("1 .M N... 100644 100644 100644 3b 3b lib/x.rs\0? y.rs\0");
+        let outside = Staging {
+            staged: true,
+            include_untracked: true,
+            cwd_prefix: Some("src/"),
+        };
+        assert_eq!(
+            super::skipped(&entries, &outside),
+            [
+                ("lib/x.rs".to_string(), "outside src/ (--cwd)".to_string()),
+                ("y.rs".to_string(), "outside src/ (--cwd)".to_string())
+            ]
+        );
+        let unstaged = Staging {
+            staged: false,
+            include_untracked: true,
+            cwd_prefix: None,
+        };
+        assert_eq!(
+            super::skipped(&entries, &unstaged),
+            [
+                (
+                    "lib/x.rs".to_string(),
+                    "not staged (--no-stage)".to_string()
+                ),
+                ("y.rs".to_string(), "untracked (--no-stage)".to_string())
+            ]
+        );
+    }
+}